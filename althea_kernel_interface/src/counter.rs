@@ -8,7 +8,7 @@ use regex::Regex;
 
 use failure::Error;
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum FilterTarget {
     Input,
     Output,
@@ -119,40 +119,6 @@ add zxcv 1234:5678:9801:2345:6789:0123:4567:8902,wg0 packets 123456789 bytes 987
 }
 
 impl KernelInterface {
-    pub fn init_counter(&self, target: &FilterTarget) -> Result<(), Error> {
-        self.run_command(
-            "ipset",
-            &[
-                "create",
-                target.set_name(),
-                "hash:net,iface",
-                "family",
-                "inet6",
-                "counters",
-            ],
-        )?;
-        self.add_iptables_rule(
-            "ip6tables",
-            &[
-                "-w",
-                "-A",
-                target.table(),
-                "-m",
-                "set",
-                "!",
-                "--match-set",
-                target.set_name(),
-                &format!("dst,{}", target.interface()),
-                "-j",
-                "SET",
-                "--add-set",
-                target.set_name(),
-                &format!("dst,{}", target.interface()),
-            ],
-        )?;
-        Ok(())
-    }
-
     pub fn read_counters(
         &self,
         target: &FilterTarget,
@@ -187,72 +153,6 @@ impl KernelInterface {
     }
 }
 
-#[test]
-fn test_init_counter() {
-    use std::os::unix::process::ExitStatusExt;
-    use std::process::ExitStatus;
-    use std::process::Output;
-
-    use KI;
-
-    let mut counter = 0;
-
-    KI.set_mock(Box::new(move |program, args| {
-        counter += 1;
-        match counter {
-            1 => {
-                assert_eq!(program, "ipset");
-                assert_eq!(
-                    args,
-                    vec![
-                        "create",
-                        "rita_input",
-                        "hash:net,iface",
-                        "family",
-                        "inet6",
-                        "counters",
-                    ]
-                );
-                Ok(Output {
-                    stdout: b"".to_vec(),
-                    stderr: b"".to_vec(),
-                    status: ExitStatus::from_raw(0),
-                })
-            }
-            2 => {
-                assert_eq!(program, "ip6tables");
-                assert_eq!(
-                    args,
-                    vec![
-                        "-w",
-                        "-C",
-                        "INPUT",
-                        "-m",
-                        "set",
-                        "!",
-                        "--match-set",
-                        "rita_input",
-                        "dst,src",
-                        "-j",
-                        "SET",
-                        "--add-set",
-                        "rita_input",
-                        "dst,src",
-                    ]
-                );
-                Ok(Output {
-                    stdout: b"".to_vec(),
-                    stderr: b"".to_vec(),
-                    status: ExitStatus::from_raw(0),
-                })
-            }
-
-            _ => panic!("Unexpected call {} {:?} {:?}", counter, program, args),
-        }
-    }));
-    KI.init_counter(&FilterTarget::Input)
-        .expect("Unable to init counter");
-}
 #[test]
 fn test_read_counters() {
     use std::net::Ipv6Addr;
@@ -1,10 +1,10 @@
-use super::{KernelInterface, KernelInterfaceError};
+use super::{KernelInterface, KernelInterfaceError, KernelOp};
 
 use failure::Error;
 
 impl KernelInterface {
     pub fn delete_tunnel(&self, interface: &String) -> Result<(), Error> {
-        let output = self.run_command("ip", &["link", "del", &interface])?;
+        let output = self.apply_op(&KernelOp::DelLink(interface.clone()))?;
         if !output.stderr.is_empty() {
             return Err(KernelInterfaceError::RuntimeError(format!(
                 "received error deleting wireguard interface: {}",
@@ -23,7 +23,7 @@ fn test_delete_tunnel_linux() {
 
     use KI;
 
-    let ip_args = &["link", "del", "wg1"];
+    let ip_args = &["link", "del", "dev", "wg1"];
 
     KI.set_mock(Box::new(move |program, args| {
         assert_eq!(program, "ip");
@@ -8,7 +8,7 @@ use regex::Regex;
 
 use failure::Error;
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum ExitFilterTarget {
     Input,
     Output,
@@ -114,40 +114,6 @@ add zxcv fd00::b4dc:0d3 packets 123456789 bytes 987654320
 }
 
 impl KernelInterface {
-    pub fn init_exit_counter(&self, target: &ExitFilterTarget) -> Result<(), Error> {
-        self.run_command(
-            "ipset",
-            &[
-                "create",
-                target.set_name(),
-                "hash:net",
-                "family",
-                "inet6",
-                "counters",
-            ],
-        )?;
-        self.add_iptables_rule(
-            "ip6tables",
-            &[
-                "-w",
-                "-A",
-                target.table(),
-                "-m",
-                "set",
-                "!",
-                "--match-set",
-                target.set_name(),
-                target.direction(),
-                "-j",
-                "SET",
-                "--add-set",
-                target.set_name(),
-                target.direction(),
-            ],
-        )?;
-        Ok(())
-    }
-
     pub fn read_exit_server_counters(
         &self,
         target: &ExitFilterTarget,
@@ -186,73 +152,6 @@ impl KernelInterface {
     }
 }
 
-#[test]
-fn test_init_exit_counter() {
-    use std::os::unix::process::ExitStatusExt;
-    use std::process::ExitStatus;
-    use std::process::Output;
-
-    use KI;
-
-    let mut counter = 0;
-
-    KI.set_mock(Box::new(move |program, args| {
-        counter += 1;
-        match counter {
-            1 => {
-                assert_eq!(program, "ipset");
-                assert_eq!(
-                    args,
-                    vec![
-                        "create",
-                        "rita_exit_input",
-                        "hash:net",
-                        "family",
-                        "inet6",
-                        "counters",
-                    ]
-                );
-                Ok(Output {
-                    stdout: b"".to_vec(),
-                    stderr: b"".to_vec(),
-                    status: ExitStatus::from_raw(0),
-                })
-            }
-            2 => {
-                assert_eq!(program, "ip6tables");
-                assert_eq!(
-                    args,
-                    vec![
-                        "-w",
-                        "-C",
-                        "INPUT",
-                        "-m",
-                        "set",
-                        "!",
-                        "--match-set",
-                        "rita_exit_input",
-                        "src",
-                        "-j",
-                        "SET",
-                        "--add-set",
-                        "rita_exit_input",
-                        "src",
-                    ]
-                );
-                Ok(Output {
-                    stdout: b"".to_vec(),
-                    stderr: b"".to_vec(),
-                    status: ExitStatus::from_raw(0),
-                })
-            }
-
-            _ => panic!("Unexpected call {} {:?} {:?}", counter, program, args),
-        }
-    }));
-    KI.init_exit_counter(&ExitFilterTarget::Input)
-        .expect("Unable to init exit counter");
-}
-
 #[test]
 fn test_read_exit_server_counters() {
     use std::net::Ipv6Addr;
@@ -1,10 +1,12 @@
-use super::{KernelInterface, KernelInterfaceError};
+use super::{KernelInterface, KernelInterfaceError, KernelOp, WgDevice, WgEndpoint, WgPeer};
 
 use std::collections::HashSet;
 
 use failure::Error;
 
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
+
+use ip_route::max_prefix_len;
 
 #[derive(Debug)]
 pub struct ExitClient {
@@ -23,53 +25,45 @@ impl KernelInterface {
         local_ip: &IpAddr,
         netmask: u8,
     ) -> Result<(), Error> {
-        let command = "wg".to_string();
-
-        let mut args = Vec::new();
-        args.push("set".into());
-        args.push("wg_exit".into());
-        args.push("listen-port".into());
-        args.push(format!("{}", listen_port));
-        args.push("private-key".into());
-        args.push(private_key_path.to_string());
+        let mut device = WgDevice::new("wg_exit");
+        device.listen_port = Some(listen_port);
+        device.private_key_path = Some(private_key_path.to_string());
 
         let mut client_pubkeys = HashSet::new();
 
         for c in clients {
-            args.push("peer".into());
-            args.push(format!("{}", c.public_key));
-            args.push("endpoint".into());
-            args.push(format!("[{}]:{}", c.mesh_ip, c.port));
-            args.push("allowed-ips".into());
-            args.push(format!("{}", c.internal_ip));
-            args.push("persistent-keepalive".into());
-            args.push("5".into());
+            let mut peer = WgPeer::new(&c.public_key);
+            peer.endpoint = Some(WgEndpoint {
+                addr: SocketAddr::new(c.mesh_ip, c.port),
+                scope: None,
+            });
+            peer.allowed_ips = Some(vec![(c.internal_ip, max_prefix_len(&c.internal_ip))]);
+            peer.persistent_keepalive = Some(5);
+            device.peers.push(peer);
 
             client_pubkeys.insert(c.public_key.clone());
         }
 
-        let arg_str: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
-
-        self.run_command(&command, &arg_str[..])?;
+        self.apply_op(&KernelOp::WgSet(device))?;
 
         for i in self.get_peers("wg_exit")? {
             if !client_pubkeys.contains(&i.to_string()) {
-                self.run_command("wg", &["set", "wg_exit", "peer", &i, "remove"])?;
+                self.remove_exit_peer(&i)?;
             }
         }
 
-        let _output = self.run_command(
-            "ip",
-            &[
-                "address",
-                "add",
-                &format!("{}/{}", local_ip, netmask),
-                "dev",
-                "wg_exit",
-            ],
-        )?;
-
-        let output = self.run_command("ip", &["link", "set", "dev", "wg_exit", "mtu", "1340"])?;
+        let _output = self.apply_op(&KernelOp::Address {
+            add: true,
+            dev: "wg_exit".to_string(),
+            addr: *local_ip,
+            prefix_len: netmask,
+        })?;
+
+        let output = self.apply_op(&KernelOp::SetLink {
+            dev: "wg_exit".to_string(),
+            up: None,
+            mtu: Some(1340),
+        })?;
         if !output.stderr.is_empty() {
             return Err(KernelInterfaceError::RuntimeError(format!(
                 "received error adding wg link: {}",
@@ -77,7 +71,11 @@ impl KernelInterface {
             )).into());
         }
 
-        let output = self.run_command("ip", &["link", "set", "dev", "wg_exit", "up"])?;
+        let output = self.apply_op(&KernelOp::SetLink {
+            dev: "wg_exit".to_string(),
+            up: Some(true),
+            mtu: None,
+        })?;
         if !output.stderr.is_empty() {
             return Err(KernelInterfaceError::RuntimeError(format!(
                 "received error setting wg interface up: {}",
@@ -88,60 +86,11 @@ impl KernelInterface {
         Ok(())
     }
 
-    pub fn setup_nat(&self, external_interface: &str) -> Result<(), Error> {
-        self.add_iptables_rule(
-            "iptables",
-            &[
-                "-w",
-                "-t",
-                "nat",
-                "-A",
-                "POSTROUTING",
-                "-o",
-                external_interface,
-                "-j",
-                "MASQUERADE",
-            ],
-        )?;
-
-        self.add_iptables_rule(
-            "iptables",
-            &[
-                "-w",
-                "-t",
-                "filter",
-                "-A",
-                "FORWARD",
-                "-o",
-                external_interface,
-                "-i",
-                "wg_exit",
-                "-j",
-                "ACCEPT",
-            ],
-        )?;
-
-        self.add_iptables_rule(
-            "iptables",
-            &[
-                "-w",
-                "-t",
-                "filter",
-                "-A",
-                "FORWARD",
-                "-o",
-                "wg_exit",
-                "-i",
-                external_interface,
-                "-m",
-                "state",
-                "--state",
-                "RELATED,ESTABLISHED",
-                "-j",
-                "ACCEPT",
-            ],
-        )?;
-
+    /// Removes a peer from the wg_exit interface
+    pub fn remove_exit_peer(&self, pubkey: &str) -> Result<(), Error> {
+        let mut device = WgDevice::new("wg_exit");
+        device.peers.push(WgPeer::removal(pubkey));
+        self.apply_op(&KernelOp::WgSet(device))?;
         Ok(())
     }
 }
@@ -0,0 +1,725 @@
+//! Declarative firewall management. Rather than adding rules one at a time and never removing
+//! them we describe everything Rita needs as a Ruleset and render it to iptables-restore input.
+//! All of our rules live in chains we own, which are flushed and refilled in a single atomic
+//! restore, so reapplying never duplicates rules and removing a group removes its rules.
+//!
+//! Rules are rendered in the same canonical form iptables-save prints them in so that the live
+//! state can be diffed against the Ruleset textually.
+
+use super::{ExitFilterTarget, FilterTarget, KernelInterface, KernelInterfaceError};
+
+use std::net::IpAddr;
+
+use failure::Error;
+
+const FILTER_CHAINS: &[(&str, &str)] = &[
+    ("INPUT", "RITA_INPUT"),
+    ("OUTPUT", "RITA_OUTPUT"),
+    ("FORWARD", "RITA_FORWARD"),
+];
+const NAT_CHAINS: &[(&str, &str)] = &[("POSTROUTING", "RITA_POSTROUTING")];
+
+/// How rulesets are applied, both take iptables-restore syntax but the nftables backend commits
+/// it as a single nftables transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirewallBackend {
+    Legacy,
+    Nftables,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    fn command(&self, backend: FirewallBackend) -> &'static str {
+        match (*self, backend) {
+            (IpFamily::V4, FirewallBackend::Legacy) => "iptables",
+            (IpFamily::V6, FirewallBackend::Legacy) => "ip6tables",
+            (IpFamily::V4, FirewallBackend::Nftables) => "iptables-nft",
+            (IpFamily::V6, FirewallBackend::Nftables) => "ip6tables-nft",
+        }
+    }
+
+    fn restore_command(&self, backend: FirewallBackend) -> String {
+        format!("{}-restore", self.command(backend))
+    }
+
+    fn save_command(&self, backend: FirewallBackend) -> String {
+        format!("{}-save", self.command(backend))
+    }
+}
+
+/// A group of rules serving one purpose, groups are added and removed as a unit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleGroup {
+    /// Per neighbor traffic counters for billing
+    Counters(FilterTarget),
+    /// Per client traffic counters on an exit
+    ExitCounters(ExitFilterTarget),
+    /// Byte counters for all traffic over an interface
+    IfaceCounters(String),
+    /// NAT for a lan interface out over wg_exit
+    ClientNat(String),
+    /// Keeps our hello port from being reached through wg_exit
+    ExitTunnelHelloDrop(u16),
+    /// NAT for exit clients out over the given external interface
+    ExitNat(String),
+    /// Drops all traffic forwarded over a suspended tunnel
+    SuspendTunnel(String),
+    /// Rate limits an exit client's internal ip to the given kbit/s in each direction
+    ThrottleExitClient(IpAddr, u32),
+}
+
+/// Chains and their rules for one table of one address family
+#[derive(Debug, Default, PartialEq)]
+struct TableRules {
+    chains: Vec<String>,
+    rules: Vec<String>,
+    /// Chains we no longer use, they are flushed and deleted
+    stale: Vec<String>,
+}
+
+impl TableRules {
+    fn chain(&mut self, name: &str) {
+        if !self.chains.iter().any(|c| c == name) {
+            self.chains.push(name.to_string());
+        }
+    }
+
+    fn rule(&mut self, chain: &str, rule: &str) {
+        let rule = format!("-A {} {}", chain, rule);
+        if !self.rules.contains(&rule) {
+            self.rules.push(rule);
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Ruleset {
+    groups: Vec<RuleGroup>,
+    /// Chains of removed groups which still exist on the system until the next apply
+    stale_chains: Vec<String>,
+}
+
+impl Ruleset {
+    pub fn new() -> Ruleset {
+        Ruleset {
+            groups: Vec::new(),
+            stale_chains: Vec::new(),
+        }
+    }
+
+    /// Returns true if the group was not already present
+    pub fn insert(&mut self, group: RuleGroup) -> bool {
+        if self.groups.contains(&group) {
+            return false;
+        }
+        if let RuleGroup::IfaceCounters(ref iface) = group {
+            let chain = format!("{}-counter", iface);
+            self.stale_chains.retain(|c| *c != chain);
+        }
+        self.groups.push(group);
+        true
+    }
+
+    /// Returns true if the group was present
+    pub fn remove(&mut self, group: &RuleGroup) -> bool {
+        let len = self.groups.len();
+        self.groups.retain(|g| g != group);
+        if len == self.groups.len() {
+            return false;
+        }
+        if let RuleGroup::IfaceCounters(ref iface) = *group {
+            self.stale_chains.push(format!("{}-counter", iface));
+        }
+        true
+    }
+
+    pub fn groups(&self) -> &[RuleGroup] {
+        &self.groups
+    }
+
+    /// Called once the ruleset has been applied, the stale chains are gone at that point
+    pub fn clear_stale_chains(&mut self) {
+        self.stale_chains.clear();
+    }
+
+    /// The ipsets referenced by the ruleset as `ipset create` arguments, they must exist before
+    /// the rules referencing them can be restored
+    fn ipsets(&self) -> Vec<Vec<&str>> {
+        let mut sets = Vec::new();
+        for group in self.groups.iter() {
+            match *group {
+                RuleGroup::Counters(ref target) => sets.push(vec![
+                    "-exist",
+                    "create",
+                    target.set_name(),
+                    "hash:net,iface",
+                    "family",
+                    "inet6",
+                    "counters",
+                ]),
+                RuleGroup::ExitCounters(ref target) => sets.push(vec![
+                    "-exist",
+                    "create",
+                    target.set_name(),
+                    "hash:net",
+                    "family",
+                    "inet6",
+                    "counters",
+                ]),
+                _ => {}
+            }
+        }
+        sets
+    }
+
+    /// Rules for the given family and table, order matters so this is done in passes rather
+    /// than in the order groups were added. Suspension drops come first so that suspended
+    /// traffic is never counted or accepted.
+    fn table(&self, family: IpFamily, table: &str) -> TableRules {
+        let mut rules = TableRules::default();
+        let builtin = match table {
+            "filter" => FILTER_CHAINS,
+            "nat" => NAT_CHAINS,
+            _ => &[],
+        };
+        for &(_, chain) in builtin.iter() {
+            rules.chain(chain);
+        }
+
+        match (family, table) {
+            (IpFamily::V6, "filter") => {
+                for group in self.groups.iter() {
+                    if let RuleGroup::SuspendTunnel(ref iface) = *group {
+                        rules.rule("RITA_FORWARD", &format!("-i {} -j DROP", iface));
+                        rules.rule("RITA_FORWARD", &format!("-o {} -j DROP", iface));
+                    }
+                }
+                for group in self.groups.iter() {
+                    match *group {
+                        RuleGroup::Counters(ref target) => {
+                            let set = format!(
+                                "{} dst,{}",
+                                target.set_name(),
+                                target.interface()
+                            );
+                            rules.rule(
+                                &format!("RITA_{}", target.table()),
+                                &format!(
+                                    "-m set ! --match-set {} -j SET --add-set {}",
+                                    set, set
+                                ),
+                            );
+                        }
+                        RuleGroup::ExitCounters(ref target) => {
+                            let set = format!("{} {}", target.set_name(), target.direction());
+                            rules.rule(
+                                &format!("RITA_{}", target.table()),
+                                &format!(
+                                    "-m set ! --match-set {} -j SET --add-set {}",
+                                    set, set
+                                ),
+                            );
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            (IpFamily::V4, "filter") => {
+                for group in self.groups.iter() {
+                    if let RuleGroup::ThrottleExitClient(ip, kbps) = *group {
+                        // hashlimit takes byte rates
+                        let rate = ::std::cmp::max(kbps / 8, 1);
+                        rules.rule(
+                            "RITA_FORWARD",
+                            &format!(
+                                "-d {}/32 -o wg_exit -m hashlimit --hashlimit-above {}kb/s \
+                                 --hashlimit-mode dstip --hashlimit-name rita_dl_{} -j DROP",
+                                ip, rate, rate
+                            ),
+                        );
+                        rules.rule(
+                            "RITA_FORWARD",
+                            &format!(
+                                "-s {}/32 -i wg_exit -m hashlimit --hashlimit-above {}kb/s \
+                                 --hashlimit-mode srcip --hashlimit-name rita_ul_{} -j DROP",
+                                ip, rate, rate
+                            ),
+                        );
+                    }
+                }
+                for chain in self.stale_chains.iter() {
+                    rules.stale.push(chain.clone());
+                }
+                for group in self.groups.iter() {
+                    if let RuleGroup::IfaceCounters(ref iface) = *group {
+                        let chain = format!("{}-counter", iface);
+                        rules.chain(&chain);
+                        rules.rule("RITA_INPUT", &format!("-j {}", chain));
+                        rules.rule("RITA_OUTPUT", &format!("-j {}", chain));
+                        rules.rule(&chain, &format!("-o {}", iface));
+                        rules.rule(&chain, &format!("-i {}", iface));
+                        rules.rule(&chain, "-j RETURN");
+                    }
+                }
+                for group in self.groups.iter() {
+                    match *group {
+                        RuleGroup::ExitTunnelHelloDrop(port) => rules.rule(
+                            "RITA_OUTPUT",
+                            &format!("-o wg_exit -p tcp -m tcp --dport {} -j DROP", port),
+                        ),
+                        RuleGroup::ClientNat(ref lan_nic) => {
+                            rules.rule(
+                                "RITA_FORWARD",
+                                &format!("-i {} -o wg_exit -j ACCEPT", lan_nic),
+                            );
+                            rules.rule(
+                                "RITA_FORWARD",
+                                &format!("-i wg_exit -o {} -j ACCEPT", lan_nic),
+                            );
+                            // should be the same as --set-mss 1300
+                            rules.rule(
+                                "RITA_FORWARD",
+                                "-p tcp -m tcp --tcp-flags SYN,RST SYN -j TCPMSS --clamp-mss-to-pmtu",
+                            );
+                        }
+                        RuleGroup::ExitNat(ref external_nic) => {
+                            rules.rule(
+                                "RITA_FORWARD",
+                                &format!("-i wg_exit -o {} -j ACCEPT", external_nic),
+                            );
+                            rules.rule(
+                                "RITA_FORWARD",
+                                &format!(
+                                    "-i {} -o wg_exit -m state --state RELATED,ESTABLISHED -j ACCEPT",
+                                    external_nic
+                                ),
+                            );
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            (IpFamily::V4, "nat") => {
+                for group in self.groups.iter() {
+                    match *group {
+                        RuleGroup::ClientNat(_) => {
+                            rules.rule("RITA_POSTROUTING", "-o wg_exit -j MASQUERADE")
+                        }
+                        RuleGroup::ExitNat(ref external_nic) => rules.rule(
+                            "RITA_POSTROUTING",
+                            &format!("-o {} -j MASQUERADE", external_nic),
+                        ),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+        rules
+    }
+
+    fn tables(family: IpFamily) -> &'static [&'static str] {
+        match family {
+            IpFamily::V4 => &["filter", "nat"],
+            // we have no ipv6 nat rules and the table may not exist on older kernels
+            IpFamily::V6 => &["filter"],
+        }
+    }
+
+    /// Renders the ruleset as input for `iptables-restore --noflush`, declaring a chain flushes
+    /// it so the restore replaces everything in our chains and touches nothing else. Stale
+    /// chains are flushed and then deleted once nothing jumps to them anymore
+    pub fn render(&self, family: IpFamily) -> String {
+        let mut out = String::new();
+        for &table in Ruleset::tables(family).iter() {
+            let rules = self.table(family, table);
+            out.push_str(&format!("*{}\n", table));
+            for chain in rules.chains.iter().chain(rules.stale.iter()) {
+                out.push_str(&format!(":{} - [0:0]\n", chain));
+            }
+            for rule in rules.rules.iter() {
+                out.push_str(rule);
+                out.push('\n');
+            }
+            for chain in rules.stale.iter() {
+                out.push_str(&format!("-X {}\n", chain));
+            }
+            out.push_str("COMMIT\n");
+        }
+        out
+    }
+}
+
+/// Differences between a ruleset and what is actually loaded, rules are prefixed with their table
+#[derive(Debug, Default, PartialEq)]
+pub struct RulesetDiff {
+    /// Rules in the ruleset that are not loaded
+    pub missing: Vec<String>,
+    /// Rules loaded in our chains that are not in the ruleset
+    pub unexpected: Vec<String>,
+}
+
+impl RulesetDiff {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.unexpected.is_empty()
+    }
+}
+
+/// Compares the rules in the ruleset against the output of iptables-save for one family
+fn diff_family(ruleset: &Ruleset, family: IpFamily, saved: &str, diff: &mut RulesetDiff) {
+    let mut live: Vec<String> = Vec::new();
+    let mut table = String::new();
+    for line in saved.lines() {
+        let line = line.trim();
+        if line.starts_with('*') {
+            table = line[1..].to_string();
+        } else if line.starts_with("-A ") {
+            live.push(format!("{}: {}", table, line));
+        }
+    }
+
+    for &table in Ruleset::tables(family).iter() {
+        let rules = ruleset.table(family, table);
+        let builtin = match table {
+            "filter" => FILTER_CHAINS,
+            _ => NAT_CHAINS,
+        };
+
+        let mut expected: Vec<String> = rules
+            .rules
+            .iter()
+            .map(|rule| format!("{}: {}", table, rule))
+            .collect();
+        for &(from, to) in builtin.iter() {
+            expected.push(format!("{}: -A {} -j {}", table, from, to));
+        }
+
+        for rule in expected.iter() {
+            if !live.contains(rule) {
+                diff.missing.push(rule.clone());
+            }
+        }
+        for rule in live.iter() {
+            let owned = rules
+                .chains
+                .iter()
+                .any(|chain| rule.starts_with(&format!("{}: -A {} ", table, chain)));
+            if owned && !expected.contains(rule) {
+                diff.unexpected.push(rule.clone());
+            }
+        }
+    }
+}
+
+impl KernelInterface {
+    /// iptables 1.8 can be built against either kernel api, rules need to be applied through
+    /// whichever one the rest of the system is using
+    pub fn detect_firewall_backend(&self) -> FirewallBackend {
+        match self.run_command("iptables", &["--version"]) {
+            Ok(ref output) if String::from_utf8_lossy(&output.stdout).contains("nf_tables") => {
+                FirewallBackend::Nftables
+            }
+            _ => FirewallBackend::Legacy,
+        }
+    }
+
+    /// Atomically replaces the contents of our chains with the ruleset
+    pub fn apply_ruleset(&self, ruleset: &Ruleset, backend: FirewallBackend) -> Result<(), Error> {
+        for set in ruleset.ipsets() {
+            let output = self.run_command("ipset", &set)?;
+            if !output.status.success() {
+                return Err(KernelInterfaceError::RuntimeError(format!(
+                    "received error creating ipset: {}",
+                    String::from_utf8(output.stderr)?
+                )).into());
+            }
+        }
+
+        for family in [IpFamily::V4, IpFamily::V6].iter() {
+            let output = self.run_command_stdin(
+                &family.restore_command(backend),
+                &["--noflush"],
+                ruleset.render(*family).as_bytes(),
+            )?;
+            if !output.status.success() {
+                return Err(KernelInterfaceError::RuntimeError(format!(
+                    "received error restoring {:?} firewall: {}",
+                    family,
+                    String::from_utf8(output.stderr)?
+                )).into());
+            }
+
+            // The jumps into our chains live in chains we don't own so they can't be part of the
+            // restore, they only need to be added once
+            for &table in Ruleset::tables(*family).iter() {
+                let builtin = match table {
+                    "filter" => FILTER_CHAINS,
+                    _ => NAT_CHAINS,
+                };
+                for &(from, to) in builtin.iter() {
+                    self.add_iptables_rule(
+                        family.command(backend),
+                        &["-w", "-t", table, "-I", from, "-j", to],
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reports how the loaded rules differ from the ruleset
+    pub fn diff_ruleset(
+        &self,
+        ruleset: &Ruleset,
+        backend: FirewallBackend,
+    ) -> Result<RulesetDiff, Error> {
+        let mut diff = RulesetDiff::default();
+        for family in [IpFamily::V4, IpFamily::V6].iter() {
+            let output = self.run_command(&family.save_command(backend), &[])?;
+            diff_family(ruleset, *family, &String::from_utf8(output.stdout)?, &mut diff);
+        }
+        Ok(diff)
+    }
+}
+
+#[cfg(test)]
+fn test_ruleset() -> Ruleset {
+    let mut ruleset = Ruleset::new();
+    ruleset.insert(RuleGroup::Counters(FilterTarget::Input));
+    ruleset.insert(RuleGroup::Counters(FilterTarget::ForwardOutput));
+    ruleset.insert(RuleGroup::IfaceCounters("wg_exit".to_string()));
+    ruleset.insert(RuleGroup::ClientNat("br-lan".to_string()));
+    ruleset.insert(RuleGroup::ExitTunnelHelloDrop(4876));
+    ruleset.insert(RuleGroup::SuspendTunnel("wg3".to_string()));
+    ruleset
+}
+
+#[test]
+fn test_ruleset_insert_remove() {
+    let mut ruleset = test_ruleset();
+    assert!(!ruleset.insert(RuleGroup::ExitTunnelHelloDrop(4876)));
+    assert_eq!(ruleset.groups().len(), 6);
+    assert!(ruleset.remove(&RuleGroup::SuspendTunnel("wg3".to_string())));
+    assert!(!ruleset.remove(&RuleGroup::SuspendTunnel("wg3".to_string())));
+    assert!(!ruleset.render(IpFamily::V6).contains("wg3"));
+}
+
+#[test]
+fn test_remove_iface_counters() {
+    let mut ruleset = test_ruleset();
+    assert!(ruleset.remove(&RuleGroup::IfaceCounters("wg_exit".to_string())));
+    let v4 = ruleset.render(IpFamily::V4);
+    // the chain is flushed and deleted after the jumps to it are gone
+    assert!(v4.contains(":wg_exit-counter - [0:0]\n"));
+    assert!(!v4.contains("-j wg_exit-counter"));
+    assert!(v4.contains("-X wg_exit-counter\nCOMMIT\n*nat"));
+
+    ruleset.clear_stale_chains();
+    assert!(!ruleset.render(IpFamily::V4).contains("wg_exit-counter"));
+
+    // adding the group back doesn't delete the chain it uses
+    ruleset.remove(&RuleGroup::IfaceCounters("wg_exit".to_string()));
+    ruleset.insert(RuleGroup::IfaceCounters("wg_exit".to_string()));
+    assert!(!ruleset.render(IpFamily::V4).contains("-X wg_exit-counter"));
+}
+
+#[test]
+fn test_render_v6() {
+    assert_eq!(
+        test_ruleset().render(IpFamily::V6),
+        "*filter
+:RITA_INPUT - [0:0]
+:RITA_OUTPUT - [0:0]
+:RITA_FORWARD - [0:0]
+-A RITA_FORWARD -i wg3 -j DROP
+-A RITA_FORWARD -o wg3 -j DROP
+-A RITA_INPUT -m set ! --match-set rita_input dst,src -j SET --add-set rita_input dst,src
+-A RITA_FORWARD -m set ! --match-set rita_fwd_output dst,dst -j SET --add-set rita_fwd_output dst,dst
+COMMIT
+"
+    );
+}
+
+#[test]
+fn test_render_v4() {
+    let mut ruleset = test_ruleset();
+    // a second lan interface shares the mss clamp and masquerade rules
+    ruleset.insert(RuleGroup::ClientNat("wlan0".to_string()));
+    assert_eq!(
+        ruleset.render(IpFamily::V4),
+        "*filter
+:RITA_INPUT - [0:0]
+:RITA_OUTPUT - [0:0]
+:RITA_FORWARD - [0:0]
+:wg_exit-counter - [0:0]
+-A RITA_INPUT -j wg_exit-counter
+-A RITA_OUTPUT -j wg_exit-counter
+-A wg_exit-counter -o wg_exit
+-A wg_exit-counter -i wg_exit
+-A wg_exit-counter -j RETURN
+-A RITA_FORWARD -i br-lan -o wg_exit -j ACCEPT
+-A RITA_FORWARD -i wg_exit -o br-lan -j ACCEPT
+-A RITA_FORWARD -p tcp -m tcp --tcp-flags SYN,RST SYN -j TCPMSS --clamp-mss-to-pmtu
+-A RITA_OUTPUT -o wg_exit -p tcp -m tcp --dport 4876 -j DROP
+-A RITA_FORWARD -i wlan0 -o wg_exit -j ACCEPT
+-A RITA_FORWARD -i wg_exit -o wlan0 -j ACCEPT
+COMMIT
+*nat
+:RITA_POSTROUTING - [0:0]
+-A RITA_POSTROUTING -o wg_exit -j MASQUERADE
+COMMIT
+"
+    );
+}
+
+#[test]
+fn test_render_exit() {
+    let mut ruleset = Ruleset::new();
+    ruleset.insert(RuleGroup::ExitCounters(ExitFilterTarget::Input));
+    ruleset.insert(RuleGroup::ExitNat("eth0".to_string()));
+    ruleset.insert(RuleGroup::ThrottleExitClient("172.168.1.5".parse().unwrap(), 1024));
+    let v4 = ruleset.render(IpFamily::V4);
+    assert!(v4.contains("-A RITA_POSTROUTING -o eth0 -j MASQUERADE\n"));
+    // throttling has to come before the exit accepts the client's traffic
+    let throttle = v4
+        .find(
+            "-A RITA_FORWARD -d 172.168.1.5/32 -o wg_exit -m hashlimit --hashlimit-above 128kb/s \
+             --hashlimit-mode dstip --hashlimit-name rita_dl_128 -j DROP\n",
+        ).unwrap();
+    assert!(throttle < v4.find("-A RITA_FORWARD -i wg_exit -o eth0 -j ACCEPT").unwrap());
+    assert!(v4.contains(
+        "-A RITA_FORWARD -s 172.168.1.5/32 -i wg_exit -m hashlimit --hashlimit-above 128kb/s \
+         --hashlimit-mode srcip --hashlimit-name rita_ul_128 -j DROP\n"
+    ));
+    assert!(v4.contains(
+        "-A RITA_FORWARD -i eth0 -o wg_exit -m state --state RELATED,ESTABLISHED -j ACCEPT\n"
+    ));
+    assert!(ruleset.render(IpFamily::V6).contains(
+        "-A RITA_INPUT -m set ! --match-set rita_exit_input src -j SET --add-set rita_exit_input src\n"
+    ));
+    assert_eq!(
+        ruleset.ipsets(),
+        vec![vec![
+            "-exist",
+            "create",
+            "rita_exit_input",
+            "hash:net",
+            "family",
+            "inet6",
+            "counters",
+        ]]
+    );
+}
+
+#[test]
+fn test_diff_ruleset() {
+    let ruleset = test_ruleset();
+
+    // Saved output of the rendered ruleset, plus a stale suspension and a rule that isn't ours
+    let saved = "# Generated by iptables-save
+*filter
+:INPUT ACCEPT [0:0]
+:RITA_FORWARD - [0:0]
+:RITA_INPUT - [0:0]
+-A INPUT -j RITA_INPUT
+-A FORWARD -j RITA_FORWARD
+-A INPUT -p tcp -m tcp --dport 22 -j ACCEPT
+-A RITA_FORWARD -i wg3 -j DROP
+-A RITA_FORWARD -o wg3 -j DROP
+-A RITA_FORWARD -i wg7 -j DROP
+-A RITA_INPUT -m set ! --match-set rita_input dst,src -j SET --add-set rita_input dst,src
+-A RITA_FORWARD -m set ! --match-set rita_fwd_output dst,dst -j SET --add-set rita_fwd_output dst,dst
+COMMIT
+";
+    let mut diff = RulesetDiff::default();
+    diff_family(&ruleset, IpFamily::V6, saved, &mut diff);
+    assert_eq!(diff.missing, vec!["filter: -A OUTPUT -j RITA_OUTPUT"]);
+    assert_eq!(diff.unexpected, vec!["filter: -A RITA_FORWARD -i wg7 -j DROP"]);
+
+    let mut diff = RulesetDiff::default();
+    diff_family(
+        &ruleset,
+        IpFamily::V6,
+        &format!("{}-A OUTPUT -j RITA_OUTPUT\n", saved.replace("-A RITA_FORWARD -i wg7 -j DROP\n", "")),
+        &mut diff,
+    );
+    assert!(diff.is_empty());
+}
+
+#[test]
+fn test_apply_ruleset() {
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+    use std::process::Output;
+    use KI;
+
+    let mut counter = 0;
+    KI.set_mock(Box::new(move |program, args| {
+        counter += 1;
+        let status = match counter {
+            1 => {
+                assert_eq!(program, "ipset");
+                assert_eq!(args[..3], ["-exist", "create", "rita_input"]);
+                0
+            }
+            2 => {
+                assert_eq!(program, "ipset");
+                assert_eq!(args[2], "rita_fwd_output");
+                0
+            }
+            3 => {
+                assert_eq!(program, "iptables-nft-restore");
+                assert_eq!(args[0], "--noflush");
+                let rules = &args[1];
+                assert!(rules.contains("-A RITA_POSTROUTING -o wg_exit -j MASQUERADE"));
+                0
+            }
+            // the jump into RITA_INPUT is already there, the others are added
+            4 => {
+                assert_eq!(program, "iptables-nft");
+                assert_eq!(args, ["-w", "-t", "filter", "-C", "INPUT", "-j", "RITA_INPUT"]);
+                0
+            }
+            5 | 7 | 9 => 1,
+            6 => {
+                assert_eq!(args, ["-w", "-t", "filter", "-I", "OUTPUT", "-j", "RITA_OUTPUT"]);
+                0
+            }
+            8 => {
+                assert_eq!(args[4], "FORWARD");
+                0
+            }
+            10 => {
+                assert_eq!(
+                    args,
+                    ["-w", "-t", "nat", "-I", "POSTROUTING", "-j", "RITA_POSTROUTING"]
+                );
+                0
+            }
+            11 => {
+                assert_eq!(program, "ip6tables-nft-restore");
+                let rules = &args[1];
+                assert!(rules.contains("-A RITA_FORWARD -i wg3 -j DROP"));
+                0
+            }
+            12..=17 => {
+                assert_eq!(program, "ip6tables-nft");
+                0
+            }
+            _ => panic!("Unexpected call {} {:?} {:?}", counter, program, args),
+        };
+        Ok(Output {
+            stdout: b"".to_vec(),
+            stderr: b"".to_vec(),
+            status: ExitStatus::from_raw(status << 8),
+        })
+    }));
+
+    KI.apply_ruleset(&test_ruleset(), FirewallBackend::Nftables)
+        .expect("Unable to apply ruleset");
+}
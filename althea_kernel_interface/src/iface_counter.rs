@@ -38,24 +38,6 @@ fn test_iface_counter_error() {
 }
 
 impl KernelInterface {
-    pub fn init_iface_counters(&self, interface: &str) -> Result<(), Error> {
-        let chain_name = format!("{}-counter", interface);
-
-        // Create a new chain with the chain name
-        self.run_command("iptables", &["-N", &chain_name])?;
-
-        // Redirect everything to that chain
-        self.add_iptables_rule("iptables", &["-w", "-I", "OUTPUT", "-j", &chain_name])?;
-        self.add_iptables_rule("iptables", &["-w", "-I", "INPUT", "-j", &chain_name])?;
-
-        // Check if they are going in or out over the epecific interface
-        self.add_iptables_rule("iptables", &["-w", "-A", &chain_name, "-o", interface])?;
-        self.add_iptables_rule("iptables", &["-w", "-A", &chain_name, "-i", interface])?;
-        // Return packet for further processing
-        self.add_iptables_rule("iptables", &["-w", "-A", &chain_name, "-j", "RETURN"])?;
-        Ok(())
-    }
-
     /// returns (input counters, output counters)
     pub fn read_iface_counters(
         &self,
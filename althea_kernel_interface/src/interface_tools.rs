@@ -1,4 +1,4 @@
-use super::KernelInterface;
+use super::{KernelInterface, KernelOp};
 
 use regex::Regex;
 
@@ -6,6 +6,9 @@ use failure::Error;
 use std::net::IpAddr;
 use std::str::from_utf8;
 
+/// The administrative up flag in /sys/class/net/<iface>/flags
+const IFF_UP: u32 = 0x1;
+
 impl KernelInterface {
     /// Returns all existing interfaces
     pub fn get_interfaces(&self) -> Result<Vec<String>, Error> {
@@ -23,7 +26,7 @@ impl KernelInterface {
 
     /// Deletes an named interface
     pub fn del_interface(&self, name: &str) -> Result<(), Error> {
-        self.run_command("ip", &["link", "del", "dev", name])?;
+        self.apply_op(&KernelOp::DelLink(name.to_string()))?;
         Ok(())
     }
 
@@ -36,6 +39,17 @@ impl KernelInterface {
         Ok(output.trim_right().to_string())
     }
 
+    /// Checks that an interface exists and is administratively up, whether or not it has a carrier
+    pub fn is_iface_admin_up(&self, iface: &str) -> Result<bool, Error> {
+        // cat so we can mock
+        let output = self.run_command("cat", &[&format!("/sys/class/net/{}/flags", iface)])?;
+        if !output.status.success() {
+            return Ok(false);
+        }
+        let flags = from_utf8(&output.stdout)?.trim().trim_left_matches("0x");
+        Ok(u32::from_str_radix(flags, 16)? & IFF_UP != 0)
+    }
+
     pub fn get_wg_remote_ip(&self, name: &str) -> Result<IpAddr, Error> {
         let output = self.run_command("wg", &["show", name, "endpoints"])?;
         let stdout = String::from_utf8(output.stdout)?;
@@ -150,3 +164,32 @@ fn test_get_wg_remote_ip() {
         "fe80::78e4:1cff:fe61:560d".parse::<IpAddr>().unwrap()
     );
 }
+
+#[test]
+fn test_is_iface_admin_up() {
+    use KI;
+
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+    use std::process::Output;
+
+    KI.set_mock(Box::new(move |program, args| {
+        assert_eq!(program, "cat");
+        assert_eq!(args, &["/sys/class/net/eth0/flags"]);
+        Ok(Output {
+            stdout: b"0x1003\n".to_vec(),
+            stderr: b"".to_vec(),
+            status: ExitStatus::from_raw(0),
+        })
+    }));
+    assert!(KI.is_iface_admin_up("eth0").unwrap());
+
+    KI.set_mock(Box::new(move |_, _| {
+        Ok(Output {
+            stdout: b"0x1002\n".to_vec(),
+            stderr: b"".to_vec(),
+            status: ExitStatus::from_raw(0),
+        })
+    }));
+    assert!(!KI.is_iface_admin_up("eth0").unwrap());
+}
@@ -1,108 +1,408 @@
-use super::KernelInterface;
+//! A typed model of the routes and policy routing rules we manage.
+//!
+//! The exit route lives in its own table which is consulted by a rule placed after a lookup of
+//! the main table that ignores default routes. Everything with a specific route (the mesh, lan
+//! subnets and the host routes we add for manual peers) keeps using the main table, everything
+//! else goes out wg_exit. The main table default route is never touched so the tunnels carrying
+//! the exit traffic always have a way out.
 
+use super::{KernelInterface, KernelInterfaceError, KernelOp};
+
+use std::fmt;
 use std::net::IpAddr;
+use std::str::FromStr;
 
 use failure::Error;
 
-pub enum IpRoute {
-    /// For creating default routes
-    DefaultRoute,
-    /// A route to a specific address
-    ToAddr(IpAddr),
+/// Table holding the default route over wg_exit
+pub const EXIT_ROUTE_TABLE: u32 = 101;
+/// Priority of the rule looking up non default routes in the main table
+pub const MAIN_RULE_PRIORITY: u32 = 100;
+/// Priority of the rule sending everything else to the exit table
+pub const EXIT_RULE_PRIORITY: u32 = 101;
+
+/// Keywords in `ip route` output that are followed by a value we don't model
+const IGNORED_ROUTE_ARGS: &[&str] = &["pref", "expires", "mtu", "advmss", "hoplimit", "realm"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteDestination {
+    /// The default route of whatever family the rest of the route implies
+    Default,
+    /// An address and prefix length
+    Prefix(IpAddr, u8),
+}
+
+impl RouteDestination {
+    /// A route to exactly one address
+    pub fn host(ip: IpAddr) -> RouteDestination {
+        RouteDestination::Prefix(ip, max_prefix_len(&ip))
+    }
+}
+
+pub fn max_prefix_len(ip: &IpAddr) -> u8 {
+    match *ip {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
 }
 
-impl ToString for IpRoute {
-    fn to_string(&self) -> String {
+impl fmt::Display for RouteDestination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            IpRoute::DefaultRoute => "default".into(),
-            IpRoute::ToAddr(addr) => addr.to_string(),
+            RouteDestination::Default => write!(f, "default"),
+            // ip prints host routes without a prefix length, do the same so we round trip
+            RouteDestination::Prefix(ip, len) if len == max_prefix_len(&ip) => write!(f, "{}", ip),
+            RouteDestination::Prefix(ip, len) => write!(f, "{}/{}", ip, len),
+        }
+    }
+}
+
+impl FromStr for RouteDestination {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<RouteDestination, Error> {
+        if s == "default" {
+            return Ok(RouteDestination::Default);
+        }
+        let mut parts = s.splitn(2, '/');
+        let ip: IpAddr = parts.next().unwrap_or("").parse()?;
+        let len = match parts.next() {
+            Some(len) => len.parse()?,
+            None => max_prefix_len(&ip),
+        };
+        if len > max_prefix_len(&ip) {
+            bail!("Invalid prefix length in {}", s);
         }
+        Ok(RouteDestination::Prefix(ip, len))
+    }
+}
+
+/// A single route as understood by `ip route`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub destination: RouteDestination,
+    pub gateway: Option<IpAddr>,
+    pub dev: Option<String>,
+    pub proto: Option<String>,
+    pub metric: Option<u32>,
+    pub src: Option<IpAddr>,
+    /// None is the main table
+    pub table: Option<u32>,
+}
+
+impl Route {
+    pub fn new(destination: RouteDestination) -> Route {
+        Route {
+            destination,
+            gateway: None,
+            dev: None,
+            proto: None,
+            metric: None,
+            src: None,
+            table: None,
+        }
+    }
+
+    /// Whether the route is ipv4, a default route without addresses is taken to be ipv4 like
+    /// `ip` does
+    pub fn is_ipv4(&self) -> bool {
+        match self.destination {
+            RouteDestination::Prefix(ip, _) => ip.is_ipv4(),
+            RouteDestination::Default => self.gateway.map(|gw| gw.is_ipv4()).unwrap_or(true),
+        }
+    }
+
+    /// The arguments following `ip route <add|del>` to describe this route
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = vec![self.destination.to_string()];
+        if let Some(gateway) = self.gateway {
+            args.push("via".into());
+            args.push(gateway.to_string());
+        }
+        if let Some(ref dev) = self.dev {
+            args.push("dev".into());
+            args.push(dev.clone());
+        }
+        if let Some(ref proto) = self.proto {
+            args.push("proto".into());
+            args.push(proto.clone());
+        }
+        if let Some(metric) = self.metric {
+            args.push("metric".into());
+            args.push(metric.to_string());
+        }
+        if let Some(src) = self.src {
+            args.push("src".into());
+            args.push(src.to_string());
+        }
+        if let Some(table) = self.table {
+            args.push("table".into());
+            args.push(table.to_string());
+        }
+        args
+    }
+}
+
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_args().join(" "))
+    }
+}
+
+impl FromStr for Route {
+    type Err = Error;
+
+    /// Parses a line of `ip route` output, flags and attributes we don't model are skipped
+    fn from_str(s: &str) -> Result<Route, Error> {
+        let mut tokens = s.split_whitespace().peekable();
+        if tokens.peek() == Some(&"unicast") {
+            tokens.next();
+        }
+        let mut route = Route::new(match tokens.next() {
+            Some(dest) => dest.parse()?,
+            None => bail!("Empty route"),
+        });
+
+        while let Some(token) = tokens.next() {
+            let takes_value = match token {
+                "via" | "dev" | "proto" | "metric" | "src" | "table" | "scope" => true,
+                other => IGNORED_ROUTE_ARGS.contains(&other),
+            };
+            if !takes_value {
+                // linkdown, onlink and friends
+                continue;
+            }
+            let value = match tokens.next() {
+                Some(value) => value,
+                None => bail!("Missing value for {} in route {}", token, s),
+            };
+            match token {
+                "via" => route.gateway = Some(value.parse()?),
+                "dev" => route.dev = Some(value.to_string()),
+                "proto" => route.proto = Some(value.to_string()),
+                "metric" => route.metric = Some(value.parse()?),
+                "src" => route.src = Some(value.parse()?),
+                "table" => route.table = Some(value.parse()?),
+                _ => {}
+            }
+        }
+        Ok(route)
     }
 }
 
 impl KernelInterface {
-    fn get_default_route(&self) -> Option<Vec<String>> {
-        let output = self
-            .run_command("ip", &["route", "list", "default"])
-            .unwrap();
-
-        let stdout = String::from_utf8(output.stdout).unwrap();
-
-        // Get the first line that starts with "default", and
-        // convert token separated by whitespace into a valid
-        // result of type Vec<String>. Otherwise returns
-        // None if it couldn't be found.
-        Some(
-            stdout
-                .lines()
-                .filter(|line| line.starts_with("default"))
-                .nth(0)?
-                .split_whitespace() // Extract first
-                .map(|s| s.to_string())
-                .collect(),
-        )
-    }
-
-    fn set_route(&self, to: &IpRoute, route: &Vec<String>) -> Result<(), Error> {
-        let to = to.to_string();
-        let mut def_route = vec!["route", "add", &to];
-
-        let tokens = route.iter().skip(1);
-        def_route.reserve_exact(tokens.len());
-        for token in tokens {
-            def_route.push(&token);
-        }
-        self.run_command("ip", &def_route)?;
+    /// Returns the ipv4 default route of the main table, with several the first is used as
+    /// that's the one the kernel prefers
+    pub fn get_default_route(&self) -> Result<Option<Route>, Error> {
+        let output = self.run_command("ip", &["route", "list", "default"])?;
+        let stdout = String::from_utf8(output.stdout)?;
+
+        for line in stdout.lines() {
+            if line.trim_left().starts_with("default") {
+                return Ok(Some(line.parse()?));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn add_route(&self, route: &Route) -> Result<(), Error> {
+        self.route_command(true, route)
+    }
+
+    pub fn del_route(&self, route: &Route) -> Result<(), Error> {
+        self.route_command(false, route)
+    }
+
+    fn route_command(&self, add: bool, route: &Route) -> Result<(), Error> {
+        let output = self.apply_op(&KernelOp::Route {
+            add,
+            route: route.clone(),
+        })?;
+        if !output.status.success() {
+            let stderr = String::from_utf8(output.stderr)?;
+            // adding a route we already have isn't a problem
+            if !(add && stderr.contains("exists")) {
+                return Err(KernelInterfaceError::RuntimeError(format!(
+                    "received error on ip route {} {}: {}",
+                    if add { "add" } else { "del" },
+                    route,
+                    stderr
+                )).into());
+            }
+        }
         Ok(())
     }
 
-    pub fn update_settings_route(
-        &self,
-        settings_default_route: &mut Vec<String>,
-    ) -> Result<(), Error> {
-        let def_route = match self.get_default_route() {
+    /// Routes a manual peer's endpoint over our default route so that our tunnel to it doesn't
+    /// depend on anything that may later be routed through the mesh
+    pub fn manual_peers_route(&self, endpoint_ip: &IpAddr) -> Result<(), Error> {
+        let default_route = match self.get_default_route()? {
             Some(route) => route,
             None => return Ok(()),
         };
 
-        if !def_route.contains(&String::from("wg_exit")) {
-            // update the default route if default route is not wg exit
-            *settings_default_route = def_route.clone();
+        // A gateway can only route addresses of its own family, if the endpoint is of the
+        // other family we leave it to that family's default route
+        if default_route.is_ipv4() != endpoint_ip.is_ipv4() {
+            trace!(
+                "Not routing {} over default route {}, address families differ",
+                endpoint_ip,
+                default_route
+            );
+            return Ok(());
         }
-        Ok(())
-    }
 
-    pub fn manual_peers_route(
-        &self,
-        endpoint_ip: &IpAddr,
-        settings_default_route: &mut Vec<String>,
-    ) -> Result<(), Error> {
-        self.update_settings_route(settings_default_route)?;
+        self.add_route(&Route {
+            destination: RouteDestination::host(*endpoint_ip),
+            ..default_route
+        })
+    }
 
-        self.set_route(&IpRoute::ToAddr(*endpoint_ip), &settings_default_route)?;
+    /// Installs the rules that send traffic without a specific route to the exit table,
+    /// any rules we installed previously are replaced
+    fn set_exit_rules(&self) -> Result<(), Error> {
+        self.del_exit_rules();
+        let main_priority = MAIN_RULE_PRIORITY.to_string();
+        let exit_priority = EXIT_RULE_PRIORITY.to_string();
+        let exit_table = EXIT_ROUTE_TABLE.to_string();
+        for args in [
+            vec![
+                "rule",
+                "add",
+                "pref",
+                &main_priority,
+                "lookup",
+                "main",
+                "suppress_prefixlength",
+                "0",
+            ],
+            vec!["rule", "add", "pref", &exit_priority, "lookup", &exit_table],
+        ]
+            .iter()
+        {
+            let output = self.run_command("ip", args)?;
+            if !output.status.success() {
+                return Err(KernelInterfaceError::RuntimeError(format!(
+                    "received error adding ip rule: {}",
+                    String::from_utf8(output.stderr)?
+                )).into());
+            }
+        }
         Ok(())
     }
 
-    pub fn restore_default_route(
-        &self,
-        settings_default_route: &mut Vec<String>,
-    ) -> Result<(), Error> {
-        match self.get_default_route() {
-            Some(route) => {
-                if route.contains(&String::from("wg_exit")) {
-                    self.set_route(&IpRoute::DefaultRoute, settings_default_route)?;
-                } else {
-                    *settings_default_route = route;
-                }
-            }
-            None => {
-                self.set_route(&IpRoute::DefaultRoute, settings_default_route)?;
+    fn del_exit_rules(&self) {
+        for priority in [MAIN_RULE_PRIORITY, EXIT_RULE_PRIORITY].iter() {
+            match self.run_command("ip", &["rule", "del", "pref", &priority.to_string()]) {
+                Err(e) => warn!("Failed to delete ip rule {} {:?}", priority, e),
+                _ => (),
             }
+        }
+    }
+
+    /// Sends all traffic without a more specific route through wg_exit to the given gateway
+    pub fn set_route_to_tunnel(&self, gateway: &IpAddr) -> Result<(), Error> {
+        let mut exit_route = Route::new(RouteDestination::Default);
+        exit_route.table = Some(EXIT_ROUTE_TABLE);
+        // there is only ever one route in the exit table, it may be to a previous exit
+        match self.del_route(&exit_route) {
+            Err(e) => trace!("No exit route to delete {:?}", e),
+            _ => (),
         };
+
+        exit_route.gateway = Some(*gateway);
+        exit_route.dev = Some("wg_exit".to_string());
+        self.add_route(&exit_route)?;
+        self.set_exit_rules()
+    }
+
+    /// Removes the exit rules and route, traffic goes back to the main table default route
+    pub fn restore_default_route(&self) -> Result<(), Error> {
+        self.del_exit_rules();
+        let output = self.run_command(
+            "ip",
+            &["route", "flush", "table", &EXIT_ROUTE_TABLE.to_string()],
+        )?;
+        if !output.status.success() {
+            trace!(
+                "Flushing exit table returned {:?}",
+                String::from_utf8(output.stderr)?
+            );
+        }
+
+        // older versions replaced the main default route with one over wg_exit, that route
+        // goes away with the interface and the original one is restored by dhcp
+        if let Some(route) = self.get_default_route()? {
+            if route.dev == Some("wg_exit".to_string()) {
+                self.del_route(&route)?;
+            }
+        }
         Ok(())
     }
 }
 
+#[test]
+fn test_parse_route() {
+    let route: Route = "default via 192.168.8.1 dev eth0 proto dhcp metric 600"
+        .parse()
+        .unwrap();
+    assert_eq!(route.destination, RouteDestination::Default);
+    assert_eq!(route.gateway, Some("192.168.8.1".parse().unwrap()));
+    assert_eq!(route.dev, Some("eth0".to_string()));
+    assert_eq!(route.proto, Some("dhcp".to_string()));
+    assert_eq!(route.metric, Some(600));
+    assert_eq!(route.table, None);
+    assert!(route.is_ipv4());
+
+    let route: Route = "172.17.0.0/16 dev docker0 proto kernel scope link src 172.17.0.1 linkdown"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        route.destination,
+        RouteDestination::Prefix("172.17.0.0".parse().unwrap(), 16)
+    );
+    assert_eq!(route.gateway, None);
+    assert_eq!(route.src, Some("172.17.0.1".parse().unwrap()));
+
+    let route: Route = "fd00::1 via fe80::1 dev wg0 proto babel metric 1024 pref medium"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        route.destination,
+        RouteDestination::host("fd00::1".parse().unwrap())
+    );
+    assert_eq!(route.metric, Some(1024));
+    assert!(!route.is_ipv4());
+
+    let route: Route = "default via 10.0.0.1 dev wg_exit table 101".parse().unwrap();
+    assert_eq!(route.table, Some(101));
+
+    assert!("".parse::<Route>().is_err());
+    assert!("1.2.3.4/33 dev eth0".parse::<Route>().is_err());
+    assert!("default via".parse::<Route>().is_err());
+    assert!("default via eth0".parse::<Route>().is_err());
+}
+
+#[test]
+fn test_render_route() {
+    for line in [
+        "default via 192.168.8.1 dev eth0 proto dhcp metric 600",
+        "1.2.3.4 via 192.168.8.1 dev eth0",
+        "10.0.0.0/8 dev eth1 src 10.0.0.1",
+        "default via 10.0.0.1 dev wg_exit table 101",
+        "2001::/64 via fe80::1 dev wg0 metric 5",
+    ]
+        .iter()
+    {
+        let route: Route = line.parse().unwrap();
+        assert_eq!(&route.to_string(), line);
+    }
+
+    let mut route = Route::new(RouteDestination::host("1.2.3.4".parse().unwrap()));
+    route.gateway = Some("192.168.8.1".parse().unwrap());
+    assert_eq!(route.to_args(), vec!["1.2.3.4", "via", "192.168.8.1"]);
+}
+
 #[test]
 fn test_get_default_route_invalid() {
     use std::os::unix::process::ExitStatusExt;
@@ -131,7 +431,7 @@ fn test_get_default_route_invalid() {
     }));
 
     assert!(
-        KI.get_default_route().is_none(),
+        KI.get_default_route().unwrap().is_none(),
         "Invalid `ip route` unexpectedly returned a valid route"
     );
 }
@@ -171,25 +471,18 @@ default via 192.168.9.1 dev wifiinterface proto dhcp metric 1200
         }
     }));
 
-    let result = KI.get_default_route().expect("Unable to get default route");
+    let result = KI
+        .get_default_route()
+        .unwrap()
+        .expect("Unable to get default route");
     assert_eq!(
-        result,
-        vec![
-            "default",
-            "via",
-            "192.168.8.1",
-            "dev",
-            "wifiinterface",
-            "proto",
-            "dhcp",
-            "metric",
-            "600",
-        ]
+        result.to_string(),
+        "default via 192.168.8.1 dev wifiinterface proto dhcp metric 600"
     );
 }
 
 #[test]
-fn test_set_route() {
+fn test_add_route() {
     use std::net::Ipv4Addr;
     use std::os::unix::process::ExitStatusExt;
     use std::process::ExitStatus;
@@ -202,26 +495,37 @@ fn test_set_route() {
         match counter {
             1 => {
                 assert_eq!(program, "ip");
-                assert_eq!(args, vec!["route", "add", "127.0.0.1", "token2", "token3"]);
+                assert_eq!(args, vec!["route", "add", "127.0.0.1", "dev", "lo"]);
 
                 Ok(Output {
                     stdout: b"".to_vec(),
-                    stderr: b"".to_vec(),
-                    status: ExitStatus::from_raw(0),
+                    stderr: b"RTNETLINK answers: File exists\n".to_vec(),
+                    status: ExitStatus::from_raw(2 << 8),
+                })
+            }
+            2 => {
+                assert_eq!(args, vec!["route", "del", "127.0.0.1", "dev", "lo"]);
+                Ok(Output {
+                    stdout: b"".to_vec(),
+                    stderr: b"RTNETLINK answers: No such process\n".to_vec(),
+                    status: ExitStatus::from_raw(2 << 8),
                 })
             }
             _ => panic!("Unexpected call {} {:?} {:?}", counter, program, args),
         }
     }));
 
-    KI.set_route(
-        &IpRoute::ToAddr(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))),
-        &vec!["token1".into(), "token2".into(), "token3".into()],
-    ).expect("Unable to set route");
+    let mut route = Route::new(RouteDestination::host(IpAddr::V4(Ipv4Addr::new(
+        127, 0, 0, 1,
+    ))));
+    route.dev = Some("lo".to_string());
+    // an existing route is fine, a failed delete is not
+    KI.add_route(&route).expect("Unable to add route");
+    assert!(KI.del_route(&route).is_err());
 }
 
 #[test]
-fn test_set_default_route() {
+fn test_set_route_to_tunnel() {
     use std::os::unix::process::ExitStatusExt;
     use std::process::ExitStatus;
     use std::process::Output;
@@ -230,21 +534,73 @@ fn test_set_default_route() {
 
     KI.set_mock(Box::new(move |program, args| {
         counter += 1;
-        match counter {
-            1 => {
-                assert_eq!(program, "ip");
-                assert_eq!(args, vec!["route", "add", "default"]);
+        assert_eq!(program, "ip");
+        let expected: Vec<&str> = match counter {
+            1 => vec!["route", "del", "default", "table", "101"],
+            2 => vec![
+                "route", "add", "default", "via", "172.168.1.254", "dev", "wg_exit", "table",
+                "101",
+            ],
+            3 => vec!["rule", "del", "pref", "100"],
+            4 => vec!["rule", "del", "pref", "101"],
+            5 => vec![
+                "rule",
+                "add",
+                "pref",
+                "100",
+                "lookup",
+                "main",
+                "suppress_prefixlength",
+                "0",
+            ],
+            6 => vec!["rule", "add", "pref", "101", "lookup", "101"],
+            _ => panic!("Unexpected call {} {:?} {:?}", counter, program, args),
+        };
+        assert_eq!(args, expected);
+        Ok(Output {
+            stdout: b"".to_vec(),
+            stderr: b"".to_vec(),
+            status: ExitStatus::from_raw(0),
+        })
+    }));
 
-                Ok(Output {
-                    stdout: b"".to_vec(),
-                    stderr: b"".to_vec(),
-                    status: ExitStatus::from_raw(0),
-                })
-            }
+    KI.set_route_to_tunnel(&"172.168.1.254".parse().unwrap())
+        .expect("Unable to set exit route");
+}
+
+#[test]
+fn test_restore_default_route() {
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+    use std::process::Output;
+    use KI;
+    let mut counter = 0;
+
+    KI.set_mock(Box::new(move |program, args| {
+        counter += 1;
+        assert_eq!(program, "ip");
+        let (expected, stdout): (Vec<&str>, &[u8]) = match counter {
+            1 => (vec!["rule", "del", "pref", "100"], &b""[..]),
+            2 => (vec!["rule", "del", "pref", "101"], &b""[..]),
+            3 => (vec!["route", "flush", "table", "101"], &b""[..]),
+            4 => (
+                vec!["route", "list", "default"],
+                &b"default via 172.168.1.254 dev wg_exit"[..],
+            ),
+            5 => (
+                vec!["route", "del", "default", "via", "172.168.1.254", "dev", "wg_exit"],
+                &b""[..],
+            ),
             _ => panic!("Unexpected call {} {:?} {:?}", counter, program, args),
-        }
+        };
+        assert_eq!(args, expected);
+        Ok(Output {
+            stdout: stdout.to_vec(),
+            stderr: b"".to_vec(),
+            status: ExitStatus::from_raw(0),
+        })
     }));
 
-    KI.set_route(&IpRoute::DefaultRoute, &vec![])
-        .expect("Unable to set default route");
+    KI.restore_default_route()
+        .expect("Unable to restore default route");
 }
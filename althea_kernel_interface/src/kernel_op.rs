@@ -0,0 +1,325 @@
+//! Typed descriptions of the link, address, route and wireguard changes made by this crate.
+//! Every CommandRunner can apply them, the command based runners run the `ip` or `wg`
+//! invocation an op renders to while the netlink runner sends it to the kernel directly.
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+use ip_route::{Route, RouteDestination};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KernelOp {
+    /// ip link add <name> type wireguard
+    AddWireguardLink(String),
+    /// ip link del dev <name>
+    DelLink(String),
+    /// ip link set dev <name> [mtu <mtu>] [up|down]
+    SetLink {
+        dev: String,
+        up: Option<bool>,
+        mtu: Option<u32>,
+    },
+    /// ip address add|delete <addr>[/<len>] dev <name>
+    Address {
+        add: bool,
+        dev: String,
+        addr: IpAddr,
+        prefix_len: u8,
+    },
+    /// ip route add|del <route>
+    Route { add: bool, route: Route },
+    /// wg set <name> ...
+    WgSet(WgDevice),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WgDevice {
+    pub ifname: String,
+    pub listen_port: Option<u16>,
+    pub private_key_path: Option<String>,
+    pub peers: Vec<WgPeer>,
+}
+
+impl WgDevice {
+    pub fn new(ifname: &str) -> WgDevice {
+        WgDevice {
+            ifname: ifname.to_string(),
+            listen_port: None,
+            private_key_path: None,
+            peers: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WgPeer {
+    pub public_key: String,
+    pub remove: bool,
+    pub endpoint: Option<WgEndpoint>,
+    /// Some replaces the peer's allowed ips, like `wg set` does
+    pub allowed_ips: Option<Vec<(IpAddr, u8)>>,
+    /// Zero turns the keepalive off
+    pub persistent_keepalive: Option<u16>,
+}
+
+impl WgPeer {
+    pub fn new(public_key: &str) -> WgPeer {
+        WgPeer {
+            public_key: public_key.to_string(),
+            remove: false,
+            endpoint: None,
+            allowed_ips: None,
+            persistent_keepalive: None,
+        }
+    }
+
+    /// A peer entry that removes the peer from the device
+    pub fn removal(public_key: &str) -> WgPeer {
+        WgPeer {
+            remove: true,
+            ..WgPeer::new(public_key)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WgEndpoint {
+    pub addr: SocketAddr,
+    /// Interface for link local ipv6 endpoints, resolved to a scope id when sent
+    pub scope: Option<String>,
+}
+
+impl fmt::Display for WgEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.addr, &self.scope) {
+            (SocketAddr::V4(addr), _) => write!(f, "{}:{}", addr.ip(), addr.port()),
+            (SocketAddr::V6(addr), &Some(ref scope)) => {
+                write!(f, "[{}%{}]:{}", addr.ip(), scope, addr.port())
+            }
+            (SocketAddr::V6(addr), &None) => write!(f, "[{}]:{}", addr.ip(), addr.port()),
+        }
+    }
+}
+
+/// Formats an address the way `ip` and `wg` accept it, host prefixes are left off
+fn prefix_to_string(addr: IpAddr, prefix_len: u8) -> String {
+    RouteDestination::Prefix(addr, prefix_len).to_string()
+}
+
+impl KernelOp {
+    /// The program and arguments that make the same change
+    pub fn to_command(&self) -> (&'static str, Vec<String>) {
+        match *self {
+            KernelOp::AddWireguardLink(ref name) => (
+                "ip",
+                vec![
+                    "link".into(),
+                    "add".into(),
+                    name.clone(),
+                    "type".into(),
+                    "wireguard".into(),
+                ],
+            ),
+            KernelOp::DelLink(ref name) => (
+                "ip",
+                vec!["link".into(), "del".into(), "dev".into(), name.clone()],
+            ),
+            KernelOp::SetLink { ref dev, up, mtu } => {
+                let mut args = vec!["link".into(), "set".into(), "dev".into(), dev.clone()];
+                if let Some(mtu) = mtu {
+                    args.push("mtu".into());
+                    args.push(mtu.to_string());
+                }
+                match up {
+                    Some(true) => args.push("up".into()),
+                    Some(false) => args.push("down".into()),
+                    None => {}
+                }
+                ("ip", args)
+            }
+            KernelOp::Address {
+                add,
+                ref dev,
+                addr,
+                prefix_len,
+            } => (
+                "ip",
+                vec![
+                    "address".into(),
+                    if add { "add" } else { "delete" }.into(),
+                    prefix_to_string(addr, prefix_len),
+                    "dev".into(),
+                    dev.clone(),
+                ],
+            ),
+            KernelOp::Route { add, ref route } => {
+                let action = if add { "add" } else { "del" };
+                let mut args: Vec<String> = vec!["route".into(), action.into()];
+                args.extend(route.to_args());
+                ("ip", args)
+            }
+            KernelOp::WgSet(ref device) => ("wg", device.to_args()),
+        }
+    }
+}
+
+impl WgDevice {
+    fn to_args(&self) -> Vec<String> {
+        let mut args = vec!["set".into(), self.ifname.clone()];
+        if let Some(port) = self.listen_port {
+            args.push("listen-port".into());
+            args.push(port.to_string());
+        }
+        if let Some(ref path) = self.private_key_path {
+            args.push("private-key".into());
+            args.push(path.clone());
+        }
+        for peer in self.peers.iter() {
+            args.push("peer".into());
+            args.push(peer.public_key.clone());
+            if peer.remove {
+                args.push("remove".into());
+            }
+            if let Some(ref endpoint) = peer.endpoint {
+                args.push("endpoint".into());
+                args.push(endpoint.to_string());
+            }
+            if let Some(ref ips) = peer.allowed_ips {
+                let ips: Vec<String> = ips
+                    .iter()
+                    .map(|&(addr, len)| prefix_to_string(addr, len))
+                    .collect();
+                args.push("allowed-ips".into());
+                args.push(ips.join(","));
+            }
+            match peer.persistent_keepalive {
+                Some(0) => {
+                    args.push("persistent-keepalive".into());
+                    args.push("off".into());
+                }
+                Some(interval) => {
+                    args.push("persistent-keepalive".into());
+                    args.push(interval.to_string());
+                }
+                None => {}
+            }
+        }
+        args
+    }
+}
+
+#[test]
+fn test_link_commands() {
+    let (program, args) = KernelOp::AddWireguardLink("wg1".to_string()).to_command();
+    assert_eq!(program, "ip");
+    assert_eq!(args, vec!["link", "add", "wg1", "type", "wireguard"]);
+    assert_eq!(
+        KernelOp::DelLink("wg1".to_string()).to_command().1,
+        vec!["link", "del", "dev", "wg1"]
+    );
+    let op = KernelOp::SetLink {
+        dev: "wg_exit".to_string(),
+        up: Some(true),
+        mtu: Some(1340),
+    };
+    assert_eq!(
+        op.to_command().1,
+        vec!["link", "set", "dev", "wg_exit", "mtu", "1340", "up"]
+    );
+}
+
+#[test]
+fn test_address_commands() {
+    let op = KernelOp::Address {
+        add: true,
+        dev: "wg1".to_string(),
+        addr: "fd00::1".parse().unwrap(),
+        prefix_len: 128,
+    };
+    assert_eq!(
+        op.to_command().1,
+        vec!["address", "add", "fd00::1", "dev", "wg1"]
+    );
+    let op = KernelOp::Address {
+        add: false,
+        dev: "wg_exit".to_string(),
+        addr: "172.16.0.5".parse().unwrap(),
+        prefix_len: 12,
+    };
+    assert_eq!(
+        op.to_command().1,
+        vec!["address", "delete", "172.16.0.5/12", "dev", "wg_exit"]
+    );
+}
+
+#[test]
+fn test_route_command() {
+    let mut route = Route::new(RouteDestination::Default);
+    route.gateway = Some("192.168.8.1".parse().unwrap());
+    route.dev = Some("eth0".to_string());
+    route.table = Some(101);
+    let (program, args) = KernelOp::Route { add: false, route }.to_command();
+    assert_eq!(program, "ip");
+    assert_eq!(
+        args,
+        vec!["route", "del", "default", "via", "192.168.8.1", "dev", "eth0", "table", "101"]
+    );
+}
+
+#[test]
+fn test_wg_set_command() {
+    let mut device = WgDevice::new("wg_exit");
+    device.listen_port = Some(59999);
+    device.private_key_path = Some("private_key".to_string());
+    let mut peer = WgPeer::new("a");
+    peer.endpoint = Some(WgEndpoint {
+        addr: "[fe80::1]:60000".parse().unwrap(),
+        scope: Some("eth2".to_string()),
+    });
+    peer.allowed_ips = Some(vec![
+        ("172.16.0.1".parse().unwrap(), 32),
+        ("::".parse().unwrap(), 0),
+    ]);
+    peer.persistent_keepalive = Some(5);
+    device.peers.push(peer);
+    device.peers.push(WgPeer::removal("b"));
+
+    let (program, args) = KernelOp::WgSet(device).to_command();
+    assert_eq!(program, "wg");
+    assert_eq!(
+        args,
+        vec![
+            "set",
+            "wg_exit",
+            "listen-port",
+            "59999",
+            "private-key",
+            "private_key",
+            "peer",
+            "a",
+            "endpoint",
+            "[fe80::1%eth2]:60000",
+            "allowed-ips",
+            "172.16.0.1,::/0",
+            "persistent-keepalive",
+            "5",
+            "peer",
+            "b",
+            "remove",
+        ]
+    );
+}
+
+#[test]
+fn test_endpoint_display() {
+    let endpoint = WgEndpoint {
+        addr: "1.2.3.4:60000".parse().unwrap(),
+        scope: None,
+    };
+    assert_eq!(endpoint.to_string(), "1.2.3.4:60000");
+    let endpoint = WgEndpoint {
+        addr: "[2001::1]:60000".parse().unwrap(),
+        scope: None,
+    };
+    assert_eq!(endpoint.to_string(), "[2001::1]:60000");
+}
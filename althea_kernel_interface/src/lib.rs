@@ -5,13 +5,17 @@ extern crate lazy_static;
 #[macro_use]
 extern crate log;
 
+extern crate althea_types;
+extern crate base64;
 extern crate eui48;
 extern crate itertools;
+extern crate libc;
 extern crate regex;
 
 use std::env;
-use std::io::ErrorKind;
-use std::process::{Command, Output};
+use std::io::{ErrorKind, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::process::{Command, Output, Stdio};
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
@@ -24,6 +28,7 @@ mod dns;
 mod exit_client_tunnel;
 mod exit_server_counter;
 mod exit_server_tunnel;
+mod firewall;
 mod fs_sync;
 mod get_neighbors;
 mod iface_counter;
@@ -31,8 +36,10 @@ mod interface_tools;
 mod ip_addr;
 mod ip_route;
 mod iptables;
+mod kernel_op;
 mod link_local_tools;
 mod manipulate_uci;
+mod netlink;
 mod open_tunnel;
 mod openwrt_ubus;
 mod ping_check;
@@ -43,6 +50,11 @@ pub use counter::FilterTarget;
 pub use create_wg_key::WgKeypair;
 pub use exit_server_counter::ExitFilterTarget;
 pub use exit_server_tunnel::ExitClient;
+pub use firewall::{FirewallBackend, IpFamily, RuleGroup, Ruleset, RulesetDiff};
+pub use iface_counter::IfaceCounter;
+pub use ip_route::{Route, RouteDestination};
+pub use kernel_op::{KernelOp, WgDevice, WgEndpoint, WgPeer};
+pub use netlink::NetlinkCommandRunner;
 
 use failure::Error;
 
@@ -50,6 +62,10 @@ use failure::Error;
 pub enum KernelInterfaceError {
     #[fail(display = "Runtime Error: {:?}", _0)]
     RuntimeError(String),
+    #[fail(display = "Mesh IP {} is not in fd00::/8", _0)]
+    InvalidMeshIp(IpAddr),
+    #[fail(display = "Link local endpoint {} has no interface", _0)]
+    NoInterfaceForLinkLocal(SocketAddr),
 }
 
 #[cfg(test)]
@@ -63,11 +79,37 @@ lazy_static! {
 
 #[cfg(not(test))]
 lazy_static! {
-    pub static ref KI: Box<KernelInterface> = Box::new(LinuxCommandRunner {});
+    pub static ref KI: Box<KernelInterface> = linux_kernel_interface();
+}
+
+/// Picks the backend for a real system, ALTHEA_KERNEL_BACKEND=netlink|command overrides the
+/// default which is netlink when built with the netlink feature
+pub fn linux_kernel_interface() -> Box<KernelInterface> {
+    let use_netlink = match env::var("ALTHEA_KERNEL_BACKEND") {
+        Ok(ref backend) if backend == "netlink" => true,
+        Ok(ref backend) if backend == "command" => false,
+        _ => cfg!(feature = "netlink"),
+    };
+    if use_netlink {
+        info!("Using the netlink kernel interface backend");
+        Box::new(NetlinkCommandRunner::new())
+    } else {
+        Box::new(LinuxCommandRunner {})
+    }
 }
 
 pub trait CommandRunner {
     fn run_command(&self, program: &str, args: &[&str]) -> Result<Output, Error>;
+    /// Runs a command with the given input written to its stdin
+    fn run_command_stdin(&self, program: &str, args: &[&str], stdin: &[u8])
+        -> Result<Output, Error>;
+    /// Makes a change to the network configuration, by default by running the equivalent
+    /// `ip` or `wg` command
+    fn apply_op(&self, op: &KernelOp) -> Result<Output, Error> {
+        let (program, args) = op.to_command();
+        let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+        self.run_command(program, &args)
+    }
     fn set_mock(&self, mock: Box<FnMut(String, Vec<String>) -> Result<Output, Error> + Send>);
 }
 
@@ -101,6 +143,35 @@ impl CommandRunner for LinuxCommandRunner {
         return Ok(output);
     }
 
+    fn run_command_stdin(
+        &self,
+        program: &str,
+        args: &[&str],
+        stdin: &[u8],
+    ) -> Result<Output, Error> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        // taking the pipe closes it once written so the command sees EOF
+        match child.stdin.take() {
+            Some(mut pipe) => pipe.write_all(stdin)?,
+            None => bail!("Unable to open stdin of {:?}", program),
+        }
+        let output = child.wait_with_output()?;
+
+        trace!("Command {:?} {:?} returned: {:?}", program, args, output);
+        if !output.status.success() {
+            info!(
+                "Command {:?} {:?} returned: an error {:?}",
+                program, args, output
+            );
+        }
+        Ok(output)
+    }
+
     fn set_mock(&self, _mock: Box<FnMut(String, Vec<String>) -> Result<Output, Error> + Send>) {
         unimplemented!()
     }
@@ -120,6 +191,19 @@ impl CommandRunner for TestCommandRunner {
         (&mut *self.run_command.lock().unwrap())(program.to_string(), args_owned)
     }
 
+    /// The mock sees stdin as a trailing argument
+    fn run_command_stdin(
+        &self,
+        program: &str,
+        args: &[&str],
+        stdin: &[u8],
+    ) -> Result<Output, Error> {
+        let mut args_owned: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        args_owned.push(String::from_utf8_lossy(stdin).to_string());
+
+        (&mut *self.run_command.lock().unwrap())(program.to_string(), args_owned)
+    }
+
     fn set_mock(&self, mock: Box<FnMut(String, Vec<String>) -> Result<Output, Error> + Send>) {
         *self.run_command.lock().unwrap() = mock
     }
@@ -139,6 +139,32 @@ impl KernelInterface {
         Ok(retval)
     }
 
+    /// The whole of a config, including any changes that haven't been committed yet, in the
+    /// format `uci import` reads
+    pub fn uci_export(&self, config: &str) -> Result<String, Error> {
+        let output = self.run_command("uci", &["export", config])?;
+        if !output.status.success() {
+            return Err(KernelInterfaceError::RuntimeError(format!(
+                "received error while exporting UCI: {}",
+                String::from_utf8(output.stderr)?
+            )).into());
+        }
+        Ok(String::from_utf8(output.stdout)?)
+    }
+
+    /// Replaces a config with a snapshot taken with `uci_export`. The import is committed right
+    /// away and anything staged for the config since the snapshot is dropped
+    pub fn uci_restore(&self, config: &str, snapshot: &str) -> Result<(), Error> {
+        let output = self.run_command_stdin("uci", &["import", config], snapshot.as_bytes())?;
+        if !output.status.success() {
+            return Err(KernelInterfaceError::RuntimeError(format!(
+                "received error while restoring UCI: {}",
+                String::from_utf8(output.stderr)?
+            )).into());
+        }
+        Ok(())
+    }
+
     pub fn openwrt_reset_wireless(&self) -> Result<(), Error> {
         self.run_command("wifi", &[])?;
         Ok(())
@@ -149,3 +175,39 @@ impl KernelInterface {
         Ok(())
     }
 }
+
+#[test]
+fn test_uci_export_restore() {
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{ExitStatus, Output};
+
+    use KI;
+
+    let export = "package network\n\nconfig interface 'lan'\n\toption ifname 'eth0'\n";
+    let mut counter = 0;
+    KI.set_mock(Box::new(move |program, args| {
+        counter += 1;
+        assert_eq!(program, "uci");
+        let stdout = match counter {
+            1 => {
+                assert_eq!(args, vec!["export", "network"]);
+                export.as_bytes().to_vec()
+            }
+            2 => {
+                // the mock gets stdin as the last argument
+                assert_eq!(args, vec!["import", "network", export]);
+                Vec::new()
+            }
+            _ => panic!("Unexpected command {} {:?}", program, args),
+        };
+        Ok(Output {
+            stdout,
+            stderr: b"".to_vec(),
+            status: ExitStatus::from_raw(0),
+        })
+    }));
+
+    let snapshot = KI.uci_export("network").unwrap();
+    assert_eq!(snapshot, export);
+    KI.uci_restore("network", &snapshot).unwrap();
+}
@@ -0,0 +1,222 @@
+//! Building and parsing of raw netlink messages and their attributes
+
+pub const NLMSG_HDRLEN: usize = 16;
+
+pub const NLMSG_ERROR: u16 = 2;
+pub const NLMSG_DONE: u16 = 3;
+
+pub const NLM_F_REQUEST: u16 = 0x1;
+pub const NLM_F_MULTI: u16 = 0x2;
+pub const NLM_F_ACK: u16 = 0x4;
+pub const NLM_F_EXCL: u16 = 0x200;
+pub const NLM_F_CREATE: u16 = 0x400;
+
+pub const NLA_F_NESTED: u16 = 0x8000;
+
+/// Netlink attributes and messages are aligned to 4 bytes
+pub fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+pub fn u16_bytes(v: u16) -> [u8; 2] {
+    v.to_ne_bytes()
+}
+
+pub fn u32_bytes(v: u32) -> [u8; 4] {
+    v.to_ne_bytes()
+}
+
+pub fn read_u16(buf: &[u8]) -> Option<u16> {
+    if buf.len() < 2 {
+        return None;
+    }
+    Some(u16::from_ne_bytes([buf[0], buf[1]]))
+}
+
+pub fn read_u32(buf: &[u8]) -> Option<u32> {
+    if buf.len() < 4 {
+        return None;
+    }
+    Some(u32::from_ne_bytes([buf[0], buf[1], buf[2], buf[3]]))
+}
+
+/// A netlink request under construction, the header is filled in by finish()
+#[derive(Debug)]
+pub struct NlMsg {
+    buf: Vec<u8>,
+    nests: Vec<usize>,
+}
+
+impl NlMsg {
+    pub fn new(msg_type: u16, flags: u16) -> NlMsg {
+        let mut buf = vec![0u8; NLMSG_HDRLEN];
+        buf[4..6].copy_from_slice(&u16_bytes(msg_type));
+        buf[6..8].copy_from_slice(&u16_bytes(flags));
+        NlMsg {
+            buf,
+            nests: Vec::new(),
+        }
+    }
+
+    fn pad(&mut self) {
+        let len = align(self.buf.len());
+        self.buf.resize(len, 0);
+    }
+
+    /// Appends a fixed size family header such as ifinfomsg or rtmsg
+    pub fn push_bytes(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+        self.pad();
+    }
+
+    pub fn attr(&mut self, attr_type: u16, data: &[u8]) {
+        self.buf
+            .extend_from_slice(&u16_bytes((4 + data.len()) as u16));
+        self.buf.extend_from_slice(&u16_bytes(attr_type));
+        self.buf.extend_from_slice(data);
+        self.pad();
+    }
+
+    pub fn attr_u8(&mut self, attr_type: u16, v: u8) {
+        self.attr(attr_type, &[v]);
+    }
+
+    pub fn attr_u16(&mut self, attr_type: u16, v: u16) {
+        self.attr(attr_type, &u16_bytes(v));
+    }
+
+    pub fn attr_u32(&mut self, attr_type: u16, v: u32) {
+        self.attr(attr_type, &u32_bytes(v));
+    }
+
+    /// Null terminated string attribute
+    pub fn attr_str(&mut self, attr_type: u16, v: &str) {
+        let mut data = v.as_bytes().to_vec();
+        data.push(0);
+        self.attr(attr_type, &data);
+    }
+
+    /// Starts a nested attribute, everything added until end_nested() is inside it
+    pub fn begin_nested(&mut self, attr_type: u16) {
+        self.nests.push(self.buf.len());
+        self.buf.extend_from_slice(&u16_bytes(0));
+        self.buf
+            .extend_from_slice(&u16_bytes(attr_type | NLA_F_NESTED));
+    }
+
+    pub fn end_nested(&mut self) {
+        let start = self.nests.pop().expect("end_nested without begin_nested");
+        let len = (self.buf.len() - start) as u16;
+        self.buf[start..start + 2].copy_from_slice(&u16_bytes(len));
+    }
+
+    pub fn finish(mut self, seq: u32) -> Vec<u8> {
+        assert!(self.nests.is_empty(), "unterminated nested attribute");
+        let len = self.buf.len() as u32;
+        self.buf[0..4].copy_from_slice(&u32_bytes(len));
+        self.buf[8..12].copy_from_slice(&u32_bytes(seq));
+        self.buf
+    }
+}
+
+/// A received message, payload excludes the netlink header
+#[derive(Debug)]
+pub struct NlReply {
+    pub msg_type: u16,
+    pub flags: u16,
+    pub seq: u32,
+    pub payload: Vec<u8>,
+}
+
+/// Splits a datagram from the kernel into its messages
+pub fn parse_messages(buf: &[u8]) -> Vec<NlReply> {
+    let mut res = Vec::new();
+    let mut offset = 0;
+    while offset + NLMSG_HDRLEN <= buf.len() {
+        let len = read_u32(&buf[offset..]).unwrap() as usize;
+        if len < NLMSG_HDRLEN || offset + len > buf.len() {
+            break;
+        }
+        res.push(NlReply {
+            msg_type: read_u16(&buf[offset + 4..]).unwrap(),
+            flags: read_u16(&buf[offset + 6..]).unwrap(),
+            seq: read_u32(&buf[offset + 8..]).unwrap(),
+            payload: buf[offset + NLMSG_HDRLEN..offset + len].to_vec(),
+        });
+        offset += align(len);
+    }
+    res
+}
+
+/// Iterates over the (type, data) attributes in a buffer, ignoring the nested flag
+pub fn parse_attrs(buf: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut res = Vec::new();
+    let mut offset = 0;
+    while offset + 4 <= buf.len() {
+        let len = read_u16(&buf[offset..]).unwrap() as usize;
+        if len < 4 || offset + len > buf.len() {
+            break;
+        }
+        let attr_type = read_u16(&buf[offset + 2..]).unwrap() & !NLA_F_NESTED;
+        res.push((attr_type, &buf[offset + 4..offset + len]));
+        offset += align(len);
+    }
+    res
+}
+
+#[test]
+fn test_attr_padding() {
+    let mut msg = NlMsg::new(16, NLM_F_REQUEST | NLM_F_ACK);
+    msg.attr_str(3, "wg0");
+    msg.attr_u8(4, 1);
+    let buf = msg.finish(7);
+
+    // 16 header + (4 + "wg0\0") + (4 + 1 padded to 8)
+    assert_eq!(buf.len(), 16 + 8 + 8);
+    assert_eq!(read_u32(&buf[0..]), Some(32));
+    assert_eq!(read_u16(&buf[4..]), Some(16));
+    assert_eq!(read_u16(&buf[6..]), Some(NLM_F_REQUEST | NLM_F_ACK));
+    assert_eq!(read_u32(&buf[8..]), Some(7));
+
+    let attrs = parse_attrs(&buf[NLMSG_HDRLEN..]);
+    assert_eq!(attrs.len(), 2);
+    assert_eq!(attrs[0], (3, &b"wg0\0"[..]));
+    assert_eq!(attrs[1], (4, &[1u8][..]));
+}
+
+#[test]
+fn test_nested_attrs() {
+    let mut msg = NlMsg::new(16, NLM_F_REQUEST);
+    msg.begin_nested(8);
+    msg.begin_nested(0);
+    msg.attr_u16(5, 25);
+    msg.end_nested();
+    msg.end_nested();
+    let buf = msg.finish(1);
+
+    let outer = parse_attrs(&buf[NLMSG_HDRLEN..]);
+    assert_eq!(outer.len(), 1);
+    assert_eq!(outer[0].0, 8);
+    let inner = parse_attrs(outer[0].1);
+    assert_eq!(inner.len(), 1);
+    assert_eq!(inner[0].0, 0);
+    let attrs = parse_attrs(inner[0].1);
+    assert_eq!(attrs[0].0, 5);
+    assert_eq!(read_u16(attrs[0].1), Some(25));
+}
+
+#[test]
+fn test_parse_messages() {
+    let mut first = NlMsg::new(NLMSG_ERROR, 0);
+    first.push_bytes(&u32_bytes(0));
+    let mut buf = first.finish(3);
+    buf.extend(NlMsg::new(NLMSG_DONE, NLM_F_MULTI).finish(4));
+
+    let msgs = parse_messages(&buf);
+    assert_eq!(msgs.len(), 2);
+    assert_eq!(msgs[0].msg_type, NLMSG_ERROR);
+    assert_eq!(msgs[0].seq, 3);
+    assert_eq!(msgs[0].payload, vec![0, 0, 0, 0]);
+    assert_eq!(msgs[1].msg_type, NLMSG_DONE);
+    assert_eq!(msgs[1].flags, NLM_F_MULTI);
+}
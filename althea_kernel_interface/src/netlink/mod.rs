@@ -0,0 +1,148 @@
+//! A netlink backend for the kernel interface. The NetlinkCommandRunner applies the typed
+//! KernelOps that modify links, addresses, routes and wireguard devices directly over netlink,
+//! avoiding a process spawn per operation. Reads and other commands are run by the
+//! LinuxCommandRunner, as are ops when netlink support is missing, so behavior is identical
+//! either way.
+
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{ExitStatus, Output};
+
+use failure::Error;
+use libc;
+
+use super::{CommandRunner, KernelInterface, KernelOp, LinuxCommandRunner};
+
+mod message;
+mod rtnl;
+mod socket;
+mod wireguard;
+
+#[derive(Debug)]
+pub enum NetlinkError {
+    /// The kernel rejected the request, holds a positive errno
+    Kernel(i32),
+    /// Netlink or the required family isn't usable here, the command runner should be used
+    Unavailable(io::Error),
+}
+
+pub fn if_index(name: &str) -> Result<u32, NetlinkError> {
+    let c_name = match CString::new(name) {
+        Ok(n) => n,
+        Err(_) => return Err(NetlinkError::Kernel(libc::EINVAL)),
+    };
+    match unsafe { libc::if_nametoindex(c_name.as_ptr()) } {
+        0 => Err(NetlinkError::Kernel(libc::ENODEV)),
+        idx => Ok(idx),
+    }
+}
+
+fn execute(op: &KernelOp) -> Result<(), NetlinkError> {
+    match *op {
+        KernelOp::AddWireguardLink(ref name) => rtnl::add_wireguard_link(name),
+        KernelOp::DelLink(ref name) => rtnl::del_link(name),
+        KernelOp::SetLink { ref dev, up, mtu } => rtnl::set_link(dev, up, mtu),
+        KernelOp::Address {
+            add,
+            ref dev,
+            ref addr,
+            prefix_len,
+        } => rtnl::address(add, dev, addr, prefix_len),
+        KernelOp::Route { add, ref route } => rtnl::route(add, route),
+        KernelOp::WgSet(ref device) => wireguard::set_device(device),
+    }
+}
+
+/// Builds the Output the equivalent command would have produced, callers in this crate only
+/// look at the exit status and stderr
+fn to_output(result: Result<(), i32>) -> Output {
+    match result {
+        Ok(()) => Output {
+            status: ExitStatus::from_raw(0),
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        },
+        Err(errno) => {
+            let msg = unsafe { CStr::from_ptr(libc::strerror(errno)) };
+            Output {
+                // ip exits with 2 for kernel errors, wait status keeps the code in the high byte
+                status: ExitStatus::from_raw(2 << 8),
+                stdout: Vec::new(),
+                stderr: format!("RTNETLINK answers: {}\n", msg.to_string_lossy()).into_bytes(),
+            }
+        }
+    }
+}
+
+pub struct NetlinkCommandRunner {
+    fallback: LinuxCommandRunner,
+}
+
+impl NetlinkCommandRunner {
+    pub fn new() -> NetlinkCommandRunner {
+        NetlinkCommandRunner {
+            fallback: LinuxCommandRunner {},
+        }
+    }
+}
+
+impl CommandRunner for NetlinkCommandRunner {
+    fn run_command(&self, program: &str, args: &[&str]) -> Result<Output, Error> {
+        self.fallback.run_command(program, args)
+    }
+
+    fn run_command_stdin(
+        &self,
+        program: &str,
+        args: &[&str],
+        stdin: &[u8],
+    ) -> Result<Output, Error> {
+        self.fallback.run_command_stdin(program, args, stdin)
+    }
+
+    fn apply_op(&self, op: &KernelOp) -> Result<Output, Error> {
+        match execute(op) {
+            Ok(()) => {
+                trace!("Netlink {:?} succeeded", op);
+                Ok(to_output(Ok(())))
+            }
+            Err(NetlinkError::Kernel(errno)) => {
+                info!("Netlink {:?} returned errno {}", op, errno);
+                Ok(to_output(Err(errno)))
+            }
+            Err(NetlinkError::Unavailable(e)) => {
+                warn!("Netlink unavailable for {:?} ({:?}), running command", op, e);
+                self.fallback.apply_op(op)
+            }
+        }
+    }
+
+    fn set_mock(&self, _mock: Box<FnMut(String, Vec<String>) -> Result<Output, Error> + Send>) {
+        unimplemented!()
+    }
+}
+
+impl KernelInterface for NetlinkCommandRunner {}
+
+#[test]
+fn test_to_output() {
+    let output = to_output(Ok(()));
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+
+    let output = to_output(Err(libc::EEXIST));
+    assert!(!output.status.success());
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("exists"));
+}
+
+#[test]
+fn test_unknown_interface() {
+    match if_index("definitely_not_an_interface") {
+        Err(NetlinkError::Kernel(errno)) => assert_eq!(errno, libc::ENODEV),
+        other => panic!("Unexpected result {:?}", other),
+    }
+}
@@ -0,0 +1,223 @@
+//! rtnetlink requests for links, addresses and routes
+
+use std::io;
+use std::net::IpAddr;
+
+use libc;
+
+use super::message::{u32_bytes, NlMsg, NLM_F_ACK, NLM_F_CREATE, NLM_F_EXCL, NLM_F_REQUEST};
+use super::socket::{NetlinkSocket, NETLINK_ROUTE};
+use super::{if_index, NetlinkError};
+use ip_route::{Route, RouteDestination};
+
+const RTM_NEWLINK: u16 = 16;
+const RTM_DELLINK: u16 = 17;
+const RTM_NEWADDR: u16 = 20;
+const RTM_DELADDR: u16 = 21;
+const RTM_NEWROUTE: u16 = 24;
+const RTM_DELROUTE: u16 = 25;
+
+const IFLA_IFNAME: u16 = 3;
+const IFLA_MTU: u16 = 4;
+const IFLA_LINKINFO: u16 = 18;
+const IFLA_INFO_KIND: u16 = 1;
+
+const IFA_ADDRESS: u16 = 1;
+const IFA_LOCAL: u16 = 2;
+
+const RTA_DST: u16 = 1;
+const RTA_OIF: u16 = 4;
+const RTA_GATEWAY: u16 = 5;
+const RTA_PRIORITY: u16 = 6;
+const RTA_PREFSRC: u16 = 7;
+const RTA_TABLE: u16 = 15;
+
+const IFF_UP: u32 = 0x1;
+
+const RT_TABLE_UNSPEC: u8 = 0;
+const RT_TABLE_MAIN: u8 = 254;
+const RTPROT_BOOT: u8 = 3;
+const RT_SCOPE_UNIVERSE: u8 = 0;
+const RT_SCOPE_LINK: u8 = 253;
+const RT_SCOPE_NOWHERE: u8 = 255;
+const RTN_UNICAST: u8 = 1;
+
+fn family(ip: &IpAddr) -> u8 {
+    match ip {
+        &IpAddr::V4(_) => libc::AF_INET as u8,
+        &IpAddr::V6(_) => libc::AF_INET6 as u8,
+    }
+}
+
+fn ip_bytes(ip: &IpAddr) -> Vec<u8> {
+    match ip {
+        &IpAddr::V4(ip) => ip.octets().to_vec(),
+        &IpAddr::V6(ip) => ip.octets().to_vec(),
+    }
+}
+
+/// struct ifinfomsg
+fn ifinfomsg(index: u32, flags: u32, change: u32) -> Vec<u8> {
+    let mut hdr = vec![libc::AF_UNSPEC as u8, 0, 0, 0];
+    hdr.extend_from_slice(&u32_bytes(index));
+    hdr.extend_from_slice(&u32_bytes(flags));
+    hdr.extend_from_slice(&u32_bytes(change));
+    hdr
+}
+
+/// Numbers for the protocol names `ip` understands, others are left to the command
+fn protocol_number(name: &str) -> Result<u8, NetlinkError> {
+    match name {
+        "redirect" => Ok(1),
+        "kernel" => Ok(2),
+        "boot" => Ok(3),
+        "static" => Ok(4),
+        "dhcp" => Ok(16),
+        _ => name.parse().map_err(|_| {
+            NetlinkError::Unavailable(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown route protocol {}", name),
+            ))
+        }),
+    }
+}
+
+fn route_socket() -> Result<NetlinkSocket, NetlinkError> {
+    NetlinkSocket::open(NETLINK_ROUTE)
+}
+
+pub fn add_wireguard_link(name: &str) -> Result<(), NetlinkError> {
+    let mut msg = NlMsg::new(
+        RTM_NEWLINK,
+        NLM_F_REQUEST | NLM_F_ACK | NLM_F_CREATE | NLM_F_EXCL,
+    );
+    msg.push_bytes(&ifinfomsg(0, 0, 0));
+    msg.attr_str(IFLA_IFNAME, name);
+    msg.begin_nested(IFLA_LINKINFO);
+    msg.attr(IFLA_INFO_KIND, b"wireguard");
+    msg.end_nested();
+    route_socket()?.request(msg)?;
+    Ok(())
+}
+
+pub fn del_link(name: &str) -> Result<(), NetlinkError> {
+    let mut msg = NlMsg::new(RTM_DELLINK, NLM_F_REQUEST | NLM_F_ACK);
+    msg.push_bytes(&ifinfomsg(if_index(name)?, 0, 0));
+    route_socket()?.request(msg)?;
+    Ok(())
+}
+
+pub fn set_link(name: &str, up: Option<bool>, mtu: Option<u32>) -> Result<(), NetlinkError> {
+    let (flags, change) = match up {
+        Some(true) => (IFF_UP, IFF_UP),
+        Some(false) => (0, IFF_UP),
+        None => (0, 0),
+    };
+    let mut msg = NlMsg::new(RTM_NEWLINK, NLM_F_REQUEST | NLM_F_ACK);
+    msg.push_bytes(&ifinfomsg(if_index(name)?, flags, change));
+    if let Some(mtu) = mtu {
+        msg.attr_u32(IFLA_MTU, mtu);
+    }
+    route_socket()?.request(msg)?;
+    Ok(())
+}
+
+pub fn address(add: bool, dev: &str, addr: &IpAddr, prefix_len: u8) -> Result<(), NetlinkError> {
+    let mut msg = if add {
+        NlMsg::new(
+            RTM_NEWADDR,
+            NLM_F_REQUEST | NLM_F_ACK | NLM_F_CREATE | NLM_F_EXCL,
+        )
+    } else {
+        NlMsg::new(RTM_DELADDR, NLM_F_REQUEST | NLM_F_ACK)
+    };
+    // struct ifaddrmsg
+    let mut hdr = vec![family(addr), prefix_len, 0, RT_SCOPE_UNIVERSE];
+    hdr.extend_from_slice(&u32_bytes(if_index(dev)?));
+    msg.push_bytes(&hdr);
+    msg.attr(IFA_LOCAL, &ip_bytes(addr));
+    msg.attr(IFA_ADDRESS, &ip_bytes(addr));
+    route_socket()?.request(msg)?;
+    Ok(())
+}
+
+pub fn route(add: bool, route: &Route) -> Result<(), NetlinkError> {
+    let mut msg = if add {
+        NlMsg::new(
+            RTM_NEWROUTE,
+            NLM_F_REQUEST | NLM_F_ACK | NLM_F_CREATE | NLM_F_EXCL,
+        )
+    } else {
+        NlMsg::new(RTM_DELROUTE, NLM_F_REQUEST | NLM_F_ACK)
+    };
+
+    let family = if route.is_ipv4() {
+        libc::AF_INET as u8
+    } else {
+        libc::AF_INET6 as u8
+    };
+    let dst = match route.destination {
+        RouteDestination::Default => None,
+        RouteDestination::Prefix(ip, len) => Some((ip, len)),
+    };
+    let dst_len = dst.map(|(_, len)| len).unwrap_or(0);
+    let protocol = match route.proto {
+        Some(ref name) => Some(protocol_number(name)?),
+        None => None,
+    };
+    let table = route.table.unwrap_or(RT_TABLE_MAIN as u32);
+    let rtm_table = if table < 256 {
+        table as u8
+    } else {
+        RT_TABLE_UNSPEC
+    };
+    // defaults match what `ip route` sends
+    let (protocol, scope, route_type) = if add {
+        let scope = if route.gateway.is_none() && route.dev.is_some() {
+            RT_SCOPE_LINK
+        } else {
+            RT_SCOPE_UNIVERSE
+        };
+        (protocol.unwrap_or(RTPROT_BOOT), scope, RTN_UNICAST)
+    } else {
+        (protocol.unwrap_or(0), RT_SCOPE_NOWHERE, 0)
+    };
+
+    // struct rtmsg
+    let mut hdr = vec![
+        family, dst_len, 0, 0, rtm_table, protocol, scope, route_type,
+    ];
+    hdr.extend_from_slice(&u32_bytes(0));
+    msg.push_bytes(&hdr);
+
+    if let Some((dst, _)) = dst {
+        msg.attr(RTA_DST, &ip_bytes(&dst));
+    }
+    if let Some(gateway) = route.gateway {
+        msg.attr(RTA_GATEWAY, &ip_bytes(&gateway));
+    }
+    if let Some(ref dev) = route.dev {
+        msg.attr_u32(RTA_OIF, if_index(dev)?);
+    }
+    if let Some(metric) = route.metric {
+        msg.attr_u32(RTA_PRIORITY, metric);
+    }
+    if let Some(src) = route.src {
+        msg.attr(RTA_PREFSRC, &ip_bytes(&src));
+    }
+    if table >= 256 {
+        msg.attr_u32(RTA_TABLE, table);
+    }
+    route_socket()?.request(msg)?;
+    Ok(())
+}
+
+#[test]
+fn test_protocol_number() {
+    assert_eq!(protocol_number("dhcp").unwrap(), 16);
+    assert_eq!(protocol_number("42").unwrap(), 42);
+    match protocol_number("babel") {
+        Err(NetlinkError::Unavailable(_)) => {}
+        other => panic!("Unexpected result {:?}", other),
+    }
+}
@@ -0,0 +1,118 @@
+//! A minimal blocking netlink socket, one is opened per request so there is no shared state
+//! to lock between threads
+
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+
+use libc;
+
+use super::message::{parse_messages, read_u32, NlMsg, NlReply, NLMSG_DONE, NLMSG_ERROR};
+use super::NetlinkError;
+
+pub const NETLINK_ROUTE: i32 = 0;
+pub const NETLINK_GENERIC: i32 = 16;
+
+#[repr(C)]
+struct SockaddrNl {
+    nl_family: libc::sa_family_t,
+    nl_pad: u16,
+    nl_pid: u32,
+    nl_groups: u32,
+}
+
+pub struct NetlinkSocket {
+    fd: RawFd,
+    seq: u32,
+}
+
+impl NetlinkSocket {
+    pub fn open(protocol: i32) -> Result<NetlinkSocket, NetlinkError> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                protocol,
+            )
+        };
+        if fd < 0 {
+            return Err(NetlinkError::Unavailable(io::Error::last_os_error()));
+        }
+        // from here on the socket is closed on drop
+        let socket = NetlinkSocket { fd, seq: 0 };
+
+        let addr = SockaddrNl {
+            nl_family: libc::AF_NETLINK as libc::sa_family_t,
+            nl_pad: 0,
+            nl_pid: 0,
+            nl_groups: 0,
+        };
+        let res = unsafe {
+            libc::bind(
+                socket.fd,
+                &addr as *const SockaddrNl as *const libc::sockaddr,
+                mem::size_of::<SockaddrNl>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(NetlinkError::Unavailable(io::Error::last_os_error()));
+        }
+        Ok(socket)
+    }
+
+    /// Sends a request and collects the replies up to and including the kernel's ack, the
+    /// request must have NLM_F_ACK set
+    pub fn request(&mut self, msg: NlMsg) -> Result<Vec<NlReply>, NetlinkError> {
+        self.seq = self.seq.wrapping_add(1);
+        let seq = self.seq;
+        let buf = msg.finish(seq);
+
+        let sent = unsafe { libc::send(self.fd, buf.as_ptr() as *const libc::c_void, buf.len(), 0) };
+        if sent < 0 {
+            return Err(NetlinkError::Unavailable(io::Error::last_os_error()));
+        }
+
+        let mut replies = Vec::new();
+        let mut recv_buf = vec![0u8; 32768];
+        loop {
+            let len = unsafe {
+                libc::recv(
+                    self.fd,
+                    recv_buf.as_mut_ptr() as *mut libc::c_void,
+                    recv_buf.len(),
+                    0,
+                )
+            };
+            if len < 0 {
+                return Err(NetlinkError::Unavailable(io::Error::last_os_error()));
+            }
+
+            for reply in parse_messages(&recv_buf[..len as usize]) {
+                if reply.seq != seq {
+                    trace!("Dropping stale netlink message {:?}", reply);
+                    continue;
+                }
+                match reply.msg_type {
+                    NLMSG_ERROR => {
+                        let errno = read_u32(&reply.payload).unwrap_or(0) as i32;
+                        return if errno == 0 {
+                            Ok(replies)
+                        } else {
+                            Err(NetlinkError::Kernel(-errno))
+                        };
+                    }
+                    NLMSG_DONE => return Ok(replies),
+                    _ => replies.push(reply),
+                }
+            }
+        }
+    }
+}
+
+impl Drop for NetlinkSocket {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
@@ -0,0 +1,258 @@
+//! WireGuard configuration over its generic netlink family, equivalent to `wg set`
+
+use std::fs::File;
+use std::io::Read;
+use std::net::SocketAddr;
+
+use base64;
+use libc;
+
+use super::message::{
+    parse_attrs, read_u16, u16_bytes, u32_bytes, NlMsg, NLM_F_ACK, NLM_F_REQUEST,
+};
+use super::socket::{NetlinkSocket, NETLINK_GENERIC};
+use super::{if_index, NetlinkError};
+use kernel_op::{WgDevice, WgEndpoint, WgPeer};
+
+const GENL_ID_CTRL: u16 = 0x10;
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+
+const WG_GENL_NAME: &str = "wireguard";
+const WG_GENL_VERSION: u8 = 1;
+const WG_CMD_SET_DEVICE: u8 = 1;
+
+const WGDEVICE_A_IFNAME: u16 = 2;
+const WGDEVICE_A_PRIVATE_KEY: u16 = 3;
+const WGDEVICE_A_LISTEN_PORT: u16 = 6;
+const WGDEVICE_A_PEERS: u16 = 8;
+
+const WGPEER_A_PUBLIC_KEY: u16 = 1;
+const WGPEER_A_FLAGS: u16 = 3;
+const WGPEER_A_ENDPOINT: u16 = 4;
+const WGPEER_A_PERSISTENT_KEEPALIVE_INTERVAL: u16 = 5;
+const WGPEER_A_ALLOWEDIPS: u16 = 9;
+
+const WGPEER_F_REMOVE_ME: u32 = 1;
+const WGPEER_F_REPLACE_ALLOWEDIPS: u32 = 2;
+
+const WGALLOWEDIP_A_FAMILY: u16 = 1;
+const WGALLOWEDIP_A_IPADDR: u16 = 2;
+const WGALLOWEDIP_A_CIDR_MASK: u16 = 3;
+
+const WG_KEY_LEN: usize = 32;
+
+/// struct genlmsghdr
+fn genlmsghdr(cmd: u8, version: u8) -> [u8; 4] {
+    [cmd, version, 0, 0]
+}
+
+fn decode_key(key: &str) -> Result<Vec<u8>, NetlinkError> {
+    match base64::decode(key.trim()) {
+        Ok(ref key) if key.len() == WG_KEY_LEN => Ok(key.clone()),
+        _ => Err(NetlinkError::Kernel(libc::EINVAL)),
+    }
+}
+
+/// Resolves the id the kernel assigned to the wireguard family, if the module isn't loaded
+/// there is nothing netlink can do and the wg tool gets a chance instead
+fn family_id(socket: &mut NetlinkSocket) -> Result<u16, NetlinkError> {
+    let mut msg = NlMsg::new(GENL_ID_CTRL, NLM_F_REQUEST | NLM_F_ACK);
+    msg.push_bytes(&genlmsghdr(CTRL_CMD_GETFAMILY, 1));
+    msg.attr_str(CTRL_ATTR_FAMILY_NAME, WG_GENL_NAME);
+
+    let replies = match socket.request(msg) {
+        Ok(replies) => replies,
+        Err(NetlinkError::Kernel(errno)) if errno == libc::ENOENT => {
+            return Err(NetlinkError::Unavailable(io_error(
+                "wireguard generic netlink family not found",
+            )))
+        }
+        Err(e) => return Err(e),
+    };
+    for reply in replies.iter() {
+        if reply.payload.len() < 4 {
+            continue;
+        }
+        for (attr_type, data) in parse_attrs(&reply.payload[4..]) {
+            if attr_type == CTRL_ATTR_FAMILY_ID {
+                if let Some(id) = read_u16(data) {
+                    return Ok(id);
+                }
+            }
+        }
+    }
+    Err(NetlinkError::Unavailable(io_error(
+        "no family id in nlctrl reply",
+    )))
+}
+
+fn io_error(msg: &str) -> ::std::io::Error {
+    ::std::io::Error::new(::std::io::ErrorKind::Other, msg)
+}
+
+/// struct sockaddr_in or sockaddr_in6, ports are in network order
+fn sockaddr_bytes(endpoint: &WgEndpoint) -> Result<Vec<u8>, NetlinkError> {
+    let mut buf = Vec::new();
+    match endpoint.addr {
+        SocketAddr::V4(addr) => {
+            buf.extend_from_slice(&u16_bytes(libc::AF_INET as u16));
+            buf.extend_from_slice(&[(addr.port() >> 8) as u8, addr.port() as u8]);
+            buf.extend_from_slice(&addr.ip().octets());
+            buf.extend_from_slice(&[0u8; 8]);
+        }
+        SocketAddr::V6(addr) => {
+            let scope_id = match endpoint.scope {
+                Some(ref iface) => if_index(iface)?,
+                None => addr.scope_id(),
+            };
+            buf.extend_from_slice(&u16_bytes(libc::AF_INET6 as u16));
+            buf.extend_from_slice(&[(addr.port() >> 8) as u8, addr.port() as u8]);
+            buf.extend_from_slice(&u32_bytes(0));
+            buf.extend_from_slice(&addr.ip().octets());
+            buf.extend_from_slice(&u32_bytes(scope_id));
+        }
+    }
+    Ok(buf)
+}
+
+fn push_peer(msg: &mut NlMsg, peer: &WgPeer) -> Result<(), NetlinkError> {
+    // peers are an array, the index is the attribute type and is ignored by the kernel
+    msg.begin_nested(0);
+    msg.attr(WGPEER_A_PUBLIC_KEY, &decode_key(&peer.public_key)?);
+
+    let mut flags = 0;
+    if peer.remove {
+        flags |= WGPEER_F_REMOVE_ME;
+    }
+    if peer.allowed_ips.is_some() {
+        flags |= WGPEER_F_REPLACE_ALLOWEDIPS;
+    }
+    msg.attr_u32(WGPEER_A_FLAGS, flags);
+
+    if let Some(ref endpoint) = peer.endpoint {
+        msg.attr(WGPEER_A_ENDPOINT, &sockaddr_bytes(endpoint)?);
+    }
+    if let Some(interval) = peer.persistent_keepalive {
+        msg.attr_u16(WGPEER_A_PERSISTENT_KEEPALIVE_INTERVAL, interval);
+    }
+    if let Some(ref allowed_ips) = peer.allowed_ips {
+        msg.begin_nested(WGPEER_A_ALLOWEDIPS);
+        for &(ip, cidr) in allowed_ips.iter() {
+            msg.begin_nested(0);
+            match ip {
+                ::std::net::IpAddr::V4(ip) => {
+                    msg.attr_u16(WGALLOWEDIP_A_FAMILY, libc::AF_INET as u16);
+                    msg.attr(WGALLOWEDIP_A_IPADDR, &ip.octets());
+                }
+                ::std::net::IpAddr::V6(ip) => {
+                    msg.attr_u16(WGALLOWEDIP_A_FAMILY, libc::AF_INET6 as u16);
+                    msg.attr(WGALLOWEDIP_A_IPADDR, &ip.octets());
+                }
+            }
+            msg.attr_u8(WGALLOWEDIP_A_CIDR_MASK, cidr);
+            msg.end_nested();
+        }
+        msg.end_nested();
+    }
+    msg.end_nested();
+    Ok(())
+}
+
+/// Builds the WG_CMD_SET_DEVICE request, split out from set_device for testing
+fn set_device_msg(
+    family: u16,
+    device: &WgDevice,
+    private_key: Option<&str>,
+) -> Result<NlMsg, NetlinkError> {
+    let mut msg = NlMsg::new(family, NLM_F_REQUEST | NLM_F_ACK);
+    msg.push_bytes(&genlmsghdr(WG_CMD_SET_DEVICE, WG_GENL_VERSION));
+    msg.attr_str(WGDEVICE_A_IFNAME, &device.ifname);
+    if let Some(key) = private_key {
+        msg.attr(WGDEVICE_A_PRIVATE_KEY, &decode_key(key)?);
+    }
+    if let Some(port) = device.listen_port {
+        msg.attr_u16(WGDEVICE_A_LISTEN_PORT, port);
+    }
+    if !device.peers.is_empty() {
+        msg.begin_nested(WGDEVICE_A_PEERS);
+        for peer in device.peers.iter() {
+            push_peer(&mut msg, peer)?;
+        }
+        msg.end_nested();
+    }
+    Ok(msg)
+}
+
+pub fn set_device(device: &WgDevice) -> Result<(), NetlinkError> {
+    // like `wg` we read the key from a file so it never shows up in a command line
+    let private_key = match device.private_key_path {
+        Some(ref path) => {
+            let mut key = String::new();
+            File::open(path)
+                .and_then(|mut f| f.read_to_string(&mut key))
+                .map_err(NetlinkError::Unavailable)?;
+            Some(key)
+        }
+        None => None,
+    };
+
+    let mut socket = NetlinkSocket::open(NETLINK_GENERIC)?;
+    let family = family_id(&mut socket)?;
+    let msg = set_device_msg(family, device, private_key.as_ref().map(|k| k.as_str()))?;
+    socket.request(msg)?;
+    Ok(())
+}
+
+#[test]
+fn test_set_device_msg() {
+    use super::message::{read_u32, NLMSG_HDRLEN};
+
+    let mut device = WgDevice::new("wg_exit");
+    device.listen_port = Some(59999);
+    let mut peer = WgPeer::new("x8AcR9wI4t97aowYFlis077BDBk9SLdq6khMiixuTsQ=");
+    peer.endpoint = Some(WgEndpoint {
+        addr: "[fd00::1]:60000".parse().unwrap(),
+        scope: None,
+    });
+    peer.allowed_ips = Some(vec![("0.0.0.0".parse().unwrap(), 0)]);
+    peer.persistent_keepalive = Some(5);
+    device.peers.push(peer);
+    let buf = set_device_msg(0x1a, &device, None).unwrap().finish(1);
+    assert_eq!(read_u16(&buf[4..]), Some(0x1a));
+    assert_eq!(&buf[NLMSG_HDRLEN..NLMSG_HDRLEN + 2], &[WG_CMD_SET_DEVICE, WG_GENL_VERSION]);
+
+    let attrs = parse_attrs(&buf[NLMSG_HDRLEN + 4..]);
+    assert_eq!(attrs[0], (WGDEVICE_A_IFNAME, &b"wg_exit\0"[..]));
+    assert_eq!(attrs[1].0, WGDEVICE_A_LISTEN_PORT);
+    assert_eq!(read_u16(attrs[1].1), Some(59999));
+    assert_eq!(attrs[2].0, WGDEVICE_A_PEERS);
+
+    let peers = parse_attrs(attrs[2].1);
+    assert_eq!(peers.len(), 1);
+    let peer = parse_attrs(peers[0].1);
+    assert_eq!(peer[0].0, WGPEER_A_PUBLIC_KEY);
+    assert_eq!(peer[0].1.len(), WG_KEY_LEN);
+    assert_eq!(peer[1].0, WGPEER_A_FLAGS);
+    assert_eq!(read_u32(peer[1].1), Some(WGPEER_F_REPLACE_ALLOWEDIPS));
+    assert_eq!(peer[2].0, WGPEER_A_ENDPOINT);
+    // sockaddr_in6, port in network order
+    assert_eq!(peer[2].1.len(), 28);
+    assert_eq!(&peer[2].1[2..4], &[0xea, 0x60]);
+    assert_eq!(peer[3].0, WGPEER_A_PERSISTENT_KEEPALIVE_INTERVAL);
+    assert_eq!(peer[4].0, WGPEER_A_ALLOWEDIPS);
+
+    let allowed = parse_attrs(peer[4].1);
+    let allowed = parse_attrs(allowed[0].1);
+    assert_eq!(read_u16(allowed[0].1), Some(libc::AF_INET as u16));
+    assert_eq!(allowed[1].1, &[0u8, 0, 0, 0][..]);
+    assert_eq!(allowed[2].1, &[0u8][..]);
+}
+
+#[test]
+fn test_bad_key() {
+    assert!(decode_key("not a key").is_err());
+    assert!(decode_key("AAAA").is_err());
+    assert!(decode_key("x8AcR9wI4t97aowYFlis077BDBk9SLdq6khMiixuTsQ=\n").is_ok());
+}
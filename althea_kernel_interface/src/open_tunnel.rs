@@ -1,29 +1,32 @@
-use super::{KernelInterface, KernelInterfaceError};
+use super::{KernelInterface, KernelInterfaceError, KernelOp, WgDevice, WgEndpoint, WgPeer};
 
 use std::net::{IpAddr, Ipv6Addr, SocketAddr};
 use std::path::Path;
 
 use failure::Error;
 
-fn to_wg_local(ip: &IpAddr) -> IpAddr {
+/// Derives the link local address used on wg interfaces from our mesh ip, which must be
+/// in fd00::/8. Tunnel endpoints may be of either address family but the mesh itself is ipv6 only
+fn to_wg_local(ip: &IpAddr) -> Result<IpAddr, KernelInterfaceError> {
     match ip {
-        &IpAddr::V6(ip) => {
-            let mut seg = ip.segments();
-            assert_eq!((seg[0] & 0xfd00), 0xfd00);
-            IpAddr::V6(Ipv6Addr::new(
+        &IpAddr::V6(ip) if (ip.segments()[0] & 0xff00) == 0xfd00 => {
+            let seg = ip.segments();
+            Ok(IpAddr::V6(Ipv6Addr::new(
                 0xfe80, 0x0, 0x0, 0x0, seg[4], seg[5], seg[6], seg[7],
-            ))
+            )))
         }
-        _ => unreachable!(),
+        _ => Err(KernelInterfaceError::InvalidMeshIp(*ip)),
     }
 }
 
 #[test]
 fn test_to_wg_local() {
     assert_eq!(
-        to_wg_local(&"fd00::1".parse().unwrap()),
+        to_wg_local(&"fd00::1".parse().unwrap()).unwrap(),
         "fe80::1".parse::<IpAddr>().unwrap()
-    )
+    );
+    assert!(to_wg_local(&"2001::1".parse().unwrap()).is_err());
+    assert!(to_wg_local(&"10.0.0.1".parse().unwrap()).is_err());
 }
 
 fn is_link_local(ip: IpAddr) -> bool {
@@ -33,23 +36,73 @@ fn is_link_local(ip: IpAddr) -> bool {
     false
 }
 
-/// socket to string with interface id support
-fn socket_to_string(endpoint: &SocketAddr, interface_name: Option<String>) -> String {
-    match endpoint {
-        &SocketAddr::V6(endpoint) => {
-            if is_link_local(IpAddr::V6(endpoint.ip().clone())) {
-                format!(
-                    "[{}%{}]:{}",
-                    endpoint.ip(),
-                    interface_name.expect("Link local without interface"),
-                    endpoint.port()
-                )
-            } else {
-                format!("[{}]:{}", endpoint.ip(), endpoint.port())
-            }
-        }
-        &SocketAddr::V4(endpoint) => format!("{}:{}", endpoint.ip(), endpoint.port()),
-    }
+/// Builds the wireguard endpoint, link local addresses need the interface they are reached on
+fn to_wg_endpoint(
+    endpoint: &SocketAddr,
+    interface_name: Option<String>,
+) -> Result<WgEndpoint, KernelInterfaceError> {
+    let scope = match endpoint {
+        &SocketAddr::V6(_) if is_link_local(endpoint.ip()) => match interface_name {
+            Some(interface_name) => Some(interface_name),
+            None => return Err(KernelInterfaceError::NoInterfaceForLinkLocal(*endpoint)),
+        },
+        _ => None,
+    };
+    Ok(WgEndpoint {
+        addr: *endpoint,
+        scope,
+    })
+}
+
+#[test]
+fn test_to_wg_endpoint() {
+    assert_eq!(
+        to_wg_endpoint(&"1.2.3.4:60000".parse().unwrap(), None)
+            .unwrap()
+            .to_string(),
+        "1.2.3.4:60000"
+    );
+    // the interface is only needed for link local addresses
+    assert_eq!(
+        to_wg_endpoint(&"1.2.3.4:60000".parse().unwrap(), Some("eth0".into()))
+            .unwrap()
+            .to_string(),
+        "1.2.3.4:60000"
+    );
+    assert_eq!(
+        to_wg_endpoint(&"[2001::1]:60000".parse().unwrap(), Some("eth0".into()))
+            .unwrap()
+            .to_string(),
+        "[2001::1]:60000"
+    );
+    assert_eq!(
+        to_wg_endpoint(&"[fe80::1]:60000".parse().unwrap(), Some("eth0".into()))
+            .unwrap()
+            .to_string(),
+        "[fe80::1%eth0]:60000"
+    );
+    assert!(to_wg_endpoint(&"[fe80::1]:60000".parse().unwrap(), None).is_err());
+}
+
+/// The wireguard config of a tunnel to a single mesh neighbor, without an endpoint we wait for
+/// the neighbor to connect to us
+fn tunnel_device(
+    interface: &str,
+    port: u16,
+    private_key_path: &Path,
+    remote_pub_key: &str,
+    endpoint: Option<WgEndpoint>,
+) -> WgDevice {
+    let mut peer = WgPeer::new(remote_pub_key);
+    peer.endpoint = endpoint;
+    peer.allowed_ips = Some(vec![(IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0)), 0)]);
+    peer.persistent_keepalive = Some(5);
+
+    let mut device = WgDevice::new(interface);
+    device.listen_port = Some(port);
+    device.private_key_path = Some(private_key_path.to_string_lossy().into_owned());
+    device.peers.push(peer);
+    device
 }
 
 impl KernelInterface {
@@ -62,8 +115,9 @@ impl KernelInterface {
         private_key_path: &Path,
         own_ip: &IpAddr,
         external_nic: Option<String>,
-        settings_default_route: &mut Vec<String>,
     ) -> Result<(), Error> {
+        // check this before we touch the interface so a bad mesh ip doesn't leave it half setup
+        let wg_local_ip = to_wg_local(own_ip)?;
         let external_peer;
 
         let phy_name = match self.get_device_name(endpoint.ip()) {
@@ -76,61 +130,29 @@ impl KernelInterface {
                 external_nic
             }
         };
-        let socket_connect_str = socket_to_string(endpoint, phy_name);
-        trace!("socket conenct string: {}", socket_connect_str);
-        let output = self.run_command(
-            "wg",
-            &[
-                "set",
-                &interface,
-                "listen-port",
-                &format!("{}", port),
-                "private-key",
-                &format!("{}", private_key_path.to_str().unwrap()),
-                "peer",
-                &format!("{}", remote_pub_key),
-                "endpoint",
-                &socket_connect_str,
-                "allowed-ips",
-                "::/0",
-                "persistent-keepalive",
-                "5",
-            ],
-        )?;
+        let wg_endpoint = to_wg_endpoint(endpoint, phy_name)?;
+        trace!("socket conenct string: {}", wg_endpoint);
+        let device = tunnel_device(
+            interface,
+            port,
+            private_key_path,
+            remote_pub_key,
+            Some(wg_endpoint),
+        );
+        let output = self.apply_op(&KernelOp::WgSet(device))?;
         if !output.stderr.is_empty() {
             return Err(KernelInterfaceError::RuntimeError(format!(
                 "received error from wg command: {}",
                 String::from_utf8(output.stderr)?
             )).into());
         }
-        let _output = self.run_command(
-            "ip",
-            &["address", "add", &format!("{}", own_ip), "dev", &interface],
-        )?;
-
-        self.run_command(
-            "ip",
-            &[
-                "address",
-                "add",
-                &format!("{}/64", to_wg_local(own_ip)),
-                "dev",
-                &interface,
-            ],
-        )?;
+        self.add_tunnel_addresses(interface, own_ip, &wg_local_ip)?;
 
         if external_peer {
-            self.manual_peers_route(&endpoint.ip(), settings_default_route)?;
+            self.manual_peers_route(&endpoint.ip())?;
         }
 
-        let output = self.run_command("ip", &["link", "set", "dev", &interface, "up"])?;
-        if !output.stderr.is_empty() {
-            return Err(KernelInterfaceError::RuntimeError(format!(
-                "received error setting wg interface up: {}",
-                String::from_utf8(output.stderr)?
-            )).into());
-        }
-        Ok(())
+        self.set_tunnel_up(interface)
     }
 
     pub fn open_tunnel_listener(
@@ -141,46 +163,47 @@ impl KernelInterface {
         private_key_path: &Path,
         own_ip: &IpAddr,
     ) -> Result<(), Error> {
-        let output = self.run_command(
-            "wg",
-            &[
-                "set",
-                &interface,
-                "listen-port",
-                &format!("{}", port),
-                "private-key",
-                &format!("{}", private_key_path.to_str().unwrap()),
-                "peer",
-                &format!("{}", remote_pub_key),
-                "allowed-ips",
-                "::/0",
-                "persistent-keepalive",
-                "5",
-            ],
-        )?;
+        let wg_local_ip = to_wg_local(own_ip)?;
+        let device = tunnel_device(interface, port, private_key_path, remote_pub_key, None);
+        let output = self.apply_op(&KernelOp::WgSet(device))?;
         if !output.stderr.is_empty() {
             return Err(KernelInterfaceError::RuntimeError(format!(
                 "received error from wg command: {}",
                 String::from_utf8(output.stderr)?
             )).into());
         }
-        let _output = self.run_command(
-            "ip",
-            &["address", "add", &format!("{}", own_ip), "dev", &interface],
-        )?;
-
-        self.run_command(
-            "ip",
-            &[
-                "address",
-                "add",
-                &format!("{}/64", to_wg_local(own_ip)),
-                "dev",
-                &interface,
-            ],
-        )?;
-
-        let output = self.run_command("ip", &["link", "set", "dev", &interface, "up"])?;
+        self.add_tunnel_addresses(interface, own_ip, &wg_local_ip)?;
+        self.set_tunnel_up(interface)
+    }
+
+    /// Adds our mesh ip and the link local address derived from it to a tunnel
+    fn add_tunnel_addresses(
+        &self,
+        interface: &str,
+        own_ip: &IpAddr,
+        wg_local_ip: &IpAddr,
+    ) -> Result<(), Error> {
+        self.apply_op(&KernelOp::Address {
+            add: true,
+            dev: interface.to_string(),
+            addr: *own_ip,
+            prefix_len: 128,
+        })?;
+        self.apply_op(&KernelOp::Address {
+            add: true,
+            dev: interface.to_string(),
+            addr: *wg_local_ip,
+            prefix_len: 64,
+        })?;
+        Ok(())
+    }
+
+    fn set_tunnel_up(&self, interface: &str) -> Result<(), Error> {
+        let output = self.apply_op(&KernelOp::SetLink {
+            dev: interface.to_string(),
+            up: Some(true),
+            mtu: None,
+        })?;
         if !output.stderr.is_empty() {
             return Err(KernelInterfaceError::RuntimeError(format!(
                 "received error setting wg interface up: {}",
@@ -299,6 +322,218 @@ fe80::433:25ff:fe8c:e1ea dev eth0 lladdr 1a:32:06:78:05:0a STALE
         &private_key_path,
         &own_mesh_ip,
         None,
-        &mut vec![],
     ).unwrap();
 }
+
+#[test]
+fn test_open_tunnel_ipv4_external() {
+    use KI;
+
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+    use std::process::Output;
+
+    let interface = String::from("wg2");
+    let own_mesh_ip = "fd00::1".parse::<IpAddr>().unwrap();
+    let endpoint = "1.2.3.4:60000".parse::<SocketAddr>().unwrap();
+    let remote_pub_key = String::from("x8AcR9wI4t97aowYFlis077BDBk9SLdq6khMiixuTsQ=");
+    let private_key_path = Path::new("private_key");
+
+    let wg_args = &[
+        "set",
+        "wg2",
+        "listen-port",
+        "60001",
+        "private-key",
+        "private_key",
+        "peer",
+        "x8AcR9wI4t97aowYFlis077BDBk9SLdq6khMiixuTsQ=",
+        "endpoint",
+        "1.2.3.4:60000",
+        "allowed-ips",
+        "::/0",
+        "persistent-keepalive",
+        "5",
+    ];
+
+    let mut counter = 0;
+
+    KI.set_mock(Box::new(move |program, args| {
+        counter += 1;
+        match counter {
+            1 => {
+                // the endpoint is not a neighbor, so it's a manual peer
+                assert_eq!(program, "ip");
+                assert_eq!(args, &["neighbor"]);
+
+                Ok(Output {
+                    stdout: b"10.0.2.2 dev eth0 lladdr 00:00:00:aa:00:03 STALE".to_vec(),
+                    stderr: b"".to_vec(),
+                    status: ExitStatus::from_raw(0),
+                })
+            }
+            2 => {
+                assert_eq!(program, "wg");
+                assert_eq!(args, wg_args);
+                Ok(Output {
+                    stdout: b"".to_vec(),
+                    stderr: b"".to_vec(),
+                    status: ExitStatus::from_raw(0),
+                })
+            }
+            3 => {
+                assert_eq!(program, "ip");
+                assert_eq!(args, ["address", "add", "fd00::1", "dev", "wg2"]);
+                Ok(Output {
+                    stdout: b"".to_vec(),
+                    stderr: b"".to_vec(),
+                    status: ExitStatus::from_raw(0),
+                })
+            }
+            4 => {
+                assert_eq!(program, "ip");
+                assert_eq!(args, ["address", "add", "fe80::1/64", "dev", "wg2"]);
+                Ok(Output {
+                    stdout: b"".to_vec(),
+                    stderr: b"".to_vec(),
+                    status: ExitStatus::from_raw(0),
+                })
+            }
+            5 => {
+                assert_eq!(program, "ip");
+                assert_eq!(args, ["route", "list", "default"]);
+                Ok(Output {
+                    stdout: b"default via 192.168.8.1 dev eth0 proto dhcp metric 600".to_vec(),
+                    stderr: b"".to_vec(),
+                    status: ExitStatus::from_raw(0),
+                })
+            }
+            6 => {
+                // the manual peer is routed over the ipv4 default route
+                assert_eq!(program, "ip");
+                assert_eq!(
+                    args,
+                    [
+                        "route",
+                        "add",
+                        "1.2.3.4",
+                        "via",
+                        "192.168.8.1",
+                        "dev",
+                        "eth0",
+                        "proto",
+                        "dhcp",
+                        "metric",
+                        "600"
+                    ]
+                );
+                Ok(Output {
+                    stdout: b"".to_vec(),
+                    stderr: b"".to_vec(),
+                    status: ExitStatus::from_raw(0),
+                })
+            }
+            7 => {
+                assert_eq!(program, "ip");
+                assert_eq!(args, ["link", "set", "dev", "wg2", "up"]);
+                Ok(Output {
+                    stdout: b"".to_vec(),
+                    stderr: b"".to_vec(),
+                    status: ExitStatus::from_raw(0),
+                })
+            }
+            _ => panic!("Unexpected call {} {:?} {:?}", counter, program, args),
+        }
+    }));
+
+    KI.open_tunnel(
+        &interface,
+        60001,
+        &endpoint,
+        &remote_pub_key,
+        &private_key_path,
+        &own_mesh_ip,
+        Some("eth0".to_string()),
+    ).unwrap();
+}
+
+#[test]
+fn test_open_tunnel_ipv6_global_over_ipv4_route() {
+    use KI;
+
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+    use std::process::Output;
+
+    let interface = String::from("wg3");
+    let own_mesh_ip = "fd00::1".parse::<IpAddr>().unwrap();
+    let endpoint = "[2001::1]:60000".parse::<SocketAddr>().unwrap();
+    let remote_pub_key = String::from("x8AcR9wI4t97aowYFlis077BDBk9SLdq6khMiixuTsQ=");
+    let private_key_path = Path::new("private_key");
+
+    let mut counter = 0;
+
+    KI.set_mock(Box::new(move |program, args| {
+        counter += 1;
+        let stdout = match counter {
+            1 => {
+                assert_eq!(args, &["neighbor"]);
+                b"".to_vec()
+            }
+            2 => {
+                assert_eq!(program, "wg");
+                assert_eq!(args[9], "[2001::1]:60000");
+                b"".to_vec()
+            }
+            3 | 4 => {
+                assert_eq!(args[0], "address");
+                b"".to_vec()
+            }
+            5 => {
+                assert_eq!(args, ["route", "list", "default"]);
+                b"default via 192.168.8.1 dev eth0 proto dhcp metric 600".to_vec()
+            }
+            6 => {
+                // no ipv4 route is added for the ipv6 endpoint
+                assert_eq!(args, ["link", "set", "dev", "wg3", "up"]);
+                b"".to_vec()
+            }
+            _ => panic!("Unexpected call {} {:?} {:?}", counter, program, args),
+        };
+        Ok(Output {
+            stdout,
+            stderr: b"".to_vec(),
+            status: ExitStatus::from_raw(0),
+        })
+    }));
+
+    KI.open_tunnel(
+        &interface,
+        60001,
+        &endpoint,
+        &remote_pub_key,
+        &private_key_path,
+        &own_mesh_ip,
+        Some("eth0".to_string()),
+    ).unwrap();
+}
+
+#[test]
+fn test_open_tunnel_bad_mesh_ip() {
+    use KI;
+
+    KI.set_mock(Box::new(move |program, args| {
+        panic!("Unexpected call {:?} {:?}", program, args);
+    }));
+
+    let res = KI.open_tunnel(
+        &String::from("wg4"),
+        60001,
+        &"1.2.3.4:60000".parse().unwrap(),
+        &String::from("x8AcR9wI4t97aowYFlis077BDBk9SLdq6khMiixuTsQ="),
+        &Path::new("private_key"),
+        &"10.0.0.1".parse().unwrap(),
+        None,
+    );
+    assert!(res.is_err());
+}
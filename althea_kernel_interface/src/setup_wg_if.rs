@@ -1,4 +1,4 @@
-use super::{KernelInterface, KernelInterfaceError};
+use super::{KernelInterface, KernelInterfaceError, KernelOp};
 use failure::err_msg;
 use std::str::from_utf8;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
@@ -37,7 +37,7 @@ impl KernelInterface {
 
     /// calls iproute2 to set up a new interface with a given name.
     pub fn setup_wg_if_named(&self, name: &str) -> Result<(), Error> {
-        let output = self.run_command("ip", &["link", "add", &name, "type", "wireguard"])?;
+        let output = self.apply_op(&KernelOp::AddWireguardLink(name.to_string()))?;
         let stderr = String::from_utf8(output.stderr)?;
         if !stderr.is_empty() {
             if stderr.contains("exists") {
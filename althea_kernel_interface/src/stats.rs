@@ -1,8 +1,174 @@
-use super::KernelInterface;
+use super::{KernelInterface, KernelInterfaceError};
+
+use althea_types::{CpuInfo, CpuTimes, DeviceStats, LoadAvg, MemInfo, ProcStat, RouteStats, Stats};
 
 use failure::Error;
 use std::fs::File;
 use std::io::Read;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn malformed(file: &str, line: &str) -> Error {
+    KernelInterfaceError::RuntimeError(format!("Malformed line in {}: '{}'", file, line)).into()
+}
+
+/// Parses the whitespace separated field at index, failing with the whole line for context
+fn field<T: FromStr>(file: &str, line: &str, fields: &[&str], index: usize) -> Result<T, Error> {
+    match fields.get(index).and_then(|f| f.parse().ok()) {
+        Some(val) => Ok(val),
+        None => Err(malformed(file, line)),
+    }
+}
+
+fn parse_cpu_times(line: &str, fields: &[&str]) -> Result<CpuTimes, Error> {
+    let file = "/proc/stat";
+    Ok(CpuTimes {
+        user: field(file, line, fields, 1)?,
+        nice: field(file, line, fields, 2)?,
+        system: field(file, line, fields, 3)?,
+        idle: field(file, line, fields, 4)?,
+        iowait: field(file, line, fields, 5)?,
+        irq: field(file, line, fields, 6)?,
+        softirq: field(file, line, fields, 7)?,
+        // older kernels do not report steal time
+        steal: field(file, line, fields, 8).unwrap_or(0),
+    })
+}
+
+fn parse_proc_stat(contents: &str) -> Result<ProcStat, Error> {
+    let file = "/proc/stat";
+    let mut stat = ProcStat::default();
+    for line in contents.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.first() {
+            Some(&"cpu") => stat.total = parse_cpu_times(line, &fields)?,
+            Some(name) if name.starts_with("cpu") => {
+                stat.cpus.push(parse_cpu_times(line, &fields)?)
+            }
+            Some(&"ctxt") => stat.context_switches = field(file, line, &fields, 1)?,
+            Some(&"btime") => stat.boot_time = field(file, line, &fields, 1)?,
+            Some(&"processes") => stat.processes = field(file, line, &fields, 1)?,
+            Some(&"procs_running") => stat.procs_running = field(file, line, &fields, 1)?,
+            Some(&"procs_blocked") => stat.procs_blocked = field(file, line, &fields, 1)?,
+            _ => {}
+        }
+    }
+    Ok(stat)
+}
+
+fn parse_load_avg(contents: &str) -> Result<LoadAvg, Error> {
+    let file = "/proc/loadavg";
+    let line = contents.trim();
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let procs: Vec<&str> = fields.get(3).unwrap_or(&"").split('/').collect();
+    Ok(LoadAvg {
+        one: field(file, line, &fields, 0)?,
+        five: field(file, line, &fields, 1)?,
+        fifteen: field(file, line, &fields, 2)?,
+        runnable: field(file, line, &procs, 0)?,
+        total: field(file, line, &procs, 1)?,
+        last_pid: field(file, line, &fields, 4)?,
+    })
+}
+
+fn parse_devices(contents: &str) -> Result<Vec<DeviceStats>, Error> {
+    let file = "/proc/net/dev";
+    let mut devices = Vec::new();
+    // the first two lines are headers
+    for line in contents.lines().skip(2) {
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim();
+        let fields: Vec<&str> = match parts.next() {
+            Some(counters) => counters.split_whitespace().collect(),
+            None => return Err(malformed(file, line)),
+        };
+        devices.push(DeviceStats {
+            name: name.to_string(),
+            rx_bytes: field(file, line, &fields, 0)?,
+            rx_packets: field(file, line, &fields, 1)?,
+            rx_errors: field(file, line, &fields, 2)?,
+            rx_dropped: field(file, line, &fields, 3)?,
+            tx_bytes: field(file, line, &fields, 8)?,
+            tx_packets: field(file, line, &fields, 9)?,
+            tx_errors: field(file, line, &fields, 10)?,
+            tx_dropped: field(file, line, &fields, 11)?,
+        });
+    }
+    Ok(devices)
+}
+
+/// Addresses in /proc/net/route are the raw network order bytes printed as a host order integer,
+/// so laying the integer back out in native order gives the address bytes on any cpu
+fn parse_route_addr(line: &str, hex: &str) -> Result<Ipv4Addr, Error> {
+    match u32::from_str_radix(hex, 16) {
+        Ok(val) => Ok(Ipv4Addr::from(val.to_ne_bytes())),
+        Err(_) => Err(malformed("/proc/net/route", line)),
+    }
+}
+
+fn parse_routes(contents: &str) -> Result<Vec<RouteStats>, Error> {
+    let file = "/proc/net/route";
+    let mut routes = Vec::new();
+    // the first line is a header
+    for line in contents.lines().skip(1) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 8 {
+            return Err(malformed(file, line));
+        }
+        let flags = match u16::from_str_radix(fields[3], 16) {
+            Ok(flags) => flags,
+            Err(_) => return Err(malformed(file, line)),
+        };
+        routes.push(RouteStats {
+            iface: fields[0].to_string(),
+            destination: parse_route_addr(line, fields[1])?,
+            gateway: parse_route_addr(line, fields[2])?,
+            mask: parse_route_addr(line, fields[7])?,
+            flags,
+            metric: field(file, line, &fields, 6)?,
+        });
+    }
+    Ok(routes)
+}
+
+fn parse_meminfo(contents: &str) -> Result<MemInfo, Error> {
+    let file = "/proc/meminfo";
+    let mut meminfo = MemInfo::default();
+    for line in contents.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let value = || field::<u64>(file, line, &fields, 1);
+        match fields.first() {
+            Some(&"MemTotal:") => meminfo.total = value()?,
+            Some(&"MemFree:") => meminfo.free = value()?,
+            Some(&"MemAvailable:") => meminfo.available = Some(value()?),
+            Some(&"Buffers:") => meminfo.buffers = value()?,
+            Some(&"Cached:") => meminfo.cached = value()?,
+            Some(&"SwapTotal:") => meminfo.swap_total = value()?,
+            Some(&"SwapFree:") => meminfo.swap_free = value()?,
+            _ => {}
+        }
+    }
+    Ok(meminfo)
+}
+
+fn parse_cpuinfo(contents: &str) -> CpuInfo {
+    let mut cpuinfo = CpuInfo::default();
+    for line in contents.lines() {
+        let mut parts = line.splitn(2, ':');
+        let key = parts.next().unwrap_or("").trim();
+        let value = parts.next().unwrap_or("").trim();
+        match key {
+            "processor" => cpuinfo.processors += 1,
+            // x86 and arm use "model name", mips uses "cpu model"
+            "model name" | "cpu model" if cpuinfo.model.is_none() => {
+                cpuinfo.model = Some(value.to_string())
+            }
+            _ => {}
+        }
+    }
+    cpuinfo
+}
 
 impl KernelInterface {
     fn read_file(&self, path_str: &str) -> Result<String, Error> {
@@ -13,51 +179,152 @@ impl KernelInterface {
         Ok(contents)
     }
 
-    pub fn get_proc_stat(&self) -> Result<String, Error> {
+    pub fn get_proc_stat(&self) -> Result<ProcStat, Error> {
         debug!("getting proc stat");
-
-        let contents = self.read_file("/proc/stat")?;
-
-        Ok(contents)
+        parse_proc_stat(&self.read_file("/proc/stat")?)
     }
 
-    pub fn get_proc_load_avg(&self) -> Result<String, Error> {
+    pub fn get_proc_load_avg(&self) -> Result<LoadAvg, Error> {
         debug!("getting proc loadavg");
-
-        let contents = self.read_file("/proc/loadavg")?;
-
-        Ok(contents)
+        parse_load_avg(&self.read_file("/proc/loadavg")?)
     }
 
-    pub fn get_device_stats(&self) -> Result<String, Error> {
+    pub fn get_device_stats(&self) -> Result<Vec<DeviceStats>, Error> {
         debug!("getting device stats");
-
-        let contents = self.read_file("/proc/net/dev")?;
-
-        Ok(contents)
+        parse_devices(&self.read_file("/proc/net/dev")?)
     }
 
-    pub fn get_meminfo_stats(&self) -> Result<String, Error> {
+    pub fn get_meminfo_stats(&self) -> Result<MemInfo, Error> {
         debug!("getting meminfo");
+        parse_meminfo(&self.read_file("/proc/meminfo")?)
+    }
 
-        let contents = self.read_file("/proc/meminfo")?;
+    pub fn get_cpuinfo_stats(&self) -> Result<CpuInfo, Error> {
+        debug!("getting cpuinfo");
+        Ok(parse_cpuinfo(&self.read_file("/proc/cpuinfo")?))
+    }
 
-        Ok(contents)
+    pub fn get_route_stats(&self) -> Result<Vec<RouteStats>, Error> {
+        debug!("getting route stats");
+        parse_routes(&self.read_file("/proc/net/route")?)
     }
 
-    pub fn get_cpuinfo_stats(&self) -> Result<String, Error> {
-        debug!("getting cpuinfo");
+    /// Takes a sample of everything the stats server is interested in
+    pub fn get_stats(&self) -> Result<Stats, Error> {
+        Ok(Stats {
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            proc_stat: self.get_proc_stat()?,
+            proc_load_avg: self.get_proc_load_avg()?,
+            devices: self.get_device_stats()?,
+            routes: self.get_route_stats()?,
+            meminfo: self.get_meminfo_stats()?,
+            cpuinfo: self.get_cpuinfo_stats()?,
+        })
+    }
+}
 
-        let contents = self.read_file("/proc/cpuinfo")?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        Ok(contents)
+    #[test]
+    fn test_proc_stat() {
+        let stat = parse_proc_stat(
+            "cpu  4705 356 584 3699176 23060 0 277 0 0 0\n\
+             cpu0 1393 280 297 924011 8034 0 193 0 0 0\n\
+             cpu1 3312 76 287 2775165 15026 0 84\n\
+             intr 114930548 113199788 3 0 5 263 0 4 [... lots more numbers ...]\n\
+             ctxt 1990473\n\
+             btime 1062191376\n\
+             processes 2915\n\
+             procs_running 1\n\
+             procs_blocked 0\n",
+        ).unwrap();
+        assert_eq!(stat.total.idle, 3699176);
+        assert_eq!(stat.cpus.len(), 2);
+        assert_eq!(stat.cpus[1].softirq, 84);
+        assert_eq!(stat.cpus[1].steal, 0);
+        assert_eq!(stat.context_switches, 1990473);
+        assert_eq!(stat.boot_time, 1062191376);
+        assert_eq!(stat.processes, 2915);
+        assert_eq!(stat.procs_running, 1);
     }
 
-    pub fn get_route_stats(&self) -> Result<String, Error> {
-        debug!("getting route stats");
+    #[test]
+    fn test_load_avg() {
+        let load = parse_load_avg("0.20 0.18 0.12 1/80 11206\n").unwrap();
+        assert_eq!(load.five, 0.18);
+        assert_eq!(load.runnable, 1);
+        assert_eq!(load.total, 80);
+        assert_eq!(load.last_pid, 11206);
+        assert!(parse_load_avg("0.20 0.18").is_err());
+    }
 
-        let contents = self.read_file("/proc/net/route")?;
+    #[test]
+    fn test_devices() {
+        let devices = parse_devices(
+            "Inter-|   Receive                                                |  Transmit\n \
+             face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets \
+             errs drop fifo colls carrier compressed\n    \
+             lo: 2776770   11307    0    0    0     0          0         0  2776770   11307    \
+             0    0    0     0       0          0\n  \
+             eth0:1215645    2751    1    2    0     0          0         0  1782404    4324    \
+             3    4    0   427       0          0\n",
+        ).unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[1].name, "eth0");
+        assert_eq!(devices[1].rx_bytes, 1215645);
+        assert_eq!(devices[1].rx_dropped, 2);
+        assert_eq!(devices[1].tx_packets, 4324);
+        assert_eq!(devices[1].tx_errors, 3);
+    }
 
-        Ok(contents)
+    #[test]
+    fn test_routes() {
+        let routes = parse_routes(
+            "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n\
+             eth0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0\n\
+             eth0\t0001A8C0\t00000000\t0001\t0\t0\t0\t00FFFFFF\t0\t0\t0\n",
+        ).unwrap();
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].metric, 100);
+        assert_eq!(routes[0].flags, 3);
+        if cfg!(target_endian = "little") {
+            assert_eq!(routes[0].gateway, Ipv4Addr::new(192, 168, 1, 1));
+            assert_eq!(routes[1].destination, Ipv4Addr::new(192, 168, 1, 0));
+            assert_eq!(routes[1].mask, Ipv4Addr::new(255, 255, 255, 0));
+        }
+    }
+
+    #[test]
+    fn test_meminfo() {
+        let meminfo = parse_meminfo(
+            "MemTotal:         125280 kB\n\
+             MemFree:           68552 kB\n\
+             Buffers:            4256 kB\n\
+             Cached:            19600 kB\n\
+             SwapCached:            0 kB\n\
+             SwapTotal:             0 kB\n\
+             SwapFree:              0 kB\n",
+        ).unwrap();
+        assert_eq!(meminfo.total, 125280);
+        assert_eq!(meminfo.free, 68552);
+        assert_eq!(meminfo.available, None);
+        assert_eq!(meminfo.cached, 19600);
+    }
+
+    #[test]
+    fn test_cpuinfo() {
+        let cpuinfo = parse_cpuinfo(
+            "system type\t\t: MediaTek MT7621 ver:1 eco:3\n\
+             machine\t\t\t: GL-MT1300\n\
+             processor\t\t: 0\n\
+             cpu model\t\t: MIPS 1004Kc V2.15\n\
+             \n\
+             processor\t\t: 1\n\
+             cpu model\t\t: MIPS 1004Kc V2.15\n",
+        );
+        assert_eq!(cpuinfo.processors, 2);
+        assert_eq!(cpuinfo.model, Some("MIPS 1004Kc V2.15".to_string()));
     }
 }
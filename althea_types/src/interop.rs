@@ -1,5 +1,5 @@
 use num256::Uint256;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr};
 use EthAddress;
 
 #[cfg(feature = "actix")]
@@ -130,11 +130,61 @@ pub struct ExitDetails {
     pub wg_exit_port: u16,
     pub exit_price: u64,
     pub description: String,
+    /// Billing plans offered by the exit besides paying `exit_price` per byte
+    #[serde(default)]
+    pub plans: Vec<ExitPlan>,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
 pub struct ExitClientDetails {
     pub client_internal_ip: IpAddr,
+    /// Name of the plan the exit bills us on, `None` means paying per byte
+    #[serde(default)]
+    pub plan: Option<String>,
+    /// Unix time the current period of our plan started at, zero if it hasn't started yet
+    #[serde(default)]
+    pub period_start: u64,
+    /// Bytes the exit has counted against our plan this period
+    #[serde(default)]
+    pub period_bytes: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanType {
+    /// `exit_price` plus the route price per byte, the same as having no plan
+    PerByte,
+    /// A fixed price per period with no per byte charges
+    Flat,
+    /// A fixed price per period covering `quota_bytes`, traffic beyond it is handled according
+    /// to the plan's `overage`
+    Quota,
+}
+
+/// What happens to traffic beyond a quota plan's quota
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Overage {
+    /// Billed per byte like a client without a plan
+    Bill,
+    /// Free but rate limited to the plan's `throttle_kbps`
+    Throttle,
+}
+
+/// A billing plan an exit operator assigns to clients
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
+pub struct ExitPlan {
+    pub name: String,
+    pub plan_type: PlanType,
+    /// Bytes in either direction covered each period by a quota plan
+    pub quota_bytes: u64,
+    /// Length of the billing period in seconds
+    pub period: u64,
+    /// Wei per period, charged gradually over the period as it goes by
+    pub price: u64,
+    pub overage: Overage,
+    pub throttle_kbps: u32,
+    pub description: String,
 }
 
 #[cfg(feature = "actix")]
@@ -164,13 +214,106 @@ pub struct PaymentTx {
     pub amount: Uint256,
 }
 
+/// Time a cpu has spent in each state since boot, in USER_HZ ticks, from /proc/stat
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct CpuTimes {
+    pub user: u64,
+    pub nice: u64,
+    pub system: u64,
+    pub idle: u64,
+    pub iowait: u64,
+    pub irq: u64,
+    pub softirq: u64,
+    pub steal: u64,
+}
+
+/// The parts of /proc/stat we care about
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct ProcStat {
+    /// Summed over all cpus
+    pub total: CpuTimes,
+    pub cpus: Vec<CpuTimes>,
+    pub context_switches: u64,
+    /// Seconds since the unix epoch
+    pub boot_time: u64,
+    /// Forks since boot
+    pub processes: u64,
+    pub procs_running: u64,
+    pub procs_blocked: u64,
+}
+
+/// /proc/loadavg
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct LoadAvg {
+    pub one: f32,
+    pub five: f32,
+    pub fifteen: f32,
+    pub runnable: u32,
+    pub total: u32,
+    pub last_pid: u32,
+}
+
+/// A single interface from /proc/net/dev
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct DeviceStats {
+    pub name: String,
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    pub rx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_errors: u64,
+    pub tx_dropped: u64,
+}
+
+/// A single ipv4 route from /proc/net/route
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RouteStats {
+    pub iface: String,
+    pub destination: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+    pub mask: Ipv4Addr,
+    pub flags: u16,
+    pub metric: u32,
+}
+
+/// Memory figures from /proc/meminfo in kB
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct MemInfo {
+    pub total: u64,
+    pub free: u64,
+    /// Only reported by kernels since 3.14
+    pub available: Option<u64>,
+    pub buffers: u64,
+    pub cached: u64,
+    pub swap_total: u64,
+    pub swap_free: u64,
+}
+
+/// A summary of /proc/cpuinfo
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct CpuInfo {
+    pub model: Option<String>,
+    pub processors: u32,
+}
+
 /// This contains all the info we need to send the the stats server
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Stats {
-    pub proc_stat: String,
-    pub proc_load_avg: String,
-    pub devices: String,
-    pub routes: String,
-    pub meminfo: String,
-    pub cpuinfo: String,
+    /// Seconds since the unix epoch when the sample was taken
+    pub timestamp: u64,
+    pub proc_stat: ProcStat,
+    pub proc_load_avg: LoadAvg,
+    pub devices: Vec<DeviceStats>,
+    pub routes: Vec<RouteStats>,
+    pub meminfo: MemInfo,
+    pub cpuinfo: CpuInfo,
+}
+
+/// Samples from a single node as they are posted to the stats server
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct StatsBatch {
+    pub from: Identity,
+    pub samples: Vec<Stats>,
 }
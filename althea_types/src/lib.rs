@@ -195,6 +195,7 @@ mod tests {
                     wg_exit_port: 50000,
                     exit_price: 50,
                     description: "An exit".to_string(),
+                    plans: Vec::new(),
                 },
                 auto_register: false,
                 message: "got info ok".to_string()
@@ -212,6 +213,7 @@ mod tests {
                     wg_exit_port: 50000,
                     exit_price: 50,
                     description: "An exit".to_string(),
+                    plans: Vec::new(),
                 },
                 auto_register: false,
                 message: "got info ok".to_string()
@@ -229,6 +231,7 @@ mod tests {
                     wg_exit_port: 50000,
                     exit_price: 50,
                     description: "An exit".to_string(),
+                    plans: Vec::new(),
                 },
                 email_code: Some("123456".to_string()),
                 message: "got info ok".to_string()
@@ -246,10 +249,30 @@ mod tests {
                     wg_exit_port: 50000,
                     exit_price: 50,
                     description: "An exit".to_string(),
+                    plans: Vec::new(),
                 },
                 email_code: None,
                 message: "got info ok".to_string()
             }
         );
     }
+
+    #[test]
+    fn exit_plan_deserialize() {
+        let s = "{\"name\":\"basic\",\"plan_type\":\"quota\",\"quota_bytes\":1000,\"period\":2592000,\"price\":100,\"overage\":\"throttle\",\"throttle_kbps\":512,\"description\":\"\"}";
+
+        assert_eq!(
+            serde_json::from_str::<ExitPlan>(s).unwrap(),
+            ExitPlan {
+                name: "basic".to_string(),
+                plan_type: PlanType::Quota,
+                quota_bytes: 1000,
+                period: 2592000,
+                price: 100,
+                overage: Overage::Throttle,
+                throttle_kbps: 512,
+                description: String::new(),
+            }
+        );
+    }
 }
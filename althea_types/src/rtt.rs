@@ -1,9 +1,49 @@
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime, SystemTimeError};
 
-/// This is a helper struct for measuring the round trip time over the exit tunnel independently
-/// from Babel. In the future, `RTTimestamps` should aid in RTT-related overadvertisement detection.
+/// This is a helper struct for measuring the round trip time to an exit or neighbor independently
+/// from Babel, it's what lets Rita notice routes that are advertised as faster than they are.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RTTimestamps {
     pub exit_rx: SystemTime,
     pub exit_tx: SystemTime,
 }
+
+impl RTTimestamps {
+    /// The round trip between sending the request at `sent` and getting these timestamps back at
+    /// `received`, without the time the other side spent handling it
+    pub fn inner_rtt(
+        &self,
+        sent: SystemTime,
+        received: SystemTime,
+    ) -> Result<Duration, SystemTimeError> {
+        let total = received.duration_since(sent)?;
+        let processing = self.exit_tx.duration_since(self.exit_rx)?;
+        Ok(total.checked_sub(processing).unwrap_or_else(|| Duration::new(0, 0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inner_rtt() {
+        let sent = SystemTime::now();
+        let timestamps = RTTimestamps {
+            exit_rx: sent + Duration::from_millis(10),
+            exit_tx: sent + Duration::from_millis(15),
+        };
+        let received = sent + Duration::from_millis(30);
+        assert_eq!(
+            timestamps.inner_rtt(sent, received).unwrap(),
+            Duration::from_millis(25)
+        );
+        // the clocks of the two sides aren't compared, only the intervals they measured
+        let received = sent + Duration::from_millis(3);
+        assert_eq!(
+            timestamps.inner_rtt(sent, received).unwrap(),
+            Duration::new(0, 0)
+        );
+        assert!(timestamps.inner_rtt(received, sent).is_err());
+    }
+}
@@ -0,0 +1,214 @@
+//! Typed configuration statements for Babel. These are sent over the local interface and take
+//! effect immediately, they are not written to the babeld config file.
+
+use std::net::IpAddr;
+
+use failure::Error;
+use ipnetwork::IpNetwork;
+
+use super::BabelMonitorError;
+
+/// Parameters of the rtt based metric of an interface, unset values are left alone
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RttParams {
+    /// rtt in milliseconds below which no penalty is applied
+    pub rtt_min: Option<u32>,
+    /// rtt in milliseconds at which the full penalty is applied
+    pub rtt_max: Option<u32>,
+    pub max_rtt_penalty: Option<u16>,
+    /// Smoothing factor out of 256 for new rtt samples
+    pub rtt_decay: Option<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RedistributeAction {
+    Allow,
+    Deny,
+    Metric(u16),
+}
+
+/// A redistribute filter, matching routes are announced or suppressed according to the action
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedistributeRule {
+    /// Only match addresses configured on this machine
+    pub local: bool,
+    pub prefix: Option<IpNetwork>,
+    pub iface: Option<String>,
+    pub action: RedistributeAction,
+}
+
+impl RedistributeRule {
+    /// A rule for the host route of a single address
+    pub fn ip(ip: IpAddr, allow: bool) -> RedistributeRule {
+        let prefix_len = match ip {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        RedistributeRule {
+            local: false,
+            prefix: Some(IpNetwork::new(ip, prefix_len).unwrap()),
+            iface: None,
+            action: if allow {
+                RedistributeAction::Allow
+            } else {
+                RedistributeAction::Deny
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BabelConfig {
+    /// The fee we charge for forwarding traffic
+    LocalFee(u32),
+    /// The rxcost babel advertises on an interface
+    InterfaceCost(String, u16),
+    InterfaceRtt(String, RttParams),
+    Redistribute(RedistributeRule),
+}
+
+fn invalid(msg: String) -> Error {
+    BabelMonitorError::InvalidConfig(msg).into()
+}
+
+impl BabelConfig {
+    /// The statement to send to Babel, fails for values Babel would reject
+    pub fn to_command(&self) -> Result<String, Error> {
+        match *self {
+            BabelConfig::LocalFee(fee) => Ok(format!("fee {}", fee)),
+            BabelConfig::InterfaceCost(ref iface, cost) => {
+                if cost == 0 {
+                    return Err(invalid(format!("rxcost of {} must be positive", iface)));
+                }
+                Ok(format!("interface {} rxcost {}", iface, cost))
+            }
+            BabelConfig::InterfaceRtt(ref iface, ref params) => {
+                let mut command = format!("interface {}", iface);
+                if let (Some(min), Some(max)) = (params.rtt_min, params.rtt_max) {
+                    if min >= max {
+                        let msg = format!("rtt-min {} is not below rtt-max {}", min, max);
+                        return Err(invalid(msg));
+                    }
+                }
+                if let Some(min) = params.rtt_min {
+                    command.push_str(&format!(" rtt-min {}", min));
+                }
+                if let Some(max) = params.rtt_max {
+                    command.push_str(&format!(" rtt-max {}", max));
+                }
+                if let Some(penalty) = params.max_rtt_penalty {
+                    command.push_str(&format!(" max-rtt-penalty {}", penalty));
+                }
+                match params.rtt_decay {
+                    Some(decay) if decay == 0 || decay > 256 => {
+                        return Err(invalid(format!("rtt-decay {} is not in 1-256", decay)))
+                    }
+                    Some(decay) => command.push_str(&format!(" rtt-decay {}", decay)),
+                    None => {}
+                }
+                if *params == RttParams::default() {
+                    return Err(invalid(format!("no rtt parameters given for {}", iface)));
+                }
+                Ok(command)
+            }
+            BabelConfig::Redistribute(ref rule) => {
+                let mut command = String::from("redistribute");
+                if rule.local {
+                    command.push_str(" local");
+                }
+                if let Some(prefix) = rule.prefix {
+                    command.push_str(&format!(" ip {}", prefix));
+                }
+                if let Some(ref iface) = rule.iface {
+                    command.push_str(&format!(" if {}", iface));
+                }
+                match rule.action {
+                    RedistributeAction::Allow => command.push_str(" allow"),
+                    RedistributeAction::Deny => command.push_str(" deny"),
+                    RedistributeAction::Metric(metric) => {
+                        command.push_str(&format!(" metric {}", metric))
+                    }
+                }
+                Ok(command)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands() {
+        assert_eq!(BabelConfig::LocalFee(50).to_command().unwrap(), "fee 50");
+        assert_eq!(
+            BabelConfig::InterfaceCost("wg0".to_string(), 96)
+                .to_command()
+                .unwrap(),
+            "interface wg0 rxcost 96"
+        );
+        assert_eq!(
+            BabelConfig::InterfaceRtt(
+                "wg0".to_string(),
+                RttParams {
+                    rtt_min: Some(10),
+                    rtt_max: Some(120),
+                    max_rtt_penalty: Some(150),
+                    rtt_decay: None,
+                }
+            ).to_command()
+            .unwrap(),
+            "interface wg0 rtt-min 10 rtt-max 120 max-rtt-penalty 150"
+        );
+        assert_eq!(
+            BabelConfig::Redistribute(RedistributeRule::ip("fd00::1".parse().unwrap(), false))
+                .to_command()
+                .unwrap(),
+            "redistribute ip fd00::1/128 deny"
+        );
+        assert_eq!(
+            BabelConfig::Redistribute(RedistributeRule {
+                local: true,
+                prefix: None,
+                iface: Some("br-lan".to_string()),
+                action: RedistributeAction::Metric(128),
+            }).to_command()
+            .unwrap(),
+            "redistribute local if br-lan metric 128"
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(
+            BabelConfig::InterfaceCost("wg0".to_string(), 0)
+                .to_command()
+                .is_err()
+        );
+        assert!(
+            BabelConfig::InterfaceRtt("wg0".to_string(), RttParams::default())
+                .to_command()
+                .is_err()
+        );
+        let params = RttParams {
+            rtt_min: Some(100),
+            rtt_max: Some(10),
+            ..Default::default()
+        };
+        assert!(
+            BabelConfig::InterfaceRtt("wg0".to_string(), params)
+                .to_command()
+                .is_err()
+        );
+        let params = RttParams {
+            rtt_decay: Some(300),
+            ..Default::default()
+        };
+        assert!(
+            BabelConfig::InterfaceRtt("wg0".to_string(), params)
+                .to_command()
+                .is_err()
+        );
+    }
+}
@@ -1,10 +1,29 @@
+extern crate actix;
 extern crate bufstream;
 #[macro_use]
 extern crate failure;
+extern crate futures;
 extern crate ipnetwork;
 #[macro_use]
 extern crate log;
 extern crate mockstream;
+extern crate tokio;
+extern crate tokio_codec;
+
+mod config;
+mod manager;
+mod parser;
+mod table;
+
+pub use config::{BabelConfig, RedistributeAction, RedistributeRule, RttParams};
+pub use parser::{parse_entry, Entry, Record, RecordKind};
+pub use table::BabelTable;
+
+pub use manager::{
+    configure, local_fee, monitor, neighbors, redistribute, routes, set_interface_cost,
+    set_interface_rtt, set_local_fee, table, unmonitor, BabelManager, Configure, Connect,
+    GetLocalFee, GetNeighbors, GetRoutes, GetTable, Monitor, Unmonitor, DUMP_MAX_AGE_MILLIS,
+};
 
 use std::collections::VecDeque;
 use std::io::{BufRead, Read, Write};
@@ -16,7 +35,7 @@ use bufstream::BufStream;
 use failure::Error;
 use ipnetwork::IpNetwork;
 
-#[derive(Debug, Fail)]
+#[derive(Debug, Clone, Fail)]
 pub enum BabelMonitorError {
     #[fail(display = "variable '{}' not found in '{}'", _0, _1)]
     VariableNotFound(String, String),
@@ -32,30 +51,18 @@ pub enum BabelMonitorError {
     NoTerminator(String),
     #[fail(display = "No Neighbor was found matching address:\n{}", _0)]
     NoNeighbor(String),
+    #[fail(display = "Not connected to Babel")]
+    NotConnected,
+    #[fail(display = "Invalid Babel configuration: {}", _0)]
+    InvalidConfig(String),
 }
 
 use BabelMonitorError::*;
 
-// If a function doesn't modify the state of the Babel object
-// we don't want to place it as a member function.
-fn find_babel_val(val: &str, line: &str) -> Result<String, Error> {
-    let mut iter = line.split(" ");
-    while let Some(entry) = iter.next() {
-        if entry.to_string().contains(val) {
-            match iter.next() {
-                Some(v) => return Ok(v.to_string()),
-                None => continue,
-            }
-        }
-    }
-    return Err(VariableNotFound(String::from(val), String::from(line)).into());
-}
-
 #[derive(Debug, Clone)]
 pub struct Route {
     pub id: String,
     pub iface: String,
-    pub xroute: bool,
     pub installed: bool,
     pub neigh_ip: IpAddr,
     pub prefix: IpNetwork,
@@ -66,6 +73,22 @@ pub struct Route {
     pub fee: u32,
 }
 
+#[derive(Debug, Clone)]
+pub struct XRoute {
+    pub id: String,
+    pub prefix: IpNetwork,
+    pub from: IpNetwork,
+    pub metric: u16,
+}
+
+#[derive(Debug, Clone)]
+pub struct Interface {
+    pub name: String,
+    pub up: bool,
+    pub ipv6: Option<IpAddr>,
+    pub ipv4: Option<IpAddr>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Neighbor {
     pub id: String,
@@ -145,20 +168,7 @@ impl<T: Read + Write> Babel<T> {
     }
 
     pub fn local_fee(&mut self) -> Result<u32, Error> {
-        let babel_output = self.command("dump")?;
-        let fee_entry = match babel_output.split("\n").nth(0) {
-            Some(entry) => entry,
-            // Even an empty string wouldn't yield None
-            None => return Err(LocalFeeNotFound(String::from("<Babel output is None>")).into()),
-        };
-
-        if fee_entry.contains("local fee") {
-            let fee = find_babel_val("fee", fee_entry)?.parse()?;
-            trace!("Retrieved a local fee of {}", fee);
-            return Ok(fee);
-        }
-
-        Err(LocalFeeNotFound(String::from(fee_entry)).into())
+        local_fee_from_dump(&self.command("dump")?)
     }
 
     pub fn monitor(&mut self, iface: &str) -> Result<(), Error> {
@@ -167,17 +177,16 @@ impl<T: Read + Write> Babel<T> {
         Ok(())
     }
 
-    pub fn redistribute_ip(&mut self, ip: &IpAddr, allow: bool) -> Result<(), Error> {
-        let commmand = format!(
-            "redistribute ip {}/128 {}",
-            ip,
-            if allow { "allow" } else { "deny" }
-        );
-        self.command(&commmand)?;
-        let _ = self.read_babel()?;
+    pub fn configure(&mut self, config: &BabelConfig) -> Result<(), Error> {
+        self.command(&config.to_command()?)?;
+        info!("Babel configured with {:?}", config);
         Ok(())
     }
 
+    pub fn redistribute_ip(&mut self, ip: &IpAddr, allow: bool) -> Result<(), Error> {
+        self.configure(&BabelConfig::Redistribute(RedistributeRule::ip(*ip, allow)))
+    }
+
     pub fn unmonitor(&mut self, iface: &str) -> Result<(), Error> {
         self.command(&format!("unmonitor {}\n", iface))?;
         let _ = self.read_babel()?;
@@ -185,97 +194,89 @@ impl<T: Read + Write> Babel<T> {
     }
 
     pub fn parse_neighs(&mut self) -> Result<VecDeque<Neighbor>, Error> {
-        let mut vector: VecDeque<Neighbor> = VecDeque::with_capacity(5);
-        for entry in self.command("dump")?.split("\n") {
-            if entry.contains("add neighbour") {
-                vector.push_back(Neighbor {
-                    id: find_babel_val("neighbour", entry)?,
-                    address: find_babel_val("address", entry)?.parse()?,
-                    iface: find_babel_val("if", entry)?,
-                    reach: u16::from_str_radix(&find_babel_val("reach", entry)?, 16)?,
-                    txcost: find_babel_val("txcost", entry)?.parse()?,
-                    rxcost: find_babel_val("rxcost", entry)?.parse()?,
-                    rtt: 0.0,
-                    rttcost: 0,
-                    cost: find_babel_val("cost", entry)?.parse()?,
-                });
-            }
-        }
-        Ok(vector)
+        neighs_from_dump(&self.command("dump")?)
     }
 
     pub fn parse_routes(&mut self) -> Result<VecDeque<Route>, Error> {
-        let mut vector: VecDeque<Route> = VecDeque::with_capacity(20);
         let babel_out = self.command("dump")?;
         trace!("Got from babel dump: {}", babel_out);
+        routes_from_dump(&babel_out)
+    }
+}
 
-        for entry in babel_out.split("\n") {
-            if entry.contains("add route") {
-                trace!("Parsing 'add route' entry: {}", entry);
-                vector.push_back(Route {
-                    id: find_babel_val("route", entry)?,
-                    iface: find_babel_val("if", entry)?,
-                    xroute: false,
-                    installed: find_babel_val("installed", entry)?.contains("yes"),
-                    neigh_ip: find_babel_val("via", entry)?.parse()?,
-                    prefix: find_babel_val("prefix", entry)?.parse()?,
-                    metric: find_babel_val("metric", entry)?.parse()?,
-                    refmetric: find_babel_val("refmetric", entry)?.parse()?,
-                    full_path_rtt: { find_babel_val("full-path-rtt", entry)?.parse()? },
-                    price: find_babel_val("price", entry)?.parse()?,
-                    fee: find_babel_val("fee", entry)?.parse()?,
-                });
-            }
+/// Reads the local fee from the output of a Babel dump
+pub fn local_fee_from_dump(babel_output: &str) -> Result<u32, Error> {
+    for line in babel_output.lines() {
+        if let Some(Entry::LocalFee(fee)) = parse_entry(line)? {
+            trace!("Retrieved a local fee of {}", fee);
+            return Ok(fee);
         }
-        Ok(vector)
     }
+    Err(LocalFeeNotFound(String::from(babel_output)).into())
+}
 
-    /// In this function we take a route snapshot then loop over the routes list twice
-    /// to find the neighbor local address and then the route to the destination
-    /// via that neighbor. This could be dramatically more efficient if we had the neighbors
-    /// local ip lying around somewhere.
-    pub fn get_route_via_neigh(
-        &mut self,
-        neigh_mesh_ip: IpAddr,
-        dest_mesh_ip: IpAddr,
-        routes: &VecDeque<Route>,
-    ) -> Result<Route, Error> {
-        // First find the neighbors route to itself to get the local address
-        for neigh_route in routes.iter() {
-            // This will fail on v4 babel routes etc
-            if let IpNetwork::V6(ref ip) = neigh_route.prefix {
-                if ip.ip() == neigh_mesh_ip {
-                    let neigh_local_ip = neigh_route.neigh_ip;
-                    // Now we take the neigh_local_ip and search for a route via that
-                    for route in routes.iter() {
-                        if let IpNetwork::V6(ref ip) = route.prefix {
-                            if ip.ip() == dest_mesh_ip && route.neigh_ip == neigh_local_ip {
-                                return Ok(route.clone());
-                            }
+/// Parses the neighbour entries out of the output of a Babel dump
+pub fn neighs_from_dump(babel_output: &str) -> Result<VecDeque<Neighbor>, Error> {
+    let mut vector: VecDeque<Neighbor> = VecDeque::with_capacity(5);
+    for line in babel_output.lines() {
+        if let Some(Entry::Neighbor(neigh)) = parse_entry(line)? {
+            vector.push_back(neigh);
+        }
+    }
+    Ok(vector)
+}
+
+/// Parses the route entries out of the output of a Babel dump
+pub fn routes_from_dump(babel_output: &str) -> Result<VecDeque<Route>, Error> {
+    let mut vector: VecDeque<Route> = VecDeque::with_capacity(20);
+    for line in babel_output.lines() {
+        if let Some(Entry::Route(route)) = parse_entry(line)? {
+            vector.push_back(route);
+        }
+    }
+    Ok(vector)
+}
+
+/// In this function we loop over the routes list twice to find the neighbor local address
+/// and then the route to the destination via that neighbor. This could be dramatically more
+/// efficient if we had the neighbors local ip lying around somewhere.
+pub fn get_route_via_neigh(
+    neigh_mesh_ip: IpAddr,
+    dest_mesh_ip: IpAddr,
+    routes: &VecDeque<Route>,
+) -> Result<Route, Error> {
+    // First find the neighbors route to itself to get the local address
+    for neigh_route in routes.iter() {
+        // This will fail on v4 babel routes etc
+        if let IpNetwork::V6(ref ip) = neigh_route.prefix {
+            if ip.ip() == neigh_mesh_ip {
+                let neigh_local_ip = neigh_route.neigh_ip;
+                // Now we take the neigh_local_ip and search for a route via that
+                for route in routes.iter() {
+                    if let IpNetwork::V6(ref ip) = route.prefix {
+                        if ip.ip() == dest_mesh_ip && route.neigh_ip == neigh_local_ip {
+                            return Ok(route.clone());
                         }
                     }
                 }
             }
         }
-        Err(NoNeighbor(neigh_mesh_ip.to_string()).into())
     }
+    Err(NoNeighbor(neigh_mesh_ip.to_string()).into())
+}
 
-    /// Checks if Babel has an installed route to the given destination
-    pub fn do_we_have_route(
-        &mut self,
-        mesh_ip: &IpAddr,
-        routes: &VecDeque<Route>,
-    ) -> Result<bool, Error> {
-        for route in routes.iter() {
-            if let IpNetwork::V6(ref ip) = route.prefix {
-                if ip.ip() == *mesh_ip && route.installed {
-                    return Ok(true);
-                }
+/// Checks if Babel has an installed route to the given destination
+pub fn do_we_have_route(mesh_ip: &IpAddr, routes: &VecDeque<Route>) -> bool {
+    for route in routes.iter() {
+        if let IpNetwork::V6(ref ip) = route.prefix {
+            if ip.ip() == *mesh_ip && route.installed {
+                return true;
             }
         }
-        Ok(false)
     }
+    false
 }
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -294,13 +295,13 @@ rtt 18.674 rttcost 473 cost 817\n\
 add neighbour 14f0488 address fe80::e914:2335:a76:bda3 if wlan0 reach feff rxcost 258 txcost 256 \
 rtt 22.805 rttcost 698 cost 956\n\
 add xroute 10.28.119.131/32-::/0 prefix 10.28.119.131/32 from ::/0 metric 0\n\
-add route 14f0820 prefix 10.28.7.7/32 from 0.0.0.0/0 installed yes id ba:27:eb:ff:fe:5b:fe:c7\
+add route 14f0820 prefix 10.28.7.7/32 from 0.0.0.0/0 installed yes id ba:27:eb:ff:fe:5b:fe:c7 \
 metric 1596 price 3072 fee 3072 refmetric 638 full-path-rtt 22.805 via fe80::e914:2335:a76:bda3 if wlan0\n\
-add route 14f07a0 prefix 10.28.7.7/32 from 0.0.0.0/0 installed no id ba:27:eb:ff:fe:5b:fe:c7\
+add route 14f07a0 prefix 10.28.7.7/32 from 0.0.0.0/0 installed no id ba:27:eb:ff:fe:5b:fe:c7 \
 metric 1569 price 5032 fee 5032 refmetric 752 full-path-rtt 42.805 via fe80::e9d0:498f:6c61:be29 if wlan0\n\
-add route 14f06d8 prefix 10.28.20.151/32 from 0.0.0.0/0 installed yes id ba:27:eb:ff:fe:c1:2d:d5\
+add route 14f06d8 prefix 10.28.20.151/32 from 0.0.0.0/0 installed yes id ba:27:eb:ff:fe:c1:2d:d5 \
 metric 817 price 4008 fee 4008 refmetric 0 full-path-rtt 18.674 via fe80::e9d0:498f:6c61:be29 if wlan0\n\
-add route 14f0548 prefix 10.28.244.138/32 from 0.0.0.0/0 installed yes id ba:27:eb:ff:fe:d1:3e:ba\
+add route 14f0548 prefix 10.28.244.138/32 from 0.0.0.0/0 installed yes id ba:27:eb:ff:fe:d1:3e:ba \
 metric 958 price 2048 fee 2048 refmetric 0 full-path-rtt 56.805 via fe80::e914:2335:a76:bda3 if wlan0\n\
 ok\n";
 
@@ -345,23 +346,21 @@ ok\n";
 
     #[test]
     fn line_parse() {
-        assert_eq!(find_babel_val("metric", XROUTE_LINE).unwrap(), "0");
-        assert_eq!(
-            find_babel_val("prefix", XROUTE_LINE).unwrap(),
-            "10.28.119.131/32"
-        );
-        assert_eq!(find_babel_val("route", ROUTE_LINE).unwrap(), "14f06d8");
-        assert_eq!(find_babel_val("if", ROUTE_LINE).unwrap(), "wlan0");
-        assert_eq!(
-            find_babel_val("via", ROUTE_LINE).unwrap(),
-            "fe80::e9d0:498f:6c61:be29"
-        );
-        assert_eq!(find_babel_val("reach", NEIGH_LINE).unwrap(), "ffff");
-        assert_eq!(find_babel_val("rxcost", NEIGH_LINE).unwrap(), "256");
-        assert_eq!(find_babel_val("rtt", NEIGH_LINE).unwrap(), "29.264");
-        assert_eq!(find_babel_val("interface", IFACE_LINE).unwrap(), "wlan0");
-        assert_eq!(find_babel_val("ipv4", IFACE_LINE).unwrap(), "10.28.119.131");
-        assert_eq!(find_babel_val("price", PRICE_LINE).unwrap(), "1024");
+        let xroute = Record::parse(XROUTE_LINE).unwrap();
+        assert_eq!(xroute.get("metric"), Some("0"));
+        assert_eq!(xroute.get("prefix"), Some("10.28.119.131/32"));
+        let route = Record::parse(ROUTE_LINE).unwrap();
+        assert_eq!(route.id, "14f06d8");
+        assert_eq!(route.get("if"), Some("wlan0"));
+        assert_eq!(route.get("via"), Some("fe80::e9d0:498f:6c61:be29"));
+        let neigh = Record::parse(NEIGH_LINE).unwrap();
+        assert_eq!(neigh.get("reach"), Some("ffff"));
+        assert_eq!(neigh.get("rxcost"), Some("256"));
+        assert_eq!(neigh.get("rtt"), Some("29.264"));
+        let iface = Record::parse(IFACE_LINE).unwrap();
+        assert_eq!(iface.id, "wlan0");
+        assert_eq!(iface.get("ipv4"), Some("10.28.119.131"));
+        assert_eq!(Record::parse(PRICE_LINE).unwrap().id, "1024");
     }
 
     #[test]
@@ -375,6 +374,8 @@ ok\n";
         let neigh = neigh.unwrap();
         assert_eq!(neighs.len(), 4);
         assert_eq!(neigh.id, "14f19a8");
+        assert_eq!(neigh.rtt, 26.723);
+        assert_eq!(neigh.rttcost, 912);
     }
 
     #[test]
@@ -399,6 +400,15 @@ ok\n";
         assert_eq!(b.local_fee().unwrap(), 1024);
     }
 
+    #[test]
+    fn route_via_neigh() {
+        let routes = routes_from_dump(TABLE).unwrap();
+        assert!(!do_we_have_route(&"::1".parse().unwrap(), &routes));
+        assert!(
+            get_route_via_neigh("::1".parse().unwrap(), "::2".parse().unwrap(), &routes).is_err()
+        );
+    }
+
     #[test]
     fn multiple_babel_outputs_in_stream() {
         let mut s = SharedMockStream::new();
@@ -427,4 +437,14 @@ ok\n";
         let mut b = Babel::new(s);
         b.command("interface wg0").unwrap();
     }
+
+    #[test]
+    fn mock_configure() {
+        let mut s = SharedMockStream::new();
+        s.push_bytes_to_read(b"ok\n");
+
+        let mut b = Babel::new(s.clone());
+        b.configure(&BabelConfig::LocalFee(50)).unwrap();
+        assert_eq!(s.pop_bytes_written(), b"fee 50\n");
+    }
 }
@@ -0,0 +1,666 @@
+//! BabelManager keeps a single long lived connection to the Babel local configuration interface.
+//! Commands from all callers are queued and written one at a time over that connection. Once
+//! connected the manager puts Babel into monitor mode and keeps a BabelTable up to date from the
+//! events Babel sends, routes, neighbors and the local fee are then answered from that table.
+//! Until the table is synced, or if Babel does not support monitor mode, they are answered from
+//! a dump instead and the output of the latest dump is cached so that callers within a short
+//! window share it. If the connection drops any queued commands are failed and it is reopened
+//! with an exponential backoff.
+
+use std::collections::VecDeque;
+use std::io;
+use std::mem;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use actix::fut;
+use actix::io::{FramedWrite, WriteHandler};
+use actix::prelude::*;
+use failure::Error;
+use futures::future;
+use futures::sync::oneshot;
+use futures::Future;
+use tokio::io::{AsyncRead, WriteHalf};
+use tokio::net::TcpStream;
+use tokio_codec::{FramedRead, LinesCodec};
+
+use super::{
+    BabelConfig, BabelMonitorError, BabelTable, Neighbor, RedistributeRule, Route, RttParams,
+};
+
+/// How long in milliseconds a dump is served from the cache before a new one is requested
+pub const DUMP_MAX_AGE_MILLIS: u64 = 1000;
+/// Initial delay in seconds before reconnecting to Babel
+const MIN_BACKOFF: u64 = 1;
+/// Longest delay in seconds between reconnection attempts
+const MAX_BACKOFF: u64 = 60;
+
+type Reply = oneshot::Sender<Result<String, BabelMonitorError>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ConnectionState {
+    Disconnected,
+    Connecting,
+    /// Connected but waiting for Babel to send its preamble
+    Preamble,
+    Connected,
+}
+
+struct PendingCommand {
+    command: String,
+    /// Everyone waiting on this command, dumps are only sent if none is already pending
+    replies: Vec<Reply>,
+}
+
+impl PendingCommand {
+    fn reply(self, result: Result<String, BabelMonitorError>) {
+        for reply in self.replies {
+            // the caller may have given up waiting
+            let _ = reply.send(result.clone());
+        }
+    }
+}
+
+struct DumpSnapshot {
+    output: String,
+    timestamp: Instant,
+}
+
+pub struct BabelManager {
+    addr: Option<SocketAddr>,
+    state: ConnectionState,
+    reader: Option<SpawnHandle>,
+    writer: Option<FramedWrite<WriteHalf<TcpStream>, LinesCodec>>,
+    queue: VecDeque<PendingCommand>,
+    in_flight: Option<PendingCommand>,
+    /// Lines received for the command in flight so far
+    output: String,
+    backoff: Duration,
+    dump: Option<DumpSnapshot>,
+    /// True once monitor mode has been requested on this connection
+    monitoring: bool,
+    /// True once the table holds a full copy of Babel's state
+    synced: bool,
+    table: BabelTable,
+}
+
+impl Actor for BabelManager {
+    type Context = Context<Self>;
+}
+
+impl Supervised for BabelManager {}
+impl SystemService for BabelManager {
+    fn service_started(&mut self, _ctx: &mut Context<Self>) {
+        info!("Babel manager started");
+    }
+}
+
+impl Default for BabelManager {
+    fn default() -> BabelManager {
+        BabelManager {
+            addr: None,
+            state: ConnectionState::Disconnected,
+            reader: None,
+            writer: None,
+            queue: VecDeque::new(),
+            in_flight: None,
+            output: String::new(),
+            backoff: Duration::from_secs(MIN_BACKOFF),
+            dump: None,
+            monitoring: false,
+            synced: false,
+            table: BabelTable::new(),
+        }
+    }
+}
+
+impl BabelManager {
+    fn connect(&mut self, ctx: &mut Context<Self>) {
+        let addr = match self.addr {
+            Some(addr) => addr,
+            None => return,
+        };
+        trace!("Connecting to Babel at {}", addr);
+        self.state = ConnectionState::Connecting;
+
+        ctx.spawn(
+            TcpStream::connect(&addr)
+                .into_actor(self)
+                .then(move |res, act, ctx| {
+                    match res {
+                        Ok(stream) => {
+                            let (read, write) = stream.split();
+                            act.reader =
+                                Some(ctx.add_stream(FramedRead::new(read, LinesCodec::new())));
+                            act.writer = Some(FramedWrite::new(write, LinesCodec::new(), ctx));
+                            act.state = ConnectionState::Preamble;
+                        }
+                        Err(e) => {
+                            warn!("Failed to connect to Babel at {} with {:?}", addr, e);
+                            act.disconnected(ctx);
+                        }
+                    }
+                    fut::ok(())
+                }),
+        );
+    }
+
+    /// Tears down the connection, fails everything waiting on it and schedules a reconnect
+    fn disconnected(&mut self, ctx: &mut Context<Self>) {
+        if self.state == ConnectionState::Disconnected {
+            return;
+        }
+        if let Some(reader) = self.reader.take() {
+            ctx.cancel_future(reader);
+        }
+        if let Some(mut writer) = self.writer.take() {
+            writer.close();
+        }
+        self.output.clear();
+        self.state = ConnectionState::Disconnected;
+        self.monitoring = false;
+        self.synced = false;
+        self.table = BabelTable::new();
+
+        if let Some(command) = self.in_flight.take() {
+            command.reply(Err(BabelMonitorError::NotConnected));
+        }
+        for command in self.queue.drain(..) {
+            command.reply(Err(BabelMonitorError::NotConnected));
+        }
+
+        let delay = self.backoff;
+        self.backoff = (self.backoff * 2).min(Duration::from_secs(MAX_BACKOFF));
+        warn!("Lost connection to Babel, reconnecting in {:?}", delay);
+        ctx.run_later(delay, |act, ctx| {
+            if act.state == ConnectionState::Disconnected {
+                act.connect(ctx);
+            }
+        });
+    }
+
+    /// Writes the next queued command if nothing is currently waiting on Babel
+    fn send_next(&mut self) {
+        if self.state != ConnectionState::Connected || self.in_flight.is_some() {
+            return;
+        }
+        if let Some(command) = self.queue.pop_front() {
+            trace!("Sending '{}' to babel", command.command);
+            if command.command == "monitor" {
+                self.monitoring = true;
+                self.table = BabelTable::new();
+            }
+            if let Some(ref mut writer) = self.writer {
+                writer.write(command.command.clone());
+            }
+            self.in_flight = Some(command);
+        }
+    }
+
+    fn complete(&mut self, result: Result<String, BabelMonitorError>) {
+        let command = match self.in_flight.take() {
+            Some(command) => command,
+            None => {
+                warn!("Unexpected output from Babel {:?}", result);
+                return;
+            }
+        };
+        let result = result.map_err(|e| {
+            error!("Babel command '{}' failed with {}", command.command, e);
+            BabelMonitorError::CommandFailed(command.command.clone(), e.to_string())
+        });
+
+        match result {
+            Ok(_) if command.command == "monitor" => {
+                info!("Babel table synced, following Babel in monitor mode");
+                self.synced = true;
+            }
+            Err(ref e) if command.command == "monitor" => {
+                warn!("Babel monitor mode failed with {}, falling back to dumps", e);
+                self.monitoring = false;
+                self.table = BabelTable::new();
+            }
+            Ok(ref output) if command.command == "dump" => {
+                self.dump = Some(DumpSnapshot {
+                    output: output.clone(),
+                    timestamp: Instant::now(),
+                });
+            }
+            // anything else may change what the next dump will contain
+            Ok(_) => self.dump = None,
+            Err(_) => {}
+        }
+        command.reply(result);
+    }
+
+    fn command(&mut self, command: String) -> Box<Future<Item = String, Error = Error>> {
+        let (tx, rx) = oneshot::channel();
+        if self.state == ConnectionState::Disconnected {
+            let _ = tx.send(Err(BabelMonitorError::NotConnected));
+        } else {
+            let mut tx = Some(tx);
+            if command == "dump" {
+                // the reply to monitor starts with a full dump
+                if let Some(pending) = self
+                    .in_flight
+                    .iter_mut()
+                    .chain(self.queue.iter_mut())
+                    .find(|c| c.command == "dump" || c.command == "monitor")
+                {
+                    pending.replies.extend(tx.take());
+                }
+            }
+            if let Some(tx) = tx {
+                self.queue.push_back(PendingCommand {
+                    command,
+                    replies: vec![tx],
+                });
+            }
+            self.send_next();
+        }
+
+        Box::new(rx.then(|res| match res {
+            Ok(Ok(output)) => Ok(output),
+            Ok(Err(e)) => Err(e.into()),
+            // the manager went away before answering
+            Err(_) => Err(BabelMonitorError::NotConnected.into()),
+        }))
+    }
+
+    /// Returns the cached dump if it is recent enough, otherwise requests a new one
+    fn dump(&mut self) -> Box<Future<Item = String, Error = Error>> {
+        if let Some(ref dump) = self.dump {
+            if dump.timestamp.elapsed() < Duration::from_millis(DUMP_MAX_AGE_MILLIS) {
+                return Box::new(future::ok(dump.output.clone()));
+            }
+        }
+        self.command("dump".to_string())
+    }
+
+    /// Returns a copy of the table if it is synced, otherwise builds one from a dump
+    fn table(&mut self) -> Box<Future<Item = BabelTable, Error = Error>> {
+        if self.synced {
+            return Box::new(future::ok(self.table.clone()));
+        }
+        Box::new(self.dump().and_then(|dump| BabelTable::from_dump(&dump)))
+    }
+}
+
+impl StreamHandler<String, io::Error> for BabelManager {
+    fn handle(&mut self, line: String, ctx: &mut Context<Self>) {
+        if self.monitoring {
+            if let Err(e) = self.table.apply(&line) {
+                warn!("Failed to apply '{}' to the Babel table with {}", line, e);
+            }
+        }
+        // in monitor mode events arrive while no command is waiting, they are not output
+        if self.state == ConnectionState::Preamble || self.in_flight.is_some() {
+            self.output.push_str(&line);
+            self.output.push_str("\n");
+        }
+        let result = match line.trim() {
+            "ok" => Ok(mem::replace(&mut self.output, String::new())),
+            "bad" | "no" => Err(BabelMonitorError::ReadFailed(mem::replace(
+                &mut self.output,
+                String::new(),
+            ))),
+            _ => return,
+        };
+
+        match self.state {
+            ConnectionState::Preamble => match result {
+                // Note you have changed the config interface, bump to 1.1 in babel
+                Ok(ref preamble) if preamble.contains("ALTHEA 0.1") => {
+                    info!("Attached OK to Babel with preamble: {}", preamble);
+                    self.state = ConnectionState::Connected;
+                    self.backoff = Duration::from_secs(MIN_BACKOFF);
+                    // dumps asked for while connecting are answered by the monitor reply
+                    let (dumps, queue): (Vec<PendingCommand>, VecDeque<PendingCommand>) = self
+                        .queue
+                        .drain(..)
+                        .partition(|c| c.command == "dump");
+                    let mut monitor = PendingCommand {
+                        command: "monitor".to_string(),
+                        replies: Vec::new(),
+                    };
+                    for dump in dumps {
+                        monitor.replies.extend(dump.replies);
+                    }
+                    self.queue = queue;
+                    self.queue.push_front(monitor);
+                    self.send_next();
+                }
+                Ok(preamble) | Err(BabelMonitorError::ReadFailed(preamble)) => {
+                    error!("{}", BabelMonitorError::InvalidPreamble(preamble));
+                    self.disconnected(ctx);
+                }
+                Err(_) => self.disconnected(ctx),
+            },
+            ConnectionState::Connected => {
+                self.complete(result);
+                self.send_next();
+            }
+            _ => {}
+        }
+    }
+
+    fn error(&mut self, err: io::Error, ctx: &mut Context<Self>) -> Running {
+        warn!("Error reading from Babel {:?}", err);
+        self.disconnected(ctx);
+        Running::Continue
+    }
+
+    fn finished(&mut self, ctx: &mut Context<Self>) {
+        self.disconnected(ctx);
+    }
+}
+
+impl WriteHandler<io::Error> for BabelManager {
+    fn error(&mut self, err: io::Error, ctx: &mut Context<Self>) -> Running {
+        warn!("Error writing to Babel {:?}", err);
+        self.disconnected(ctx);
+        Running::Continue
+    }
+
+    fn finished(&mut self, _ctx: &mut Context<Self>) {}
+}
+
+/// Sets the address of the Babel local interface and connects to it
+pub struct Connect(pub SocketAddr);
+
+impl Message for Connect {
+    type Result = ();
+}
+
+impl Handler<Connect> for BabelManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: Connect, ctx: &mut Context<Self>) -> Self::Result {
+        self.addr = Some(msg.0);
+        if self.state == ConnectionState::Disconnected {
+            self.connect(ctx);
+        }
+    }
+}
+
+pub struct GetTable;
+
+impl Message for GetTable {
+    type Result = Result<BabelTable, Error>;
+}
+
+impl Handler<GetTable> for BabelManager {
+    type Result = ResponseFuture<BabelTable, Error>;
+
+    fn handle(&mut self, _: GetTable, _: &mut Context<Self>) -> Self::Result {
+        self.table()
+    }
+}
+
+pub struct GetRoutes;
+
+impl Message for GetRoutes {
+    type Result = Result<VecDeque<Route>, Error>;
+}
+
+impl Handler<GetRoutes> for BabelManager {
+    type Result = ResponseFuture<VecDeque<Route>, Error>;
+
+    fn handle(&mut self, _: GetRoutes, _: &mut Context<Self>) -> Self::Result {
+        Box::new(self.table().map(|table| table.routes()))
+    }
+}
+
+pub struct GetNeighbors;
+
+impl Message for GetNeighbors {
+    type Result = Result<VecDeque<Neighbor>, Error>;
+}
+
+impl Handler<GetNeighbors> for BabelManager {
+    type Result = ResponseFuture<VecDeque<Neighbor>, Error>;
+
+    fn handle(&mut self, _: GetNeighbors, _: &mut Context<Self>) -> Self::Result {
+        Box::new(self.table().map(|table| table.neighbors()))
+    }
+}
+
+pub struct GetLocalFee;
+
+impl Message for GetLocalFee {
+    type Result = Result<u32, Error>;
+}
+
+impl Handler<GetLocalFee> for BabelManager {
+    type Result = ResponseFuture<u32, Error>;
+
+    fn handle(&mut self, _: GetLocalFee, _: &mut Context<Self>) -> Self::Result {
+        Box::new(self.table().and_then(|table| table.local_fee()))
+    }
+}
+
+/// Asks Babel to start monitoring an interface
+pub struct Monitor(pub String);
+
+impl Message for Monitor {
+    type Result = Result<(), Error>;
+}
+
+impl Handler<Monitor> for BabelManager {
+    type Result = ResponseFuture<(), Error>;
+
+    fn handle(&mut self, msg: Monitor, _: &mut Context<Self>) -> Self::Result {
+        let iface = msg.0;
+        Box::new(
+            self.command(format!("interface {} enable-timestamps true", iface))
+                .map(move |_| info!("Babel started monitoring: {}", iface)),
+        )
+    }
+}
+
+/// Asks Babel to stop monitoring an interface
+pub struct Unmonitor(pub String);
+
+impl Message for Unmonitor {
+    type Result = Result<(), Error>;
+}
+
+impl Handler<Unmonitor> for BabelManager {
+    type Result = ResponseFuture<(), Error>;
+
+    fn handle(&mut self, msg: Unmonitor, _: &mut Context<Self>) -> Self::Result {
+        let iface = msg.0;
+        Box::new(
+            self.command(format!("unmonitor {}", iface))
+                .map(move |_| info!("Babel stopped monitoring: {}", iface)),
+        )
+    }
+}
+
+/// Applies a configuration statement, it is validated before anything is sent to Babel
+pub struct Configure(pub BabelConfig);
+
+impl Message for Configure {
+    type Result = Result<(), Error>;
+}
+
+impl Handler<Configure> for BabelManager {
+    type Result = ResponseActFuture<Self, (), Error>;
+
+    fn handle(&mut self, msg: Configure, _: &mut Context<Self>) -> Self::Result {
+        let command = match msg.0.to_command() {
+            Ok(command) => command,
+            Err(e) => return Box::new(fut::err(e)),
+        };
+        let config = msg.0;
+        Box::new(fut::wrap_future(self.command(command)).map(
+            move |_, act: &mut Self, _| {
+                info!("Babel configured with {:?}", config);
+                // Babel only reports the fee in a dump, keep the table in step ourselves
+                if let BabelConfig::LocalFee(fee) = config {
+                    act.table.set_local_fee(fee);
+                }
+            },
+        ))
+    }
+}
+
+/// A consistent snapshot of everything Babel knows
+pub fn table() -> Box<Future<Item = BabelTable, Error = Error>> {
+    Box::new(
+        BabelManager::from_registry()
+            .send(GetTable)
+            .from_err()
+            .and_then(|res| res),
+    )
+}
+
+pub fn routes() -> Box<Future<Item = VecDeque<Route>, Error = Error>> {
+    Box::new(
+        BabelManager::from_registry()
+            .send(GetRoutes)
+            .from_err()
+            .and_then(|res| res),
+    )
+}
+
+pub fn neighbors() -> Box<Future<Item = VecDeque<Neighbor>, Error = Error>> {
+    Box::new(
+        BabelManager::from_registry()
+            .send(GetNeighbors)
+            .from_err()
+            .and_then(|res| res),
+    )
+}
+
+pub fn local_fee() -> Box<Future<Item = u32, Error = Error>> {
+    Box::new(
+        BabelManager::from_registry()
+            .send(GetLocalFee)
+            .from_err()
+            .and_then(|res| res),
+    )
+}
+
+pub fn monitor(iface: &str) -> Box<Future<Item = (), Error = Error>> {
+    Box::new(
+        BabelManager::from_registry()
+            .send(Monitor(iface.to_string()))
+            .from_err()
+            .and_then(|res| res),
+    )
+}
+
+pub fn unmonitor(iface: &str) -> Box<Future<Item = (), Error = Error>> {
+    Box::new(
+        BabelManager::from_registry()
+            .send(Unmonitor(iface.to_string()))
+            .from_err()
+            .and_then(|res| res),
+    )
+}
+
+pub fn configure(config: BabelConfig) -> Box<Future<Item = (), Error = Error>> {
+    Box::new(
+        BabelManager::from_registry()
+            .send(Configure(config))
+            .from_err()
+            .and_then(|res| res),
+    )
+}
+
+/// Sets the fee we charge for forwarding traffic
+pub fn set_local_fee(fee: u32) -> Box<Future<Item = (), Error = Error>> {
+    configure(BabelConfig::LocalFee(fee))
+}
+
+pub fn set_interface_cost(iface: &str, cost: u16) -> Box<Future<Item = (), Error = Error>> {
+    configure(BabelConfig::InterfaceCost(iface.to_string(), cost))
+}
+
+pub fn set_interface_rtt(iface: &str, params: RttParams) -> Box<Future<Item = (), Error = Error>> {
+    configure(BabelConfig::InterfaceRtt(iface.to_string(), params))
+}
+
+pub fn redistribute(rule: RedistributeRule) -> Box<Future<Item = (), Error = Error>> {
+    configure(BabelConfig::Redistribute(rule))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    static PREAMBLE: &'static str =
+        "ALTHEA 0.1\nversion babeld-1.8.0-24-g6335378\nhost raspberrypi\nmy-id \
+         ba:27:eb:ff:fe:09:06:dd\nok\n";
+
+    static DUMP: &'static str = "local fee 1024\n\
+add neighbour 14f05f0 address fe80::e9d0:498f:6c61:be29 if wlan0 reach ffff rxcost 256 txcost 256 \
+rtt 29.264 rttcost 1050 cost 1306\n\
+ok\n";
+
+    static EVENT: &'static str = "change neighbour 14f05f0 address fe80::e9d0:498f:6c61:be29 if \
+wlan0 reach ffff rxcost 256 txcost 256 rtt 29.264 rttcost 1050 cost 99\n";
+
+    /// Answers a single connection like babeld would, counting the dumps it is asked for and
+    /// hanging up after the first command it does not know. Monitor mode sends a single event.
+    fn fake_babel() -> (SocketAddr, thread::JoinHandle<usize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(PREAMBLE.as_bytes()).unwrap();
+            let mut dumps = 0;
+            for line in BufReader::new(stream.try_clone().unwrap()).lines() {
+                match line.unwrap().as_str() {
+                    "monitor" => {
+                        stream.write_all(DUMP.as_bytes()).unwrap();
+                        stream.write_all(EVENT.as_bytes()).unwrap();
+                    }
+                    "dump" => {
+                        dumps += 1;
+                        stream.write_all(DUMP.as_bytes()).unwrap();
+                    }
+                    _ => {
+                        stream.write_all(b"bad\n").unwrap();
+                        break;
+                    }
+                }
+            }
+            dumps
+        });
+        (addr, handle)
+    }
+
+    #[test]
+    fn test_monitor_table() {
+        let (addr, babel) = fake_babel();
+        let system = System::new("test");
+
+        BabelManager::from_registry().do_send(Connect(addr));
+        // asked before the table is synced, answered by the reply to monitor
+        Arbiter::spawn(
+            local_fee()
+                .join(neighbors())
+                .and_then(|(fee, neighs)| {
+                    assert_eq!(fee, 1024);
+                    assert_eq!(neighs.len(), 1);
+                    neighbors()
+                }).and_then(|neighs| {
+                    // answered from the table, which has seen the change event
+                    assert_eq!(neighs[0].cost, 99);
+                    monitor("wg0")
+                }).then(|res| {
+                    assert!(res.is_err());
+                    System::current().stop();
+                    Ok(())
+                }),
+        );
+        system.run();
+
+        assert_eq!(babel.join().unwrap(), 0);
+    }
+}
@@ -0,0 +1,353 @@
+//! Parser for the records Babel prints in dumps and in monitor mode. Every record is a single
+//! line of the form `<verb> <kind> <id> <key> <value> ...`, so a line is split into words once
+//! and values are then looked up by exact key.
+
+use std::error::Error as StdError;
+use std::str::FromStr;
+
+use failure::Error;
+
+use super::{BabelMonitorError, Interface, Neighbor, Route, XRoute};
+
+/// A single line of Babel output split into words
+#[derive(Debug)]
+pub struct Record<'a> {
+    pub verb: &'a str,
+    pub kind: &'a str,
+    pub id: &'a str,
+    fields: Vec<(&'a str, &'a str)>,
+    line: &'a str,
+}
+
+impl<'a> Record<'a> {
+    /// Returns None for lines that are too short to be a record, like `ok`
+    pub fn parse(line: &'a str) -> Option<Record<'a>> {
+        let mut words = line.split_whitespace();
+        let verb = words.next()?;
+        let kind = words.next()?;
+        let id = words.next()?;
+
+        let mut fields = Vec::new();
+        while let (Some(key), Some(value)) = (words.next(), words.next()) {
+            fields.push((key, value));
+        }
+
+        Some(Record {
+            verb,
+            kind,
+            id,
+            fields,
+            line,
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.fields
+            .iter()
+            .find(|&&(k, _)| k == key)
+            .map(|&(_, value)| value)
+    }
+
+    fn required(&self, key: &str) -> Result<&'a str, Error> {
+        match self.get(key) {
+            Some(value) => Ok(value),
+            None => Err(BabelMonitorError::VariableNotFound(
+                String::from(key),
+                String::from(self.line),
+            ).into()),
+        }
+    }
+
+    fn field<T>(&self, key: &str) -> Result<T, Error>
+    where
+        T: FromStr,
+        T::Err: StdError + Send + Sync + 'static,
+    {
+        Ok(self.required(key)?.parse()?)
+    }
+
+    fn optional<T>(&self, key: &str) -> Result<Option<T>, Error>
+    where
+        T: FromStr,
+        T::Err: StdError + Send + Sync + 'static,
+    {
+        match self.get(key) {
+            Some(value) => Ok(Some(value.parse()?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordKind {
+    Interface,
+    Neighbor,
+    Route,
+    XRoute,
+}
+
+/// A parsed line of Babel output
+#[derive(Debug, Clone)]
+pub enum Entry {
+    LocalFee(u32),
+    /// An added or changed record
+    Interface(Interface),
+    Neighbor(Neighbor),
+    Route(Route),
+    XRoute(XRoute),
+    /// A record that is gone, only the id is used
+    Flush(RecordKind, String),
+}
+
+/// Parses a line of Babel output, lines that are not records return None
+pub fn parse_entry(line: &str) -> Result<Option<Entry>, Error> {
+    let record = match Record::parse(line) {
+        Some(record) => record,
+        None => return Ok(None),
+    };
+
+    let kind = match record.kind {
+        "interface" => RecordKind::Interface,
+        "neighbour" => RecordKind::Neighbor,
+        "route" => RecordKind::Route,
+        "xroute" => RecordKind::XRoute,
+        "fee" if record.verb == "local" => return Ok(Some(Entry::LocalFee(record.id.parse()?))),
+        _ => return Ok(None),
+    };
+
+    let entry = match record.verb {
+        "add" | "change" => match kind {
+            RecordKind::Interface => Entry::Interface(parse_interface(&record)?),
+            RecordKind::Neighbor => Entry::Neighbor(parse_neighbor(&record)?),
+            RecordKind::Route => Entry::Route(parse_route(&record)?),
+            RecordKind::XRoute => Entry::XRoute(parse_xroute(&record)?),
+        },
+        "flush" => Entry::Flush(kind, record.id.to_string()),
+        _ => return Ok(None),
+    };
+    Ok(Some(entry))
+}
+
+fn parse_interface(record: &Record) -> Result<Interface, Error> {
+    // interfaces that are down carry no addresses
+    Ok(Interface {
+        name: record.id.to_string(),
+        up: record.field("up")?,
+        ipv6: record.optional("ipv6")?,
+        ipv4: record.optional("ipv4")?,
+    })
+}
+
+fn parse_neighbor(record: &Record) -> Result<Neighbor, Error> {
+    // rtt is only reported for neighbours that have timestamps enabled
+    Ok(Neighbor {
+        id: record.id.to_string(),
+        address: record.field("address")?,
+        iface: record.field("if")?,
+        reach: u16::from_str_radix(record.required("reach")?, 16)?,
+        txcost: record.field("txcost")?,
+        rxcost: record.field("rxcost")?,
+        rtt: record.optional("rtt")?.unwrap_or(0.0),
+        rttcost: record.optional("rttcost")?.unwrap_or(0),
+        cost: record.field("cost")?,
+    })
+}
+
+fn parse_route(record: &Record) -> Result<Route, Error> {
+    Ok(Route {
+        id: record.id.to_string(),
+        iface: record.field("if")?,
+        installed: record.required("installed")? == "yes",
+        neigh_ip: record.field("via")?,
+        prefix: record.field("prefix")?,
+        metric: record.field("metric")?,
+        refmetric: record.field("refmetric")?,
+        full_path_rtt: record.field("full-path-rtt")?,
+        price: record.field("price")?,
+        fee: record.field("fee")?,
+    })
+}
+
+fn parse_xroute(record: &Record) -> Result<XRoute, Error> {
+    Ok(XRoute {
+        id: record.id.to_string(),
+        prefix: record.field("prefix")?,
+        from: record.field("from")?,
+        metric: record.field("metric")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ipnetwork::IpNetwork;
+    use std::net::IpAddr;
+
+    /// Captured from a gateway node with a wired and a wireless mesh peer
+    static GATEWAY_DUMP: &'static str = "local fee 50\n\
+add interface wg0 up true ipv6 fe80::6bd3:e3fe:28d7:1ce6\n\
+add interface wg1 up true ipv6 fe80::4d4f:be5a:a9dc:ed4e\n\
+add interface wg2 up false\n\
+add neighbour 1ba1be0 address fe80::a48e:3e29:c5fa:73e5 if wg0 reach ffff ureach 0000 rxcost 96 \
+txcost 96 rtt 1.123 rttcost 0 cost 96\n\
+add neighbour 1ba1b00 address fe80::f5ee:3ac1:7a0c:cb1c if wg1 reach fff0 ureach 0000 rxcost 128 \
+txcost 256 cost 256\n\
+add xroute fd00::aabb/128-::/0 prefix fd00::aabb/128 from ::/0 metric 0\n\
+add route 1ba2380 prefix fd00::1/128 from ::/0 installed yes id 9a:dd:b8:ff:fe:38:47:73 metric 96 \
+price 10 fee 10 refmetric 0 full-path-rtt 1.123 via fe80::a48e:3e29:c5fa:73e5 if wg0\n\
+add route 1ba2420 prefix fd00::2/128 from ::/0 installed no id f2:ac:c3:ff:fe:0d:7f:66 metric 352 \
+price 25 fee 15 refmetric 256 full-path-rtt 40.5 via fe80::a48e:3e29:c5fa:73e5 if wg0\n\
+add route 1ba2500 prefix fd00::2/128 from ::/0 installed yes id f2:ac:c3:ff:fe:0d:7f:66 metric 256 \
+price 15 fee 15 refmetric 0 full-path-rtt 12.6 via fe80::f5ee:3ac1:7a0c:cb1c if wg1\n\
+ok\n";
+
+    /// Captured from an exit, which redistributes its ipv4 client range
+    static EXIT_DUMP: &'static str = "local fee 0\n\
+add interface wg0 up true ipv6 fe80::d2c9:dbb7:6af1:5cb5 ipv4 172.168.1.254\n\
+add neighbour 2034c90 address fe80::a48e:3e29:c5fa:73e5 if wg0 reach ffff ureach 0000 rxcost 96 \
+txcost 96 rtt 23.512 rttcost 312 cost 408\n\
+add xroute 172.168.0.0/16-0.0.0.0/0 prefix 172.168.0.0/16 from 0.0.0.0/0 metric 0\n\
+add xroute fd00::1337/128-::/0 prefix fd00::1337/128 from ::/0 metric 0\n\
+add route 2035a00 prefix fd00::aabb/128 from ::/0 installed yes id 6a:18:ef:ff:fe:22:d1:85 \
+metric 408 price 50 fee 50 refmetric 0 full-path-rtt 23.512 via fe80::a48e:3e29:c5fa:73e5 if wg0\n\
+ok\n";
+
+    fn parse_dump(dump: &str) -> Vec<Entry> {
+        dump.lines()
+            .filter_map(|line| parse_entry(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_exact_keys() {
+        let record = Record::parse(
+            "add route 1ba2380 prefix fd00::1/128 from ::/0 installed yes id \
+             9a:dd:b8:ff:fe:38:47:73 metric 96 price 10 fee 7 refmetric 0 full-path-rtt 1.123 \
+             via fe80::a48e:3e29:c5fa:73e5 if wg0",
+        ).unwrap();
+        assert_eq!(record.verb, "add");
+        assert_eq!(record.kind, "route");
+        assert_eq!(record.id, "1ba2380");
+        assert_eq!(record.get("fee"), Some("7"));
+        assert_eq!(record.get("metric"), Some("96"));
+        assert_eq!(record.get("refmetric"), Some("0"));
+        assert_eq!(record.get("id"), Some("9a:dd:b8:ff:fe:38:47:73"));
+        assert_eq!(record.get("rtt"), None);
+        assert_eq!(record.get("full-path"), None);
+
+        assert!(Record::parse("ok").is_none());
+    }
+
+    #[test]
+    fn test_missing_key() {
+        let line = "add neighbour 1ba1be0 address fe80::1 if wg0 reach ffff";
+        let err = parse_entry(line).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "variable 'txcost' not found in 'add neighbour 1ba1be0 address fe80::1 if wg0 reach \
+             ffff'"
+        );
+    }
+
+    #[test]
+    fn test_gateway_dump() {
+        let entries = parse_dump(GATEWAY_DUMP);
+        assert_eq!(entries.len(), 10);
+
+        match entries[0] {
+            Entry::LocalFee(fee) => assert_eq!(fee, 50),
+            ref e => panic!("Unexpected entry {:?}", e),
+        }
+        match entries[3] {
+            Entry::Interface(ref iface) => {
+                assert_eq!(iface.name, "wg2");
+                assert!(!iface.up);
+                assert!(iface.ipv6.is_none());
+            }
+            ref e => panic!("Unexpected entry {:?}", e),
+        }
+        match entries[4] {
+            Entry::Neighbor(ref neigh) => {
+                assert_eq!(neigh.iface, "wg0");
+                assert_eq!(neigh.reach, 0xffff);
+                assert_eq!(neigh.rtt, 1.123);
+                assert_eq!(neigh.cost, 96);
+            }
+            ref e => panic!("Unexpected entry {:?}", e),
+        }
+        match entries[5] {
+            Entry::Neighbor(ref neigh) => {
+                assert_eq!(neigh.rtt, 0.0);
+                assert_eq!(neigh.rttcost, 0);
+                assert_eq!(neigh.txcost, 256);
+            }
+            ref e => panic!("Unexpected entry {:?}", e),
+        }
+        match entries[7] {
+            Entry::Route(ref route) => {
+                assert_eq!(route.id, "1ba2380");
+                assert!(route.installed);
+                assert_eq!(route.price, 10);
+                assert_eq!(route.full_path_rtt, 1.123);
+            }
+            ref e => panic!("Unexpected entry {:?}", e),
+        }
+        match entries[8] {
+            Entry::Route(ref route) => {
+                assert!(!route.installed);
+                assert_eq!(route.price, 25);
+                assert_eq!(route.fee, 15);
+            }
+            ref e => panic!("Unexpected entry {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_exit_dump() {
+        let entries = parse_dump(EXIT_DUMP);
+        assert_eq!(entries.len(), 6);
+
+        match entries[1] {
+            Entry::Interface(ref iface) => {
+                assert!(iface.up);
+                assert_eq!(iface.ipv4, Some("172.168.1.254".parse::<IpAddr>().unwrap()));
+            }
+            ref e => panic!("Unexpected entry {:?}", e),
+        }
+        match entries[2] {
+            Entry::Neighbor(ref neigh) => {
+                assert_eq!(neigh.rtt, 23.512);
+                assert_eq!(neigh.rttcost, 312);
+            }
+            ref e => panic!("Unexpected entry {:?}", e),
+        }
+        match entries[3] {
+            Entry::XRoute(ref xroute) => {
+                assert_eq!(xroute.id, "172.168.0.0/16-0.0.0.0/0");
+                assert_eq!(xroute.prefix, "172.168.0.0/16".parse::<IpNetwork>().unwrap());
+                assert_eq!(xroute.from, "0.0.0.0/0".parse::<IpNetwork>().unwrap());
+                assert_eq!(xroute.metric, 0);
+            }
+            ref e => panic!("Unexpected entry {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_flush() {
+        match parse_entry("flush interface wg2").unwrap() {
+            Some(Entry::Flush(RecordKind::Interface, ref id)) => assert_eq!(id, "wg2"),
+            e => panic!("Unexpected entry {:?}", e),
+        }
+        match parse_entry(
+            "flush route 1ba2420 prefix fd00::2/128 from ::/0 installed no id \
+             f2:ac:c3:ff:fe:0d:7f:66 metric 65535 price 25 fee 15 refmetric 256 full-path-rtt \
+             40.5 via fe80::a48e:3e29:c5fa:73e5 if wg0",
+        ).unwrap()
+        {
+            Some(Entry::Flush(RecordKind::Route, ref id)) => assert_eq!(id, "1ba2420"),
+            e => panic!("Unexpected entry {:?}", e),
+        }
+        assert!(parse_entry("local price 10").unwrap().is_none());
+        assert!(parse_entry("version babeld-1.8.0-24-g6335378").unwrap().is_none());
+    }
+}
@@ -0,0 +1,162 @@
+//! BabelTable is an in memory copy of Babel's state, it is built from the `add`, `change` and
+//! `flush` events Babel sends in monitor mode, the same records that make up a dump.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use failure::Error;
+
+use super::{
+    parse_entry, BabelMonitorError, Entry, Interface, Neighbor, RecordKind, Route, XRoute,
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct BabelTable {
+    local_fee: Option<u32>,
+    interfaces: BTreeMap<String, Interface>,
+    neighbors: BTreeMap<String, Neighbor>,
+    routes: BTreeMap<String, Route>,
+    xroutes: BTreeMap<String, XRoute>,
+}
+
+impl BabelTable {
+    pub fn new() -> BabelTable {
+        BabelTable::default()
+    }
+
+    /// Builds a table from the output of a Babel dump
+    pub fn from_dump(babel_output: &str) -> Result<BabelTable, Error> {
+        let mut table = BabelTable::new();
+        for line in babel_output.lines() {
+            table.apply(line)?;
+        }
+        Ok(table)
+    }
+
+    /// Applies a single line of Babel output, returns false if the line was not a table update
+    pub fn apply(&mut self, line: &str) -> Result<bool, Error> {
+        let entry = match parse_entry(line)? {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+
+        match entry {
+            Entry::LocalFee(fee) => self.local_fee = Some(fee),
+            Entry::Interface(iface) => {
+                self.interfaces.insert(iface.name.clone(), iface);
+            }
+            Entry::Neighbor(neigh) => {
+                self.neighbors.insert(neigh.id.clone(), neigh);
+            }
+            Entry::Route(route) => {
+                self.routes.insert(route.id.clone(), route);
+            }
+            Entry::XRoute(xroute) => {
+                self.xroutes.insert(xroute.id.clone(), xroute);
+            }
+            Entry::Flush(RecordKind::Interface, id) => {
+                self.interfaces.remove(&id);
+            }
+            Entry::Flush(RecordKind::Neighbor, id) => {
+                self.neighbors.remove(&id);
+            }
+            Entry::Flush(RecordKind::Route, id) => {
+                self.routes.remove(&id);
+            }
+            Entry::Flush(RecordKind::XRoute, id) => {
+                self.xroutes.remove(&id);
+            }
+        }
+        trace!("Applied '{}' to the Babel table", line);
+        Ok(true)
+    }
+
+    /// Records a fee we have just configured, so the table does not wait on Babel to report it
+    pub fn set_local_fee(&mut self, fee: u32) {
+        self.local_fee = Some(fee);
+    }
+
+    pub fn local_fee(&self) -> Result<u32, Error> {
+        match self.local_fee {
+            Some(fee) => Ok(fee),
+            None => Err(BabelMonitorError::LocalFeeNotFound(String::from("<Babel table>")).into()),
+        }
+    }
+
+    pub fn interfaces(&self) -> VecDeque<Interface> {
+        self.interfaces.values().cloned().collect()
+    }
+
+    pub fn neighbors(&self) -> VecDeque<Neighbor> {
+        self.neighbors.values().cloned().collect()
+    }
+
+    pub fn routes(&self) -> VecDeque<Route> {
+        self.routes.values().cloned().collect()
+    }
+
+    pub fn xroutes(&self) -> VecDeque<XRoute> {
+        self.xroutes.values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static DUMP: &'static str = "local fee 1024\n\
+add interface wlan0 up true ipv6 fe80::1a8b:ec1:8542:1bd8 ipv4 10.28.119.131\n\
+add interface wg0 up false\n\
+add neighbour 14f05f0 address fe80::e9d0:498f:6c61:be29 if wlan0 reach ffff rxcost 256 txcost 256 \
+rtt 29.264 rttcost 1050 cost 1306\n\
+add xroute 10.28.119.131/32-::/0 prefix 10.28.119.131/32 from ::/0 metric 0\n\
+add route 14f06d8 prefix 10.28.20.151/32 from 0.0.0.0/0 installed yes id ba:27:eb:ff:fe:c1:2d:d5 \
+metric 817 price 4008 fee 4008 refmetric 0 full-path-rtt 18.674 via fe80::e9d0:498f:6c61:be29 \
+if wlan0\n\
+ok\n";
+
+    #[test]
+    fn test_from_dump() {
+        let table = BabelTable::from_dump(DUMP).unwrap();
+        assert_eq!(table.local_fee().unwrap(), 1024);
+        assert_eq!(table.interfaces().len(), 2);
+        assert_eq!(table.neighbors().len(), 1);
+        assert_eq!(table.routes().len(), 1);
+        assert_eq!(table.xroutes().len(), 1);
+
+        let down = table.interfaces().into_iter().find(|i| i.name == "wg0").unwrap();
+        assert!(!down.up);
+        assert!(down.ipv6.is_none());
+    }
+
+    #[test]
+    fn test_events() {
+        let mut table = BabelTable::from_dump(DUMP).unwrap();
+
+        assert!(
+            table
+                .apply(
+                    "change route 14f06d8 prefix 10.28.20.151/32 from 0.0.0.0/0 installed no id \
+                     ba:27:eb:ff:fe:c1:2d:d5 metric 65535 price 4008 fee 4008 refmetric 0 \
+                     full-path-rtt 18.674 via fe80::e9d0:498f:6c61:be29 if wlan0"
+                ).unwrap()
+        );
+        let route = table.routes().pop_front().unwrap();
+        assert!(!route.installed);
+        assert_eq!(route.metric, 65535);
+
+        assert!(
+            table
+                .apply(
+                    "flush neighbour 14f05f0 address fe80::e9d0:498f:6c61:be29 if wlan0 reach \
+                     0000 rxcost 65535 txcost 65535 rtt 0.000 rttcost 0 cost 65535"
+                ).unwrap()
+        );
+        assert!(table.neighbors().is_empty());
+
+        assert!(table.apply("local fee 50").unwrap());
+        assert_eq!(table.local_fee().unwrap(), 50);
+
+        assert!(!table.apply("ok").unwrap());
+        assert!(!table.apply("version babeld-1.8.0").unwrap());
+    }
+}
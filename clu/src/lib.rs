@@ -10,7 +10,10 @@ extern crate lazy_static;
 use std::net::IpAddr;
 
 extern crate settings;
-use settings::RitaCommonSettings;
+use settings::{PaymentSettings, RitaCommonSettings};
+
+extern crate web3;
+use web3::{generate_key, key_address};
 
 extern crate ipgen;
 extern crate rand;
@@ -29,6 +32,7 @@ use std::path::Path;
 use std::sync::{Arc, RwLock};
 
 extern crate althea_types;
+use althea_types::EthAddress;
 extern crate regex;
 
 #[derive(Debug, Fail)]
@@ -57,6 +61,41 @@ pub fn validate_mesh_ip(ip: &IpAddr) -> bool {
     ip.is_ipv6() && !ip.is_unspecified()
 }
 
+/// True for the placeholder addresses in the defaults, which nobody holds the key to
+fn is_default_eth_address(address: &EthAddress) -> bool {
+    let default_toml: EthAddress = [1u8; 20].into();
+    let default_struct: EthAddress = 1.into();
+    address.is_zero() || *address == default_toml || *address == default_struct
+}
+
+/// Generates an eth key if the address is still unset. A configured address is never replaced,
+/// that would silently change our identity and orphan our debts and DAO memberships, if it
+/// doesn't match the key nothing will be signed with the key until it's fixed.
+fn ensure_eth_key(payment_settings: &mut PaymentSettings) -> Result<(), Error> {
+    match payment_settings.eth_private_key {
+        Some(key) => {
+            let address = key_address(&key)?;
+            if payment_settings.eth_address != address {
+                error!(
+                    "Eth address {:?} doesn't match our key's address {:?}, refusing to sign",
+                    payment_settings.eth_address, address
+                );
+            }
+        }
+        None if is_default_eth_address(&payment_settings.eth_address) => {
+            info!("There's no eth address configured, generating a key");
+            let key = generate_key();
+            payment_settings.eth_private_key = Some(key);
+            payment_settings.eth_address = key_address(&key)?;
+        }
+        None => warn!(
+            "There's no key for eth address {:?}, transactions can't be signed",
+            payment_settings.eth_address
+        ),
+    }
+    Ok(())
+}
+
 /// Called before anything is started to delete existing wireguard per hop tunnels
 pub fn cleanup() -> Result<(), Error> {
     debug!("Cleaning up WireGuard tunnels");
@@ -84,7 +123,7 @@ pub fn cleanup() -> Result<(), Error> {
 
 fn linux_init(config: Arc<RwLock<settings::RitaSettingsStruct>>) -> Result<(), Error> {
     cleanup()?;
-    KI.restore_default_route(&mut config.get_network_mut().default_route)?;
+    KI.restore_default_route()?;
 
     let mut network_settings = config.get_network_mut();
     let privkey = network_settings.wg_private_key.clone();
@@ -146,6 +185,10 @@ fn linux_init(config: Arc<RwLock<settings::RitaSettingsStruct>>) -> Result<(), E
         &Path::new(&network_settings.wg_private_key_path),
         &network_settings.wg_private_key,
     )?;
+    drop(network_settings);
+
+    let mut payment_settings = config.get_payment_mut();
+    ensure_eth_key(&mut payment_settings)?;
 
     Ok(())
 }
@@ -209,6 +252,10 @@ fn linux_exit_init(config: Arc<RwLock<settings::RitaExitSettingsStruct>>) -> Res
         &Path::new(&network_settings.wg_private_key_path),
         &network_settings.wg_private_key,
     )?;
+    drop(network_settings);
+
+    let mut payment_settings = config.get_payment_mut();
+    ensure_eth_key(&mut payment_settings)?;
 
     Ok(())
 }
@@ -260,6 +307,31 @@ mod tests {
         assert_eq!(validate_wg_key(&keypair.private), true);
     }
 
+    #[test]
+    fn test_ensure_eth_key() {
+        let mut payment_settings = PaymentSettings::default();
+        ensure_eth_key(&mut payment_settings).unwrap();
+        let key = payment_settings.eth_private_key.unwrap();
+        assert_eq!(payment_settings.eth_address, key_address(&key).unwrap());
+
+        ensure_eth_key(&mut payment_settings).unwrap();
+        assert_eq!(payment_settings.eth_private_key, Some(key));
+    }
+
+    #[test]
+    fn test_ensure_eth_key_keeps_address() {
+        let address: EthAddress = 42.into();
+        let mut payment_settings = PaymentSettings::default();
+        payment_settings.eth_address = address;
+        ensure_eth_key(&mut payment_settings).unwrap();
+        assert_eq!(payment_settings.eth_private_key, None);
+        assert_eq!(payment_settings.eth_address, address);
+
+        payment_settings.eth_private_key = Some(generate_key());
+        ensure_eth_key(&mut payment_settings).unwrap();
+        assert_eq!(payment_settings.eth_address, address);
+    }
+
     #[test]
     fn test_validate_mesh_ip() {
         let good_ip = "fd44:94c:41e2::9e6".parse::<IpAddr>().unwrap();
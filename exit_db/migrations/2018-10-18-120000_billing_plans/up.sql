@@ -0,0 +1,17 @@
+CREATE TABLE plans
+(   name VARCHAR NOT NULL PRIMARY KEY,
+    plan_type VARCHAR NOT NULL,
+    quota_bytes BIGINT DEFAULT 0 NOT NULL,
+    period INTEGER DEFAULT 2592000 NOT NULL,
+    price BIGINT DEFAULT 0 NOT NULL,
+    overage VARCHAR DEFAULT "bill" NOT NULL,
+    throttle_kbps INTEGER DEFAULT 0 NOT NULL,
+    description VARCHAR DEFAULT "" NOT NULL
+);
+
+ALTER TABLE clients
+  ADD plan VARCHAR DEFAULT "" NOT NULL;
+ALTER TABLE clients
+  ADD period_start BIGINT DEFAULT 0 NOT NULL;
+ALTER TABLE clients
+  ADD period_bytes BIGINT DEFAULT 0 NOT NULL;
@@ -1,4 +1,4 @@
-use schema::clients;
+use schema::{clients, plans};
 
 #[derive(Queryable, Serialize, Deserialize, Debug, Insertable, Clone, AsChangeset, Default)]
 #[table_name = "clients"]
@@ -13,4 +13,26 @@ pub struct Client {
     pub verified: bool,
     // TODO change before 2038; it's left that way because diesel cannot do `Insertable` for i64
     pub email_sent_time: i32,
+    /// Name of the client's billing plan, empty for paying per byte
+    pub plan: String,
+    pub period_start: i64,
+    /// Bytes used since `period_start`
+    pub period_bytes: i64,
+}
+
+#[derive(Queryable, Serialize, Deserialize, Debug, Insertable, Clone, AsChangeset)]
+#[table_name = "plans"]
+pub struct Plan {
+    pub name: String,
+    /// "per_byte", "flat" or "quota"
+    pub plan_type: String,
+    pub quota_bytes: i64,
+    /// In seconds
+    pub period: i32,
+    /// Wei per period
+    pub price: i64,
+    /// "bill" or "throttle"
+    pub overage: String,
+    pub throttle_kbps: i32,
+    pub description: String,
 }
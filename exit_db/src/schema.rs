@@ -9,5 +9,23 @@ table! {
         email_code -> Text,
         verified -> Bool,
         email_sent_time -> Integer,
+        plan -> Text,
+        period_start -> BigInt,
+        period_bytes -> BigInt,
     }
 }
+
+table! {
+    plans (name) {
+        name -> Text,
+        plan_type -> Text,
+        quota_bytes -> BigInt,
+        period -> Integer,
+        price -> BigInt,
+        overage -> Text,
+        throttle_kbps -> Integer,
+        description -> Text,
+    }
+}
+
+allow_tables_to_appear_in_same_query!(clients, plans,);
@@ -0,0 +1,195 @@
+//! Conversions and arithmetic that can't panic. The operator impls in the crate root panic on
+//! overflow, anything that handles amounts computed from untrusted counters or peers should use
+//! the `checked_`, `saturating_` or `try_` versions here instead.
+
+use num::bigint::BigInt;
+use num::pow;
+use num::traits::Signed;
+use num::ToPrimitive;
+use std::error;
+use std::fmt;
+
+use super::units::uint_to_bigint;
+use super::{Int256, Uint256};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConversionError {
+    /// Unsigned numbers can't hold negative amounts
+    Negative(Int256),
+    /// The value needs more bits than the target type has
+    Overflow,
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConversionError::Negative(ref n) => write!(f, "{} is negative", n),
+            ConversionError::Overflow => write!(f, "value doesn't fit in the target type"),
+        }
+    }
+}
+
+impl error::Error for ConversionError {
+    fn description(&self) -> &str {
+        "invalid numeric conversion"
+    }
+}
+
+fn clamp(value: BigInt) -> Int256 {
+    if value.bits() <= 255 {
+        Int256(value)
+    } else if value.is_negative() {
+        Int256::min_value()
+    } else {
+        Int256::max_value()
+    }
+}
+
+impl Int256 {
+    /// 2^255 - 1, the largest value the operators accept
+    pub fn max_value() -> Int256 {
+        Int256(pow(BigInt::from(2), 255) - BigInt::from(1))
+    }
+
+    pub fn min_value() -> Int256 {
+        -Int256::max_value()
+    }
+
+    pub fn try_from_uint(n: &Uint256) -> Result<Int256, ConversionError> {
+        let value = uint_to_bigint(n);
+        if value.bits() > 255 {
+            Err(ConversionError::Overflow)
+        } else {
+            Ok(Int256(value))
+        }
+    }
+
+    pub fn checked_from_uint(n: &Uint256) -> Option<Int256> {
+        Int256::try_from_uint(n).ok()
+    }
+
+    pub fn saturating_from_uint(n: &Uint256) -> Int256 {
+        clamp(uint_to_bigint(n))
+    }
+
+    pub fn try_to_uint(&self) -> Result<Uint256, ConversionError> {
+        if self.0.is_negative() {
+            Err(ConversionError::Negative(self.clone()))
+        } else {
+            Ok(self.clone().into())
+        }
+    }
+
+    pub fn checked_uint256(&self) -> Option<Uint256> {
+        self.try_to_uint().ok()
+    }
+
+    /// Negative amounts become zero
+    pub fn saturating_uint256(&self) -> Uint256 {
+        self.try_to_uint().unwrap_or_else(|_| Uint256::zero())
+    }
+
+    pub fn checked_i64(&self) -> Option<i64> {
+        self.0.to_i64()
+    }
+
+    pub fn checked_u64(&self) -> Option<u64> {
+        self.0.to_u64()
+    }
+
+    pub fn saturating_i64(&self) -> i64 {
+        match self.0.to_i64() {
+            Some(n) => n,
+            None if self.0.is_negative() => i64::min_value(),
+            None => i64::max_value(),
+        }
+    }
+
+    pub fn saturating_add(&self, v: &Int256) -> Int256 {
+        clamp(self.0.clone() + v.0.clone())
+    }
+
+    pub fn saturating_sub(&self, v: &Int256) -> Int256 {
+        clamp(self.0.clone() - v.0.clone())
+    }
+
+    pub fn saturating_mul(&self, v: &Int256) -> Int256 {
+        clamp(self.0.clone() * v.0.clone())
+    }
+
+    /// The price of `bytes` at `price` per byte, None only if the product overflows
+    pub fn checked_mul_u64(&self, bytes: u64) -> Option<Int256> {
+        let value = self.0.clone() * BigInt::from(bytes);
+        if value.bits() > 255 {
+            None
+        } else {
+            Some(Int256(value))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num::traits::ops::checked::CheckedAdd;
+
+    #[test]
+    fn test_uint_conversions() {
+        let max = Uint256::from_big_endian(&[255u8; 32]);
+        assert_eq!(Int256::try_from_uint(&max), Err(ConversionError::Overflow));
+        assert_eq!(Int256::checked_from_uint(&max), None);
+        assert_eq!(Int256::saturating_from_uint(&max), Int256::max_value());
+        assert_eq!(
+            Int256::try_from_uint(&Uint256::from(7u64)),
+            Ok(Int256::from(7))
+        );
+
+        assert_eq!(
+            Int256::from(-5).try_to_uint(),
+            Err(ConversionError::Negative(Int256::from(-5)))
+        );
+        assert_eq!(Int256::from(-5).checked_uint256(), None);
+        assert_eq!(Int256::from(-5).saturating_uint256(), Uint256::zero());
+        assert_eq!(Int256::from(5).try_to_uint(), Ok(Uint256::from(5u64)));
+        let biggest: Uint256 = Int256::max_value().try_to_uint().unwrap();
+        assert_eq!(Int256::try_from_uint(&biggest), Ok(Int256::max_value()));
+    }
+
+    #[test]
+    fn test_primitive_conversions() {
+        let big = Int256::from(i64::max_value()) * 2;
+        assert_eq!(big.checked_i64(), None);
+        assert_eq!(big.saturating_i64(), i64::max_value());
+        assert_eq!((-big).saturating_i64(), i64::min_value());
+        assert_eq!(Int256::from(-5).checked_i64(), Some(-5));
+        assert_eq!(Int256::from(-5).checked_u64(), None);
+        assert_eq!(Int256::from(u64::max_value()).checked_u64(), Some(u64::max_value()));
+    }
+
+    #[test]
+    fn test_saturating_arithmetic() {
+        let max = Int256::max_value();
+        let min = Int256::min_value();
+        assert_eq!(max.checked_add(&Int256::from(1)), None);
+        assert_eq!(max.saturating_add(&Int256::from(1)), max);
+        assert_eq!(min.saturating_sub(&Int256::from(1)), min);
+        assert_eq!(max.saturating_mul(&Int256::from(-2)), min);
+        assert_eq!(min.saturating_mul(&Int256::from(-2)), max);
+        assert_eq!(
+            Int256::from(3).saturating_sub(&Int256::from(5)),
+            Int256::from(-2)
+        );
+    }
+
+    #[test]
+    fn test_checked_mul_u64() {
+        // the largest counter at the largest price is nowhere near overflowing
+        let price = Int256::from(u64::max_value());
+        let expected = Int256::from(u64::max_value()) * Int256::from(u64::max_value());
+        assert_eq!(price.checked_mul_u64(u64::max_value()), Some(expected));
+        assert_eq!(Int256::max_value().checked_mul_u64(1), Some(Int256::max_value()));
+        assert_eq!(Int256::max_value().checked_mul_u64(2), None);
+        assert_eq!(Int256::min_value().checked_mul_u64(2), None);
+        assert_eq!(Int256::from(-3).checked_mul_u64(0), Some(Int256::zero()));
+    }
+}
@@ -22,6 +22,12 @@ extern crate serde_derive;
 #[macro_use]
 extern crate lazy_static;
 
+mod convert;
+mod units;
+
+pub use convert::ConversionError;
+pub use units::{ether, gwei, uint_from_decimal, uint_to_decimal, ParseAmountError, Unit};
+
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Int256(BigInt);
 
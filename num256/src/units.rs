@@ -0,0 +1,286 @@
+//! Decimal formatting and parsing of amounts in the usual Ethereum units. Amounts are always kept
+//! in wei, units only matter when an amount is shown to or entered by a person.
+
+use num::bigint::{BigInt, BigUint, Sign};
+use num::pow;
+use num::traits::Signed;
+use num::Integer;
+use num::Zero;
+use std::error;
+use std::fmt;
+
+use super::{Int256, Uint256};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Wei,
+    Gwei,
+    Ether,
+}
+
+impl Unit {
+    pub fn decimals(self) -> usize {
+        match self {
+            Unit::Wei => 0,
+            Unit::Gwei => 9,
+            Unit::Ether => 18,
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Unit::Wei => "wei",
+            Unit::Gwei => "gwei",
+            Unit::Ether => "ETH",
+        }
+    }
+
+    fn scale(self) -> BigInt {
+        pow(BigInt::from(10), self.decimals())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseAmountError {
+    Invalid(String),
+    /// More decimal places than the unit can be divided into
+    TooPrecise(String, Unit),
+    Negative(String),
+    Overflow(String),
+}
+
+impl fmt::Display for ParseAmountError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseAmountError::Invalid(ref s) => write!(f, "'{}' is not a decimal amount", s),
+            ParseAmountError::TooPrecise(ref s, unit) => write!(
+                f,
+                "'{}' has more than {} decimal places for {}",
+                s,
+                unit.decimals(),
+                unit.symbol()
+            ),
+            ParseAmountError::Negative(ref s) => write!(f, "'{}' can't be negative", s),
+            ParseAmountError::Overflow(ref s) => write!(f, "'{}' is too large", s),
+        }
+    }
+}
+
+impl error::Error for ParseAmountError {
+    fn description(&self) -> &str {
+        "invalid amount"
+    }
+}
+
+fn format_decimal(value: &BigInt, unit: Unit) -> String {
+    let sign = if value.is_negative() { "-" } else { "" };
+    let (whole, frac) = value.abs().div_rem(&unit.scale());
+    if frac.is_zero() {
+        return format!("{}{}", sign, whole);
+    }
+    let frac = format!("{:0>width$}", frac, width = unit.decimals());
+    format!("{}{}.{}", sign, whole, frac.trim_right_matches('0'))
+}
+
+fn parse_decimal(s: &str, unit: Unit) -> Result<BigInt, ParseAmountError> {
+    let invalid = || ParseAmountError::Invalid(s.to_string());
+    let trimmed = s.trim();
+    let (negative, digits) = if trimmed.starts_with('-') {
+        (true, &trimmed[1..])
+    } else {
+        (false, trimmed)
+    };
+    let mut parts = digits.splitn(2, '.');
+    let whole = parts.next().unwrap_or("");
+    let frac = parts.next().unwrap_or("");
+    let all_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+    if (whole.is_empty() && frac.is_empty()) || !all_digits(whole) || !all_digits(frac) {
+        return Err(invalid());
+    }
+
+    let frac = frac.trim_right_matches('0');
+    if frac.len() > unit.decimals() {
+        return Err(ParseAmountError::TooPrecise(s.to_string(), unit));
+    }
+    let padded = format!("{}{:0<width$}", whole, frac, width = unit.decimals());
+    let value = match BigInt::parse_bytes(padded.as_bytes(), 10) {
+        Some(value) => value,
+        None => BigInt::zero(),
+    };
+    Ok(if negative { -value } else { value })
+}
+
+/// Rounds half away from zero to the given number of decimal places
+fn round_decimal(value: &BigInt, unit: Unit, places: usize) -> BigInt {
+    if places >= unit.decimals() {
+        return value.clone();
+    }
+    let step = pow(BigInt::from(10), unit.decimals() - places);
+    let (quotient, remainder) = value.abs().div_rem(&step);
+    let rounded = if remainder * BigInt::from(2) >= step {
+        (quotient + BigInt::from(1)) * step
+    } else {
+        quotient * step
+    };
+    if value.is_negative() {
+        -rounded
+    } else {
+        rounded
+    }
+}
+
+pub(crate) fn uint_to_bigint(n: &Uint256) -> BigInt {
+    let mut bytes = [0u8; 32];
+    n.to_big_endian(&mut bytes);
+    BigInt::from_biguint(Sign::Plus, BigUint::from_bytes_be(&bytes))
+}
+
+impl Int256 {
+    /// The exact amount in the unit, without trailing zeroes
+    pub fn to_decimal(&self, unit: Unit) -> String {
+        format_decimal(&self.0, unit)
+    }
+
+    pub fn from_decimal(s: &str, unit: Unit) -> Result<Int256, ParseAmountError> {
+        let value = parse_decimal(s, unit)?;
+        if value.bits() > 255 {
+            return Err(ParseAmountError::Overflow(s.to_string()));
+        }
+        Ok(Int256(value))
+    }
+
+    /// Rounded for showing to people, like `1.5 ETH`
+    pub fn display(&self, unit: Unit, places: usize) -> String {
+        format!(
+            "{} {}",
+            format_decimal(&round_decimal(&self.0, unit, places), unit),
+            unit.symbol()
+        )
+    }
+}
+
+pub fn uint_to_decimal(n: &Uint256, unit: Unit) -> String {
+    format_decimal(&uint_to_bigint(n), unit)
+}
+
+pub fn uint_from_decimal(s: &str, unit: Unit) -> Result<Uint256, ParseAmountError> {
+    let value = parse_decimal(s, unit)?;
+    if value.is_negative() {
+        return Err(ParseAmountError::Negative(s.to_string()));
+    }
+    if value.bits() > 256 {
+        return Err(ParseAmountError::Overflow(s.to_string()));
+    }
+    let (_, bytes) = value.to_bytes_be();
+    Ok(Uint256::from_big_endian(&bytes))
+}
+
+macro_rules! serde_unit {
+    ($module:ident, $unit:expr) => {
+        /// Serializes an `Int256` as a decimal string in this unit, for use with `#[serde(with)]`
+        pub mod $module {
+            use serde::{Deserialize, Deserializer, Serializer};
+            use Int256;
+
+            pub fn serialize<S>(value: &Int256, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                serializer.serialize_str(&value.to_decimal($unit))
+            }
+
+            pub fn deserialize<'de, D>(deserializer: D) -> Result<Int256, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                let s = String::deserialize(deserializer)?;
+                Int256::from_decimal(&s, $unit).map_err(::serde::de::Error::custom)
+            }
+        }
+    };
+}
+
+serde_unit!(gwei, super::Unit::Gwei);
+serde_unit!(ether, super::Unit::Ether);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn test_to_decimal() {
+        let amount = Int256::from(1_500_000_000_000_000_000i64);
+        assert_eq!(amount.to_decimal(Unit::Ether), "1.5");
+        assert_eq!(amount.to_decimal(Unit::Gwei), "1500000000");
+        assert_eq!(amount.to_decimal(Unit::Wei), "1500000000000000000");
+        assert_eq!(Int256::from(-1).to_decimal(Unit::Ether), "-0.000000000000000001");
+        assert_eq!(Int256::zero().to_decimal(Unit::Ether), "0");
+        assert_eq!(
+            uint_to_decimal(&Uint256::from(2_000_000_000u64), Unit::Gwei),
+            "2"
+        );
+    }
+
+    #[test]
+    fn test_from_decimal() {
+        assert_eq!(
+            Int256::from_decimal("1.5", Unit::Ether).unwrap(),
+            Int256::from(1_500_000_000_000_000_000i64)
+        );
+        assert_eq!(
+            Int256::from_decimal(" -0.25 ", Unit::Gwei).unwrap(),
+            Int256::from(-250_000_000)
+        );
+        assert_eq!(
+            Int256::from_decimal(".5", Unit::Gwei).unwrap(),
+            Int256::from(500_000_000)
+        );
+        assert_eq!(
+            Int256::from_decimal("2.000", Unit::Wei).unwrap(),
+            Int256::from(2)
+        );
+        assert_eq!(
+            Int256::from_decimal("0.5", Unit::Wei),
+            Err(ParseAmountError::TooPrecise("0.5".to_string(), Unit::Wei))
+        );
+        for bad in &["", ".", "-", "1.2.3", "1e18", "0x10", "--1", "1,5"] {
+            assert!(Int256::from_decimal(bad, Unit::Ether).is_err(), "{}", bad);
+        }
+        let huge = format!("1{}", "0".repeat(60));
+        assert!(Int256::from_decimal(&huge, Unit::Ether).is_err());
+
+        assert!(uint_from_decimal("-1", Unit::Wei).is_err());
+        assert_eq!(
+            uint_from_decimal("1", Unit::Gwei).unwrap(),
+            Uint256::from(1_000_000_000u64)
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let amount = Int256::from(1_234_560_000_000_000_000i64);
+        assert_eq!(amount.display(Unit::Ether, 4), "1.2346 ETH");
+        assert_eq!(amount.display(Unit::Ether, 2), "1.23 ETH");
+        assert_eq!((-amount).display(Unit::Ether, 0), "-1 ETH");
+        assert_eq!(Int256::from(40).display(Unit::Ether, 4), "0 ETH");
+        assert_eq!(Int256::from(40).display(Unit::Wei, 4), "40 wei");
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Price {
+        #[serde(with = "ether")]
+        amount: Int256,
+    }
+
+    #[test]
+    fn test_serde_units() {
+        let price = Price {
+            amount: Int256::from(250_000_000_000_000_000i64),
+        };
+        let json = serde_json::to_string(&price).unwrap();
+        assert_eq!(json, r#"{"amount":"0.25"}"#);
+        assert_eq!(serde_json::from_str::<Price>(&json).unwrap(), price);
+        assert!(serde_json::from_str::<Price>(r#"{"amount":"abc"}"#).is_err());
+    }
+}
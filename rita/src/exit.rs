@@ -34,12 +34,15 @@ extern crate lazy_static;
 #[macro_use]
 extern crate log;
 #[macro_use]
+extern crate prometheus;
+#[macro_use]
 extern crate serde_derive;
 #[macro_use]
 extern crate serde_json;
 
 extern crate actix;
 extern crate actix_web;
+extern crate bcrypt;
 extern crate byteorder;
 extern crate bytes;
 extern crate clu;
@@ -54,6 +57,7 @@ extern crate lettre;
 extern crate lettre_email;
 extern crate minihttpse;
 extern crate num_traits;
+extern crate openssl;
 extern crate openssl_probe;
 extern crate rand;
 extern crate regex;
@@ -79,16 +83,20 @@ extern crate althea_types;
 extern crate babel_monitor;
 extern crate exit_db;
 extern crate num256;
+extern crate web3;
 
 pub mod actix_utils;
 mod middleware;
 mod rita_common;
 mod rita_exit;
 
+use rita_common::dashboard::auth::{get_auth_status, login, logout, set_password, setup};
+use rita_common::dashboard::events::get_events;
 use rita_common::dashboard::network_endpoints::*;
 use rita_common::network_endpoints::*;
 use rita_exit::network_endpoints::*;
 
+use std::collections::HashSet;
 use std::sync::{Arc, RwLock};
 
 #[cfg(test)]
@@ -117,7 +125,7 @@ About:
 use althea_kernel_interface::KernelInterface;
 
 #[cfg(not(test))]
-use althea_kernel_interface::LinuxCommandRunner;
+use althea_kernel_interface::linux_kernel_interface;
 #[cfg(test)]
 use althea_kernel_interface::TestCommandRunner;
 
@@ -132,7 +140,7 @@ lazy_static! {
 
 #[cfg(not(test))]
 lazy_static! {
-    pub static ref KI: Box<KernelInterface> = Box::new(LinuxCommandRunner {});
+    pub static ref KI: Box<KernelInterface> = linux_kernel_interface();
 }
 
 #[cfg(not(test))]
@@ -193,21 +201,41 @@ fn main() {
 
     let system = actix::System::new(format!("main {:?}", SETTING.get_network().mesh_ip));
 
+    babel_monitor::BabelManager::from_registry().do_send(babel_monitor::Connect(
+        format!("[::1]:{}", SETTING.get_network().babel_port)
+            .parse()
+            .unwrap(),
+    ));
+    if let Some(fee) = SETTING.get_payment().local_fee {
+        babel_monitor::BabelManager::from_registry()
+            .do_send(babel_monitor::Configure(babel_monitor::BabelConfig::LocalFee(fee)));
+    }
+
     assert!(rita_common::debt_keeper::DebtKeeper::from_registry().connected());
     assert!(rita_common::payment_controller::PaymentController::from_registry().connected());
     assert!(rita_common::tunnel_manager::TunnelManager::from_registry().connected());
     assert!(rita_common::http_client::HTTPClient::from_registry().connected());
     assert!(rita_common::traffic_watcher::TrafficWatcher::from_registry().connected());
+    assert!(rita_common::traffic_stats::TrafficStats::from_registry().connected());
+    assert!(rita_common::event_bus::EventBus::from_registry().connected());
+    assert!(
+        rita_common::overadvertisement::AdvertisementMonitor::from_registry().connected()
+    );
+    assert!(rita_common::pricing::PriceManager::from_registry().connected());
     assert!(rita_common::peer_listener::PeerListener::from_registry().connected());
 
     assert!(rita_exit::traffic_watcher::TrafficWatcher::from_registry().connected());
     assert!(rita_exit::db_client::DbClient::from_registry().connected());
 
-    server::new(|| App::new().resource("/hello", |r| r.method(Method::POST).with(hello_response)))
-        .bind(format!("[::0]:{}", SETTING.get_network().rita_hello_port))
-        .unwrap()
-        .shutdown_timeout(0)
-        .start();
+    server::new(|| {
+        App::new()
+            .resource("/hello", |r| r.method(Method::POST).with(hello_response))
+            .resource("/rtt", |r| r.method(Method::GET).with(rtt))
+    }).workers(1)
+    .bind(format!("[::0]:{}", SETTING.get_network().rita_hello_port))
+    .unwrap()
+    .shutdown_timeout(0)
+    .start();
     server::new(|| {
         App::new().resource("/make_payment", |r| {
             r.method(Method::POST).with(make_payments)
@@ -236,32 +264,71 @@ fn main() {
     .start();
 
     // Dashboard
-    server::new(|| {
+    let dashboard = server::new(|| {
         App::new()
             .middleware(middleware::Headers)
+            .middleware(middleware::Auth::new(HashSet::new))
+            .route("/auth/status", Method::GET, get_auth_status)
+            .route("/login", Method::POST, login)
+            .route("/logout", Method::POST, logout)
+            .route("/password", Method::POST, set_password)
+            .route("/setup", Method::POST, setup)
             // assuming exit nodes dont need wifi
             //.resource("/wifisettings", |r| r.route().filter(pred::Get()).h(get_wifi_config))
             //.resource("/wifisettings", |r| r.route().filter(pred::Post()).h(set_wifi_config))
             .route("/info", Method::GET, get_own_info)
+            .route("/overadvertisement", Method::GET, get_overadvertisement)
+            .route("/price", Method::GET, get_local_fee)
+            .route("/price/{fee}", Method::POST, set_local_fee)
+            .route("/pricing", Method::GET, get_pricing)
             .route("/settings", Method::GET, get_settings)
             .route("/settings", Method::POST, set_settings)
+            .route("/spending", Method::GET, get_spending)
+            .route("/tunnels", Method::GET, get_tunnels)
+            .route("/usage", Method::GET, get_usage)
             .route("/version", Method::GET, version)
             .route("/wipe", Method::POST, wipe)
             .route("/database", Method::DELETE, nuke_db)
             .route("/debts", Method::GET, get_debts)
+            .route("/earnings", Method::GET, get_earnings)
+            .route("/events", Method::GET, get_events)
+            .route("/credit_policies", Method::GET, get_credit_policies)
+            .route("/credit_policies", Method::POST, set_credit_policy)
+            .route("/credit_policies/remove", Method::POST, remove_credit_policy)
+            .route(
+                "/credit_policies/allowlist_only/{enabled}",
+                Method::POST,
+                set_allowlist_only,
+            )
             .route("/dao_list", Method::GET, get_dao_list)
             .route("/dao_list/add/{address}", Method::POST, add_to_dao_list)
+            .route("/dao_list/join/{address}", Method::POST, join_dao)
+            .route("/dao_list/status", Method::GET, get_dao_status)
             .route(
                 "/dao_list/remove/{address}",
                 Method::POST,
                 remove_from_dao_list,
             )
-    }).bind(format!(
-        "[::0]:{}",
-        SETTING.get_network().rita_dashboard_port
-    )).unwrap()
-    .shutdown_timeout(0)
-    .start();
+    });
+    let dashboard_addr = format!("[::0]:{}", SETTING.get_network().rita_dashboard_port);
+    let tls = SETTING.get_dashboard().tls;
+    let dashboard = if tls {
+        // the session cookie and password must never fall back to cleartext
+        let acceptor = rita_common::dashboard::tls::acceptor()
+            .expect("Unable to set up dashboard TLS, refusing to serve it over http");
+        dashboard.bind_ssl(dashboard_addr, acceptor)
+    } else {
+        dashboard.bind(dashboard_addr)
+    };
+    dashboard.unwrap().shutdown_timeout(0).start();
+
+    // metrics, only reachable from the router itself
+    server::new(|| App::new().route("/metrics", Method::GET, rita_common::metrics::get_metrics))
+        .workers(1)
+        .bind(format!("[::1]:{}", SETTING.get_network().metrics_port))
+        .unwrap()
+        .shutdown_timeout(0)
+        .start();
 
     let common = rita_common::rita_loop::RitaLoop::new();
     let _: Addr<_> = common.start();
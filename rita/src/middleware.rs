@@ -1,10 +1,42 @@
 //! This is the Actix-web middleware that attaches the content headers we need for
-//! the client dashboard
+//! the client dashboard and checks that requests to it are logged in
 
 use actix_web::middleware::{Middleware, Response, Started};
 use actix_web::{HttpRequest, HttpResponse, Result};
 use http::{header, HttpTryFrom, Method, StatusCode};
-use regex::Regex;
+
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+
+use rita_common::dashboard::auth;
+use rita_common::network_endpoints::unmap_ipv4;
+
+use KI;
+
+/// Strips the port from a host, handling bracketed ipv6 addresses
+fn host_without_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        match host.find(']') {
+            Some(end) => &host[..=end],
+            None => host,
+        }
+    } else {
+        host.split(':').next().unwrap_or(host)
+    }
+}
+
+/// The dashboard is served from the router on another port, so only origins with the same host
+/// as the request are allowed
+fn origin_allowed(origin: &str, host: &str) -> bool {
+    let origin_host = if origin.starts_with("http://") {
+        &origin["http://".len()..]
+    } else if origin.starts_with("https://") {
+        &origin["https://".len()..]
+    } else {
+        return false;
+    };
+    host_without_port(origin_host) == host_without_port(host)
+}
 
 pub struct Headers;
 
@@ -14,20 +46,179 @@ impl<S> Middleware<S> for Headers {
     }
 
     fn response(&self, req: &HttpRequest<S>, mut resp: HttpResponse) -> Result<Response> {
-        let url = req.connection_info().host().to_owned();
-        let re = Regex::new(r"^(.*):").unwrap();
-        let url_no_port = re.captures(&url).unwrap()[1].to_string();
         if req.method() == &Method::OPTIONS {
             *resp.status_mut() = StatusCode::OK;
         }
+        let origin = req
+            .headers()
+            .get(header::ORIGIN)
+            .and_then(|origin| origin.to_str().ok())
+            .map(|origin| origin.to_string());
+        if let Some(origin) = origin {
+            if origin_allowed(&origin, req.connection_info().host()) {
+                resp.headers_mut().insert(
+                    header::HeaderName::try_from("Access-Control-Allow-Origin").unwrap(),
+                    header::HeaderValue::from_str(&origin).unwrap(),
+                );
+            }
+        }
         resp.headers_mut().insert(
-            header::HeaderName::try_from("Access-Control-Allow-Origin").unwrap(),
-            header::HeaderValue::from_str(&format!("http://{}", url_no_port)).unwrap(),
+            header::VARY,
+            header::HeaderValue::from_static("Origin"),
         );
         resp.headers_mut().insert(
             header::HeaderName::try_from("Access-Control-Allow-Headers").unwrap(),
-            header::HeaderValue::from_static("content-type"),
+            header::HeaderValue::from_static("authorization, content-type"),
+        );
+        resp.headers_mut().insert(
+            header::HeaderName::try_from("Access-Control-Allow-Methods").unwrap(),
+            header::HeaderValue::from_static("GET, POST, DELETE, OPTIONS"),
         );
         Ok(Response::Done(resp))
     }
 }
+
+/// Endpoints that can be used without logging in
+const PUBLIC_PATHS: [&str; 2] = ["/auth/status", "/login"];
+
+/// Sets the first password, only reachable from the router itself or its LAN
+const SETUP_PATH: &str = "/setup";
+
+fn is_loopback(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_loopback(),
+        IpAddr::V6(ip) => {
+            ip.is_loopback() || (ip.segments()[..6] == [0, 0, 0, 0, 0, 0xffff]
+                && ip.to_ipv4().map(|v4| v4.is_loopback()).unwrap_or(false))
+        }
+    }
+}
+
+/// Requires a session token for everything but logging in. Until a password has been set the
+/// dashboard is left open to the router itself so that it can still be configured locally.
+pub struct Auth {
+    /// Interfaces whose neighbors may use `/setup`, read on every request so that changes to
+    /// the settings apply right away
+    lan_nics: fn() -> HashSet<String>,
+}
+
+impl Auth {
+    pub fn new(lan_nics: fn() -> HashSet<String>) -> Auth {
+        Auth { lan_nics }
+    }
+
+    /// True for requests from the router itself or a device directly on one of its LAN
+    /// interfaces, peers reaching us over the mesh or the internet don't qualify
+    fn from_lan<S>(&self, req: &HttpRequest<S>) -> bool {
+        match req.peer_addr() {
+            Some(addr) => self.addr_on_lan(addr),
+            None => false,
+        }
+    }
+
+    /// ipv4 clients reach our dual stack listener as v4 mapped addresses while `ip neighbor`
+    /// lists them as plain ipv4, so they are unmapped before looking up their interface
+    fn addr_on_lan(&self, addr: SocketAddr) -> bool {
+        let ip = unmap_ipv4(addr).ip();
+        if is_loopback(ip) {
+            return true;
+        }
+        match KI.get_device_name(ip) {
+            Ok(dev) => (self.lan_nics)().contains(&dev),
+            Err(e) => {
+                trace!("Unable to find the interface for {}: {:?}", ip, e);
+                false
+            }
+        }
+    }
+}
+
+impl<S> Middleware<S> for Auth {
+    fn start(&self, req: &HttpRequest<S>) -> Result<Started> {
+        if req.method() == &Method::OPTIONS || PUBLIC_PATHS.contains(&req.path()) {
+            return Ok(Started::Done);
+        }
+
+        if req.path() == SETUP_PATH {
+            if self.from_lan(req) {
+                return Ok(Started::Done);
+            }
+            return Ok(Started::Response(
+                HttpResponse::Forbidden().body("Setup is only available from the LAN"),
+            ));
+        }
+
+        if !auth::password_set() {
+            let local = req.peer_addr().map(|addr| is_loopback(addr.ip()));
+            if local.unwrap_or(false) {
+                return Ok(Started::Done);
+            }
+            return Ok(Started::Response(
+                HttpResponse::Forbidden().body("Set a dashboard password with /setup first"),
+            ));
+        }
+
+        if auth::authenticated(req) {
+            Ok(Started::Done)
+        } else {
+            Ok(Started::Response(
+                HttpResponse::Unauthorized().body("Login required"),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_origin_allowed() {
+        assert!(origin_allowed("http://192.168.10.1", "192.168.10.1:4877"));
+        assert!(origin_allowed("https://192.168.10.1:443", "192.168.10.1:4877"));
+        assert!(origin_allowed("http://[fd00::1]", "[fd00::1]:4877"));
+        assert!(origin_allowed("http://localhost", "localhost"));
+        assert!(!origin_allowed("http://evil.com", "192.168.10.1:4877"));
+        assert!(!origin_allowed("http://192.168.10.1.evil.com", "192.168.10.1:4877"));
+        assert!(!origin_allowed("file://192.168.10.1", "192.168.10.1:4877"));
+    }
+
+    #[test]
+    fn test_is_loopback() {
+        assert!(is_loopback("127.0.0.1".parse().unwrap()));
+        assert!(is_loopback("::1".parse().unwrap()));
+        assert!(is_loopback("::ffff:127.0.0.1".parse().unwrap()));
+        assert!(!is_loopback("192.168.10.2".parse().unwrap()));
+        assert!(!is_loopback("fd00::1".parse().unwrap()));
+    }
+
+    fn lan_nics() -> HashSet<String> {
+        let mut nics = HashSet::new();
+        nics.insert("br-lan".to_string());
+        nics
+    }
+
+    #[test]
+    fn test_addr_on_lan() {
+        use std::os::unix::process::ExitStatusExt;
+        use std::process::{ExitStatus, Output};
+
+        KI.set_mock(Box::new(|program, args| {
+            assert_eq!(program, "ip");
+            assert_eq!(args, vec!["neighbor"]);
+            Ok(Output {
+                stdout: b"192.168.10.2 dev br-lan lladdr 00:00:00:aa:00:03 REACHABLE
+10.0.1.2 dev eth0 lladdr 00:00:00:aa:00:05 REACHABLE"
+                    .to_vec(),
+                stderr: b"".to_vec(),
+                status: ExitStatus::from_raw(0),
+            })
+        }));
+
+        let auth = Auth::new(lan_nics);
+        assert!(auth.addr_on_lan("[::ffff:192.168.10.2]:50000".parse().unwrap()));
+        assert!(auth.addr_on_lan("192.168.10.2:50000".parse().unwrap()));
+        assert!(!auth.addr_on_lan("[::ffff:10.0.1.2]:50000".parse().unwrap()));
+        assert!(!auth.addr_on_lan("[::ffff:10.0.9.9]:50000".parse().unwrap()));
+    }
+}
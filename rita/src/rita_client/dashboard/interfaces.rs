@@ -4,19 +4,29 @@ A generalized interface for modifying networking interface assignments using UCI
 
 use actix::prelude::*;
 use failure::Error;
+use futures::future::{self, loop_fn, Loop};
 use futures::Future;
 use std::collections::HashMap;
 use std::string::ToString;
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
 use std::time::{Duration, Instant};
 use tokio::timer::Delay;
 
 use rita_common::dashboard::Dashboard;
+use rita_common::event_bus::{self, Event};
 use rita_common::peer_listener::PeerListener;
 use rita_common::peer_listener::{Listen, UnListen};
 use settings::RitaCommonSettings;
 use KI;
 use SETTING;
 
+/// Seconds an interface has to come up in its new mode before the change is rolled back
+const VERIFY_TIMEOUT: u64 = 120;
+const VERIFY_INTERVAL: u64 = 5;
+
+/// Set while a mode change is being applied and verified, only one may run at a time
+static CHANGE_IN_PROGRESS: AtomicBool = ATOMIC_BOOL_INIT;
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct InterfaceToSet {
     pub interface: String,
@@ -159,43 +169,222 @@ impl Message for InterfaceToSet {
 }
 
 impl Handler<InterfaceToSet> for Dashboard {
-    type Result = Result<(), Error>;
+    type Result = ResponseFuture<(), Error>;
     fn handle(&mut self, msg: InterfaceToSet, _ctx: &mut Self::Context) -> Self::Result {
-        let iface_name = msg.interface;
-        let target_mode = msg.mode;
-        let interfaces = get_interfaces()?;
-        let current_mode = get_current_interface_mode(&interfaces, &iface_name);
-        if !interfaces.contains_key(&iface_name) {
-            bail!("Attempted to configure non-existant or unavailable itnerface!");
-        } else if target_mode == InterfaceMode::WAN {
-            // we can only have one WAN interface, check for others
-            for entry in interfaces {
-                let mode = entry.1;
-                if mode == InterfaceMode::WAN {
-                    bail!("There can only be one WAN interface!");
-                }
+        let current_mode = match check_mode_change(&msg.interface, &msg.mode) {
+            Ok(mode) => mode,
+            Err(e) => return Box::new(future::err(e)),
+        };
+        if CHANGE_IN_PROGRESS.swap(true, Ordering::SeqCst) {
+            return Box::new(future::err(format_err!(
+                "Another interface mode change is still being verified"
+            )));
+        }
+        Box::new(
+            set_interface_mode(msg.interface, current_mode, msg.mode).then(|res| {
+                CHANGE_IN_PROGRESS.store(false, Ordering::SeqCst);
+                res
+            }),
+        )
+    }
+}
+
+/// Checks that an interface can be put in the target mode, returning its current mode
+fn check_mode_change(
+    iface_name: &str,
+    target_mode: &InterfaceMode,
+) -> Result<InterfaceMode, Error> {
+    let interfaces = get_interfaces()?;
+    let current_mode = get_current_interface_mode(&interfaces, iface_name);
+    if !interfaces.contains_key(iface_name) {
+        bail!("Attempted to configure non-existant or unavailable itnerface!");
+    } else if *target_mode == InterfaceMode::WAN {
+        // we can only have one WAN interface, check for others
+        for entry in interfaces {
+            let mode = entry.1;
+            if mode == InterfaceMode::WAN {
+                bail!("There can only be one WAN interface!");
             }
-        } else if target_mode == InterfaceMode::LAN && !iface_name.contains("wlan") {
-            // we can only have one LAN ethernet interface, check for others
-            for entry in interfaces {
-                let mode = entry.1;
-                if mode == InterfaceMode::LAN {
-                    bail!("There can only be one LAN ethernet interface!");
-                }
+        }
+    } else if *target_mode == InterfaceMode::LAN && !iface_name.contains("wlan") {
+        // we can only have one LAN ethernet interface, check for others
+        for entry in interfaces {
+            let mode = entry.1;
+            if mode == InterfaceMode::LAN {
+                bail!("There can only be one LAN ethernet interface!");
             }
         }
+    }
+    Ok(current_mode)
+}
+
+/// Everything an interface mode change touches, taken before the change so that it can be undone
+struct Snapshot {
+    network: String,
+    wireless: String,
+    external_nic: Option<String>,
+    mode: InterfaceMode,
+}
+
+impl Snapshot {
+    fn take(mode: InterfaceMode) -> Result<Snapshot, Error> {
+        Ok(Snapshot {
+            network: KI.uci_export("network")?,
+            wireless: KI.uci_export("wireless")?,
+            external_nic: SETTING.get_network().external_nic.clone(),
+            mode,
+        })
+    }
+
+    /// Puts UCI, the settings and the peer listener back the way they were before changing to
+    /// the target mode
+    fn restore(self, ifname: &str, target: &InterfaceMode) -> Result<(), Error> {
+        warn!("Rolling back mode change of {}", ifname);
+        KI.uci_restore("network", &self.network)?;
+        KI.uci_restore("wireless", &self.wireless)?;
+        commit_changes(ifname.contains("wlan"))?;
+
+        SETTING.get_network_mut().external_nic = self.external_nic;
+        // the peer listener handles messages in order, so these come after anything the change sent
+        if *target == InterfaceMode::Mesh {
+            PeerListener::from_registry().do_send(UnListen(ifname.to_string()));
+        } else if self.mode == InterfaceMode::Mesh {
+            // the interface needs some time to come back up after the network restart
+            let ifname = ifname.to_string();
+            let when = Instant::now() + Duration::from_secs(VERIFY_INTERVAL);
+            Arbiter::spawn(
+                Delay::new(when)
+                    .map_err(|e| warn!("timer failed; err={:?}", e))
+                    .and_then(move |_| {
+                        PeerListener::from_registry().do_send(Listen(ifname));
+                        Ok(())
+                    }),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Changes the mode of an interface as a transaction. If staging or applying the new config fails,
+/// or the interface does not come up in its new mode within VERIFY_TIMEOUT seconds, the UCI config,
+/// settings and peer listener are restored from a snapshot taken beforehand.
+pub fn set_interface_mode(
+    ifname: String,
+    a: InterfaceMode,
+    b: InterfaceMode,
+) -> Box<Future<Item = (), Error = Error>> {
+    if a == b {
+        // noop that was easy!
+        return Box::new(future::ok(()));
+    }
+    let snapshot = match Snapshot::take(a.clone()) {
+        Ok(snapshot) => snapshot,
+        Err(e) => return Box::new(future::err(e)),
+    };
+
+    let wireless = ifname.contains("wlan");
+    // in theory you can have all sorts of wonky interface names, but we know
+    // that we hardcode wlan0 and wlan0 as wlan iface names so we check for that
+    let applied = if wireless {
+        wlan_transform_mode(&ifname, a.clone(), b.clone())
+    } else {
+        ethernet_transform_mode(&ifname, a.clone(), b.clone())
+    }.and_then(|_| commit_changes(wireless));
+    if let Err(e) = applied {
+        error!("Changing {} to {} failed with {:?}", ifname, b.to_string(), e);
+        return Box::new(future::result(rollback(&ifname, &b, snapshot, e)));
+    }
+
+    let event = Event::InterfaceModeChanged {
+        iface: ifname.clone(),
+        from: a.to_string(),
+        to: b.to_string(),
+    };
+    Box::new(verify_mode(ifname.clone(), b.clone()).then(move |res| match res {
+        Ok(()) => {
+            info!("{} is up in its new mode", ifname);
+            event_bus::publish(event);
+            Ok(())
+        }
+        Err(e) => rollback(&ifname, &b, snapshot, e),
+    }))
+}
+
+/// Restores the snapshot, always resulting in an error describing why the change failed
+fn rollback(
+    ifname: &str,
+    target: &InterfaceMode,
+    snapshot: Snapshot,
+    cause: Error,
+) -> Result<(), Error> {
+    match snapshot.restore(ifname, target) {
+        Ok(()) => Err(format_err!("{}, the change was rolled back", cause)),
+        Err(e) => {
+            error!("Rolling back {} failed with {:?}", ifname, e);
+            Err(format_err!("{}, rolling back also failed: {}", cause, e))
+        }
+    }
+}
+
+fn commit_changes(wireless: bool) -> Result<(), Error> {
+    if wireless {
+        KI.uci_commit(&"wireless")?;
+    }
+    KI.uci_commit(&"network")?;
+    KI.openwrt_reset_network()?;
+    if wireless {
+        KI.openwrt_reset_wireless()?;
+    }
+
+    // We edited disk contents, force global sync
+    KI.fs_sync()?;
+    Ok(())
+}
+
+/// Waits for the interface to come back up after the network restart, and for mesh interfaces
+/// for the peer listener to be listening on it
+fn verify_mode(ifname: String, mode: InterfaceMode) -> impl Future<Item = (), Error = Error> {
+    let deadline = Instant::now() + Duration::from_secs(VERIFY_TIMEOUT);
+    loop_fn((), move |()| {
+        let ifname = ifname.clone();
+        let mode = mode.clone();
+        Delay::new(Instant::now() + Duration::from_secs(VERIFY_INTERVAL))
+            .from_err()
+            .and_then(move |_| {
+                if mode_active(&ifname, &mode) {
+                    Ok(Loop::Break(()))
+                } else if Instant::now() > deadline {
+                    bail!(
+                        "{} did not come up as {} within {} seconds",
+                        ifname,
+                        mode.to_string(),
+                        VERIFY_TIMEOUT
+                    )
+                } else {
+                    Ok(Loop::Continue(()))
+                }
+            })
+    })
+}
 
-        // in theory you can have all sorts of wonky interface names, but we know
-        // that we hardcode wlan0 and wlan0 as wlan iface names so we check for that
-        if iface_name.contains("wlan") {
-            wlan_transform_mode(&iface_name, current_mode, target_mode)
-        } else {
-            ethernet_transform_mode(&iface_name, current_mode, target_mode)
+fn mode_active(ifname: &str, mode: &InterfaceMode) -> bool {
+    match KI.is_iface_admin_up(ifname) {
+        Ok(true) => {}
+        Ok(false) => return false,
+        Err(e) => {
+            trace!("Checking if {} is up failed with {:?}", ifname, e);
+            return false;
         }
     }
+    if *mode == InterfaceMode::Mesh && !SETTING.get_network().peer_interfaces.contains(ifname) {
+        // listening fails until the interface has its link local address, so keep asking
+        PeerListener::from_registry().do_send(Listen(ifname.to_string()));
+        return false;
+    }
+    true
 }
 
-/// Transform a wired inteface from mode A to mode B
+/// Stage the UCI changes to transform a wired inteface from mode A to mode B
 pub fn ethernet_transform_mode(
     ifname: &str,
     a: InterfaceMode,
@@ -214,9 +403,8 @@ pub fn ethernet_transform_mode(
         bail!("We can't change Unknown interfaces!");
     }
 
-    // if we have edited UCI and it fails we set this var to handle cleanup later
+    // every command is attempted and the results checked at the end
     let mut return_codes = Vec::new();
-    let mut mesh_add = false;
     let filtered_ifname = format!("network.rita_{}", ifname.replace(".", ""));
 
     match a {
@@ -287,46 +475,20 @@ pub fn ethernet_transform_mode(
             return_codes.push(ret);
             let ret = KI.set_uci_var(&format!("{}.proto", filtered_ifname), "static");
             return_codes.push(ret);
-            mesh_add = true;
         }
         InterfaceMode::Unknown => unimplemented!(),
     }
 
-    // check all of our return codes in order to handle any possible issue
-    let mut error_occured = false;
+    // check all of our return codes, set_interface_mode rolls back if any failed
     for ret in return_codes {
-        if ret.is_err() {
-            error_occured = true;
+        if let Err(e) = ret {
+            bail!("Error running UCI commands! {:?}", e);
         }
     }
-    if error_occured {
-        let res = KI.uci_revert("network");
-        bail!("Error running UCI commands! Revert attempted: {:?}", res);
-    } else if mesh_add {
-        let when = Instant::now() + Duration::from_millis(60000);
-        let locally_owned_ifname = ifname.clone().to_string();
-
-        let fut = Delay::new(when)
-            .map_err(|e| warn!("timer failed; err={:?}", e))
-            .and_then(move |_| {
-                trace!("Adding mesh interface {:?}", locally_owned_ifname);
-                PeerListener::from_registry().do_send(Listen(locally_owned_ifname));
-                Ok(())
-            });
-
-        Arbiter::spawn(fut);
-    }
-
-    KI.uci_commit(&"network")?;
-    KI.openwrt_reset_network()?;
-
-    // We edited disk contents, force global sync
-    KI.fs_sync()?;
-
     Ok(())
 }
 
-/// Transform a wireless interface from mode A to mode B
+/// Stage the UCI changes to transform a wireless interface from mode A to mode B
 pub fn wlan_transform_mode(ifname: &str, a: InterfaceMode, b: InterfaceMode) -> Result<(), Error> {
     trace!(
         "wlan mode transform: ifname {:?}, a {:?}, b {:?}",
@@ -344,9 +506,8 @@ pub fn wlan_transform_mode(ifname: &str, a: InterfaceMode, b: InterfaceMode) ->
         bail!("WAN not supported for wlan interfaces!");
     }
 
-    // if we have edited UCI and it fails we set this var to handle cleanup later
+    // every command is attempted and the results checked at the end
     let mut return_codes = Vec::new();
-    let mut mesh_add = false;
 
     // we assume wlan0 => radio0 this is held true by our config
     // modifications but is not generally true for OpenWRT
@@ -409,49 +570,16 @@ pub fn wlan_transform_mode(ifname: &str, a: InterfaceMode, b: InterfaceMode) ->
             return_codes.push(ret);
             let ret = KI.set_uci_var(&format!("network.rita_{}.proto", ifname), "static");
             return_codes.push(ret);
-            mesh_add = true;
         }
         InterfaceMode::Unknown => unimplemented!(),
     }
 
-    // check all of our return codes in order to handle any possible issue
-    let mut error_occured = false;
+    // check all of our return codes, set_interface_mode rolls back if any failed
     for ret in return_codes {
-        if ret.is_err() {
-            error_occured = true;
+        if let Err(e) = ret {
+            bail!("Error running UCI commands! {:?}", e);
         }
     }
-    if error_occured {
-        let res_a = KI.uci_revert("network");
-        let res_b = KI.uci_revert("wireless");
-        bail!(
-            "Error running UCI commands! Revert attempted: {:?} {:?}",
-            res_a,
-            res_b
-        );
-    } else if mesh_add {
-        let when = Instant::now() + Duration::from_millis(60000);
-        let locally_owned_ifname = ifname.clone().to_string();
-
-        let fut = Delay::new(when)
-            .map_err(|e| warn!("timer failed; err={:?}", e))
-            .and_then(move |_| {
-                trace!("Adding mesh interface {:?}", locally_owned_ifname);
-                PeerListener::from_registry().do_send(Listen(locally_owned_ifname));
-                Ok(())
-            });
-
-        Arbiter::spawn(fut);
-    }
-
-    KI.uci_commit(&"wireless")?;
-    KI.uci_commit(&"network")?;
-    KI.openwrt_reset_network()?;
-    KI.openwrt_reset_wireless()?;
-
-    // We edited disk contents, force global sync
-    KI.fs_sync()?;
-
     Ok(())
 }
 
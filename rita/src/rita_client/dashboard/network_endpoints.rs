@@ -12,7 +12,7 @@ use rita_client::dashboard::exitinfo::{ExitInfo, GetExitInfo};
 use rita_client::dashboard::interfaces::{GetInterfaces, InterfaceMode, InterfaceToSet};
 use rita_client::dashboard::nodeinfo::{GetNodeInfo, NodeInfo};
 use rita_client::dashboard::wifi::{GetWifiConfig, WifiInterface, WifiPass, WifiSSID};
-use rita_client::exit_manager::exit_setup_request;
+use rita_client::exit_manager::{exit_setup_request, set_exit_state};
 use rita_common::dashboard::Dashboard;
 use settings::{RitaClientSettings, RitaCommonSettings};
 use KI;
@@ -65,7 +65,7 @@ pub fn reset_exit(path: Path<String>) -> Box<Future<Item = HttpResponse, Error =
 
     if let Some(exit) = exits.get_mut(&exit_name) {
         info!("Changing exit {:?} state to New", exit_name);
-        exit.info = ExitState::New;
+        set_exit_state(&exit_name, exit, ExitState::New);
         return Box::new(future::ok(HttpResponse::Ok().json(ret)));
     } else {
         error!("Requested a reset on unknown exit {:?}", exit_name);
@@ -6,26 +6,46 @@ use actix::prelude::*;
 use failure::Error;
 use futures::Future;
 use serde_json;
-use std::net::{SocketAddr, TcpStream};
 
-use babel_monitor::Babel;
-use num256::Int256;
-use rita_common::dashboard::Dashboard;
-use rita_common::debt_keeper::{DebtKeeper, Dump};
+use babel_monitor;
+use num256::{Int256, Unit};
+use rita_common::dashboard::{Dashboard, DISPLAY_PLACES};
+use rita_common::debt_keeper::{DebtKeeper, Dump, NodeDebtData};
 use settings::RitaClientSettings;
-use settings::RitaCommonSettings;
 use SETTING;
 
 #[derive(Serialize)]
 pub struct NodeInfo {
     pub nickname: String,
     pub route_metric_to_exit: u16,
+    /// Exact amounts in wei
     pub total_payments: Int256,
-    pub debt: i64,
+    pub total_payments_display: String,
+    pub debt: Int256,
+    pub debt_display: String,
     pub link_cost: u16,
     pub price_to_exit: u32,
 }
 
+impl NodeInfo {
+    /// `route` is the metric, link cost and price to the exit through this neighbor if there is one
+    fn new(nickname: String, debt_info: &NodeDebtData, route: Option<(u16, u16, u32)>) -> NodeInfo {
+        let (route_metric_to_exit, link_cost, price_to_exit) =
+            route.unwrap_or((u16::max_value(), u16::max_value(), u32::max_value()));
+        let total_payments: Int256 = debt_info.total_payment_received.into();
+        NodeInfo {
+            nickname,
+            route_metric_to_exit,
+            total_payments_display: total_payments.display(Unit::Ether, DISPLAY_PLACES),
+            total_payments,
+            debt: debt_info.debt.clone(),
+            debt_display: debt_info.debt.display(Unit::Ether, DISPLAY_PLACES),
+            link_cost,
+            price_to_exit,
+        }
+    }
+}
+
 pub struct GetNodeInfo;
 
 impl Message for GetNodeInfo {
@@ -40,14 +60,9 @@ impl Handler<GetNodeInfo> for Dashboard {
             DebtKeeper::from_registry()
                 .send(Dump {})
                 .from_err()
-                .and_then(|res| {
+                .join(babel_monitor::routes())
+                .and_then(|(res, route_table_sample)| {
                     let res = res?;
-                    let stream = TcpStream::connect::<SocketAddr>(
-                        format!("[::1]:{}", SETTING.get_network().babel_port).parse()?,
-                    )?;
-                    let mut babel = Babel::new(stream);
-                    babel.start_connection()?;
-                    let route_table_sample = babel.parse_routes()?;
 
                     let mut output = Vec::new();
 
@@ -55,9 +70,10 @@ impl Handler<GetNodeInfo> for Dashboard {
                     let current_exit = exit_client.get_current_exit();
 
                     for (identity, debt_info) in res.iter() {
+                        let nickname = serde_json::to_string(&identity.mesh_ip).unwrap();
                         if current_exit.is_some() {
                             let exit_ip = current_exit.unwrap().id.mesh_ip;
-                            let maybe_route = babel.get_route_via_neigh(
+                            let maybe_route = babel_monitor::get_route_via_neigh(
                                 identity.mesh_ip,
                                 exit_ip,
                                 &route_table_sample,
@@ -67,36 +83,19 @@ impl Handler<GetNodeInfo> for Dashboard {
                             // from them to our selected exit. Other errors can also get
                             // caught here
                             if maybe_route.is_err() {
-                                output.push(NodeInfo {
-                                    nickname: serde_json::to_string(&identity.mesh_ip).unwrap(),
-                                    route_metric_to_exit: u16::max_value(),
-                                    total_payments: debt_info.total_payment_received.into(),
-                                    debt: debt_info.debt.clone().into(),
-                                    link_cost: u16::max_value(),
-                                    price_to_exit: u32::max_value(),
-                                });
+                                output.push(NodeInfo::new(nickname, debt_info, None));
                                 continue;
                             }
                             // we check that this is safe above
                             let route = maybe_route.unwrap();
 
-                            output.push(NodeInfo {
-                                nickname: serde_json::to_string(&identity.mesh_ip).unwrap(),
-                                route_metric_to_exit: route.metric,
-                                total_payments: debt_info.total_payment_received.into(),
-                                debt: debt_info.debt.clone().into(),
-                                link_cost: route.refmetric,
-                                price_to_exit: route.price,
-                            })
+                            output.push(NodeInfo::new(
+                                nickname,
+                                debt_info,
+                                Some((route.metric, route.refmetric, route.price)),
+                            ))
                         } else {
-                            output.push(NodeInfo {
-                                nickname: serde_json::to_string(&identity.mesh_ip).unwrap(),
-                                route_metric_to_exit: u16::max_value(),
-                                total_payments: debt_info.total_payment_received.into(),
-                                debt: debt_info.debt.clone().into(),
-                                link_cost: u16::max_value(),
-                                price_to_exit: u32::max_value(),
-                            })
+                            output.push(NodeInfo::new(nickname, debt_info, None))
                         }
                     }
 
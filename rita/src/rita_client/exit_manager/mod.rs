@@ -22,6 +22,7 @@ use actix_web::client::Connection;
 use actix_web::*;
 use std::net::IpAddr;
 
+use althea_kernel_interface::RuleGroup;
 use althea_types::{ExitClientIdentity, ExitState};
 
 use settings::{ExitServer, RitaClientSettings, RitaCommonSettings};
@@ -29,6 +30,10 @@ use SETTING;
 
 use rita_client::rita_loop::Tick;
 use rita_client::traffic_watcher::{TrafficWatcher, Watch};
+use rita_common::event_bus::{self, Event};
+use rita_common::firewall::{AddRules, Firewall, RemoveRules};
+use rita_common::payment_controller::{GetSpending, PaymentController};
+use rita_common::traffic_watcher::billing::PlanUsage;
 
 use futures::future;
 use futures::future::join_all;
@@ -66,8 +71,6 @@ fn enable_remote_logging(server_internal_ip: IpAddr) -> Result<(), LogError> {
 }
 
 fn linux_setup_exit_tunnel() -> Result<(), Error> {
-    KI.update_settings_route(&mut SETTING.get_network_mut().default_route)?;
-
     let exit_client = SETTING.get_exit_client();
     let current_exit = exit_client.get_current_exit().unwrap();
     let general_details = current_exit.info.general_details().unwrap();
@@ -81,18 +84,45 @@ fn linux_setup_exit_tunnel() -> Result<(), Error> {
         SETTING.get_exit_client().wg_listen_port,
         our_details.client_internal_ip,
         general_details.netmask,
-        SETTING.get_network().rita_hello_port,
     )?;
     KI.set_route_to_tunnel(&general_details.server_internal_ip)?;
 
-    let lan_nics = &SETTING.get_exit_client().lan_nics;
-    for nic in lan_nics {
-        KI.add_client_nat_rules(&nic)?;
+    // block rita hello port on the exit tunnel
+    let firewall = Firewall::from_registry();
+    firewall.do_send(AddRules(RuleGroup::ExitTunnelHelloDrop(
+        SETTING.get_network().rita_hello_port,
+    )));
+    for nic in SETTING.get_exit_client().lan_nics.iter() {
+        firewall.do_send(AddRules(RuleGroup::ClientNat(nic.clone())));
     }
 
     Ok(())
 }
 
+/// The plan the exit bills us on, we pay per byte if we have none or the exit doesn't list it.
+/// Our usage picks up from what the exit last told us so that both sides count the same period
+fn plan_usage(info: &ExitState) -> Option<PlanUsage> {
+    let (general_details, our_details) = match (info.general_details(), info.our_details()) {
+        (Some(general), Some(ours)) => (general, ours),
+        _ => return None,
+    };
+    let name = match our_details.plan {
+        Some(ref name) => name,
+        None => return None,
+    };
+    match general_details.plans.iter().find(|plan| plan.name == *name) {
+        Some(plan) => Some(PlanUsage {
+            period_start: our_details.period_start,
+            period_bytes: our_details.period_bytes,
+            ..PlanUsage::new(plan.clone(), our_details.client_internal_ip)
+        }),
+        None => {
+            warn!("Exit put us on unknown plan {}, paying per byte", name);
+            None
+        }
+    }
+}
+
 pub fn get_exit_info(to: &SocketAddr) -> impl Future<Item = ExitState, Error = Error> {
     let endpoint = format!("http://[{}]:{}/exit_info", to.ip(), to.port());
 
@@ -163,6 +193,18 @@ pub fn send_exit_status_request(
     })
 }
 
+/// Stores an exit's new state, publishing the transition if the state changed
+pub fn set_exit_state(name: &str, exit: &mut ExitServer, state: ExitState) {
+    if exit.info != state {
+        event_bus::publish(Event::ExitStateChanged {
+            exit: name.to_string(),
+            from: exit.info.clone(),
+            to: state.clone(),
+        });
+    }
+    exit.info = state;
+}
+
 fn exit_general_details_request(exit: String) -> impl Future<Item = (), Error = Error> {
     let current_exit = match SETTING.get_exits().get(&exit) {
         Some(current_exit) => current_exit.clone(),
@@ -191,7 +233,7 @@ fn exit_general_details_request(exit: String) -> impl Future<Item = (), Error =
             _ => bail!("got incorrect state from exit details request"),
         }
 
-        current_exit.info = exit_details;
+        set_exit_state(&exit, current_exit, exit_details);
 
         Ok(())
     });
@@ -239,7 +281,7 @@ pub fn exit_setup_request(
                     None => bail!("Could not find exit {:?}", exit),
                 };
 
-                current_exit.info = exit_response.clone();
+                set_exit_state(&exit, current_exit, exit_response.clone());
 
                 trace!("Got exit setup response {:?}", exit_response.clone());
 
@@ -285,7 +327,7 @@ fn exit_status_request(exit: String) -> impl Future<Item = (), Error = Error> {
                 None => bail!("Could not find exit {:?}", exit),
             };
 
-            current_exit.info = exit_response.clone();
+            set_exit_state(&exit, current_exit, exit_response.clone());
 
             trace!("Got exit setup response {:?}", exit_response.clone());
 
@@ -303,6 +345,8 @@ pub struct ExitManager {
     // as that would cause a panic
     remote_logging_setting: bool,
     remote_logging_already_started: bool,
+    // whether forwarding over wg_exit is cut off because a spending cap was reached
+    exit_stopped: bool,
 }
 
 impl Actor for ExitManager {
@@ -339,8 +383,11 @@ impl Handler<Tick> for ExitManager {
                 // run billing at all times when an exit is setup
                 if self.last_exit.is_some() {
                     trace!("We are signed up for the selected exit!");
-                    TrafficWatcher::from_registry()
-                        .do_send(Watch(exit.id.clone(), general_details.exit_price));
+                    TrafficWatcher::from_registry().do_send(Watch(
+                        exit.id.clone(),
+                        general_details.exit_price,
+                        plan_usage(&exit.info),
+                    ));
                 }
                 // only run if we have our own details and we either have no setup exit or the chosen
                 // exit has changed
@@ -408,6 +455,42 @@ impl Handler<Tick> for ExitManager {
             }
         }
 
+        futs.push(Box::new(PaymentController::from_registry().send(GetSpending).then(
+            |res| {
+                match res {
+                    Ok(Ok(status)) => ExitManager::from_registry()
+                        .do_send(HardStop(status.hard_stop && status.cap_reached)),
+                    Ok(Err(e)) => warn!("Failed to get spending status {:?}", e),
+                    Err(e) => warn!("Payment controller mailbox error {:?}", e),
+                }
+                Ok(())
+            },
+        )));
+
         Box::new(join_all(futs).and_then(|_| Ok(()))) as ResponseFuture<(), Error>
     }
 }
+
+/// Cuts off or restores forwarding over the exit tunnel for the spending hard stop
+#[derive(Message)]
+struct HardStop(bool);
+
+impl Handler<HardStop> for ExitManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: HardStop, _ctx: &mut Context<Self>) -> Self::Result {
+        if msg.0 == self.exit_stopped {
+            return;
+        }
+
+        let group = RuleGroup::SuspendTunnel("wg_exit".to_string());
+        if msg.0 {
+            warn!("Spending cap reached, stopping traffic over the exit");
+            Firewall::from_registry().do_send(AddRules(group));
+        } else {
+            info!("Spending is under its caps again, resuming traffic over the exit");
+            Firewall::from_registry().do_send(RemoveRules(group));
+        }
+        self.exit_stopped = msg.0;
+    }
+}
@@ -1,4 +1,5 @@
 pub mod dashboard;
 pub mod exit_manager;
 pub mod rita_loop;
+pub mod stats_reporter;
 pub mod traffic_watcher;
@@ -10,6 +10,7 @@ use actix::prelude::*;
 use actix::registry::SystemService;
 
 use rita_client::exit_manager::ExitManager;
+use rita_common::metrics;
 
 use failure::Error;
 
@@ -53,6 +54,7 @@ impl Handler<Tick> for RitaLoop {
             start.elapsed().as_secs(),
             start.elapsed().subsec_nanos() / 1000000
         );
+        metrics::observe_tick("client", start);
         Ok(())
     }
 }
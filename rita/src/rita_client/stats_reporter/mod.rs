@@ -0,0 +1,260 @@
+//! StatsReporter periodically samples system statistics and, if the user has opted in, sends them
+//! in batches to a stats collector on the exit. Like remote logging the collector is only reached
+//! over the exit tunnel. Samples are kept until the collector has accepted them, if it can not be
+//! reached they are retried with an exponential backoff and the oldest are dropped once
+//! `max_buffered` samples have piled up.
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use actix::fut;
+use actix::prelude::*;
+use actix_web::client;
+use actix_web::client::Connection;
+
+use futures::Future;
+
+use tokio::net::TcpStream as TokioTcpStream;
+
+use althea_types::{Stats, StatsBatch};
+
+use settings::{RitaClientSettings, RitaCommonSettings};
+use KI;
+use SETTING;
+
+use failure::Error;
+
+/// Longest delay in seconds between attempts to reach the collector
+const MAX_RETRY_DELAY: u64 = 3600;
+
+pub struct StatsReporter {
+    samples: VecDeque<Stats>,
+    /// Samples currently being sent, returned to the front of the queue if sending fails
+    in_flight: Option<Vec<Stats>>,
+    failures: u32,
+    next_attempt: Instant,
+}
+
+impl Actor for StatsReporter {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        let interval = SETTING.get_stats().interval;
+        ctx.run_interval(Duration::from_secs(interval), |_act, ctx| {
+            ctx.address().do_send(Sample);
+        });
+    }
+}
+
+impl Supervised for StatsReporter {}
+impl SystemService for StatsReporter {
+    fn service_started(&mut self, _ctx: &mut Context<Self>) {
+        info!("Stats Reporter started");
+    }
+}
+
+impl Default for StatsReporter {
+    fn default() -> StatsReporter {
+        StatsReporter {
+            samples: VecDeque::new(),
+            in_flight: None,
+            failures: 0,
+            next_attempt: Instant::now(),
+        }
+    }
+}
+
+impl StatsReporter {
+    fn push(&mut self, sample: Stats, max_buffered: usize) {
+        self.samples.push_back(sample);
+        self.trim(max_buffered);
+    }
+
+    /// Puts a batch the collector did not accept back in front of the newer samples
+    fn requeue(&mut self, batch: Vec<Stats>, max_buffered: usize) {
+        for sample in batch.into_iter().rev() {
+            self.samples.push_front(sample);
+        }
+        self.trim(max_buffered);
+    }
+
+    fn trim(&mut self, max_buffered: usize) {
+        while self.samples.len() > max_buffered {
+            self.samples.pop_front();
+        }
+    }
+
+    fn take_batch(&mut self, batch_size: usize) -> Option<Vec<Stats>> {
+        if self.in_flight.is_some() || self.samples.len() < batch_size {
+            return None;
+        }
+        let batch: Vec<Stats> = self.samples.drain(..batch_size).collect();
+        self.in_flight = Some(batch.clone());
+        Some(batch)
+    }
+
+    fn retry_delay(&self, interval: u64) -> Duration {
+        let factor = 1u64 << self.failures.min(16);
+        let delay = interval.checked_mul(factor).unwrap_or(MAX_RETRY_DELAY);
+        Duration::from_secs(delay.min(MAX_RETRY_DELAY))
+    }
+}
+
+/// The collector listens on the exit's internal ip unless another address is configured, either
+/// way stats are only sent while we have an exit
+fn collector_addr() -> Option<SocketAddr> {
+    let (dest_ip, port) = {
+        let stats = SETTING.get_stats();
+        (stats.dest_ip, stats.dest_port)
+    };
+    let exit_client = SETTING.get_exit_client();
+    let current_exit = exit_client.get_current_exit()?;
+    let general_details = current_exit.info.general_details()?;
+    Some(SocketAddr::new(
+        dest_ip.unwrap_or(general_details.server_internal_ip),
+        port,
+    ))
+}
+
+pub fn send_stats(to: &SocketAddr, batch: StatsBatch) -> Box<Future<Item = (), Error = Error>> {
+    let endpoint = format!("http://{}/stats", to);
+
+    let stream = TokioTcpStream::connect(to);
+
+    Box::new(stream.from_err().and_then(move |stream| {
+        client::post(&endpoint)
+            .timeout(Duration::from_secs(8))
+            .with_connection(Connection::from_stream(stream))
+            .json(batch)
+            .unwrap()
+            .send()
+            .from_err()
+            .and_then(|response| {
+                if response.status().is_success() {
+                    Ok(())
+                } else {
+                    Err(format_err!("Collector responded with {}", response.status()))
+                }
+            })
+    }))
+}
+
+/// Takes a sample and sends a batch if enough have been collected
+pub struct Sample;
+
+impl Message for Sample {
+    type Result = Result<(), Error>;
+}
+
+impl Handler<Sample> for StatsReporter {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, _: Sample, ctx: &mut Context<Self>) -> Self::Result {
+        let settings = SETTING.get_stats().clone();
+        if !settings.enabled {
+            return Ok(());
+        }
+
+        match KI.get_stats() {
+            Ok(sample) => self.push(sample, settings.max_buffered),
+            Err(e) => warn!("Failed to sample stats with {:?}", e),
+        }
+
+        if Instant::now() < self.next_attempt {
+            return Ok(());
+        }
+        let (to, from) = match (collector_addr(), SETTING.get_identity()) {
+            (Some(to), Some(from)) => (to, from),
+            _ => {
+                trace!("No exit tunnel to send stats over yet");
+                return Ok(());
+            }
+        };
+        let samples = match self.take_batch(settings.batch_size) {
+            Some(samples) => samples,
+            None => return Ok(()),
+        };
+
+        trace!("Sending {} stats samples to {}", samples.len(), to);
+        ctx.spawn(
+            fut::wrap_future(send_stats(&to, StatsBatch { from, samples })).then(
+                move |res, act: &mut Self, _ctx| {
+                    let batch = act.in_flight.take().unwrap_or_default();
+                    match res {
+                        Ok(()) => {
+                            trace!("Collector accepted {} stats samples", batch.len());
+                            act.failures = 0;
+                        }
+                        Err(e) => {
+                            act.failures = act.failures.saturating_add(1);
+                            let delay = act.retry_delay(settings.interval);
+                            warn!("Sending stats failed with {:?}, retrying in {:?}", e, delay);
+                            act.next_attempt = Instant::now() + delay;
+                            act.requeue(batch, settings.max_buffered);
+                        }
+                    }
+                    fut::ok(())
+                },
+            ),
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use althea_types::{CpuInfo, LoadAvg, MemInfo, ProcStat};
+
+    fn sample(timestamp: u64) -> Stats {
+        Stats {
+            timestamp,
+            proc_stat: ProcStat::default(),
+            proc_load_avg: LoadAvg::default(),
+            devices: Vec::new(),
+            routes: Vec::new(),
+            meminfo: MemInfo::default(),
+            cpuinfo: CpuInfo::default(),
+        }
+    }
+
+    fn timestamps(reporter: &StatsReporter) -> Vec<u64> {
+        reporter.samples.iter().map(|s| s.timestamp).collect()
+    }
+
+    #[test]
+    fn test_batching() {
+        let mut reporter = StatsReporter::default();
+        for i in 0..5 {
+            reporter.push(sample(i), 4);
+        }
+        assert_eq!(timestamps(&reporter), vec![1, 2, 3, 4]);
+
+        assert!(reporter.take_batch(5).is_none());
+        let batch = reporter.take_batch(3).unwrap();
+        assert_eq!(batch.len(), 3);
+        // only one batch is sent at a time
+        assert!(reporter.take_batch(1).is_none());
+
+        // a failed batch goes back in front of what was sampled meanwhile
+        reporter.push(sample(5), 4);
+        reporter.in_flight = None;
+        reporter.requeue(batch, 4);
+        assert_eq!(timestamps(&reporter), vec![2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let mut reporter = StatsReporter::default();
+        assert_eq!(reporter.retry_delay(60), Duration::from_secs(60));
+        reporter.failures = 3;
+        assert_eq!(reporter.retry_delay(60), Duration::from_secs(480));
+        reporter.failures = 40;
+        assert_eq!(reporter.retry_delay(60), Duration::from_secs(MAX_RETRY_DELAY));
+        assert_eq!(
+            reporter.retry_delay(u64::max_value()),
+            Duration::from_secs(MAX_RETRY_DELAY)
+        );
+    }
+}
@@ -5,26 +5,50 @@
 //! This is the client specific billing code used to determine how exits should be compensted. Which is
 //! different in that mesh nodes are paid by forwarding traffic, but exits have to return traffic and
 //! must get paid for doing so.
+//!
+//! If the exit has put us on a billing plan we apply it the same way the exit does, so that what
+//! we think we owe matches what the exit bills us.
 
 use actix::prelude::*;
 use failure::Error;
 use ipnetwork::IpNetwork;
+use futures::Future;
 use reqwest;
 
-use std::collections::HashMap;
-use std::io::{Read, Write};
-use std::net::{IpAddr, SocketAddr, TcpStream};
-use std::time::{Duration, SystemTime};
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant, SystemTime};
 
+use althea_kernel_interface::RuleGroup;
 use althea_types::{Identity, RTTimestamps};
-use babel_monitor::Babel;
+use babel_monitor;
+use babel_monitor::Route;
 use num256::Int256;
 use rita_common::debt_keeper::{DebtKeeper, TrafficUpdate};
+use rita_common::firewall::{AddRules, Firewall};
+use rita_common::overadvertisement::{AdvertisementMonitor, Observe, Sample};
+use rita_common::payment_controller::spending::unix_time;
+use rita_common::traffic_watcher::billing::{apply_plan, charge, credit, PlanUsage};
 use settings::{RitaClientSettings, RitaCommonSettings};
 use KI;
 use SETTING;
 
-pub struct TrafficWatcher;
+lazy_static! {
+    /// Kept across rounds so the connection to the exit is reused and the RTT measurement doesn't
+    /// include setting it up
+    static ref RTT_CLIENT: reqwest::Client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .expect("Failed to build the exit RTT client");
+}
+
+pub struct TrafficWatcher {
+    last_watch: Option<Instant>,
+    /// Our usage of the exit's billing plan, if we are on one
+    plan: Option<PlanUsage>,
+    /// The usage the exit last reported, ours is reset to it whenever a new report comes in
+    reported: Option<PlanUsage>,
+}
 
 impl Actor for TrafficWatcher {
     type Context = Context<Self>;
@@ -34,44 +58,65 @@ impl SystemService for TrafficWatcher {
     fn service_started(&mut self, _ctx: &mut Context<Self>) {
         info!("Client traffic watcher started");
 
-        KI.init_iface_counters("wg_exit").unwrap();
+        Firewall::from_registry()
+            .do_send(AddRules(RuleGroup::IfaceCounters("wg_exit".to_string())));
     }
 }
 impl Default for TrafficWatcher {
     fn default() -> TrafficWatcher {
-        TrafficWatcher {}
+        TrafficWatcher {
+            last_watch: None,
+            plan: None,
+            reported: None,
+        }
     }
 }
 
-pub struct Watch(pub Identity, pub u64);
+/// The exit, its per byte price and the plan it bills us on, if any
+pub struct Watch(pub Identity, pub u64, pub Option<PlanUsage>);
 
 impl Message for Watch {
     type Result = Result<(), Error>;
 }
 
 impl Handler<Watch> for TrafficWatcher {
-    type Result = Result<(), Error>;
+    type Result = ResponseActFuture<Self, (), Error>;
 
     fn handle(&mut self, msg: Watch, _: &mut Context<Self>) -> Self::Result {
-        let stream = TcpStream::connect::<SocketAddr>(
-            format!("[::1]:{}", SETTING.get_network().babel_port).parse()?,
-        )?;
+        let start = Instant::now();
+        let elapsed = match self.last_watch {
+            Some(last) => last.elapsed(),
+            None => Duration::from_secs(0),
+        };
+        let elapsed_ms = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_nanos() / 1_000_000);
+        self.last_watch = Some(start);
 
-        watch(Babel::new(stream), msg.0, msg.1)
+        // the exit's count is the one we are billed on, between its reports we count on our own
+        if self.reported != msg.2 {
+            self.plan = msg.2.clone();
+            self.reported = msg.2;
+        }
+
+        let exit = msg.0;
+        let exit_price = msg.1;
+        Box::new(babel_monitor::routes().into_actor(self).and_then(
+            move |routes, act: &mut Self, _ctx| {
+                fut::result(watch(routes, exit, exit_price, act.plan.as_mut(), elapsed_ms))
+            },
+        ))
     }
 }
 
 /// This traffic watcher watches how much traffic we send to the exit, and how much the exit sends
-/// back to us.
-pub fn watch<T: Read + Write>(
-    mut babel: Babel<T>,
+/// back to us. `plan` is updated with our usage, `elapsed_ms` is the time since the last round
+/// which plan fees are charged for.
+pub fn watch(
+    routes: VecDeque<Route>,
     exit: Identity,
     exit_price: u64,
+    plan: Option<&mut PlanUsage>,
+    elapsed_ms: u64,
 ) -> Result<(), Error> {
-    babel.start_connection()?;
-
-    trace!("Getting routes");
-    let routes = babel.parse_routes()?;
     info!("Got routes: {:?}", routes);
 
     let mut destinations = HashMap::new();
@@ -108,14 +153,10 @@ pub fn watch<T: Read + Write>(
     trace!("exit price {}", exit_price);
 
     if destinations.contains_key(&exit.mesh_ip) {
-        let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(5))
-            .build()?;
-
         let target_route = destinations[&exit.mesh_ip];
         let exit_dest_price: Int256 = Int256::from(target_route.price) + exit_price;
         let client_tx = SystemTime::now();
-        let RTTimestamps { exit_rx, exit_tx } = client
+        let timestamps: RTTimestamps = RTT_CLIENT
             .get(&format!(
                 "http://[{}]:{}/rtt",
                 exit.mesh_ip,
@@ -131,7 +172,7 @@ pub fn watch<T: Read + Write>(
             .json()?;
         let client_rx = SystemTime::now();
 
-        let inner_rtt = client_rx.duration_since(client_tx)? - exit_tx.duration_since(exit_rx)?;
+        let inner_rtt = timestamps.inner_rtt(client_tx, client_rx)?;
         let inner_rtt_millis =
             inner_rtt.as_secs() as f32 * 1000.0 + inner_rtt.subsec_nanos() as f32 / 1_000_000.0;
         //                        secs -> millis                            nanos -> millis
@@ -141,12 +182,31 @@ pub fn watch<T: Read + Write>(
             target_route.full_path_rtt,
             inner_rtt_millis
         );
+        AdvertisementMonitor::from_registry().do_send(Observe {
+            iface: target_route.iface.clone(),
+            sample: Sample::Rtt {
+                advertised: target_route.full_path_rtt,
+                measured: inner_rtt_millis,
+            },
+        });
 
         trace!("exit destination price {}", exit_dest_price);
         trace!("Exit ip: {:?}", exit.mesh_ip);
         trace!("Exit destination:\n{:#?}", target_route);
 
-        owes += Int256::from(exit_price * output) + exit_dest_price * input;
+        // the exit counts what we send as its input, so it comes first when applying the plan
+        let (output, input, fee) = match plan {
+            Some(usage) => {
+                let bill = apply_plan(usage, output, input, unix_time(), elapsed_ms)?;
+                trace!("Plan bill: {:?}", bill);
+                (bill.billable_in, bill.billable_out, bill.fee)
+            }
+            None => (output, input, Int256::zero()),
+        };
+
+        credit(&mut owes, &charge(&Int256::from(exit_price), output)?)?;
+        credit(&mut owes, &charge(&exit_dest_price, input)?)?;
+        credit(&mut owes, &fee)?;
 
         let update = TrafficUpdate {
             from: exit.clone(),
@@ -170,6 +230,8 @@ mod tests {
 
     use super::*;
     use althea_types::EthAddress;
+    use babel_monitor::Babel;
+    use std::net::{SocketAddr, TcpStream};
     use std::str::FromStr;
 
     #[test]
@@ -177,14 +239,18 @@ mod tests {
     fn debug_babel_socket_client() {
         env_logger::init();
         let bm_stream = TcpStream::connect::<SocketAddr>("[::1]:9001".parse().unwrap()).unwrap();
+        let mut babel = Babel::new(bm_stream);
+        babel.start_connection().unwrap();
         watch(
-            Babel::new(bm_stream),
+            babel.parse_routes().unwrap(),
             Identity::new(
                 "0.0.0.0".parse().unwrap(),
                 EthAddress::from_str("abababababababababab").unwrap(),
                 String::from("abc0abc1abc2abc3abc4abc5abc6abc7abc8abc9"),
             ),
             5,
+            None,
+            0,
         ).unwrap();
     }
 }
@@ -0,0 +1,477 @@
+//! Joining a SubnetDAO ourselves. A join is a transaction to the DAO contract requesting
+//! membership for our mesh ip, signed with our eth key and paying the DAO's membership fee if it
+//! charges one. The transaction is tracked until it has been mined, once it succeeds the DAO is
+//! added to `dao_addresses` so that our neighbors are checked against it too.
+
+use actix::prelude::*;
+use futures::future::{err, Either};
+use futures::Future;
+use std::net::IpAddr;
+use std::time::Duration;
+use std::time::Instant;
+
+use failure::Error;
+
+use althea_types::{EthAddress, EthPrivateKey};
+use num256::Uint256;
+use settings::RitaCommonSettings;
+use web3::abi::{decode_uint, encode_call, Token};
+use web3::{
+    format_hash, key_address, Transaction, TransactionReceipt, TransactionRequest, Web3Client,
+};
+
+use super::DAOManager;
+
+use SETTING;
+
+/// Returns the fee in wei a DAO charges to join, DAOs without the function are free to join
+const FEE_SIGNATURE: &str = "getMembershipFee()";
+/// Payable, adds the sender to the DAO for the given mesh ip
+const JOIN_SIGNATURE: &str = "requestMembership(bytes16)";
+const JOIN_GAS_LIMIT: u64 = 200_000;
+/// Seconds to wait for a join to be mined before giving up on it
+const JOIN_TIMEOUT: u64 = 3600;
+
+#[derive(Debug, Fail)]
+pub enum JoinError {
+    #[fail(display = "No eth private key configured")]
+    NoKey,
+    #[fail(display = "Our eth key doesn't belong to eth address {:?}", _0)]
+    KeyMismatch(EthAddress),
+    #[fail(display = "No ipv6 mesh ip to join with")]
+    NoMeshIp,
+    #[fail(display = "Already joining {:?}", _0)]
+    AlreadyJoining(EthAddress),
+    #[fail(
+        display = "The membership fee of {} wei is more than max_join_fee {} wei",
+        fee,
+        max
+    )]
+    FeeTooHigh { fee: Uint256, max: Uint256 },
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum JoinState {
+    /// Submitted and waiting to be mined
+    Pending { tx_hash: String, fee: Uint256 },
+    Active { tx_hash: String },
+    Failed { tx_hash: String, reason: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct DAOJoin {
+    pub state: JoinState,
+    tx_hash: Uint256,
+    submitted: Instant,
+}
+
+impl DAOJoin {
+    fn new(tx_hash: Uint256, fee: Uint256, submitted: Instant) -> DAOJoin {
+        DAOJoin {
+            state: JoinState::Pending {
+                tx_hash: format_hash(&tx_hash),
+                fee,
+            },
+            tx_hash,
+            submitted,
+        }
+    }
+
+    fn is_pending(&self) -> bool {
+        match self.state {
+            JoinState::Pending { .. } => true,
+            _ => false,
+        }
+    }
+
+    fn fail(&mut self, reason: &str) {
+        self.state = JoinState::Failed {
+            tx_hash: format_hash(&self.tx_hash),
+            reason: reason.to_string(),
+        };
+    }
+
+    /// Updates the state from the transaction receipt, true if we just became a member
+    fn apply_receipt(&mut self, receipt: &TransactionReceipt) -> bool {
+        if !self.is_pending() || receipt.block_number.is_none() {
+            return false;
+        }
+        if receipt.status == Some(false) {
+            self.fail("The DAO rejected the membership request");
+            false
+        } else {
+            self.state = JoinState::Active {
+                tx_hash: format_hash(&self.tx_hash),
+            };
+            true
+        }
+    }
+}
+
+/// Our key and the call data requesting membership for our mesh ip
+fn join_request() -> Result<(EthPrivateKey, Vec<u8>), Error> {
+    let key = match SETTING.get_payment().eth_private_key {
+        Some(key) => key,
+        None => return Err(JoinError::NoKey.into()),
+    };
+    let address = SETTING.get_payment().eth_address;
+    if key_address(&key)? != address {
+        return Err(JoinError::KeyMismatch(address).into());
+    }
+    let ip_bytes = match SETTING.get_identity().map(|id| id.mesh_ip) {
+        Some(IpAddr::V6(ip)) => ip.octets(),
+        _ => return Err(JoinError::NoMeshIp.into()),
+    };
+    let data = encode_call(JOIN_SIGNATURE, &[Token::FixedBytes(ip_bytes.to_vec())])?;
+    Ok((key, data))
+}
+
+/// The fee to send along with the join, an empty result means the DAO doesn't have a fee
+/// function. Errors are passed on since sending the join with a wrong fee would waste it.
+fn membership_fee(
+    web3: &Web3Client,
+    dao: EthAddress,
+    from: EthAddress,
+) -> Box<Future<Item = Uint256, Error = Error>> {
+    let call = TransactionRequest {
+        from: Some(from),
+        to: dao,
+        data: match encode_call(FEE_SIGNATURE, &[]) {
+            Ok(data) => data,
+            Err(e) => return Box::new(err(e)),
+        },
+    };
+    Box::new(web3.eth_call(&call).and_then(|data| {
+        if data.is_empty() {
+            Ok(Uint256::zero())
+        } else {
+            decode_uint(&data)
+        }
+    }))
+}
+
+/// Looks up everything the join transaction needs, signs it and sends it off, returns the
+/// transaction hash and the fee paid. Nothing is signed if the fee is above `max_fee`.
+fn submit_join(
+    web3: Web3Client,
+    dao: EthAddress,
+    key: EthPrivateKey,
+    data: Vec<u8>,
+    max_fee: Uint256,
+) -> Box<Future<Item = (Uint256, Uint256), Error = Error>> {
+    let address = match key_address(&key) {
+        Ok(address) => address,
+        Err(e) => return Box::new(err(e)),
+    };
+    let info = membership_fee(&web3, dao, address).join4(
+        web3.eth_get_transaction_count(&address),
+        web3.eth_gas_price(),
+        web3.net_version(),
+    );
+    Box::new(info.and_then(move |(fee, nonce, gas_price, chain_id)| {
+        if fee > max_fee {
+            return Either::B(err(JoinError::FeeTooHigh { fee, max: max_fee }.into()));
+        }
+        let transaction = Transaction {
+            nonce,
+            gas_price,
+            gas_limit: Uint256::from(JOIN_GAS_LIMIT),
+            to: dao,
+            value: fee,
+            data,
+        };
+        trace!("Sending DAO join {:?}", transaction);
+        match transaction.sign(&key, chain_id) {
+            Ok(raw) => Either::A(
+                web3.eth_send_raw_transaction(&raw)
+                    .map(move |tx_hash| (tx_hash, fee)),
+            ),
+            Err(e) => Either::B(err(e)),
+        }
+    }))
+}
+
+/// Requests membership in a DAO, returns the transaction hash
+pub struct JoinDAO(pub EthAddress);
+
+impl Message for JoinDAO {
+    type Result = Result<String, Error>;
+}
+
+impl Handler<JoinDAO> for DAOManager {
+    type Result = ResponseActFuture<Self, String, Error>;
+
+    fn handle(&mut self, msg: JoinDAO, _: &mut Context<Self>) -> Self::Result {
+        let dao = msg.0;
+        if self.joins.get(&dao).map(|join| join.is_pending()) == Some(true) {
+            return Box::new(fut::err(JoinError::AlreadyJoining(dao).into()));
+        }
+        let (key, data) = match join_request() {
+            Ok(request) => request,
+            Err(e) => return Box::new(fut::err(e)),
+        };
+        self.web3.set_nodes(&SETTING.get_dao().node_list);
+
+        Box::new(
+            submit_join(
+                self.web3.clone(),
+                dao,
+                key,
+                data,
+                SETTING.get_dao().max_join_fee,
+            )
+                .into_actor(self)
+                .map(move |(tx_hash, fee), act, _ctx| {
+                    info!("Requested membership in DAO {:?} with {:?}", dao, tx_hash);
+                    act.joins
+                        .insert(dao, DAOJoin::new(tx_hash, fee, Instant::now()));
+                    format_hash(&tx_hash)
+                }),
+        )
+    }
+}
+
+/// Checks on the joins that haven't been mined yet
+pub struct CheckJoins;
+
+impl Message for CheckJoins {
+    type Result = ();
+}
+
+impl Handler<CheckJoins> for DAOManager {
+    type Result = ();
+
+    fn handle(&mut self, _: CheckJoins, _: &mut Context<Self>) -> Self::Result {
+        let now = Instant::now();
+        for (dao, join) in self.joins.iter_mut() {
+            if !join.is_pending() {
+                continue;
+            }
+            if now - join.submitted > Duration::from_secs(JOIN_TIMEOUT) {
+                warn!("Join of DAO {:?} was not mined in time", dao);
+                join.fail("The membership request was not mined in time");
+                continue;
+            }
+            let dao = *dao;
+            let tx_hash = join.tx_hash;
+            Arbiter::spawn(
+                self.web3
+                    .eth_get_transaction_receipt(&tx_hash)
+                    .then(move |receipt| {
+                        DAOManager::from_registry().do_send(JoinReceipt {
+                            dao,
+                            tx_hash,
+                            receipt,
+                        });
+                        Ok(())
+                    }),
+            );
+        }
+    }
+}
+
+struct JoinReceipt {
+    dao: EthAddress,
+    tx_hash: Uint256,
+    receipt: Result<Option<TransactionReceipt>, Error>,
+}
+
+impl Message for JoinReceipt {
+    type Result = ();
+}
+
+impl Handler<JoinReceipt> for DAOManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: JoinReceipt, _: &mut Context<Self>) -> Self::Result {
+        let receipt = match msg.receipt {
+            Ok(Some(receipt)) => receipt,
+            Ok(None) => return,
+            Err(e) => {
+                warn!("Failed to get the receipt of DAO join {:?}", e);
+                return;
+            }
+        };
+        let joined = match self.joins.get_mut(&msg.dao) {
+            Some(join) => join.tx_hash == msg.tx_hash && join.apply_receipt(&receipt),
+            None => false,
+        };
+        if joined {
+            info!("Joined DAO {:?}", msg.dao);
+            let configured = SETTING.get_dao().dao_addresses.contains(&msg.dao);
+            if !configured {
+                SETTING.get_dao_mut().dao_addresses.push(msg.dao);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DAOStatus {
+    pub address: EthAddress,
+    /// Whether our neighbors are checked against this DAO
+    pub enforced: bool,
+    pub join: Option<JoinState>,
+}
+
+pub struct GetDAOStatus;
+
+impl Message for GetDAOStatus {
+    type Result = Result<Vec<DAOStatus>, Error>;
+}
+
+impl Handler<GetDAOStatus> for DAOManager {
+    type Result = Result<Vec<DAOStatus>, Error>;
+
+    fn handle(&mut self, _: GetDAOStatus, _: &mut Context<Self>) -> Self::Result {
+        let enforced = SETTING.get_dao().dao_addresses.clone();
+        let mut list: Vec<DAOStatus> = enforced
+            .iter()
+            .map(|address| DAOStatus {
+                address: *address,
+                enforced: true,
+                join: self.joins.get(address).map(|join| join.state.clone()),
+            }).collect();
+        for (address, join) in self.joins.iter() {
+            if !enforced.contains(address) {
+                list.push(DAOStatus {
+                    address: *address,
+                    enforced: false,
+                    join: Some(join.state.clone()),
+                });
+            }
+        }
+        Ok(list)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::System;
+    use serde_json::Value;
+    use web3::mock::MockNode;
+
+    fn receipt(block_number: Option<u64>, status: Option<bool>) -> TransactionReceipt {
+        TransactionReceipt {
+            transaction_hash: Uint256::from(1u64),
+            block_number: block_number.map(Uint256::from),
+            gas_used: Uint256::from(50_000u64),
+            status,
+        }
+    }
+
+    #[test]
+    fn test_apply_receipt() {
+        let mut join = DAOJoin::new(Uint256::from(1u64), Uint256::zero(), Instant::now());
+        assert!(!join.apply_receipt(&receipt(None, None)));
+        assert!(join.is_pending());
+        assert!(join.apply_receipt(&receipt(Some(10), Some(true))));
+        assert_eq!(
+            join.state,
+            JoinState::Active {
+                tx_hash: format_hash(&Uint256::from(1u64))
+            }
+        );
+        // once decided later receipts don't change anything
+        assert!(!join.apply_receipt(&receipt(Some(10), Some(false))));
+
+        let mut join = DAOJoin::new(Uint256::from(1u64), Uint256::zero(), Instant::now());
+        assert!(!join.apply_receipt(&receipt(Some(10), Some(false))));
+        match join.state {
+            JoinState::Failed { .. } => (),
+            ref state => panic!("Unexpected state {:?}", state),
+        }
+        // nodes from before Byzantium don't report a status
+        let mut join = DAOJoin::new(Uint256::from(1u64), Uint256::zero(), Instant::now());
+        assert!(join.apply_receipt(&receipt(Some(10), None)));
+    }
+
+    #[test]
+    fn test_submit_join() {
+        let fee = format!("0x{}0a", "00".repeat(31));
+        let node = MockNode::new()
+            .result("eth_call", Value::String(fee))
+            .result("eth_getTransactionCount", Value::String("0x3".to_string()))
+            .result("eth_gasPrice", Value::String("0x1".to_string()))
+            .result("net_version", Value::String("4".to_string()))
+            .result(
+                "eth_sendRawTransaction",
+                Value::String(format_hash(&Uint256::from(5u64))),
+            ).start();
+        let web3 = Web3Client::new(&[node.url.clone()], Duration::from_secs(5));
+        let key = EthPrivateKey::from([0x46u8; 32]);
+
+        let (tx_hash, fee) = System::new("test")
+            .block_on(submit_join(
+                web3,
+                2.into(),
+                key,
+                vec![0xab],
+                Uint256::from(10u64),
+            )).unwrap();
+        assert_eq!(tx_hash, Uint256::from(5u64));
+        assert_eq!(fee, Uint256::from(10u64));
+
+        let requests = node.requests();
+        let method = |request: &Value| request["method"].as_str().unwrap().to_string();
+        assert_eq!(method(requests.last().unwrap()), "eth_sendRawTransaction");
+        let count = requests
+            .iter()
+            .find(|request| method(request) == "eth_getTransactionCount")
+            .unwrap();
+        assert_eq!(
+            count["params"][0],
+            Value::String("0x9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f".to_string())
+        );
+    }
+
+    #[test]
+    fn test_fee_above_max() {
+        let fee = format!("0x{}0a", "00".repeat(31));
+        let node = MockNode::new()
+            .result("eth_call", Value::String(fee))
+            .result("eth_getTransactionCount", Value::String("0x3".to_string()))
+            .result("eth_gasPrice", Value::String("0x1".to_string()))
+            .result("net_version", Value::String("4".to_string()))
+            .start();
+        let web3 = Web3Client::new(&[node.url.clone()], Duration::from_secs(5));
+        let key = EthPrivateKey::from([0x46u8; 32]);
+
+        let res = System::new("test").block_on(submit_join(
+            web3,
+            2.into(),
+            key,
+            vec![0xab],
+            Uint256::from(9u64),
+        ));
+        assert!(res.is_err());
+        let method = |request: &Value| request["method"].as_str().unwrap().to_string();
+        assert!(
+            node.requests()
+                .iter()
+                .all(|request| method(request) != "eth_sendRawTransaction")
+        );
+    }
+
+    #[test]
+    fn test_free_dao() {
+        let node = MockNode::new()
+            .result("eth_call", Value::String("0x".to_string()))
+            .start();
+        let web3 = Web3Client::new(&[node.url.clone()], Duration::from_secs(5));
+        let fee = System::new("test").block_on(membership_fee(&web3, 2.into(), 1.into()));
+        assert_eq!(fee.unwrap(), Uint256::zero());
+    }
+
+    #[test]
+    fn test_fee_error() {
+        let node = MockNode::new()
+            .error("eth_call", -32015, "VM execution error.")
+            .start();
+        let web3 = Web3Client::new(&[node.url.clone()], Duration::from_secs(5));
+        let fee = System::new("test").block_on(membership_fee(&web3, 2.into(), 1.into()));
+        assert!(fee.is_err());
+    }
+}
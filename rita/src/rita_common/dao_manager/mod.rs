@@ -1,48 +1,106 @@
 //! Manages subnet DAO membership, DAOManager mantains a cache of subnet DAO entries.
-//! The workflow goes as follows, an actor message DAOCheck is sent to DAOManager
-//! if the identity is not on the DAO it will run a callback to tunnel manager to remove
-//! that tunnel from operation. If the identity is on the DAO it will do nothing.
+//! The workflow goes as follows, an actor message DAOCheck is sent to DAOManager which
+//! looks up the identity on every configured DAO it doesn't have a fresh answer for, the
+//! answers are combined into a single decision, member of any DAO or not, which is sent
+//! to TunnelManager to confirm or expire that identity's tunnels.
 //! Entires from the DAO are cached for a configurable amount of time, this may of course
 //! have the effect of adding someone to the DAO taking time to kick in.
+//! Until a full node has answered at least once we are assumed to be bootstrapping, likely
+//! because the full nodes can only be reached through the very tunnels we are checking, and
+//! everyone is allowed.
 
 use actix::prelude::*;
-use actix_web::client::Connection;
-use actix_web::error::JsonPayloadError;
-use actix_web::*;
-use futures::future::ok;
 use futures::Future;
-use rand::thread_rng;
-use rand::Rng;
-use serde_json::Value;
 use std::collections::HashMap;
 use std::net::IpAddr;
-use std::net::SocketAddr;
-use std::net::ToSocketAddrs;
 use std::time::Duration;
 use std::time::Instant;
-use tokio::net::TcpStream as TokioTcpStream;
+
+use failure::Error;
 
 use althea_types::EthAddress;
 use althea_types::Identity;
 use num256::Uint256;
+use rita_common::metrics;
 use rita_common::tunnel_manager::TunnelAction;
 use rita_common::tunnel_manager::TunnelManager;
 use rita_common::tunnel_manager::TunnelStateChange;
 use settings::RitaCommonSettings;
+use web3::abi::{decode_uint, encode_call_with_selector, Token};
+use web3::{TransactionRequest, Web3Client};
+
+#[cfg(test)]
+use serde_json::Value;
+#[cfg(test)]
+use web3::mock::MockNode;
 
 use SETTING;
 
-// A json object specifcally for the web3 function
-// call response we expect from the SubnetDAO contract
-#[derive(Deserialize, Debug)]
-pub struct Web3Response {
-    jsonrpc: String,
-    result: Option<String>,
-    error: Option<Value>,
+mod join;
+
+pub use self::join::{CheckJoins, DAOStatus, GetDAOStatus, JoinDAO, JoinState};
+
+/// Seconds a lookup may go unanswered before it's sent again
+const LOOKUP_TIMEOUT: u64 = 30;
+/// Seconds a single full node gets to answer
+const NODE_TIMEOUT: u64 = 8;
+/// The SubnetDAO membership check, takes the mesh ip as bytes16
+const MEMBERSHIP_SELECTOR: [u8; 4] = [0x37, 0x66, 0x79, 0xb0];
+
+#[derive(Debug, Fail)]
+pub enum DAOManagerError {
+    #[fail(display = "Mesh IP {} is not ipv6 and can't be looked up", _0)]
+    NonIpv6MeshIp(IpAddr),
+}
+
+/// Reads the result of a SubnetDAO membership call, any nonzero result means the identity
+/// is on the list
+fn parse_membership(result: &[u8]) -> Result<bool, Error> {
+    Ok(decode_uint(result)? != Uint256::zero())
+}
+
+/// What we know about an identity on one DAO
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DAOEntry {
+    /// The last answer from the DAO, kept while it's being refreshed
+    on_list: Option<bool>,
+    last_updated: Option<Instant>,
+    /// When the lookup currently in flight was sent
+    pending: Option<Instant>,
+}
+
+impl DAOEntry {
+    fn needs_lookup(&self, now: Instant, cache_timeout: Duration) -> bool {
+        let in_flight = match self.pending {
+            Some(sent) => now - sent < Duration::from_secs(LOOKUP_TIMEOUT),
+            None => false,
+        };
+        let fresh = match self.last_updated {
+            Some(updated) => now - updated < cache_timeout,
+            None => false,
+        };
+        !in_flight && !fresh
+    }
+}
+
+/// The combined answer of every configured DAO for an identity
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Membership {
+    /// On at least one of the DAOs
+    Member,
+    /// Every DAO has answered and none of them list the identity
+    NotMember,
+    /// Not on any DAO that has answered but some haven't yet
+    Unknown,
 }
 
 pub struct DAOManager {
-    ident2dao: HashMap<Identity, Vec<DAOEntry>>,
+    ident2dao: HashMap<Identity, HashMap<EthAddress, DAOEntry>>,
+    /// Set once any full node has answered, until then we are bootstrapping
+    reached_full_node: bool,
+    web3: Web3Client,
+    /// Our own requests to join DAOs
+    joins: HashMap<EthAddress, join::DAOJoin>,
 }
 
 impl Actor for DAOManager {
@@ -51,7 +109,7 @@ impl Actor for DAOManager {
 impl Supervised for DAOManager {}
 impl SystemService for DAOManager {
     fn service_started(&mut self, _ctx: &mut Context<Self>) {
-        info!("Tunnel manager started");
+        info!("DAO manager started");
     }
 }
 
@@ -64,17 +122,91 @@ impl Default for DAOManager {
 impl DAOManager {
     fn new() -> DAOManager {
         DAOManager {
-            ident2dao: HashMap::<Identity, Vec<DAOEntry>>::new(),
+            ident2dao: HashMap::new(),
+            reached_full_node: false,
+            web3: Web3Client::new(&[], Duration::from_secs(NODE_TIMEOUT)),
+            joins: HashMap::new(),
         }
     }
-}
 
-#[derive(Debug)]
-pub struct DAOEntry {
-    on_list: bool,
-    dao_address: EthAddress,
-    id: Identity,
-    last_updated: Instant,
+    /// Marks every DAO without a fresh answer or a lookup in flight as pending and returns them
+    fn start_lookups(
+        &mut self,
+        id: &Identity,
+        daos: &[EthAddress],
+        now: Instant,
+        cache_timeout: Duration,
+    ) -> Vec<EthAddress> {
+        let entries = self.ident2dao.entry(id.clone()).or_insert_with(HashMap::new);
+        let mut lookups = Vec::new();
+        for dao in daos {
+            let entry = entries.entry(*dao).or_insert_with(DAOEntry::default);
+            if entry.needs_lookup(now, cache_timeout) {
+                entry.pending = Some(now);
+                lookups.push(*dao);
+            }
+        }
+        lookups
+    }
+
+    /// Records the result of a lookup, a failed lookup keeps the previous answer
+    fn record(&mut self, id: &Identity, dao: EthAddress, on_list: Option<bool>, now: Instant) {
+        if on_list.is_some() {
+            self.reached_full_node = true;
+        }
+        let entry = self
+            .ident2dao
+            .entry(id.clone())
+            .or_insert_with(HashMap::new)
+            .entry(dao)
+            .or_insert_with(DAOEntry::default);
+        entry.pending = None;
+        if on_list.is_some() {
+            entry.on_list = on_list;
+            entry.last_updated = Some(now);
+        }
+        trace!("Updated DAO {:?} entry for {:?} to {:?}", dao, id, entry);
+    }
+
+    /// Combines the answers from the configured DAOs, answers from DAOs that are no longer
+    /// configured are ignored. Expired answers still count until they are replaced.
+    fn membership(&self, id: &Identity, daos: &[EthAddress]) -> Membership {
+        let entries = match self.ident2dao.get(id) {
+            Some(entries) => entries,
+            None => return Membership::Unknown,
+        };
+        let answers: Vec<Option<bool>> = daos
+            .iter()
+            .map(|dao| entries.get(dao).and_then(|entry| entry.on_list))
+            .collect();
+        if answers.contains(&Some(true)) {
+            Membership::Member
+        } else if answers.iter().all(|answer| *answer == Some(false)) {
+            Membership::NotMember
+        } else {
+            Membership::Unknown
+        }
+    }
+
+    /// Whether the identity's tunnels should be up, None if it can't be decided yet
+    fn decide(&self, id: &Identity, daos: &[EthAddress]) -> Option<bool> {
+        match self.membership(id, daos) {
+            Membership::Member => Some(true),
+            Membership::NotMember => Some(false),
+            Membership::Unknown if !self.reached_full_node => {
+                trace!("No full node reached yet, allowing {:?} while bootstrapping", id);
+                Some(true)
+            }
+            Membership::Unknown => None,
+        }
+    }
+
+    fn send_decision(&self, id: &Identity, daos: &[EthAddress]) {
+        match self.decide(id, daos) {
+            Some(on_dao) => send_membership_message(on_dao, id.clone()),
+            None => trace!("Waiting on DAO lookups for {:?}", id),
+        }
+    }
 }
 
 pub struct DAOCheck(pub Identity);
@@ -85,17 +217,53 @@ impl Message for DAOCheck {
 impl Handler<DAOCheck> for DAOManager {
     type Result = ();
 
+    /// Checks if an identity is in at least one of the set of DAO's we are a member of.
+    /// will check the cache first before going out and updating via web3
     fn handle(&mut self, msg: DAOCheck, _: &mut Context<Self>) -> Self::Result {
         let their_id = msg.0;
-        check_cache(their_id, &self.ident2dao);
+        trace!("Checking the DAOManager Cache for {:?}", their_id);
+        let (daos, cache_timeout) = {
+            let dao_settings = SETTING.get_dao();
+            self.web3.set_nodes(&dao_settings.node_list);
+            // we don't care about subnet DAO's, short circuit.
+            if !dao_settings.dao_enforcement || dao_settings.dao_addresses.is_empty() {
+                trace!("DAO enforcement disabled DAOMAnager doing nothing!");
+                send_membership_message(true, their_id);
+                return;
+            }
+            (
+                dao_settings.dao_addresses.clone(),
+                Duration::from_secs(dao_settings.cache_timeout_seconds),
+            )
+        };
+
+        let cached = self.ident2dao.contains_key(&their_id);
+        let now = Instant::now();
+        let lookups = self.start_lookups(&their_id, &daos, now, cache_timeout);
+        let label = match (cached, lookups.is_empty()) {
+            (false, _) => "miss",
+            (true, true) => "hit",
+            (true, false) => "expired",
+        };
+        metrics::DAO_CACHE.with_label_values(&[label]).inc();
+
+        for dao in lookups {
+            if let Err(e) = get_membership(&self.web3, dao, their_id.clone()) {
+                warn!("DAO membership lookup failed with {:?}", e);
+                self.record(&their_id, dao, None, now);
+            }
+        }
+        self.send_decision(&their_id, &daos);
     }
 }
 
-/// Called by returning DAO requests, sends a message to TunnelManager
+/// Called by returning DAO requests, sends a message to TunnelManager once the
+/// combined membership of the identity is known
 pub struct CacheCallback {
     id: Identity,
     dao_address: EthAddress,
-    response: Web3Response,
+    /// The raw result of the membership call
+    response: Result<Vec<u8>, Error>,
 }
 
 impl Message for CacheCallback {
@@ -106,81 +274,21 @@ impl Handler<CacheCallback> for DAOManager {
     type Result = ();
 
     fn handle(&mut self, msg: CacheCallback, _: &mut Context<Self>) -> Self::Result {
-        let their_id = msg.id;
-        let dao_address = msg.dao_address;
-        let response = msg.response;
-        trace!("Got response {:?}", response);
-        let num: Uint256 = match response.result {
-            Some(uint) => match uint.replace("0x", "").parse() {
-                Ok(parsed) => parsed,
-                Err(e) => {
-                    trace!("Parsing error {:?}", e);
-                    return ();
-                }
-            },
-            None => {
-                trace!("Full node request got errror {:?}", response);
-                return ();
+        trace!("Got response {:?}", msg.response);
+        let on_list = match msg.response.and_then(|result| parse_membership(&result)) {
+            Ok(on_list) => Some(on_list),
+            Err(e) => {
+                warn!("DAO membership lookup failed with {:?}", e);
+                None
             }
         };
+        self.record(&msg.id, msg.dao_address, on_list, Instant::now());
 
-        let has_vec = self.ident2dao.contains_key(&their_id);
-        let on_dao = !(num == Uint256::zero());
-
-        if has_vec {
-            let entry_vec = self.ident2dao.get_mut(&their_id).unwrap();
-            let mut found_entry = false;
-            match entry_vec
-                .iter_mut()
-                .find(|ref i| dao_address == i.dao_address)
-            {
-                Some(entry) => {
-                    entry.on_list = on_dao;
-                    entry.last_updated = Instant::now();
-                    found_entry = true;
-                    trace!("Updating exising entry {:?}", entry);
-                    send_membership_message(on_dao, their_id.clone());
-                }
-                None => (),
-            }
-
-            // We can't place this into the match because the mutable ref lives even
-            // in the none case where it's obviously not being used. Until the borrow
-            // checker is smart enough to allow that this will have to do
-            if !found_entry {
-                // A list exists but does not contain an entry from this dao
-                // create one and insert it into the list
-                let entry = DAOEntry {
-                    on_list: on_dao,
-                    dao_address: dao_address,
-                    id: their_id.clone(),
-                    last_updated: Instant::now(),
-                };
-                trace!("Adding new cache entry to existing ID {:?}", entry);
-                entry_vec.push(entry);
-                send_membership_message(on_dao, their_id);
-            }
-        } else {
-            // No entry exists in the HashMap for this ID, create one
-            let entry = DAOEntry {
-                on_list: on_dao,
-                dao_address: dao_address,
-                id: their_id.clone(),
-                last_updated: Instant::now(),
-            };
-            trace!("Creating new ID in cache {:?}", entry);
-            self.ident2dao.insert(their_id.clone(), vec![entry]);
-
-            send_membership_message(on_dao, their_id);
-        }
+        let daos = SETTING.get_dao().dao_addresses.clone();
+        self.send_decision(&msg.id, &daos);
     }
 }
 
-/// True if timestamp does not need to be updated
-fn timer_check(timestamp: Instant) -> bool {
-    Instant::now() - timestamp < Duration::new(SETTING.get_dao().cache_timeout_seconds, 0)
-}
-
 /// Sends off a message to TunnelManager about the dao state
 fn send_membership_message(on_dao: bool, their_id: Identity) -> () {
     TunnelManager::from_registry().do_send(TunnelStateChange {
@@ -193,142 +301,173 @@ fn send_membership_message(on_dao: bool, their_id: Identity) -> () {
     });
 }
 
-/// Checks if an identity is in at least one of the set of DAO's we are a member of.
-/// will check the cache first before going out and updating via web3
-fn check_cache(their_id: Identity, ident2dao: &HashMap<Identity, Vec<DAOEntry>>) -> () {
-    trace!("Checking the DAOManager Cache for {:?}", their_id);
-    let dao_settings = SETTING.get_dao();
-    // we don't care about subnet DAO's, short circuit.
-    if !dao_settings.dao_enforcement || dao_settings.dao_addresses.len() == 0 {
-        trace!("DAO enforcement disabled DAOMAnager doing nothing!");
-        send_membership_message(true, their_id);
-        return ();
-    }
-    // TODO when we start enforcing dao state more strictly we will need
-    // to detect when we are bootstrapping and explicitly allow everyone
-
-    match ident2dao.get(&their_id) {
-        // Cache hit
-        Some(membership_list) => {
-            for entry in membership_list.iter() {
-                if entry.on_list && timer_check(entry.last_updated) {
-                    trace!(
-                        "{:?} is on the SubnetDAO {:?}",
-                        their_id.clone(),
-                        entry.dao_address
-                    );
-                    send_membership_message(true, their_id.clone());
-                } else if !timer_check(entry.last_updated) {
-                    trace!("Cache entry has expired, updating");
-                    get_membership(entry.dao_address, entry.id.clone());
-                }
-            }
-            trace!("{:?} is not on any SubnetDAO", their_id);
-            send_membership_message(false, their_id);
-        }
-        // Cache miss, do a lookup for all DAO's
-        None => {
-            for dao in dao_settings.dao_addresses.iter() {
-                get_membership(dao.clone(), their_id.clone());
-            }
-        }
-    }
-}
-
-fn get_membership(dao_address: EthAddress, target: Identity) -> () {
-    let url = get_web3_server();
-    let endpoint = format!("http://{}/", url);
-    trace!("Getting DAO membership from {}", url);
-    let socket: SocketAddr = match url.to_socket_addrs() {
-        Ok(mut iter) => match iter.next() {
-            Some(socket) => socket,
-            None => {
-                trace!("No ip found for domain name!");
-                return ();
-            }
-        },
-        Err(e) => {
-            trace!("Could not resolve full node domain name {:?}", e);
-            return ();
-        }
-    };
-    trace!("Got IP {:?}", socket);
-
+/// The SubnetDAO call checking if an identity's mesh ip is on the list
+fn membership_call(
+    dao_address: EthAddress,
+    target: &Identity,
+) -> Result<TransactionRequest, Error> {
     // We transform the ip address into a argument
     let ip_bytes = match target.mesh_ip {
         IpAddr::V6(ip) => ip.octets(),
-        _ => panic!("MeshIP must be ipv6 and is not!"),
+        ip => return Err(DAOManagerError::NonIpv6MeshIp(ip).into()),
     };
-    let mut full_bytes: [u8; 32] = [0; 32];
-    let mut i = 0;
-    let func_magic = [0x37, 0x66, 0x79, 0xb0];
-    for byte in func_magic.iter() {
-        full_bytes[i] = byte.clone();
-        i = i + 1;
-    }
-    for byte in ip_bytes.iter() {
-        full_bytes[i] = byte.clone();
-        i = i + 1;
-    }
+    Ok(TransactionRequest {
+        from: None,
+        to: dao_address,
+        data: encode_call_with_selector(
+            MEMBERSHIP_SELECTOR,
+            &[Token::FixedBytes(ip_bytes.to_vec())],
+        )?,
+    })
+}
+
+fn get_membership(
+    web3: &Web3Client,
+    dao_address: EthAddress,
+    target: Identity,
+) -> Result<(), Error> {
+    let call = membership_call(dao_address, &target)?;
+    trace!("Getting DAO membership of {:?} from {:?}", target, dao_address);
 
-    let call_args: Uint256 = full_bytes.into();
-    let func_call = format!("{{'jsonrpc':'2.0','method':'eth_call','params':[{{'to': '0x{:x}', 'data': '0x{:x}'}}, 'latest'],'id':1}}", dao_address, call_args);
-
-    // it's really awkward to escape that many " so we just repace them
-    let func_call = func_call.replace("'", "\"");
-    trace!("Calling full node with: {}", func_call);
-    let stream = TokioTcpStream::connect(&socket);
-
-    let res = stream
-        .then(move |stream| {
-            let stream = stream.expect("Error attaching to full node socket");
-            client::post(&endpoint)
-                .timeout(Duration::from_secs(8))
-                .with_connection(Connection::from_stream(stream))
-                .content_type("application/json")
-                .body(func_call)
-                .unwrap()
-                .send()
-                .then(move |response| {
-                    trace!("Got response {:?}", response);
-                    match response {
-                        Ok(res) => Box::new(res.json().then(
-                            move |val: Result<Web3Response, JsonPayloadError>| match val {
-                                Ok(val) => {
-                                    DAOManager::from_registry().do_send(CacheCallback {
-                                        id: target,
-                                        dao_address: dao_address.clone(),
-                                        response: val,
-                                    });
-                                    Ok(())
-                                }
-                                Err(e) => {
-                                    trace!("Got bad Web3Response {:?}", e);
-                                    return Ok(());
-                                }
-                            },
-                        ))
-                            as Box<Future<Item = (), Error = JsonPayloadError>>,
-                        Err(e) => {
-                            warn!("Got error from full node {:?}", e);
-                            Box::new(ok(()))
-                        }
-                    }
-                })
-        }).then(|_err| Ok(()));
-    Arbiter::spawn(res);
+    Arbiter::spawn(web3.eth_call(&call).then(move |response| {
+        DAOManager::from_registry().do_send(CacheCallback {
+            id: target,
+            dao_address,
+            response,
+        });
+        Ok(())
+    }));
+    Ok(())
 }
 
-/// Checks the list of full nodes, panics if none exist, if there exist
-/// one or more a random entry from the list is returned in an attempt
-/// to load balance across fullnodes
-fn get_web3_server() -> String {
-    if SETTING.get_dao().node_list.len() == 0 {
-        panic!("DAO enforcement enabled but not DAO's configured!");
-    }
-    let node_list = SETTING.get_dao().node_list.clone();
-    let mut rng = thread_rng();
-    let val = rng.gen_range(0, node_list.len());
+#[test]
+fn test_get_membership_ipv4_mesh_ip() {
+    use std::str::FromStr;
+
+    let id = Identity::new(
+        "10.0.0.1".parse().unwrap(),
+        EthAddress::from_str("ffffffffffffffffffffffffffffffffffffffff").unwrap(),
+        String::from("abc0abc1abc2abc3abc4abc5abc6abc7abc8abc9"),
+    );
+    let dao = EthAddress::from_str("0101010101010101010101010101010101010101").unwrap();
+    let web3 = Web3Client::new(&[], Duration::from_secs(NODE_TIMEOUT));
+    assert!(get_membership(&web3, dao, id).is_err());
+}
+
+#[cfg(test)]
+fn test_identity(mesh_ip: &str) -> Identity {
+    Identity::new(
+        mesh_ip.parse().unwrap(),
+        1.into(),
+        String::from("abc0abc1abc2abc3abc4abc5abc6abc7abc8abc9"),
+    )
+}
+
+/// Runs a membership lookup against a mock full node
+#[cfg(test)]
+fn mock_lookup(node: MockNode, id: &Identity) -> (Result<bool, Error>, Vec<Value>) {
+    let node = node.start();
+    let web3 = Web3Client::new(&[node.url.clone()], Duration::from_secs(NODE_TIMEOUT));
+    let call = membership_call(1.into(), id).unwrap();
+    let result = System::new("test")
+        .block_on(web3.eth_call(&call).and_then(|result| parse_membership(&result)));
+    (result, node.requests())
+}
+
+#[test]
+fn test_membership_lookup() {
+    let id = test_identity("fd00::1");
+    let word = |last: &str| Value::String(format!("0x{}{}", "00".repeat(31), last));
+
+    let (result, requests) = mock_lookup(MockNode::new().result("eth_call", word("01")), &id);
+    assert_eq!(result.unwrap(), true);
+    assert_eq!(
+        requests[0]["params"][0]["data"],
+        Value::String(format!("0x376679b0fd00{}01{}", "00".repeat(13), "00".repeat(16)))
+    );
+
+    let (result, _) = mock_lookup(MockNode::new().result("eth_call", word("00")), &id);
+    assert_eq!(result.unwrap(), false);
+    // not a contract, or not one that answers with a word
+    let empty = Value::String("0x".to_string());
+    let (result, _) = mock_lookup(MockNode::new().result("eth_call", empty), &id);
+    assert!(result.is_err());
+    let (result, _) = mock_lookup(MockNode::new().error("eth_call", -32015, "reverted"), &id);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_membership_across_daos() {
+    let id = test_identity("fd00::1");
+    let daos: Vec<EthAddress> = vec![1.into(), 2.into()];
+    let now = Instant::now();
+    let timeout = Duration::from_secs(600);
+
+    let mut manager = DAOManager::new();
+    assert_eq!(manager.start_lookups(&id, &daos, now, timeout), daos);
+    assert_eq!(manager.membership(&id, &daos), Membership::Unknown);
+
+    // one DAO saying no isn't enough while the other is still being asked
+    manager.record(&id, daos[0], Some(false), now);
+    assert_eq!(manager.membership(&id, &daos), Membership::Unknown);
+    assert_eq!(manager.decide(&id, &daos), None);
+
+    manager.record(&id, daos[1], Some(true), now);
+    assert_eq!(manager.membership(&id, &daos), Membership::Member);
+    assert_eq!(manager.decide(&id, &daos), Some(true));
+
+    manager.record(&id, daos[1], Some(false), now);
+    assert_eq!(manager.membership(&id, &daos), Membership::NotMember);
+    assert_eq!(manager.decide(&id, &daos), Some(false));
+
+    // a failed refresh keeps the last answer
+    manager.record(&id, daos[1], None, now);
+    assert_eq!(manager.membership(&id, &daos), Membership::NotMember);
+
+    // only the configured DAOs count
+    assert_eq!(manager.membership(&id, &daos[..1]), Membership::NotMember);
+    assert_eq!(manager.membership(&id, &[3.into()]), Membership::Unknown);
+}
+
+#[test]
+fn test_lookup_tracking() {
+    let id = test_identity("fd00::1");
+    let daos: Vec<EthAddress> = vec![1.into(), 2.into()];
+    let now = Instant::now();
+    let timeout = Duration::from_secs(600);
+
+    let mut manager = DAOManager::new();
+    assert_eq!(manager.start_lookups(&id, &daos, now, timeout), daos);
+    // lookups in flight aren't sent again until they time out
+    assert!(manager.start_lookups(&id, &daos, now, timeout).is_empty());
+    assert_eq!(
+        manager.start_lookups(&id, &daos, now + Duration::from_secs(LOOKUP_TIMEOUT), timeout),
+        daos
+    );
+
+    // fresh answers come from the cache, failures are retried on the next check
+    manager.record(&id, daos[0], Some(true), now);
+    manager.record(&id, daos[1], None, now);
+    assert_eq!(manager.start_lookups(&id, &daos, now, timeout), vec![daos[1]]);
+    // once the cache expires the answer is looked up again, as is the retry that never returned
+    assert_eq!(manager.start_lookups(&id, &daos, now + timeout, timeout), daos);
+}
+
+#[test]
+fn test_bootstrap_allows_everyone() {
+    let id = test_identity("fd00::1");
+    let other = test_identity("fd00::2");
+    let daos: Vec<EthAddress> = vec![1.into()];
+    let now = Instant::now();
+
+    let mut manager = DAOManager::new();
+    manager.start_lookups(&id, &daos, now, Duration::from_secs(600));
+    assert_eq!(manager.decide(&id, &daos), Some(true));
 
-    node_list[val].clone()
+    // a failed lookup doesn't end bootstrapping, any answer does
+    manager.record(&id, daos[0], None, now);
+    assert_eq!(manager.decide(&id, &daos), Some(true));
+    manager.record(&id, daos[0], Some(false), now);
+    assert_eq!(manager.decide(&id, &daos), Some(false));
+    assert_eq!(manager.decide(&other, &daos), None);
 }
@@ -0,0 +1,292 @@
+//! Password login for the dashboard. The password is stored as a bcrypt hash in the settings and
+//! a successful login hands out a random session token which has to be sent as
+//! `Authorization: Bearer <token>` with every other request, see `middleware::Auth`. Until a
+//! password has been set through `/setup` the dashboard is only usable from the router itself.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::http::header;
+use actix_web::{HttpRequest, HttpResponse, Json};
+
+use bcrypt;
+
+use failure::Error;
+
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+
+use serde_json::Value;
+
+use settings::RitaCommonSettings;
+use SETTING;
+
+/// Kept low since this is checked on router hardware
+const BCRYPT_COST: u32 = 8;
+const TOKEN_LENGTH: usize = 32;
+const MIN_PASSWORD_LENGTH: usize = 8;
+const EVENTS_PATH: &str = "/events";
+
+lazy_static! {
+    /// Session tokens and when they were last used
+    static ref SESSIONS: Mutex<HashMap<String, Instant>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Debug, Fail)]
+pub enum AuthError {
+    #[fail(display = "Password must be at least {} characters", _0)]
+    PasswordTooShort(usize),
+}
+
+pub fn hash_password(password: &str) -> Result<String, Error> {
+    if password.len() < MIN_PASSWORD_LENGTH {
+        return Err(AuthError::PasswordTooShort(MIN_PASSWORD_LENGTH).into());
+    }
+    Ok(bcrypt::hash(password, BCRYPT_COST)?)
+}
+
+/// Checks a password against the stored hash, false if no password is set
+pub fn check_password(password: &str) -> bool {
+    let hash = match SETTING.get_dashboard().password_hash.clone() {
+        Some(hash) => hash,
+        None => return false,
+    };
+    match bcrypt::verify(password, &hash) {
+        Ok(valid) => valid,
+        Err(e) => {
+            error!("Stored dashboard password hash is invalid {:?}", e);
+            false
+        }
+    }
+}
+
+pub fn password_set() -> bool {
+    SETTING.get_dashboard().password_hash.is_some()
+}
+
+fn new_session() -> String {
+    let token: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .collect();
+    SESSIONS
+        .lock()
+        .unwrap()
+        .insert(token.clone(), Instant::now());
+    token
+}
+
+/// Checks a token against the open sessions, using a session keeps it open for another timeout
+fn check_session(token: &str, timeout: Duration, now: Instant) -> bool {
+    let mut sessions = SESSIONS.lock().unwrap();
+    sessions.retain(|_, last_used| now.duration_since(*last_used) < timeout);
+    match sessions.get_mut(token) {
+        Some(last_used) => {
+            *last_used = now;
+            true
+        }
+        None => false,
+    }
+}
+
+fn end_session(token: &str) {
+    SESSIONS.lock().unwrap().remove(token);
+}
+
+fn end_all_sessions() {
+    SESSIONS.lock().unwrap().clear();
+}
+
+/// Whether the request opens the `/events` websocket
+fn is_events_upgrade<S>(req: &HttpRequest<S>) -> bool {
+    let upgrade = req
+        .headers()
+        .get(header::UPGRADE)
+        .and_then(|value| value.to_str().ok());
+    match upgrade {
+        Some(upgrade) => req.path() == EVENTS_PATH && upgrade.eq_ignore_ascii_case("websocket"),
+        None => false,
+    }
+}
+
+/// The bearer token of a request if it has one, browsers can't set headers on websockets so
+/// opening the `/events` websocket may pass it as the `token` query parameter instead. Tokens
+/// in urls end up in logs and browser history so no other request gets that fallback
+pub fn request_token<S>(req: &HttpRequest<S>) -> Option<String> {
+    let header = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    match header {
+        Some(value) if value.starts_with("Bearer ") => {
+            Some(value["Bearer ".len()..].trim().to_string())
+        }
+        _ if is_events_upgrade(req) => req.query().get("token").cloned(),
+        _ => None,
+    }
+}
+
+pub fn authenticated<S>(req: &HttpRequest<S>) -> bool {
+    let timeout = Duration::from_secs(SETTING.get_dashboard().session_timeout);
+    match request_token(req) {
+        Some(token) => check_session(&token, timeout, Instant::now()),
+        None => false,
+    }
+}
+
+/// The password hash and the eth private key are never handed out or changed through `/settings`
+pub fn strip_secrets(settings: &mut Value) {
+    if let Some(dashboard) = settings.get_mut("dashboard").and_then(|d| d.as_object_mut()) {
+        dashboard.remove("password_hash");
+    }
+    if let Some(payment) = settings.get_mut("payment").and_then(|p| p.as_object_mut()) {
+        payment.remove("eth_private_key");
+    }
+}
+
+#[derive(Serialize)]
+pub struct AuthStatus {
+    password_set: bool,
+    authenticated: bool,
+}
+
+#[derive(Deserialize)]
+pub struct PasswordRequest {
+    password: String,
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordRequest {
+    old_password: String,
+    new_password: String,
+}
+
+#[derive(Serialize)]
+pub struct SessionResponse {
+    token: String,
+    /// Seconds the token stays valid without being used
+    expires_in: u64,
+}
+
+fn session_response() -> HttpResponse {
+    HttpResponse::Ok().json(SessionResponse {
+        token: new_session(),
+        expires_in: SETTING.get_dashboard().session_timeout,
+    })
+}
+
+pub fn get_auth_status(req: HttpRequest) -> Result<Json<AuthStatus>, Error> {
+    debug!("/auth/status GET hit");
+    Ok(Json(AuthStatus {
+        password_set: password_set(),
+        authenticated: authenticated(&req),
+    }))
+}
+
+/// First run setup, sets the initial password and logs in
+pub fn setup(req: Json<PasswordRequest>) -> Result<HttpResponse, Error> {
+    debug!("/setup POST hit");
+    if password_set() {
+        return Ok(HttpResponse::Forbidden().body("A password has already been set"));
+    }
+    let hash = match hash_password(&req.password) {
+        Ok(hash) => hash,
+        Err(e) => return Ok(HttpResponse::BadRequest().body(format!("{}", e))),
+    };
+    SETTING.get_dashboard_mut().password_hash = Some(hash);
+    info!("Dashboard password set");
+    Ok(session_response())
+}
+
+pub fn login(req: Json<PasswordRequest>) -> Result<HttpResponse, Error> {
+    debug!("/login POST hit");
+    if !check_password(&req.password) {
+        warn!("Failed dashboard login");
+        return Ok(HttpResponse::Unauthorized().body("Wrong password"));
+    }
+    Ok(session_response())
+}
+
+pub fn logout(req: HttpRequest) -> Result<HttpResponse, Error> {
+    debug!("/logout POST hit");
+    if let Some(token) = request_token(&req) {
+        end_session(&token);
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Changes the password and ends every session, a new one is returned for the caller
+pub fn set_password(req: Json<ChangePasswordRequest>) -> Result<HttpResponse, Error> {
+    debug!("/password POST hit");
+    if !check_password(&req.old_password) {
+        return Ok(HttpResponse::Unauthorized().body("Wrong password"));
+    }
+    let hash = match hash_password(&req.new_password) {
+        Ok(hash) => hash,
+        Err(e) => return Ok(HttpResponse::BadRequest().body(format!("{}", e))),
+    };
+    SETTING.get_dashboard_mut().password_hash = Some(hash);
+    end_all_sessions();
+    info!("Dashboard password changed");
+    Ok(session_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use serde_json;
+
+    #[test]
+    fn test_sessions() {
+        let timeout = Duration::from_secs(60);
+        let token = new_session();
+        let start = Instant::now();
+        assert_eq!(token.len(), TOKEN_LENGTH);
+        assert!(!check_session("not a token", timeout, start));
+
+        // using the session keeps it alive past the original timeout
+        assert!(check_session(&token, timeout, start + Duration::from_secs(50)));
+        assert!(check_session(&token, timeout, start + Duration::from_secs(100)));
+        assert!(!check_session(&token, timeout, start + Duration::from_secs(200)));
+
+        let token = new_session();
+        end_session(&token);
+        assert!(!check_session(&token, timeout, Instant::now()));
+    }
+
+    #[test]
+    fn test_request_token() {
+        let req = TestRequest::with_header(header::AUTHORIZATION, "Bearer abc")
+            .uri("/settings")
+            .finish();
+        assert_eq!(request_token(&req), Some("abc".to_string()));
+
+        let req = TestRequest::with_header(header::UPGRADE, "websocket")
+            .uri("/events?token=abc")
+            .finish();
+        assert_eq!(request_token(&req), Some("abc".to_string()));
+
+        // the query parameter is ignored everywhere else
+        let req = TestRequest::default().uri("/events?token=abc").finish();
+        assert_eq!(request_token(&req), None);
+        let req = TestRequest::with_header(header::UPGRADE, "websocket")
+            .uri("/settings?token=abc")
+            .finish();
+        assert_eq!(request_token(&req), None);
+    }
+
+    #[test]
+    fn test_strip_secrets() {
+        let mut settings: Value = serde_json::from_str(
+            r#"{"dashboard": {"password_hash": "$2y$08$abc", "tls": true},
+                "payment": {"eth_private_key": "0x46", "buffer_period": 3}}"#,
+        ).unwrap();
+        strip_secrets(&mut settings);
+        assert_eq!(
+            settings.to_string(),
+            r#"{"dashboard":{"tls":true},"payment":{"buffer_period":3}}"#
+        );
+    }
+}
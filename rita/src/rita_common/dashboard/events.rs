@@ -0,0 +1,45 @@
+//! The `/events` websocket, every event published on the event bus is sent to the socket as a JSON
+//! text message for as long as it stays open.
+
+use actix::prelude::*;
+use actix_web::{ws, Error, HttpRequest, HttpResponse};
+
+use serde_json;
+
+use rita_common::event_bus::{Event, EventBus, Subscribe};
+
+pub struct EventSocket;
+
+impl Actor for EventSocket {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        EventBus::from_registry().do_send(Subscribe(ctx.address().recipient()));
+    }
+}
+
+impl Handler<Event> for EventSocket {
+    type Result = ();
+
+    fn handle(&mut self, event: Event, ctx: &mut Self::Context) -> Self::Result {
+        match serde_json::to_string(&event) {
+            Ok(text) => ctx.text(text),
+            Err(e) => warn!("Failed to serialize event {:?} with {:?}", event, e),
+        }
+    }
+}
+
+impl StreamHandler<ws::Message, ws::ProtocolError> for EventSocket {
+    fn handle(&mut self, msg: ws::Message, ctx: &mut Self::Context) {
+        match msg {
+            ws::Message::Ping(msg) => ctx.pong(&msg),
+            ws::Message::Close(_) => ctx.stop(),
+            _ => {}
+        }
+    }
+}
+
+pub fn get_events(req: HttpRequest) -> Result<HttpResponse, Error> {
+    debug!("/events GET hit");
+    ws::start(&req, EventSocket)
+}
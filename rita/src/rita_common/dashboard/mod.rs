@@ -1,6 +1,7 @@
 //! The common user infromation endpoints for Rita, these are http endpoints that exist for user
 //! management and automation. They exist on port 4877 by default and should be firewalled
-//! from the outside world for obvious security reasons.
+//! from the outside world for obvious security reasons. Every endpoint other than those used to
+//! log in requires a session token, see `auth`.
 
 use actix::prelude::*;
 
@@ -9,11 +10,12 @@ use futures::Future;
 
 use rita_common::payment_controller::{GetOwnBalance, PaymentController};
 
-use num256::Int256;
+use num256::{Int256, Unit};
 
+pub mod auth;
+pub mod events;
 pub mod network_endpoints;
-use num_traits::ops::checked::CheckedDiv;
-use num_traits::ToPrimitive;
+pub mod tls;
 pub struct Dashboard;
 
 impl Actor for Dashboard {
@@ -33,23 +35,37 @@ impl Default for Dashboard {
     }
 }
 
-#[derive(Debug, Fail)]
-enum OwnInfoError {
-    #[fail(display = "Unable to round balance of {} down to 1 ETH", _0)]
-    RoundDownError(Int256),
-    #[fail(
-        display = "Unable to downcast value {} to signed 64 bits",
-        _0
-    )]
-    DownCastError(Int256),
-}
+/// Decimal places kept when rounding amounts for display
+pub const DISPLAY_PLACES: usize = 4;
+
+const BYTES_PER_GB: i64 = 1_000_000_000;
 
 #[derive(Serialize)]
 pub struct OwnInfo {
-    pub balance: i64,
+    /// Exact balance in wei
+    pub balance: Int256,
+    pub balance_display: String,
     pub version: String,
 }
 
+#[derive(Serialize)]
+pub struct LocalFee {
+    /// Exact fee in wei per byte
+    pub price: Int256,
+    pub display: String,
+}
+
+impl LocalFee {
+    /// A fee of one wei per byte is one gwei per GB, which is the more readable figure
+    pub fn new(fee: u32) -> LocalFee {
+        let per_gb = Int256::from(fee) * Int256::from(BYTES_PER_GB);
+        LocalFee {
+            price: Int256::from(fee),
+            display: format!("{}/GB", per_gb.display(Unit::Gwei, DISPLAY_PLACES)),
+        }
+    }
+}
+
 pub struct GetOwnInfo;
 
 impl Message for GetOwnInfo {
@@ -64,19 +80,13 @@ impl Handler<GetOwnInfo> for Dashboard {
             PaymentController::from_registry()
                 .send(GetOwnBalance {})
                 .from_err()
-                .and_then(|own_balance| match own_balance {
-                    Ok(balance) => {
-                        let balance = balance
-                            .checked_div(&Int256::from(1_000_000_000i64))
-                            .ok_or(OwnInfoError::RoundDownError(balance.clone()))?;
-                        Ok(OwnInfo {
-                            balance: balance
-                                .to_i64()
-                                .ok_or(OwnInfoError::DownCastError(balance))?,
-                            version: env!("CARGO_PKG_VERSION").to_string(),
-                        })
-                    }
-                    Err(e) => Err(e),
+                .and_then(|own_balance| {
+                    let balance = own_balance?;
+                    Ok(OwnInfo {
+                        balance_display: balance.display(Unit::Ether, DISPLAY_PLACES),
+                        balance,
+                        version: env!("CARGO_PKG_VERSION").to_string(),
+                    })
                 }),
         )
     }
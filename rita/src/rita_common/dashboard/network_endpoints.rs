@@ -2,23 +2,36 @@ use actix::registry::SystemService;
 use rita_common::debt_keeper::GetDebtsList;
 
 use althea_types::EthAddress;
+use babel_monitor;
 
 use futures::Future;
 
+use num256::Int256;
+
 use std::boxed::Box;
 
 use failure::Error;
 
 use serde_json;
 
-use settings::RitaCommonSettings;
+use settings::{CreditPolicy, NeighborKey, RitaCommonSettings};
 use SETTING;
 
-use super::{Dashboard, GetOwnInfo, OwnInfo};
+use super::auth::strip_secrets;
+use super::{Dashboard, GetOwnInfo, LocalFee, OwnInfo};
 use actix_web::*;
 
+use rita_common::dao_manager::{DAOManager, DAOStatus, GetDAOStatus, JoinDAO};
 use rita_common::debt_keeper::{DebtKeeper, GetDebtsResult};
 use rita_common::network_endpoints::JsonStatusResponse;
+use rita_common::overadvertisement::{AdvertisementMonitor, GetSuspicions, Suspicion};
+use rita_common::payment_controller::spending::SpendingStatus;
+use rita_common::payment_controller::{GetSpending, PaymentController};
+use rita_common::pricing::{FeeSetManually, GetPricing, PriceManager, PricingStatus};
+use rita_common::traffic_stats::{
+    EarningsReport, GetEarnings, GetUsage, TrafficStats, UsageReport,
+};
+use rita_common::tunnel_manager::{GetTunnels, TunnelInfo, TunnelManager};
 
 pub fn get_own_info(_req: HttpRequest) -> Box<Future<Item = Json<OwnInfo>, Error = Error>> {
     debug!("Get own info endpoint hit!");
@@ -31,14 +44,18 @@ pub fn get_own_info(_req: HttpRequest) -> Box<Future<Item = Json<OwnInfo>, Error
 
 pub fn get_settings(_req: HttpRequest) -> Result<Json<serde_json::Value>, Error> {
     debug!("Get settings endpoint hit!");
-    Ok(Json(SETTING.get_all()?))
+    let mut settings = SETTING.get_all()?;
+    strip_secrets(&mut settings);
+    Ok(Json(settings))
 }
 
 pub fn set_settings(
     new_settings: Json<serde_json::Value>,
 ) -> Result<Json<JsonStatusResponse>, Error> {
     debug!("Set settings endpoint hit!");
-    SETTING.merge(new_settings.into_inner())?;
+    let mut new_settings = new_settings.into_inner();
+    strip_secrets(&mut new_settings);
+    SETTING.merge(new_settings)?;
 
     JsonStatusResponse::new(Ok("New settings applied".to_string()))
 }
@@ -64,7 +81,7 @@ pub fn wipe(_req: HttpRequest) -> Result<HttpResponse, Error> {
     }
 
     // Restore default route
-    match KI.restore_default_route(&mut SETTING.get_network_mut().default_route) {
+    match KI.restore_default_route() {
         Ok(_) => trace!("wipe: Restore default route success!"),
         Err(e) => {
             warn!("wipe: Unable to restore default route: {:?}", e);
@@ -115,6 +132,28 @@ pub fn get_debts(
         .responder()
 }
 
+pub fn get_tunnels(
+    _req: HttpRequest,
+) -> Box<Future<Item = Json<Vec<TunnelInfo>>, Error = Error>> {
+    trace!("get_tunnels: Hit");
+    TunnelManager::from_registry()
+        .send(GetTunnels {})
+        .from_err()
+        .and_then(move |reply| Ok(Json(reply?)))
+        .responder()
+}
+
+pub fn get_overadvertisement(
+    _req: HttpRequest,
+) -> Box<Future<Item = Json<Vec<Suspicion>>, Error = Error>> {
+    trace!("get_overadvertisement: Hit");
+    AdvertisementMonitor::from_registry()
+        .send(GetSuspicions)
+        .from_err()
+        .and_then(move |reply| Ok(Json(reply?)))
+        .responder()
+}
+
 pub fn get_dao_list(_req: HttpRequest) -> Result<Json<Vec<EthAddress>>, Error> {
     trace!("get dao list: Hit");
     Ok(Json(SETTING.get_dao().dao_addresses.clone()))
@@ -149,3 +188,149 @@ pub fn remove_from_dao_list(path: Path<(EthAddress)>) -> Result<Json<()>, Error>
     }
     Ok(Json(()))
 }
+
+#[derive(Serialize)]
+pub struct CreditPolicies {
+    allowlist_only: bool,
+    policies: Vec<CreditPolicy>,
+}
+
+pub fn get_credit_policies(_req: HttpRequest) -> Result<Json<CreditPolicies>, Error> {
+    trace!("/credit_policies GET hit");
+    let payment = SETTING.get_payment();
+    Ok(Json(CreditPolicies {
+        allowlist_only: payment.allowlist_only,
+        policies: payment.credit_policies.clone(),
+    }))
+}
+
+/// Adds a policy, replacing any existing policy for the same neighbor
+pub fn set_credit_policy(policy: Json<CreditPolicy>) -> Result<HttpResponse, Error> {
+    let policy = policy.into_inner();
+    trace!("/credit_policies POST hit with {:?}", policy);
+    if policy.pay_threshold.as_ref().map_or(false, |t| *t < Int256::from(0)) {
+        return Ok(HttpResponse::BadRequest().body("pay_threshold can't be negative"));
+    }
+    // a positive close threshold would suspend the neighbor as soon as they owe us anything
+    if policy.close_threshold.as_ref().map_or(false, |t| *t > Int256::from(0)) {
+        return Ok(HttpResponse::BadRequest().body("close_threshold can't be positive"));
+    }
+    if policy.close_fraction.as_ref().map_or(false, |f| *f <= Int256::from(0)) {
+        return Ok(HttpResponse::BadRequest().body("close_fraction must be positive"));
+    }
+    if policy.buffer_period == Some(0) {
+        return Ok(HttpResponse::BadRequest().body("buffer_period must be at least 1"));
+    }
+
+    let mut payment = SETTING.get_payment_mut();
+    let existing = payment
+        .credit_policies
+        .iter()
+        .position(|p| p.neighbor == policy.neighbor);
+    match existing {
+        Some(index) => payment.credit_policies[index] = policy,
+        None => payment.credit_policies.push(policy),
+    }
+    Ok(HttpResponse::Ok().json(()))
+}
+
+pub fn remove_credit_policy(neighbor: Json<NeighborKey>) -> Result<Json<()>, Error> {
+    let neighbor = neighbor.into_inner();
+    trace!("/credit_policies/remove POST hit with {:?}", neighbor);
+    SETTING
+        .get_payment_mut()
+        .credit_policies
+        .retain(|p| p.neighbor != neighbor);
+    Ok(Json(()))
+}
+
+pub fn set_allowlist_only(path: Path<bool>) -> Result<Json<()>, Error> {
+    let enabled = path.into_inner();
+    trace!("/credit_policies/allowlist_only/{} POST hit", enabled);
+    SETTING.get_payment_mut().allowlist_only = enabled;
+    Ok(Json(()))
+}
+
+#[derive(Serialize)]
+pub struct JoinResponse {
+    tx_hash: String,
+}
+
+/// Requests membership in a DAO, the DAO is added to the list once the request has been mined
+pub fn join_dao(path: Path<(EthAddress)>) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    trace!("Join dao: Hit");
+    DAOManager::from_registry()
+        .send(JoinDAO(path.into_inner()))
+        .from_err()
+        .and_then(move |reply| match reply {
+            Ok(tx_hash) => Ok(HttpResponse::Ok().json(JoinResponse { tx_hash })),
+            Err(e) => Ok(HttpResponse::BadRequest().body(format!("{}", e))),
+        }).responder()
+}
+
+pub fn get_dao_status(
+    _req: HttpRequest,
+) -> Box<Future<Item = Json<Vec<DAOStatus>>, Error = Error>> {
+    trace!("Get dao status: Hit");
+    DAOManager::from_registry()
+        .send(GetDAOStatus)
+        .from_err()
+        .and_then(move |reply| Ok(Json(reply?)))
+        .responder()
+}
+
+pub fn get_local_fee(_req: HttpRequest) -> Box<Future<Item = Json<LocalFee>, Error = Error>> {
+    trace!("/price GET hit");
+    Box::new(babel_monitor::local_fee().map(|fee| Json(LocalFee::new(fee))))
+}
+
+/// Sets the fee in Babel and only stores it in the settings once Babel has accepted it
+pub fn set_local_fee(path: Path<u32>) -> Box<Future<Item = Json<()>, Error = Error>> {
+    let fee = path.into_inner();
+    trace!("/price/{} POST hit", fee);
+    Box::new(babel_monitor::set_local_fee(fee).map(move |_| {
+        SETTING.get_payment_mut().local_fee = Some(fee);
+        PriceManager::from_registry().do_send(FeeSetManually);
+        Json(())
+    }))
+}
+
+pub fn get_pricing(_req: HttpRequest) -> Box<Future<Item = Json<PricingStatus>, Error = Error>> {
+    trace!("/pricing GET hit");
+    PriceManager::from_registry()
+        .send(GetPricing)
+        .from_err()
+        .and_then(move |reply| Ok(Json(reply?)))
+        .responder()
+}
+
+pub fn get_spending(
+    _req: HttpRequest,
+) -> Box<Future<Item = Json<SpendingStatus>, Error = Error>> {
+    trace!("/spending GET hit");
+    PaymentController::from_registry()
+        .send(GetSpending)
+        .from_err()
+        .and_then(move |reply| Ok(Json(reply?)))
+        .responder()
+}
+
+pub fn get_usage(_req: HttpRequest) -> Box<Future<Item = Json<UsageReport>, Error = Error>> {
+    trace!("get_usage: Hit");
+    TrafficStats::from_registry()
+        .send(GetUsage)
+        .from_err()
+        .and_then(move |reply| Ok(Json(reply?)))
+        .responder()
+}
+
+pub fn get_earnings(
+    _req: HttpRequest,
+) -> Box<Future<Item = Json<EarningsReport>, Error = Error>> {
+    trace!("get_earnings: Hit");
+    TrafficStats::from_registry()
+        .send(GetEarnings)
+        .from_err()
+        .and_then(move |reply| Ok(Json(reply?)))
+        .responder()
+}
@@ -0,0 +1,95 @@
+//! Optional https for the dashboard. Routers have no name a real certificate could be issued for
+//! so a self signed one is generated the first time the dashboard starts with tls enabled and is
+//! reused afterwards.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use failure::Error;
+
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, MsbOption};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+use openssl::ssl::{SslAcceptor, SslAcceptorBuilder, SslFiletype, SslMethod};
+use openssl::x509::{X509NameBuilder, X509};
+
+use settings::RitaCommonSettings;
+use SETTING;
+
+const KEY_BITS: u32 = 2048;
+const CERT_DAYS: u32 = 3650;
+
+/// Generates a self signed certificate and its key in PEM format
+fn generate_certificate() -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let key = PKey::from_rsa(Rsa::generate(KEY_BITS)?)?;
+
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_text("O", "Althea")?;
+    name.append_entry_by_text("CN", "althea router dashboard")?;
+    let name = name.build();
+
+    let mut serial = BigNum::new()?;
+    serial.rand(128, MsbOption::MAYBE_ZERO, false)?;
+
+    let mut cert = X509::builder()?;
+    cert.set_version(2)?;
+    cert.set_serial_number(&serial.to_asn1_integer()?)?;
+    cert.set_subject_name(&name)?;
+    cert.set_issuer_name(&name)?;
+    cert.set_pubkey(&key)?;
+    cert.set_not_before(&Asn1Time::days_from_now(0)?)?;
+    cert.set_not_after(&Asn1Time::days_from_now(CERT_DAYS)?)?;
+    cert.sign(&key, MessageDigest::sha256())?;
+
+    Ok((cert.build().to_pem()?, key.private_key_to_pem_pkcs8()?))
+}
+
+/// Writes a new certificate unless both the certificate and key already exist
+pub fn ensure_certificate(cert_path: &str, key_path: &str) -> Result<(), Error> {
+    if Path::new(cert_path).exists() && Path::new(key_path).exists() {
+        return Ok(());
+    }
+    info!("Generating self signed dashboard certificate at {}", cert_path);
+    let (cert, key) = generate_certificate()?;
+
+    let mut key_file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(key_path)?;
+    key_file.write_all(&key)?;
+    File::create(cert_path)?.write_all(&cert)?;
+    Ok(())
+}
+
+/// The acceptor to bind the dashboard with, generating the certificate if needed
+pub fn acceptor() -> Result<SslAcceptorBuilder, Error> {
+    let (cert_path, key_path) = {
+        let dashboard = SETTING.get_dashboard();
+        (dashboard.tls_cert_path.clone(), dashboard.tls_key_path.clone())
+    };
+    ensure_certificate(&cert_path, &key_path)?;
+
+    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+    builder.set_private_key_file(&key_path, SslFiletype::PEM)?;
+    builder.set_certificate_chain_file(&cert_path)?;
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_certificate() {
+        let (cert, key) = generate_certificate().unwrap();
+        let cert = X509::from_pem(&cert).unwrap();
+        let key = PKey::private_key_from_pem(&key).unwrap();
+        assert!(cert.public_key().unwrap().public_eq(&key));
+    }
+}
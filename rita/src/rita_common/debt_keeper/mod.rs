@@ -10,11 +10,13 @@ use althea_types::{Identity, PaymentTx};
 
 use num256::{Int256, Uint256};
 
-use settings::RitaCommonSettings;
+use settings::{PaymentSettings, RitaCommonSettings};
 use SETTING;
 
+use rita_common::event_bus::{self, Event};
 use rita_common::payment_controller;
 use rita_common::payment_controller::PaymentController;
+use rita_common::tunnel_manager::{TunnelAction, TunnelManager, TunnelStateChange};
 
 use failure::Error;
 
@@ -31,6 +33,9 @@ pub struct NodeDebtData {
     /// Only push to back
     #[serde(skip_serializing)]
     pub debt_buffer: VecDeque<Int256>,
+    /// Whether a credit policy has suspended this neighbor's tunnels
+    #[serde(default)]
+    pub blocked: bool,
 }
 
 impl NodeDebtData {
@@ -47,6 +52,18 @@ impl NodeDebtData {
                 }
                 buf
             },
+            blocked: false,
+        }
+    }
+
+    /// Merges the oldest entries or pads with zeroes when the buffer period has changed
+    fn resize_buffer(&mut self, buffer_period: u32) {
+        while self.debt_buffer.len() > buffer_period as usize {
+            let oldest = self.debt_buffer.pop_front().unwrap();
+            self.debt_buffer[0] += oldest;
+        }
+        while self.debt_buffer.len() < buffer_period as usize {
+            self.debt_buffer.push_back(Int256::from(0));
         }
     }
 }
@@ -115,6 +132,56 @@ impl Message for SendUpdate {
     type Result = Result<(), Error>;
 }
 
+/// The payment settings for one neighbor after applying its credit policy, if it has one
+#[derive(Debug, Clone, PartialEq)]
+struct Policy {
+    pay_threshold: Int256,
+    close_threshold: Int256,
+    close_fraction: Int256,
+    buffer_period: u32,
+    free: bool,
+    blocked: bool,
+}
+
+/// Overrides that would break the billing cycle are ignored, the dashboard rejects them but the
+/// config file can still be edited by hand
+fn resolve_policy(payment: &PaymentSettings, ident: &Identity) -> Policy {
+    let custom = payment.credit_policy(ident);
+    let zero = Int256::from(0);
+    Policy {
+        pay_threshold: custom
+            .and_then(|p| p.pay_threshold.clone())
+            .filter(|t| *t >= zero)
+            .unwrap_or_else(|| payment.pay_threshold.clone()),
+        close_threshold: custom
+            .and_then(|p| p.close_threshold.clone())
+            .filter(|t| *t <= zero)
+            .unwrap_or_else(|| payment.close_threshold.clone()),
+        close_fraction: custom
+            .and_then(|p| p.close_fraction.clone())
+            .filter(|f| *f > zero)
+            .unwrap_or_else(|| payment.close_fraction.clone()),
+        buffer_period: custom
+            .and_then(|p| p.buffer_period)
+            .filter(|b| *b > 0)
+            .unwrap_or(payment.buffer_period)
+            .max(1),
+        free: custom.map(|p| p.free).unwrap_or(false),
+        blocked: custom.map(|p| p.blocked).unwrap_or(payment.allowlist_only),
+    }
+}
+
+fn policy(ident: &Identity) -> Policy {
+    resolve_policy(&SETTING.get_payment(), ident)
+}
+
+/// The debt below which a neighbor's tunnels are suspended, lowered by a fraction of what they
+/// have paid us so far
+fn close_threshold(policy: &Policy, debt_data: &NodeDebtData) -> Int256 {
+    policy.close_threshold.clone()
+        - debt_data.total_payment_received.clone() / policy.close_fraction.clone().into()
+}
+
 /// Actions to be taken upon a neighbor's debt reaching either a negative or positive
 /// threshold.
 #[derive(Debug, PartialEq)]
@@ -122,6 +189,8 @@ pub enum DebtAction {
     SuspendTunnel,
     OpenTunnel,
     MakePayment { to: Identity, amount: Uint256 },
+    Block,
+    Unblock,
     None,
 }
 
@@ -133,9 +202,43 @@ impl Handler<SendUpdate> for DebtKeeper {
         trace!("total debt data: {:?}", self.debt_data);
         for (k, _) in self.debt_data.clone() {
             trace!("sending update for {:?}", k);
+            let crossed = {
+                let policy = policy(&k);
+                let debt_data = self.get_debt_data(&k);
+                debt_data.debt >= close_threshold(&policy, debt_data)
+            };
             match self.send_update(&k) {
-                DebtAction::SuspendTunnel => {}
-                DebtAction::OpenTunnel => {}
+                DebtAction::SuspendTunnel => {
+                    // suspension is repeated every round, only the first one is an event
+                    if crossed {
+                        event_bus::publish(Event::DebtLimitReached {
+                            neighbor: k.clone(),
+                            debt: self.get_debt_data(&k).debt.clone(),
+                        });
+                    }
+                    TunnelManager::from_registry().do_send(TunnelStateChange {
+                        identity: k.clone(),
+                        action: TunnelAction::DebtLimitReached,
+                    })
+                }
+                DebtAction::OpenTunnel => {
+                    event_bus::publish(Event::DebtPaid {
+                        neighbor: k.clone(),
+                        debt: self.get_debt_data(&k).debt.clone(),
+                    });
+                    TunnelManager::from_registry().do_send(TunnelStateChange {
+                        identity: k.clone(),
+                        action: TunnelAction::DebtPaid,
+                    })
+                }
+                DebtAction::Block => TunnelManager::from_registry().do_send(TunnelStateChange {
+                    identity: k.clone(),
+                    action: TunnelAction::Blocked,
+                }),
+                DebtAction::Unblock => TunnelManager::from_registry().do_send(TunnelStateChange {
+                    identity: k.clone(),
+                    action: TunnelAction::Unblocked,
+                }),
                 DebtAction::MakePayment { to, amount } => PaymentController::from_registry()
                     .do_send(payment_controller::MakePayment(PaymentTx {
                         to,
@@ -173,7 +276,7 @@ impl DebtKeeper {
     }
 
     fn get_debt_data(&mut self, ident: &Identity) -> &mut NodeDebtData {
-        let buffer = SETTING.get_payment().buffer_period;
+        let buffer = policy(ident).buffer_period;
         self.debt_data
             .entry(ident.clone())
             .or_insert_with(|| NodeDebtData::new(buffer))
@@ -205,6 +308,10 @@ impl DebtKeeper {
     fn traffic_update(&mut self, ident: &Identity, mut amount: Int256) {
         {
             trace!("traffic update for {} is {}", ident.mesh_ip, amount);
+            if policy(ident).free {
+                trace!("{} is a free peer, not billing", ident.mesh_ip);
+                return;
+            }
             let debt_data = self.get_debt_data(ident);
 
             if amount < Int256::from(0) {
@@ -217,8 +324,7 @@ impl DebtKeeper {
                     debt_data.incoming_payments = Int256::from(0);
 
                     // Buffer debt in the back of the debt buffer
-                    debt_data.debt_buffer[(SETTING.get_payment().buffer_period - 1) as usize] +=
-                        amount;
+                    *debt_data.debt_buffer.back_mut().unwrap() += amount;
                 }
             } else {
                 // Immediately apply credit
@@ -237,9 +343,20 @@ impl DebtKeeper {
     /// This updates a neighbor's debt and outputs a DebtAction if one is necessary.
     fn send_update(&mut self, ident: &Identity) -> DebtAction {
         trace!("debt data: {:?}", self.debt_data);
+        let policy = policy(ident);
         let debt_data = self.get_debt_data(ident);
         let debt = debt_data.debt.clone();
 
+        debt_data.resize_buffer(policy.buffer_period);
+        if policy.free {
+            // anything owed from before the neighbor became a free peer is forgiven, which also
+            // reopens their tunnels if they were suspended for debt
+            debt_data.debt = Int256::from(0);
+            for traffic in debt_data.debt_buffer.iter_mut() {
+                *traffic = Int256::from(0);
+            }
+        }
+
         let traffic = debt_data.debt_buffer.pop_front().unwrap();
         debt_data.debt_buffer.push_back(Int256::from(0));
 
@@ -273,9 +390,24 @@ impl DebtKeeper {
             debt_data.incoming_payments = Int256::from(0);
         }
 
-        let close_threshold = SETTING.get_payment().close_threshold.clone()
-            - debt_data.total_payment_received.clone()
-                / SETTING.get_payment().close_fraction.clone().into();
+        if policy.blocked {
+            trace!("{} is blocked by a credit policy", ident.mesh_ip);
+            debt_data.blocked = true;
+            return DebtAction::Block;
+        }
+
+        let close_threshold = close_threshold(&policy, debt_data);
+
+        if debt_data.blocked {
+            trace!("{} is no longer blocked", ident.mesh_ip);
+            debt_data.blocked = false;
+            // the debt kept changing while the neighbor was blocked, if it is past the close
+            // threshold now the tunnel goes straight from blocked to suspended for debt
+            if debt_data.debt < close_threshold {
+                return DebtAction::SuspendTunnel;
+            }
+            return DebtAction::Unblock;
+        }
 
         if debt_data.debt < close_threshold {
             trace!(
@@ -286,7 +418,7 @@ impl DebtKeeper {
         } else if (close_threshold < debt_data.debt) && (debt < close_threshold) {
             trace!("debt is above close threshold. resuming forwarding");
             DebtAction::OpenTunnel
-        } else if debt_data.debt > SETTING.get_payment().pay_threshold {
+        } else if debt_data.debt > policy.pay_threshold {
             let d = debt_data.debt.clone();
             trace!(
                 "debt is above payment threshold for {}. making payment of {}",
@@ -343,6 +475,7 @@ impl Handler<GetDebtsList> for DebtKeeper {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use settings::{CreditPolicy, NeighborKey};
 
     #[test]
     fn test_single_suspend() {
@@ -666,4 +799,124 @@ mod tests {
 
         assert_eq!(d.send_update(&ident), DebtAction::OpenTunnel);
     }
+
+    fn policy_ident(wg_key: &str) -> Identity {
+        Identity {
+            eth_address: 2.into(),
+            mesh_ip: "2001::9".parse().unwrap(),
+            wg_public_key: String::from(wg_key),
+        }
+    }
+
+    fn custom_policy(wg_key: &str) -> CreditPolicy {
+        CreditPolicy {
+            neighbor: NeighborKey::WgKey(wg_key.to_string()),
+            pay_threshold: Some(Int256::from(5)),
+            close_threshold: Some(Int256::from(-1000)),
+            close_fraction: Some(Int256::from(100)),
+            buffer_period: Some(1),
+            free: false,
+            blocked: false,
+        }
+    }
+
+    #[test]
+    fn test_policy_thresholds() {
+        SETTING
+            .get_payment_mut()
+            .credit_policies
+            .push(custom_policy("POLICY_THRESHOLDS"));
+        let ident = policy_ident("POLICY_THRESHOLDS");
+        let mut d = DebtKeeper::new();
+
+        // well past the default close threshold but within the custom one
+        d.traffic_update(&ident, Int256::from(-100));
+        assert_eq!(d.send_update(&ident), DebtAction::None);
+
+        d.traffic_update(&ident, Int256::from(-1000));
+        assert_eq!(d.send_update(&ident), DebtAction::SuspendTunnel);
+    }
+
+    #[test]
+    fn test_free_peer() {
+        let mut policy = custom_policy("POLICY_FREE");
+        policy.free = true;
+        SETTING.get_payment_mut().credit_policies.push(policy);
+        let ident = policy_ident("POLICY_FREE");
+        let mut d = DebtKeeper::new();
+
+        d.traffic_update(&ident, Int256::from(-100000));
+        assert_eq!(d.send_update(&ident), DebtAction::None);
+        d.traffic_update(&ident, Int256::from(100000));
+        assert_eq!(d.send_update(&ident), DebtAction::None);
+        assert_eq!(d.get_debt_data(&ident).debt, Int256::from(0));
+    }
+
+    #[test]
+    fn test_blocked_peer() {
+        let mut policy = custom_policy("POLICY_BLOCKED");
+        policy.blocked = true;
+        SETTING.get_payment_mut().credit_policies.push(policy);
+        let ident = policy_ident("POLICY_BLOCKED");
+        let mut d = DebtKeeper::new();
+
+        assert_eq!(d.send_update(&ident), DebtAction::Block);
+        assert_eq!(d.send_update(&ident), DebtAction::Block);
+
+        for policy in SETTING.get_payment_mut().credit_policies.iter_mut() {
+            if policy.neighbor == NeighborKey::WgKey("POLICY_BLOCKED".to_string()) {
+                policy.blocked = false;
+            }
+        }
+        assert_eq!(d.send_update(&ident), DebtAction::Unblock);
+        assert_eq!(d.send_update(&ident), DebtAction::None);
+    }
+
+    #[test]
+    fn test_unblock_in_debt() {
+        let mut policy = custom_policy("POLICY_UNBLOCK_DEBT");
+        policy.blocked = true;
+        SETTING.get_payment_mut().credit_policies.push(policy);
+        let ident = policy_ident("POLICY_UNBLOCK_DEBT");
+        let mut d = DebtKeeper::new();
+
+        // the debt keeps counting while blocked
+        d.traffic_update(&ident, Int256::from(-100000));
+        assert_eq!(d.send_update(&ident), DebtAction::Block);
+
+        for policy in SETTING.get_payment_mut().credit_policies.iter_mut() {
+            if policy.neighbor == NeighborKey::WgKey("POLICY_UNBLOCK_DEBT".to_string()) {
+                policy.blocked = false;
+            }
+        }
+        assert_eq!(d.send_update(&ident), DebtAction::SuspendTunnel);
+        assert!(!d.get_debt_data(&ident).blocked);
+    }
+
+    #[test]
+    fn test_resolve_policy() {
+        let mut payment = PaymentSettings::default();
+        let mut policy = custom_policy("RESOLVE");
+        policy.close_fraction = Some(Int256::from(0));
+        policy.buffer_period = None;
+        payment.credit_policies.push(policy);
+        let mut positive = custom_policy("POSITIVE");
+        positive.close_threshold = Some(Int256::from(10));
+        payment.credit_policies.push(positive);
+        payment.allowlist_only = true;
+
+        let allowed = resolve_policy(&payment, &policy_ident("RESOLVE"));
+        assert_eq!(allowed.close_threshold, Int256::from(-1000));
+        assert_eq!(allowed.close_fraction, payment.close_fraction);
+        assert_eq!(allowed.buffer_period, payment.buffer_period);
+        assert!(!allowed.blocked);
+
+        let positive = resolve_policy(&payment, &policy_ident("POSITIVE"));
+        assert_eq!(positive.close_threshold, payment.close_threshold);
+
+        let stranger = resolve_policy(&payment, &policy_ident("STRANGER"));
+        assert_eq!(stranger.close_threshold, payment.close_threshold);
+        assert!(stranger.blocked);
+        assert!(!stranger.free);
+    }
 }
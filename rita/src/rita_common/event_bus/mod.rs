@@ -0,0 +1,139 @@
+//! EventBus passes notable changes in rita's state on to whoever is interested, currently the
+//! dashboard's `/events` websocket. Actors publish events as things happen and every subscriber
+//! gets a copy, subscribers that have gone away are dropped the next time something is published.
+
+use actix::prelude::*;
+
+use althea_types::{ExitState, Identity};
+
+use num256::Int256;
+
+use rita_common::payment_controller::spending::SpendingPeriod;
+
+/// Something that happened which dashboard consumers would otherwise have to poll for
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    TunnelOpened {
+        neighbor: Identity,
+        iface: String,
+        listen_port: u16,
+    },
+    TunnelClosed {
+        neighbor: Identity,
+        iface: String,
+    },
+    ExitStateChanged {
+        exit: String,
+        from: ExitState,
+        to: ExitState,
+    },
+    PaymentSent {
+        to: Identity,
+        amount: Int256,
+    },
+    PaymentReceived {
+        from: Identity,
+        amount: Int256,
+    },
+    /// The neighbor owes us more than the close threshold, their tunnels are suspended
+    DebtLimitReached {
+        neighbor: Identity,
+        debt: Int256,
+    },
+    /// The neighbor has paid back enough to be above the close threshold again
+    DebtPaid {
+        neighbor: Identity,
+        debt: Int256,
+    },
+    InterfaceModeChanged {
+        iface: String,
+        from: String,
+        to: String,
+    },
+    /// Our balance has fallen below the configured low balance threshold
+    LowBalance {
+        balance: Int256,
+        threshold: Int256,
+    },
+    /// We have paid out as much as the daily or monthly spending cap allows
+    SpendingCapReached {
+        period: SpendingPeriod,
+        spent: Int256,
+        cap: Int256,
+    },
+}
+
+impl Message for Event {
+    type Result = ();
+}
+
+/// Sends an event to every subscriber
+pub fn publish(event: Event) {
+    EventBus::from_registry().do_send(event);
+}
+
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Vec<Recipient<Event>>,
+}
+
+impl Actor for EventBus {
+    type Context = Context<Self>;
+}
+
+impl Supervised for EventBus {}
+impl SystemService for EventBus {
+    fn service_started(&mut self, _ctx: &mut Context<Self>) {
+        info!("Event Bus started");
+    }
+}
+
+impl Handler<Event> for EventBus {
+    type Result = ();
+
+    fn handle(&mut self, event: Event, _: &mut Context<Self>) -> Self::Result {
+        trace!("Publishing {:?} to {} subscribers", event, self.subscribers.len());
+        self.subscribers.retain(|subscriber| match subscriber.do_send(event.clone()) {
+            Ok(()) => true,
+            Err(SendError::Full(_)) => {
+                warn!("Event subscriber is not keeping up, dropping an event");
+                true
+            }
+            Err(SendError::Closed(_)) => false,
+        });
+    }
+}
+
+pub struct Subscribe(pub Recipient<Event>);
+
+impl Message for Subscribe {
+    type Result = ();
+}
+
+impl Handler<Subscribe> for EventBus {
+    type Result = ();
+
+    fn handle(&mut self, msg: Subscribe, _: &mut Context<Self>) -> Self::Result {
+        self.subscribers.push(msg.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn test_event_format() {
+        let event = Event::InterfaceModeChanged {
+            iface: "eth0".to_string(),
+            from: "LAN".to_string(),
+            to: "mesh".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"type":"interface_mode_changed","iface":"eth0","from":"LAN","to":"mesh"}"#
+        );
+    }
+}
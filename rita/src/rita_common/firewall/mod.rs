@@ -0,0 +1,130 @@
+//! Firewall holds the ruleset describing every firewall rule Rita needs. Other modules add and
+//! remove groups of rules through it and the complete ruleset is applied atomically on every
+//! change, it is also periodically compared against the live rules and reapplied if something
+//! else on the system has modified our chains.
+
+use std::time::Duration;
+
+use actix::prelude::*;
+
+use althea_kernel_interface::{FirewallBackend, RuleGroup, Ruleset};
+
+use KI;
+
+use failure::Error;
+
+/// How often in seconds the live rules are checked against the ruleset
+const FIREWALL_CHECK_INTERVAL: u64 = 60;
+
+pub struct Firewall {
+    ruleset: Ruleset,
+    backend: Option<FirewallBackend>,
+}
+
+impl Actor for Firewall {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        ctx.run_interval(Duration::from_secs(FIREWALL_CHECK_INTERVAL), |_act, ctx| {
+            ctx.address().do_send(CheckFirewall);
+        });
+    }
+}
+
+impl Supervised for Firewall {}
+impl SystemService for Firewall {
+    fn service_started(&mut self, _ctx: &mut Context<Self>) {
+        info!("Firewall started");
+    }
+}
+
+impl Default for Firewall {
+    fn default() -> Firewall {
+        Firewall {
+            ruleset: Ruleset::new(),
+            backend: None,
+        }
+    }
+}
+
+impl Firewall {
+    fn backend(&mut self) -> FirewallBackend {
+        match self.backend {
+            Some(backend) => backend,
+            None => {
+                let backend = KI.detect_firewall_backend();
+                info!("Using the {:?} firewall backend", backend);
+                self.backend = Some(backend);
+                backend
+            }
+        }
+    }
+
+    fn apply(&mut self) -> Result<(), Error> {
+        let backend = self.backend();
+        trace!("Applying firewall ruleset {:?}", self.ruleset);
+        KI.apply_ruleset(&self.ruleset, backend)?;
+        self.ruleset.clear_stale_chains();
+        Ok(())
+    }
+}
+
+/// Adds a group of rules, does nothing if they are already present
+pub struct AddRules(pub RuleGroup);
+
+impl Message for AddRules {
+    type Result = Result<(), Error>;
+}
+
+impl Handler<AddRules> for Firewall {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: AddRules, _: &mut Context<Self>) -> Self::Result {
+        if self.ruleset.insert(msg.0) {
+            self.apply()?;
+        }
+        Ok(())
+    }
+}
+
+/// Removes a group of rules, does nothing if they are not present
+pub struct RemoveRules(pub RuleGroup);
+
+impl Message for RemoveRules {
+    type Result = Result<(), Error>;
+}
+
+impl Handler<RemoveRules> for Firewall {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: RemoveRules, _: &mut Context<Self>) -> Self::Result {
+        if self.ruleset.remove(&msg.0) {
+            self.apply()?;
+        }
+        Ok(())
+    }
+}
+
+/// Reapplies the ruleset if the live rules have drifted from it
+pub struct CheckFirewall;
+
+impl Message for CheckFirewall {
+    type Result = Result<(), Error>;
+}
+
+impl Handler<CheckFirewall> for Firewall {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, _: CheckFirewall, _: &mut Context<Self>) -> Self::Result {
+        if self.ruleset.groups().is_empty() {
+            return Ok(());
+        }
+        let backend = self.backend();
+        let diff = KI.diff_ruleset(&self.ruleset, backend)?;
+        if !diff.is_empty() {
+            warn!("Firewall rules have drifted, reapplying {:?}", diff);
+            self.apply()?;
+        }
+        Ok(())
+    }
+}
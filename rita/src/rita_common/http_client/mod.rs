@@ -21,6 +21,7 @@ use rita_common::tunnel_manager::{IdentityCallback, PortCallback, TunnelManager}
 
 use actix_web::client::Connection;
 use failure::Error;
+use std::net::SocketAddr;
 
 #[derive(Default)]
 pub struct HTTPClient;
@@ -46,6 +47,33 @@ impl Message for Hello {
     type Result = Result<(), Error>;
 }
 
+/// Formats the url of a peer's hello endpoint, only ipv6 hosts are bracketed
+fn hello_url(contact_socket: &SocketAddr) -> String {
+    match contact_socket {
+        &SocketAddr::V6(socket) => format!("http://[{}]:{}/hello", socket.ip(), socket.port()),
+        &SocketAddr::V4(socket) => format!("http://{}:{}/hello", socket.ip(), socket.port()),
+    }
+}
+
+#[test]
+fn test_hello_url() {
+    use std::net::SocketAddrV6;
+
+    assert_eq!(
+        hello_url(&"1.2.3.4:4876".parse().unwrap()),
+        "http://1.2.3.4:4876/hello"
+    );
+    assert_eq!(
+        hello_url(&"[2001::1]:4876".parse().unwrap()),
+        "http://[2001::1]:4876/hello"
+    );
+    // peers found by PeerListener carry their interface index as the scope id
+    assert_eq!(
+        hello_url(&SocketAddrV6::new("fe80::1".parse().unwrap(), 4876, 0, 3).into()),
+        "http://[fe80::1]:4876/hello"
+    );
+}
+
 /// Handler for sending hello messages, it's important that any path by which this handler
 /// may crash is handled such that ports are returned to tunnel manager, otherwise we end
 /// up with a port leak which will eventually crash the program
@@ -56,11 +84,7 @@ impl Handler<Hello> for HTTPClient {
 
         let stream = TokioTcpStream::connect(&msg.to.contact_socket);
 
-        let endpoint = format!(
-            "http://[{}]:{}/hello",
-            msg.to.contact_socket.ip(),
-            msg.to.contact_socket.port()
-        );
+        let endpoint = hello_url(&msg.to.contact_socket);
 
         Box::new(stream.then(move |stream| {
             trace!("stream status {:?}, to: {:?}", stream, &msg.to);
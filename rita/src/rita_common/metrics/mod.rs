@@ -0,0 +1,119 @@
+//! Prometheus metrics for rita and rita_exit. Metrics are registered in the default registry the
+//! first time they are used and served in the Prometheus text format by `get_metrics` on a port
+//! that is only bound on localhost.
+
+use std::time::{Duration, Instant};
+
+use actix_web::{HttpRequest, HttpResponse};
+
+use failure::Error;
+
+use num256::Int256;
+use num_traits::ToPrimitive;
+
+use prometheus;
+use prometheus::{
+    CounterVec, Encoder, Histogram, HistogramVec, IntCounterVec, IntGauge, TextEncoder,
+};
+
+lazy_static! {
+    /// Bytes billed per direction, `in` is traffic neighbors sent us and `out` is what we sent
+    pub static ref BYTES_BILLED: IntCounterVec = register_int_counter_vec!(
+        "rita_bytes_billed_total",
+        "Bytes of traffic billed by the traffic watcher",
+        &["direction"]
+    ).unwrap();
+    /// Wei owed for traffic, `to_us` by our neighbors and `by_us` to them
+    pub static ref WEI_OWED: CounterVec = register_counter_vec!(
+        "rita_wei_owed_total",
+        "Wei owed for traffic as computed by the traffic watcher",
+        &["direction"]
+    ).unwrap();
+    /// Wei moved by payments, `sent` by us and `received` from neighbors
+    pub static ref WEI_PAID: CounterVec = register_counter_vec!(
+        "rita_wei_paid_total",
+        "Wei sent and received in payments",
+        &["direction"]
+    ).unwrap();
+    pub static ref TUNNELS_OPEN: IntGauge =
+        register_int_gauge!("rita_tunnels_open", "Per hop tunnels currently open").unwrap();
+    pub static ref FREE_PORTS: IntGauge = register_int_gauge!(
+        "rita_free_ports",
+        "Ports still available for new per hop tunnels"
+    ).unwrap();
+    pub static ref BABEL_QUERY_SECONDS: Histogram = register_histogram!(
+        "rita_babel_query_seconds",
+        "Time taken to get the route table from Babel"
+    ).unwrap();
+    /// Duration of each loop or step within a loop, labeled by name
+    pub static ref LOOP_TICK_SECONDS: HistogramVec = register_histogram_vec!(
+        "rita_loop_tick_seconds",
+        "Time taken by a tick of a rita loop",
+        &["loop"]
+    ).unwrap();
+    pub static ref EXIT_CLIENTS_REGISTERED: IntGauge = register_int_gauge!(
+        "rita_exit_clients_registered",
+        "Verified clients in the exit database"
+    ).unwrap();
+    pub static ref EXIT_CLIENTS_ONLINE: IntGauge = register_int_gauge!(
+        "rita_exit_clients_online",
+        "Exit clients with a recent WireGuard handshake"
+    ).unwrap();
+    /// DAO membership lookups labeled `hit`, `expired` or `miss`
+    pub static ref DAO_CACHE: IntCounterVec = register_int_counter_vec!(
+        "rita_dao_cache_total",
+        "DAOManager cache lookups by result",
+        &["result"]
+    ).unwrap();
+}
+
+pub fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0
+}
+
+/// Records the time since start against the named loop
+pub fn observe_tick(name: &str, start: Instant) {
+    LOOP_TICK_SECONDS
+        .with_label_values(&[name])
+        .observe(seconds(start.elapsed()));
+}
+
+/// Prometheus only deals in floats, very large amounts lose precision
+pub fn wei(amount: &Int256) -> f64 {
+    amount.to_f64().unwrap_or(0.0)
+}
+
+/// Adds the debts computed in one traffic watcher round, negative debts are owed to us
+pub fn record_debt(amount: &Int256) {
+    let value = wei(amount);
+    if value < 0.0 {
+        WEI_OWED.with_label_values(&["to_us"]).inc_by(-value);
+    } else {
+        WEI_OWED.with_label_values(&["by_us"]).inc_by(value);
+    }
+}
+
+pub fn get_metrics(_req: HttpRequest) -> Result<HttpResponse, Error> {
+    trace!("/metrics GET hit");
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    encoder.encode(&prometheus::gather(), &mut buffer)?;
+    Ok(HttpResponse::Ok()
+        .content_type(encoder.format_type())
+        .body(buffer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_debt() {
+        let to_us = WEI_OWED.with_label_values(&["to_us"]).get();
+        let by_us = WEI_OWED.with_label_values(&["by_us"]).get();
+        record_debt(&Int256::from(-100));
+        record_debt(&Int256::from(40));
+        assert_eq!(WEI_OWED.with_label_values(&["to_us"]).get() - to_us, 100.0);
+        assert_eq!(WEI_OWED.with_label_values(&["by_us"]).get() - by_us, 40.0);
+    }
+}
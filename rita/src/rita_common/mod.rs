@@ -1,10 +1,16 @@
 pub mod dao_manager;
 pub mod dashboard;
 pub mod debt_keeper;
+pub mod event_bus;
+pub mod firewall;
 pub mod http_client;
+pub mod metrics;
 pub mod network_endpoints;
+pub mod overadvertisement;
 pub mod payment_controller;
 pub mod peer_listener;
+pub mod pricing;
 pub mod rita_loop;
+pub mod traffic_stats;
 pub mod traffic_watcher;
 pub mod tunnel_manager;
@@ -1,10 +1,11 @@
 //! Network endptoints for common Rita functionality (such as exchanging hello messages)
 
-use althea_types::{LocalIdentity, PaymentTx};
+use althea_types::{LocalIdentity, PaymentTx, RTTimestamps};
 
 use actix::registry::SystemService;
 use actix_web::*;
 
+use futures::future;
 use futures::Future;
 
 use failure::Error;
@@ -12,7 +13,8 @@ use failure::Error;
 use settings::RitaCommonSettings;
 use SETTING;
 
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::time::SystemTime;
 
 use rita_common;
 use rita_common::payment_controller::PaymentController;
@@ -52,18 +54,55 @@ pub fn make_payments(
         .responder()
 }
 
+/// Hellos from ipv4 peers arrive on our dual stack listener as v4 mapped ipv6 addresses, we turn
+/// them back into plain ipv4 so that the tunnel endpoint and routes use the right family
+pub fn unmap_ipv4(socket: SocketAddr) -> SocketAddr {
+    if let SocketAddr::V6(v6) = socket {
+        let seg = v6.ip().segments();
+        if seg[..5] == [0, 0, 0, 0, 0] && seg[5] == 0xffff {
+            if let Some(v4) = v6.ip().to_ipv4() {
+                return SocketAddr::new(IpAddr::V4(v4), v6.port());
+            }
+        }
+    }
+    socket
+}
+
+#[test]
+fn test_unmap_ipv4() {
+    assert_eq!(
+        unmap_ipv4("[::ffff:1.2.3.4]:4876".parse().unwrap()),
+        "1.2.3.4:4876".parse::<SocketAddr>().unwrap()
+    );
+    assert_eq!(
+        unmap_ipv4("[fe80::1]:4876".parse().unwrap()),
+        "[fe80::1]:4876".parse::<SocketAddr>().unwrap()
+    );
+    // ipv4 compatible addresses are deprecated and not translated
+    assert_eq!(
+        unmap_ipv4("[::1]:4876".parse().unwrap()),
+        "[::1]:4876".parse::<SocketAddr>().unwrap()
+    );
+    assert_eq!(
+        unmap_ipv4("1.2.3.4:4876".parse().unwrap()),
+        "1.2.3.4:4876".parse::<SocketAddr>().unwrap()
+    );
+}
+
 pub fn hello_response(
     req: (Json<LocalIdentity>, HttpRequest),
 ) -> Box<Future<Item = Json<LocalIdentity>, Error = Error>> {
     let their_id = req.0.clone();
 
-    let socket = req
-        .1
-        .connection_info()
-        .remote()
-        .unwrap()
-        .parse::<SocketAddr>()
-        .unwrap();
+    let socket = match req.1.connection_info().remote().map(|r| r.parse::<SocketAddr>()) {
+        Some(Ok(socket)) => unmap_ipv4(socket),
+        _ => {
+            return Box::new(future::err(format_err!(
+                "Unable to parse hello remote address {:?}",
+                req.1.connection_info().remote()
+            )))
+        }
+    };
 
     info!("Got Hello from {:?}", req.1.connection_info().remote());
 
@@ -84,7 +123,7 @@ pub fn hello_response(
             .send(IdentityCallback::new(their_id, peer, None))
             .from_err()
             .and_then(|tunnel| {
-                let tunnel = tunnel.unwrap();
+                let tunnel = tunnel?;
                 Ok(Json(LocalIdentity {
                     global: match SETTING.get_identity() {
                         Some(id) => id,
@@ -97,6 +136,16 @@ pub fn hello_response(
     )
 }
 
+/// An endpoint handler for measuring the RTT to this node independently of Babel. It responds
+/// with the request arrival and transmission timestamps, exits serve it for the inner tunnel RTT
+/// and every node serves it to its neighbors, see `overadvertisement`.
+pub fn rtt(_req: HttpRequest) -> Result<Json<RTTimestamps>> {
+    Ok(Json(RTTimestamps {
+        exit_rx: SystemTime::now(),
+        exit_tx: SystemTime::now(),
+    }))
+}
+
 pub fn version(_req: HttpRequest) -> String {
     format!(
         "crate ver {}\ngit hash {}",
@@ -0,0 +1,563 @@
+//! The AdvertisementMonitor keeps a rolling comparison of what neighbors advertise through Babel
+//! against what we can check ourselves, to catch neighbors that make their routes look faster or
+//! cheaper than they are to attract traffic.
+//!
+//! Every tick each neighbor's `/rtt` endpoint is timed over its tunnel and compared with the
+//! full path RTT Babel reports for the route to it, which Babel works out from timestamps the
+//! neighbor supplies. The client traffic watcher adds the same comparison for the exit route.
+//! Prices are checked for consistency, a route through a neighbor can't honestly cost less than
+//! the neighbor's own price plus the fee of the destination. Neighbors that fail most of their
+//! recent checks are suspected, and if `penalize_overadvertisers` is set their tunnel's rxcost
+//! is raised until they stop.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, SystemTime};
+
+use actix::prelude::*;
+use actix_web::client;
+use actix_web::client::Connection;
+use actix_web::HttpMessage;
+
+use futures::future;
+use futures::Future;
+
+use tokio::net::TcpStream as TokioTcpStream;
+
+use ipnetwork::IpNetwork;
+
+use althea_types::{Identity, RTTimestamps};
+
+use babel_monitor;
+use babel_monitor::Neighbor as BabelNeighbor;
+use babel_monitor::Route;
+
+use rita_common::tunnel_manager::Neighbor;
+
+use settings::RitaCommonSettings;
+use SETTING;
+
+use failure::Error;
+
+/// Samples kept per neighbor
+const WINDOW: usize = 20;
+/// Samples needed before a neighbor can be suspected
+const MIN_SAMPLES: usize = 5;
+/// Share of overadvertised samples at which a neighbor is suspected
+const SUSPICION_THRESHOLD: f32 = 0.5;
+/// Measurements are noisy, an advertised RTT has to be better than measured by more than this
+/// many milliseconds and by more than `RTT_TOLERANCE` of the measurement to count
+const RTT_SLACK_MS: f32 = 10.0;
+const RTT_TOLERANCE: f32 = 0.25;
+const RTT_TIMEOUT: u64 = 5;
+/// The rxcost put on the tunnel of a penalized neighbor, the cost it had before is restored after
+const PENALTY_COST: u16 = 2048;
+/// Babel's reach bitmap once the last 16 hellos have all arrived
+const FULL_REACH: u16 = 0xffff;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sample {
+    /// Round trip in milliseconds as Babel advertises it and as we measured it
+    Rtt { advertised: f32, measured: f32 },
+    /// Price of a route through the neighbor and the least it could honestly cost
+    Price { advertised: u64, floor: u64 },
+}
+
+impl Sample {
+    pub fn is_overadvertised(&self) -> bool {
+        match *self {
+            Sample::Rtt {
+                advertised,
+                measured,
+            } => measured - advertised > RTT_SLACK_MS.max(measured * RTT_TOLERANCE),
+            Sample::Price { advertised, floor } => advertised < floor,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Suspicion {
+    pub identity: Identity,
+    pub iface: String,
+    /// Samples in the rolling window
+    pub samples: usize,
+    /// Share of the samples in which the neighbor advertised better than we measured
+    pub score: f32,
+    pub suspected: bool,
+    pub penalized: bool,
+    /// The latest RTT comparison, in milliseconds
+    pub advertised_rtt: Option<f32>,
+    pub measured_rtt: Option<f32>,
+}
+
+struct History {
+    identity: Identity,
+    samples: VecDeque<Sample>,
+    penalized: bool,
+    /// The rxcost of the tunnel before it was penalized
+    original_cost: Option<u16>,
+}
+
+impl History {
+    fn new(identity: Identity) -> History {
+        History {
+            identity,
+            samples: VecDeque::with_capacity(WINDOW),
+            penalized: false,
+            original_cost: None,
+        }
+    }
+
+    fn push(&mut self, sample: Sample) {
+        if self.samples.len() == WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    fn score(&self) -> f32 {
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        let bad = self.samples.iter().filter(|s| s.is_overadvertised()).count();
+        bad as f32 / self.samples.len() as f32
+    }
+
+    fn suspected(&self) -> bool {
+        self.samples.len() >= MIN_SAMPLES && self.score() >= SUSPICION_THRESHOLD
+    }
+
+    fn suspicion(&self, iface: &str) -> Suspicion {
+        let last_rtt = self
+            .samples
+            .iter()
+            .rev()
+            .filter_map(|s| match *s {
+                Sample::Rtt {
+                    advertised,
+                    measured,
+                } => Some((advertised, measured)),
+                Sample::Price { .. } => None,
+            }).next();
+        Suspicion {
+            identity: self.identity.clone(),
+            iface: iface.to_string(),
+            samples: self.samples.len(),
+            score: self.score(),
+            suspected: self.suspected(),
+            penalized: self.penalized,
+            advertised_rtt: last_rtt.map(|r| r.0),
+            measured_rtt: last_rtt.map(|r| r.1),
+        }
+    }
+}
+
+/// The rxcost Babel uses on an interface. Babel doesn't report the configured cost, but it
+/// computes a neighbor's rxcost from it and that's only inflated by lost hellos, so a neighbor
+/// with full reach has exactly the interface's cost
+fn interface_cost(neighbors: &VecDeque<BabelNeighbor>, iface: &str) -> Option<u16> {
+    neighbors
+        .iter()
+        .find(|n| n.iface == iface && n.reach == FULL_REACH)
+        .map(|n| n.rxcost)
+}
+
+/// The route Babel has to the neighbor itself through its tunnel
+fn own_route<'a>(neighbor: &Neighbor, routes: &'a VecDeque<Route>) -> Option<&'a Route> {
+    routes.iter().find(|route| {
+        route.iface == neighbor.iface_name && match route.prefix {
+            IpNetwork::V6(ref ip) => IpAddr::V6(ip.ip()) == neighbor.identity.global.mesh_ip,
+            IpNetwork::V4(_) => false,
+        }
+    })
+}
+
+/// Checks the route through the neighbor that is cheapest compared to what it should cost
+fn price_sample(neighbor: &Neighbor, routes: &VecDeque<Route>) -> Option<Sample> {
+    let own = own_route(neighbor, routes)?;
+    routes
+        .iter()
+        .filter(|route| route.iface == neighbor.iface_name && route.prefix != own.prefix)
+        .map(|route| Sample::Price {
+            advertised: u64::from(route.price),
+            floor: u64::from(own.price) + u64::from(route.fee),
+        }).min_by_key(|sample| match *sample {
+            Sample::Price { advertised, floor } => advertised as i64 - floor as i64,
+            Sample::Rtt { .. } => 0,
+        })
+}
+
+fn millis(duration: Duration) -> f32 {
+    duration.as_secs() as f32 * 1000.0 + duration.subsec_nanos() as f32 / 1_000_000.0
+}
+
+/// Times a request to the neighbor's `/rtt` endpoint, the connection is set up before the clock
+/// starts so the handshake isn't counted
+fn measure_rtt(mesh_ip: IpAddr) -> Box<Future<Item = f32, Error = Error>> {
+    // the socket address formats v4 and v6 hosts correctly for the url
+    let addr = SocketAddr::new(mesh_ip, SETTING.get_network().rita_hello_port);
+    let endpoint = format!("http://{}/rtt", addr);
+    let stream = TokioTcpStream::connect(&addr);
+
+    Box::new(stream.from_err().and_then(move |stream| {
+        let sent = SystemTime::now();
+        let request = client::get(&endpoint)
+            .timeout(Duration::from_secs(RTT_TIMEOUT))
+            .with_connection(Connection::from_stream(stream))
+            .finish();
+        future::result(request)
+            .from_err()
+            .and_then(|request| request.send().from_err())
+            .and_then(|response| response.json().from_err())
+            .and_then(move |timestamps: RTTimestamps| -> Result<f32, Error> {
+                Ok(millis(timestamps.inner_rtt(sent, SystemTime::now())?))
+            })
+    }))
+}
+
+/// Measures the RTT to the neighbor and reports it against the `advertised` one
+fn measure_neighbor(neighbor: &Neighbor, advertised: f32) {
+    let iface = neighbor.iface_name.clone();
+    let mesh_ip = neighbor.identity.global.mesh_ip;
+    Arbiter::spawn(measure_rtt(mesh_ip).then(move |res| {
+        match res {
+            Ok(measured) => AdvertisementMonitor::from_registry().do_send(Observe {
+                iface,
+                sample: Sample::Rtt {
+                    advertised,
+                    measured,
+                },
+            }),
+            Err(e) => trace!("Failed to measure RTT to {}: {:?}", mesh_ip, e),
+        }
+        Ok(())
+    }));
+}
+
+pub struct AdvertisementMonitor {
+    /// Keyed by tunnel interface, every neighbor has its own
+    neighbors: HashMap<String, History>,
+}
+
+impl Actor for AdvertisementMonitor {
+    type Context = Context<Self>;
+}
+
+impl Supervised for AdvertisementMonitor {}
+impl SystemService for AdvertisementMonitor {
+    fn service_started(&mut self, _ctx: &mut Context<Self>) {
+        info!("AdvertisementMonitor started");
+    }
+}
+
+impl Default for AdvertisementMonitor {
+    fn default() -> AdvertisementMonitor {
+        AdvertisementMonitor {
+            neighbors: HashMap::new(),
+        }
+    }
+}
+
+impl AdvertisementMonitor {
+    /// Forgets neighbors whose tunnels are gone and starts tracking new ones
+    fn set_neighbors(&mut self, neighbors: &[Neighbor]) {
+        self.neighbors
+            .retain(|iface, _| neighbors.iter().any(|n| &n.iface_name == iface));
+        for neighbor in neighbors {
+            let identity = neighbor.identity.global.clone();
+            let history = self
+                .neighbors
+                .entry(neighbor.iface_name.clone())
+                .or_insert_with(|| History::new(identity.clone()));
+            history.identity = identity;
+        }
+    }
+
+    fn record(&mut self, iface: &str, sample: Sample) {
+        match self.neighbors.get_mut(iface) {
+            Some(history) => {
+                let was_suspected = history.suspected();
+                history.push(sample);
+                if history.suspected() && !was_suspected {
+                    warn!(
+                        "{} on {} is advertising better routes than we measure",
+                        history.identity.mesh_ip, iface
+                    );
+                }
+            }
+            None => trace!("No neighbor on {} for sample {:?}", iface, sample),
+        }
+    }
+
+    fn apply_penalties(&self, enabled: bool) {
+        for (iface, history) in self.neighbors.iter() {
+            let penalize = enabled && history.suspected();
+            if penalize == history.penalized {
+                continue;
+            }
+            let iface = iface.clone();
+            let change: Box<Future<Item = Option<u16>, Error = Error>> = if penalize {
+                // record the cost we are replacing so it can be put back
+                let read_iface = iface.clone();
+                let set_iface = iface.clone();
+                Box::new(
+                    babel_monitor::neighbors()
+                        .and_then(move |neighbors| {
+                            interface_cost(&neighbors, &read_iface).ok_or_else(|| {
+                                format_err!("No rxcost reading for {} yet", read_iface)
+                            })
+                        }).and_then(move |cost| {
+                            babel_monitor::set_interface_cost(&set_iface, PENALTY_COST)
+                                .map(move |_| Some(cost))
+                        }),
+                )
+            } else {
+                match history.original_cost {
+                    Some(cost) => {
+                        Box::new(babel_monitor::set_interface_cost(&iface, cost).map(|_| None))
+                    }
+                    None => {
+                        warn!("No original rxcost recorded for {}", iface);
+                        continue;
+                    }
+                }
+            };
+            Arbiter::spawn(change.then(move |res| {
+                match res {
+                    Ok(original_cost) => {
+                        AdvertisementMonitor::from_registry().do_send(PenaltyApplied {
+                            iface,
+                            penalized: penalize,
+                            original_cost,
+                        })
+                    }
+                    Err(e) => warn!("Failed to change the rxcost of {}: {:?}", iface, e),
+                }
+                Ok(())
+            }));
+        }
+    }
+}
+
+/// Sent every tick with the current neighbors, takes a new round of samples
+pub struct CheckNeighbors(pub Vec<Neighbor>);
+
+impl Message for CheckNeighbors {
+    type Result = ();
+}
+
+impl Handler<CheckNeighbors> for AdvertisementMonitor {
+    type Result = ();
+
+    fn handle(&mut self, msg: CheckNeighbors, ctx: &mut Context<Self>) -> Self::Result {
+        let neighbors = msg.0;
+        self.set_neighbors(&neighbors);
+
+        ctx.spawn(babel_monitor::routes().into_actor(self).then(
+            move |res, act: &mut Self, _ctx| {
+                match res {
+                    Ok(routes) => {
+                        for neighbor in neighbors.iter() {
+                            if let Some(sample) = price_sample(neighbor, &routes) {
+                                act.record(&neighbor.iface_name, sample);
+                            }
+                            if let Some(route) = own_route(neighbor, &routes) {
+                                measure_neighbor(neighbor, route.full_path_rtt);
+                            }
+                        }
+                    }
+                    Err(e) => warn!("Failed to get routes to check advertisements: {:?}", e),
+                }
+                act.apply_penalties(SETTING.get_network().penalize_overadvertisers);
+                fut::ok(())
+            },
+        ));
+    }
+}
+
+/// A sample for the neighbor on `iface` taken somewhere else, like the exit route RTT
+pub struct Observe {
+    pub iface: String,
+    pub sample: Sample,
+}
+
+impl Message for Observe {
+    type Result = ();
+}
+
+impl Handler<Observe> for AdvertisementMonitor {
+    type Result = ();
+
+    fn handle(&mut self, msg: Observe, _ctx: &mut Context<Self>) -> Self::Result {
+        self.record(&msg.iface, msg.sample);
+    }
+}
+
+struct PenaltyApplied {
+    iface: String,
+    penalized: bool,
+    original_cost: Option<u16>,
+}
+
+impl Message for PenaltyApplied {
+    type Result = ();
+}
+
+impl Handler<PenaltyApplied> for AdvertisementMonitor {
+    type Result = ();
+
+    fn handle(&mut self, msg: PenaltyApplied, _ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(history) = self.neighbors.get_mut(&msg.iface) {
+            info!("Penalized {} on {}: {}", history.identity.mesh_ip, msg.iface, msg.penalized);
+            history.penalized = msg.penalized;
+            history.original_cost = msg.original_cost;
+        }
+    }
+}
+
+pub struct GetSuspicions;
+
+impl Message for GetSuspicions {
+    type Result = Result<Vec<Suspicion>, Error>;
+}
+
+impl Handler<GetSuspicions> for AdvertisementMonitor {
+    type Result = Result<Vec<Suspicion>, Error>;
+
+    fn handle(&mut self, _msg: GetSuspicions, _ctx: &mut Context<Self>) -> Self::Result {
+        let mut suspicions: Vec<Suspicion> = self
+            .neighbors
+            .iter()
+            .map(|(iface, history)| history.suspicion(iface))
+            .collect();
+        suspicions.sort_by(|a, b| a.iface.cmp(&b.iface));
+        Ok(suspicions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use althea_types::{EthAddress, LocalIdentity};
+    use babel_monitor::{neighs_from_dump, routes_from_dump};
+    use std::str::FromStr;
+
+    static DUMP: &'static str = "local fee 50\n\
+add route 1 prefix fd00::1/128 from ::/0 installed yes id 1 metric 96 price 10 fee 10 \
+refmetric 0 full-path-rtt 1.123 via fe80::1 if wg0\n\
+add route 2 prefix fd00::2/128 from ::/0 installed no id 2 metric 352 price 25 fee 15 \
+refmetric 256 full-path-rtt 40.5 via fe80::1 if wg0\n\
+add route 3 prefix fd00::3/128 from ::/0 installed yes id 3 metric 352 price 12 fee 20 \
+refmetric 256 full-path-rtt 8.0 via fe80::1 if wg0\n\
+add route 4 prefix fd00::2/128 from ::/0 installed yes id 2 metric 256 price 15 fee 15 \
+refmetric 0 full-path-rtt 12.6 via fe80::2 if wg1\n\
+ok\n";
+
+    fn neighbor(mesh_ip: &str, iface: &str) -> Neighbor {
+        Neighbor {
+            identity: LocalIdentity {
+                wg_port: 60000,
+                have_tunnel: Some(true),
+                global: Identity::new(
+                    mesh_ip.parse().unwrap(),
+                    EthAddress::from_str("ffffffffffffffffffffffffffffffffffffffff").unwrap(),
+                    String::from("abc0abc1abc2abc3abc4abc5abc6abc7abc8abc9"),
+                ),
+            },
+            iface_name: iface.to_string(),
+            tunnel_ip: "fe80::1".parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_rtt_samples() {
+        let honest = Sample::Rtt {
+            advertised: 20.0,
+            measured: 26.0,
+        };
+        let noisy = Sample::Rtt {
+            advertised: 100.0,
+            measured: 120.0,
+        };
+        let lying = Sample::Rtt {
+            advertised: 5.0,
+            measured: 40.0,
+        };
+        assert!(!honest.is_overadvertised());
+        assert!(!noisy.is_overadvertised());
+        assert!(lying.is_overadvertised());
+    }
+
+    #[test]
+    fn test_price_sample() {
+        let routes = routes_from_dump(DUMP).unwrap();
+        // fd00::3 is advertised for 12 through a neighbor charging 10, but it charges 20 itself
+        assert_eq!(
+            price_sample(&neighbor("fd00::1", "wg0"), &routes),
+            Some(Sample::Price {
+                advertised: 12,
+                floor: 30,
+            })
+        );
+        // the only route through wg1 is the one to the neighbor itself
+        assert_eq!(price_sample(&neighbor("fd00::2", "wg1"), &routes), None);
+        assert_eq!(price_sample(&neighbor("fd00::9", "wg2"), &routes), None);
+        assert_eq!(
+            own_route(&neighbor("fd00::2", "wg1"), &routes).unwrap().full_path_rtt,
+            12.6
+        );
+    }
+
+    #[test]
+    fn test_interface_cost() {
+        let neighbors = neighs_from_dump(
+            "add neighbour 1 address fe80::1 if wg0 reach ffff ureach 0000 rxcost 96 \
+             txcost 96 rtt 1.123 rttcost 0 cost 96\n\
+             add neighbour 2 address fe80::2 if wg1 reach fff0 ureach 0000 rxcost 128 \
+             txcost 256 cost 256\n\
+             ok\n",
+        ).unwrap();
+        assert_eq!(interface_cost(&neighbors, "wg0"), Some(96));
+        // lost hellos inflate the rxcost, it can't be read until they stop
+        assert_eq!(interface_cost(&neighbors, "wg1"), None);
+        assert_eq!(interface_cost(&neighbors, "wg2"), None);
+    }
+
+    #[test]
+    fn test_suspicion_score() {
+        let honest = Sample::Rtt {
+            advertised: 20.0,
+            measured: 20.0,
+        };
+        let lying = Sample::Rtt {
+            advertised: 5.0,
+            measured: 40.0,
+        };
+        let mut monitor = AdvertisementMonitor::default();
+        monitor.set_neighbors(&[neighbor("fd00::1", "wg0")]);
+
+        for _ in 0..(MIN_SAMPLES - 1) {
+            monitor.record("wg0", lying);
+        }
+        assert!(!monitor.neighbors["wg0"].suspected());
+        monitor.record("wg0", lying);
+        let suspicion = monitor.neighbors["wg0"].suspicion("wg0");
+        assert!(suspicion.suspected);
+        assert_eq!(suspicion.score, 1.0);
+        assert_eq!(suspicion.advertised_rtt, Some(5.0));
+        assert_eq!(suspicion.measured_rtt, Some(40.0));
+
+        // honest samples push the lies out of the window
+        for _ in 0..WINDOW {
+            monitor.record("wg0", honest);
+        }
+        assert_eq!(monitor.neighbors["wg0"].samples.len(), WINDOW);
+        assert_eq!(monitor.neighbors["wg0"].score(), 0.0);
+
+        // samples for interfaces without a neighbor are dropped, as are gone neighbors
+        monitor.record("wg9", lying);
+        monitor.set_neighbors(&[neighbor("fd00::2", "wg1")]);
+        assert!(!monitor.neighbors.contains_key("wg0"));
+        assert!(monitor.neighbors["wg1"].samples.is_empty());
+    }
+}
@@ -16,10 +16,16 @@ use SETTING;
 use reqwest;
 use rita_common::debt_keeper;
 use rita_common::debt_keeper::DebtKeeper;
+use rita_common::event_bus::{self, Event};
+use rita_common::metrics;
 use serde_json;
 
 use failure::Error;
 
+pub mod spending;
+
+use self::spending::{unix_time, SpendingStatus, SpendingTracker};
+
 #[derive(Debug, Fail)]
 pub enum PaymentControllerError {
     #[fail(display = "Payment Sending Error: {:?}", _0)]
@@ -31,6 +37,7 @@ pub enum PaymentControllerError {
 pub struct PaymentController {
     pub reqwest_client: Client,
     pub balance: Int256,
+    spending: SpendingTracker,
 }
 
 impl Actor for PaymentController {
@@ -50,7 +57,12 @@ impl Handler<PaymentReceived> for PaymentController {
     type Result = ();
 
     fn handle(&mut self, msg: PaymentReceived, _: &mut Context<Self>) -> Self::Result {
+        let event = Event::PaymentReceived {
+            from: msg.0.from.clone(),
+            amount: Int256::from(msg.0.amount.clone()),
+        };
         DebtKeeper::from_registry().do_send(self.payment_received(msg.0).unwrap());
+        event_bus::publish(event);
     }
 }
 
@@ -62,7 +74,10 @@ impl Handler<MakePayment> for PaymentController {
 
     fn handle(&mut self, msg: MakePayment, _ctx: &mut Context<Self>) -> Self::Result {
         match self.make_payment(msg.clone().0) {
-            Ok(()) => {}
+            Ok(()) => event_bus::publish(Event::PaymentSent {
+                to: msg.0.to,
+                amount: Int256::from(msg.0.amount),
+            }),
             Err(err) => {
                 warn!("got error from make payment {:?}, retrying", err);
                 // ctx.notify_later(msg, Duration::from_secs(5));
@@ -101,6 +116,20 @@ impl Handler<GetOwnBalance> for PaymentController {
     }
 }
 
+pub struct GetSpending;
+
+impl Message for GetSpending {
+    type Result = Result<SpendingStatus, Error>;
+}
+
+impl Handler<GetSpending> for PaymentController {
+    type Result = Result<SpendingStatus, Error>;
+    fn handle(&mut self, _msg: GetSpending, _: &mut Context<Self>) -> Self::Result {
+        let settings = SETTING.get_spending().clone();
+        Ok(self.spending.status(&self.balance, &settings, unix_time()))
+    }
+}
+
 /// This updates a "bounty hunter" with the current balance and the last `PaymentTx`.
 /// Bounty hunters are servers which store and possibly enforce the current state of
 /// a channel. Currently they are actually just showing a completely insecure
@@ -129,9 +158,20 @@ impl PaymentController {
                 .build()
                 .unwrap(),
             balance: Int256::from(0i64),
+            spending: SpendingTracker::restore(
+                SETTING.get_spending().totals.as_ref(),
+                unix_time(),
+            ),
         }
     }
 
+    /// Sends out any spending or low balance alerts that have come up since the last check
+    fn check_spending(&mut self) {
+        let settings = SETTING.get_spending().clone();
+        let alerts = self.spending.check(&self.balance, &settings, unix_time());
+        spending::send_alerts(alerts, &settings);
+    }
+
     fn update_bounty_actual(&self, update: BountyUpdate) -> Result<(), Error> {
         trace!("Sending bounty hunter update: {:?}", update);
         let bounty_url = if cfg!(not(test)) {
@@ -190,6 +230,9 @@ impl PaymentController {
         );
 
         self.balance = self.balance.clone() + Int256::from(pmt.amount.clone());
+        metrics::WEI_PAID
+            .with_label_values(&["received"])
+            .inc_by(metrics::wei(&Int256::from(pmt.amount.clone())));
 
         trace!("current balance: {:?}", self.balance);
 
@@ -222,6 +265,7 @@ impl PaymentController {
             balance: self.balance.clone(),
         })?;
         info!("Balance update: {:?}", self.balance);
+        self.check_spending();
         Ok(())
     }
 
@@ -253,6 +297,13 @@ impl PaymentController {
 
         if r.status() == StatusCode::OK {
             self.balance = self.balance.clone() - Int256::from(pmt.amount.clone());
+            metrics::WEI_PAID
+                .with_label_values(&["sent"])
+                .inc_by(metrics::wei(&Int256::from(pmt.amount.clone())));
+            self.spending
+                .record(&Int256::from(pmt.amount.clone()), unix_time());
+            SETTING.get_spending_mut().totals = Some(self.spending.totals());
+            self.check_spending();
             self.update_bounty(BountyUpdate {
                 from: SETTING
                     .get_identity()
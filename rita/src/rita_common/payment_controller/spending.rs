@@ -0,0 +1,426 @@
+//! Tracks what we pay out per day and month against the caps in the spending settings and raises
+//! alerts when a cap is reached or the balance runs low. Alerts go out on the event bus for the
+//! dashboard and optionally to a webhook and an email address. The totals are saved in the
+//! spending settings so a restart doesn't reset them.
+
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix::prelude::*;
+use actix_web::client;
+use futures::Future;
+
+use lettre::smtp::authentication::{Credentials, Mechanism};
+use lettre::smtp::extension::ClientId;
+use lettre::smtp::ConnectionReuseParameters;
+use lettre::{file::FileTransport, SmtpClient, Transport};
+use lettre_email::EmailBuilder;
+
+use num256::{Int256, Unit};
+
+use settings::{ExitMailerSettings, SpendingSettings, SpendingTotals};
+
+use rita_common::dashboard::DISPLAY_PLACES;
+use rita_common::event_bus::{self, Event};
+
+use failure::Error;
+
+const SECONDS_PER_DAY: u64 = 86400;
+
+pub fn unix_time() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(time) => time.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+/// Year and month of a day counted from the unix epoch, Howard Hinnant's `civil_from_days`
+fn year_month(days: u64) -> (u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month)
+}
+
+fn over(spent: &Int256, cap: &Option<Int256>) -> bool {
+    match *cap {
+        Some(ref cap) => spent >= cap,
+        None => false,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpendingPeriod {
+    Day,
+    Month,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SpendingStatus {
+    pub balance: Int256,
+    pub balance_display: String,
+    pub spent_today: Int256,
+    pub spent_today_display: String,
+    pub spent_this_month: Int256,
+    pub spent_this_month_display: String,
+    pub daily_cap: Option<Int256>,
+    pub monthly_cap: Option<Int256>,
+    pub low_balance: Option<Int256>,
+    pub cap_reached: bool,
+    pub hard_stop: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct SpendingTracker {
+    day: u64,
+    month: (u64, u64),
+    spent_today: Int256,
+    spent_this_month: Int256,
+    low_balance_alerted: bool,
+    day_alerted: bool,
+    month_alerted: bool,
+}
+
+impl SpendingTracker {
+    pub fn new(now: u64) -> SpendingTracker {
+        let day = now / SECONDS_PER_DAY;
+        SpendingTracker {
+            day,
+            month: year_month(day),
+            spent_today: Int256::zero(),
+            spent_this_month: Int256::zero(),
+            low_balance_alerted: false,
+            day_alerted: false,
+            month_alerted: false,
+        }
+    }
+
+    /// Picks up the totals saved before a restart, those of a past day or month are dropped
+    pub fn restore(totals: Option<&SpendingTotals>, now: u64) -> SpendingTracker {
+        let mut tracker = match totals {
+            Some(totals) => SpendingTracker {
+                day: totals.day,
+                month: year_month(totals.day),
+                spent_today: totals.spent_today.clone(),
+                spent_this_month: totals.spent_this_month.clone(),
+                ..SpendingTracker::new(now)
+            },
+            None => SpendingTracker::new(now),
+        };
+        tracker.roll(now);
+        tracker
+    }
+
+    pub fn totals(&self) -> SpendingTotals {
+        SpendingTotals {
+            day: self.day,
+            spent_today: self.spent_today.clone(),
+            spent_this_month: self.spent_this_month.clone(),
+        }
+    }
+
+    /// Starts over the totals once `now` is past the current day or month
+    fn roll(&mut self, now: u64) {
+        let day = now / SECONDS_PER_DAY;
+        if day == self.day {
+            return;
+        }
+        self.day = day;
+        self.spent_today = Int256::zero();
+        self.day_alerted = false;
+
+        let month = year_month(day);
+        if month != self.month {
+            self.month = month;
+            self.spent_this_month = Int256::zero();
+            self.month_alerted = false;
+        }
+    }
+
+    pub fn record(&mut self, amount: &Int256, now: u64) {
+        self.roll(now);
+        self.spent_today = self.spent_today.saturating_add(amount);
+        self.spent_this_month = self.spent_this_month.saturating_add(amount);
+    }
+
+    pub fn cap_reached(&mut self, settings: &SpendingSettings, now: u64) -> bool {
+        self.roll(now);
+        over(&self.spent_today, &settings.daily_cap)
+            || over(&self.spent_this_month, &settings.monthly_cap)
+    }
+
+    /// Alerts that have newly come up, each is raised once until its condition clears
+    pub fn check(&mut self, balance: &Int256, settings: &SpendingSettings, now: u64) -> Vec<Event> {
+        self.roll(now);
+        let mut alerts = Vec::new();
+
+        match settings.low_balance {
+            Some(ref threshold) if balance < threshold => {
+                if !self.low_balance_alerted {
+                    alerts.push(Event::LowBalance {
+                        balance: balance.clone(),
+                        threshold: threshold.clone(),
+                    });
+                }
+                self.low_balance_alerted = true;
+            }
+            _ => self.low_balance_alerted = false,
+        }
+
+        if over(&self.spent_today, &settings.daily_cap) {
+            if !self.day_alerted {
+                alerts.push(Event::SpendingCapReached {
+                    period: SpendingPeriod::Day,
+                    spent: self.spent_today.clone(),
+                    cap: settings.daily_cap.clone().unwrap(),
+                });
+            }
+            self.day_alerted = true;
+        } else {
+            self.day_alerted = false;
+        }
+
+        if over(&self.spent_this_month, &settings.monthly_cap) {
+            if !self.month_alerted {
+                alerts.push(Event::SpendingCapReached {
+                    period: SpendingPeriod::Month,
+                    spent: self.spent_this_month.clone(),
+                    cap: settings.monthly_cap.clone().unwrap(),
+                });
+            }
+            self.month_alerted = true;
+        } else {
+            self.month_alerted = false;
+        }
+
+        alerts
+    }
+
+    pub fn status(
+        &mut self,
+        balance: &Int256,
+        settings: &SpendingSettings,
+        now: u64,
+    ) -> SpendingStatus {
+        let cap_reached = self.cap_reached(settings, now);
+        SpendingStatus {
+            balance: balance.clone(),
+            balance_display: balance.display(Unit::Ether, DISPLAY_PLACES),
+            spent_today: self.spent_today.clone(),
+            spent_today_display: self.spent_today.display(Unit::Ether, DISPLAY_PLACES),
+            spent_this_month: self.spent_this_month.clone(),
+            spent_this_month_display: self.spent_this_month.display(Unit::Ether, DISPLAY_PLACES),
+            daily_cap: settings.daily_cap.clone(),
+            monthly_cap: settings.monthly_cap.clone(),
+            low_balance: settings.low_balance.clone(),
+            cap_reached,
+            hard_stop: settings.hard_stop,
+        }
+    }
+}
+
+fn describe(alert: &Event) -> String {
+    match *alert {
+        Event::LowBalance {
+            ref balance,
+            ref threshold,
+        } => format!(
+            "The router's balance of {} is below {}",
+            balance.display(Unit::Ether, DISPLAY_PLACES),
+            threshold.display(Unit::Ether, DISPLAY_PLACES)
+        ),
+        Event::SpendingCapReached {
+            period,
+            ref spent,
+            ref cap,
+        } => format!(
+            "The router has spent {} this {}, reaching its cap of {}",
+            spent.display(Unit::Ether, DISPLAY_PLACES),
+            match period {
+                SpendingPeriod::Day => "day",
+                SpendingPeriod::Month => "month",
+            },
+            cap.display(Unit::Ether, DISPLAY_PLACES)
+        ),
+        ref other => format!("{:?}", other),
+    }
+}
+
+fn send_email(mailer: &ExitMailerSettings, to: &str, text: String) -> Result<(), Error> {
+    let email = EmailBuilder::new()
+        .to(to.to_string())
+        .from(mailer.from_address.clone())
+        .subject("Althea router spending alert")
+        .text(text)
+        .build()?;
+
+    if mailer.test {
+        let mut transport = FileTransport::new(&mailer.test_dir);
+        transport.send(email.into())?;
+    } else {
+        let mut transport = SmtpClient::new_simple(&mailer.smtp_url)?
+            .hello_name(ClientId::Domain(mailer.smtp_domain.clone()))
+            .credentials(Credentials::new(
+                mailer.smtp_username.clone(),
+                mailer.smtp_password.clone(),
+            )).smtp_utf8(true)
+            .authentication_mechanism(Mechanism::Plain)
+            .connection_reuse(ConnectionReuseParameters::ReuseUnlimited)
+            .transport();
+        transport.send(email.into())?;
+    }
+    Ok(())
+}
+
+fn send_webhook(url: &str, alert: &Event) {
+    let url = url.to_string();
+    let request = match client::post(&url)
+        .timeout(Duration::from_secs(5))
+        .json(alert.clone())
+    {
+        Ok(request) => request,
+        Err(e) => {
+            warn!("Failed to build alert request for {}: {:?}", url, e);
+            return;
+        }
+    };
+    Arbiter::spawn(request.send().then(move |res| {
+        match res {
+            Ok(ref res) if res.status().is_success() => {}
+            Ok(res) => warn!("Alert webhook {} returned {}", url, res.status()),
+            Err(e) => warn!("Failed to send alert to webhook {}: {:?}", url, e),
+        }
+        Ok(())
+    }));
+}
+
+/// Publishes the alerts on the event bus and sends them on to the configured webhook and email.
+/// The webhook request runs on the event loop and the emails on their own thread so a slow
+/// server doesn't hold up the caller
+pub fn send_alerts(alerts: Vec<Event>, settings: &SpendingSettings) {
+    if let (&Some(ref to), &Some(ref mailer)) = (&settings.alert_email, &settings.mailer) {
+        if !alerts.is_empty() {
+            let texts: Vec<String> = alerts.iter().map(describe).collect();
+            let to = to.clone();
+            let mailer = mailer.clone();
+            thread::spawn(move || {
+                for text in texts {
+                    if let Err(e) = send_email(&mailer, &to, text) {
+                        warn!("Failed to email alert to {}: {:?}", to, e);
+                    }
+                }
+            });
+        }
+    }
+
+    for alert in alerts {
+        warn!("{}", describe(&alert));
+
+        if let Some(ref url) = settings.webhook_url {
+            send_webhook(url, &alert);
+        }
+
+        event_bus::publish(alert);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2018-10-18 00:00 UTC
+    const OCT_18: u64 = 17822 * SECONDS_PER_DAY;
+
+    #[test]
+    fn test_year_month() {
+        assert_eq!(year_month(0), (1970, 1));
+        assert_eq!(year_month(17822), (2018, 10));
+        assert_eq!(year_month(18321), (2020, 2));
+        assert_eq!(year_month(18322), (2020, 3));
+        assert_eq!(year_month(11017), (2000, 3));
+        assert_eq!(year_month(11016), (2000, 2));
+    }
+
+    #[test]
+    fn test_spending_windows() {
+        let settings = SpendingSettings {
+            daily_cap: Some(Int256::from(100)),
+            monthly_cap: Some(Int256::from(250)),
+            ..SpendingSettings::default()
+        };
+        let mut tracker = SpendingTracker::new(OCT_18);
+
+        tracker.record(&Int256::from(60), OCT_18 + 10);
+        assert!(!tracker.cap_reached(&settings, OCT_18 + 20));
+        tracker.record(&Int256::from(40), OCT_18 + 30);
+        assert!(tracker.cap_reached(&settings, OCT_18 + 40));
+
+        // the next day starts over the daily total but not the monthly one
+        let next_day = OCT_18 + SECONDS_PER_DAY;
+        assert!(!tracker.cap_reached(&settings, next_day));
+        tracker.record(&Int256::from(90), next_day);
+        tracker.record(&Int256::from(90), next_day + 2 * SECONDS_PER_DAY);
+        assert_eq!(tracker.spent_this_month, Int256::from(280));
+        assert!(tracker.cap_reached(&settings, next_day + 2 * SECONDS_PER_DAY));
+
+        // November
+        assert!(!tracker.cap_reached(&settings, OCT_18 + 14 * SECONDS_PER_DAY));
+        assert_eq!(tracker.spent_this_month, Int256::zero());
+    }
+
+    #[test]
+    fn test_restore_totals() {
+        let mut tracker = SpendingTracker::new(OCT_18);
+        tracker.record(&Int256::from(60), OCT_18 + 10);
+        let totals = tracker.totals();
+
+        let restored = SpendingTracker::restore(Some(&totals), OCT_18 + 20);
+        assert_eq!(restored.spent_today, Int256::from(60));
+        assert_eq!(restored.spent_this_month, Int256::from(60));
+
+        // a restart on a later day of the month keeps only the monthly total
+        let restored = SpendingTracker::restore(Some(&totals), OCT_18 + SECONDS_PER_DAY);
+        assert_eq!(restored.spent_today, Int256::zero());
+        assert_eq!(restored.spent_this_month, Int256::from(60));
+
+        let restored = SpendingTracker::restore(Some(&totals), OCT_18 + 14 * SECONDS_PER_DAY);
+        assert_eq!(restored.spent_this_month, Int256::zero());
+    }
+
+    #[test]
+    fn test_alerts_raised_once() {
+        let eth = 1_000_000_000_000_000_000i64;
+        let settings = SpendingSettings {
+            daily_cap: Some(Int256::from(100)),
+            low_balance: Some(Int256::from(-eth)),
+            ..SpendingSettings::default()
+        };
+        let mut tracker = SpendingTracker::new(OCT_18);
+
+        assert!(tracker.check(&Int256::zero(), &settings, OCT_18).is_empty());
+
+        tracker.record(&Int256::from(100), OCT_18);
+        let alerts = tracker.check(&Int256::from(-2 * eth), &settings, OCT_18);
+        assert_eq!(alerts.len(), 2);
+        match alerts[1] {
+            Event::SpendingCapReached { period, .. } => assert_eq!(period, SpendingPeriod::Day),
+            ref other => panic!("unexpected alert {:?}", other),
+        }
+        assert!(tracker.check(&Int256::from(-2 * eth), &settings, OCT_18).is_empty());
+
+        // a recovered balance re-arms the low balance alert
+        assert!(tracker.check(&Int256::zero(), &settings, OCT_18).is_empty());
+        let alerts = tracker.check(&Int256::from(-eth - eth / 4), &settings, OCT_18);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(
+            describe(&alerts[0]),
+            "The router's balance of -1.25 ETH is below -1 ETH"
+        );
+    }
+}
@@ -0,0 +1,410 @@
+//! The PriceManager adjusts the fee we advertise in Babel for forwarding traffic. The base fee is
+//! `payment.local_fee` or whichever time of day schedule is running, once the busiest measured
+//! interface is past the congestion threshold the fee rises linearly toward the congestion
+//! multiplier of the base at full utilization. The result is kept between the configured floor
+//! and ceiling.
+//!
+//! Utilization is worked out from the cumulative counters in /proc/net/dev, with the same per
+//! packet overhead the iptables interface counters are billed with. Every fee change makes Babel
+//! send updates for all of our routes, so changes are only pushed once per `update_interval` and
+//! only when they are at least `min_change` percent.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use actix::prelude::*;
+
+use futures::future;
+use futures::Future;
+
+use althea_kernel_interface::IfaceCounter;
+use althea_types::DeviceStats;
+
+use babel_monitor;
+
+use settings::{PriceSchedule, PricingSettings, RitaCommonSettings};
+use KI;
+use SETTING;
+
+use failure::Error;
+
+/// The hour of the day at `offset_minutes` from UTC
+fn hour_of_day(unix_secs: u64, offset_minutes: i32) -> u8 {
+    let minutes = (unix_secs / 60) as i64 + i64::from(offset_minutes);
+    (((minutes % 1440) + 1440) % 1440 / 60) as u8
+}
+
+fn in_schedule(schedule: &PriceSchedule, hour: u8) -> bool {
+    if schedule.start <= schedule.end {
+        hour >= schedule.start && hour < schedule.end
+    } else {
+        hour >= schedule.start || hour < schedule.end
+    }
+}
+
+fn scheduled_fee(schedule: &[PriceSchedule], hour: u8) -> Option<u32> {
+    schedule
+        .iter()
+        .find(|s| in_schedule(s, hour))
+        .map(|s| s.fee)
+}
+
+/// Both directions are counted, mesh links are mostly half duplex radios
+fn total_bytes(device: &DeviceStats) -> u64 {
+    IfaceCounter::new(device.rx_bytes, device.rx_packets)
+        .total_bytes()
+        .saturating_add(IfaceCounter::new(device.tx_bytes, device.tx_packets).total_bytes())
+}
+
+/// Percentage of `capacity` bytes per second used by `bytes` over `elapsed`
+fn percent_used(bytes: u64, elapsed: Duration, capacity: u64) -> u8 {
+    let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1_000_000_000.0;
+    if capacity == 0 || secs <= 0.0 {
+        return 0;
+    }
+    let percent = bytes as f64 / secs / capacity as f64 * 100.0;
+    percent.min(100.0) as u8
+}
+
+pub fn congestion_price(base: u32, utilization: u8, settings: &PricingSettings) -> u32 {
+    let base = u64::from(base);
+    let threshold = u64::from(settings.congestion_threshold.min(100));
+    let utilization = u64::from(utilization.min(100));
+
+    let price = if utilization <= threshold || threshold == 100 {
+        base
+    } else {
+        let peak = base * u64::from(settings.congestion_multiplier.max(100)) / 100;
+        let rise = (peak - base).saturating_mul(utilization - threshold) / (100 - threshold);
+        base.saturating_add(rise)
+    };
+
+    price
+        .max(u64::from(settings.floor))
+        .min(u64::from(settings.ceiling)) as u32
+}
+
+/// Small changes aren't worth a round of Babel updates
+fn should_update(current: Option<u32>, target: u32, min_change: u8) -> bool {
+    match current {
+        None => true,
+        Some(fee) if fee == target => false,
+        Some(fee) => {
+            let diff = (i64::from(fee) - i64::from(target)).abs() as u64;
+            diff * 100 >= u64::from(fee) * u64::from(min_change)
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct PricingStatus {
+    pub enabled: bool,
+    pub base_fee: u32,
+    /// The fee we would set right now, before rate limiting
+    pub target_fee: u32,
+    /// The fee we last set in Babel, None until the first update
+    pub fee: Option<u32>,
+    /// Percentage of capacity in use on each measured interface over the last tick
+    pub utilization: HashMap<String, u8>,
+}
+
+pub struct PriceManager {
+    /// The last byte count of each measured interface and when it was read
+    counters: HashMap<String, (Instant, u64)>,
+    utilization: HashMap<String, u8>,
+    base_fee: u32,
+    target_fee: u32,
+    fee: Option<u32>,
+    /// The fee Babel had before we first changed it
+    baseline_fee: Option<u32>,
+    last_update: Option<Instant>,
+}
+
+impl Actor for PriceManager {
+    type Context = Context<Self>;
+}
+
+impl Supervised for PriceManager {}
+impl SystemService for PriceManager {
+    fn service_started(&mut self, _ctx: &mut Context<Self>) {
+        info!("PriceManager started");
+    }
+}
+
+impl Default for PriceManager {
+    fn default() -> PriceManager {
+        PriceManager {
+            counters: HashMap::new(),
+            utilization: HashMap::new(),
+            base_fee: 0,
+            target_fee: 0,
+            fee: None,
+            baseline_fee: None,
+            last_update: None,
+        }
+    }
+}
+
+impl PriceManager {
+    fn measure(
+        &mut self,
+        devices: &[DeviceStats],
+        interfaces: &HashSet<String>,
+        settings: &PricingSettings,
+        now: Instant,
+    ) {
+        let mut utilization = HashMap::new();
+        for device in devices.iter().filter(|d| interfaces.contains(&d.name)) {
+            let total = total_bytes(device);
+            if let Some(&(then, last)) = self.counters.get(&device.name) {
+                let capacity = match settings.capacities.get(&device.name) {
+                    Some(capacity) => *capacity,
+                    None => settings.link_capacity,
+                };
+                // counters start over when an interface is recreated, skip a tick when they do
+                if total >= last {
+                    let used = percent_used(total - last, now.duration_since(then), capacity);
+                    utilization.insert(device.name.clone(), used);
+                }
+            }
+            self.counters.insert(device.name.clone(), (now, total));
+        }
+        self.counters.retain(|name, _| interfaces.contains(name));
+        self.utilization = utilization;
+    }
+
+    fn busiest(&self) -> u8 {
+        self.utilization.values().cloned().max().unwrap_or(0)
+    }
+
+    fn rate_limited(&self, now: Instant, interval: u64) -> bool {
+        match self.last_update {
+            Some(last) => now.duration_since(last) < Duration::from_secs(interval),
+            None => false,
+        }
+    }
+
+    fn push_fee(&mut self, fee: u32, now: Instant, ctx: &mut Context<Self>) {
+        self.last_update = Some(now);
+        // read what Babel is using before our first change so it can be put back later
+        let baseline: Box<Future<Item = Option<u32>, Error = Error>> =
+            if self.baseline_fee.is_none() {
+                Box::new(babel_monitor::local_fee().map(Some))
+            } else {
+                Box::new(future::ok(None))
+            };
+        let set = baseline
+            .and_then(move |baseline| babel_monitor::set_local_fee(fee).map(move |_| baseline));
+        ctx.spawn(set.into_actor(self).then(move |res, act: &mut Self, _ctx| {
+            match res {
+                Ok(baseline) => {
+                    info!("Set our forwarding fee to {}", fee);
+                    act.fee = Some(fee);
+                    if act.baseline_fee.is_none() {
+                        act.baseline_fee = baseline;
+                    }
+                }
+                Err(e) => warn!("Failed to set our forwarding fee to {}: {:?}", fee, e),
+            }
+            fut::ok(())
+        }));
+    }
+}
+
+/// Sent by the rita loop every tick
+pub struct UpdatePrice;
+
+impl Message for UpdatePrice {
+    type Result = Result<(), Error>;
+}
+
+impl Handler<UpdatePrice> for PriceManager {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, _msg: UpdatePrice, ctx: &mut Context<Self>) -> Self::Result {
+        let settings = SETTING.get_pricing().clone();
+        let local_fee = SETTING.get_payment().local_fee;
+        let now = Instant::now();
+
+        if !settings.enabled {
+            // hand the fee back to the static setting once pricing is turned off, or to what
+            // Babel had before we changed it if there is none
+            if let Some(fee) = self.fee.take() {
+                match local_fee.or(self.baseline_fee) {
+                    Some(baseline) if baseline != fee => {
+                        Arbiter::spawn(babel_monitor::set_local_fee(baseline).then(move |res| {
+                            if let Err(e) = res {
+                                warn!("Failed to restore our forwarding fee {}: {:?}", baseline, e);
+                            }
+                            Ok(())
+                        }))
+                    }
+                    _ => {}
+                }
+            }
+            return Ok(());
+        }
+
+        let interfaces = if settings.interfaces.is_empty() {
+            SETTING.get_network().peer_interfaces.clone()
+        } else {
+            settings.interfaces.clone()
+        };
+        let devices = KI.get_device_stats()?;
+        self.measure(&devices, &interfaces, &settings, now);
+
+        let hour = hour_of_day(
+            SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            settings.utc_offset_minutes,
+        );
+        self.base_fee = scheduled_fee(&settings.schedule, hour)
+            .or(local_fee)
+            .unwrap_or(settings.floor);
+        self.target_fee = congestion_price(self.base_fee, self.busiest(), &settings);
+        trace!(
+            "Utilization {:?}, base fee {}, target fee {}",
+            self.utilization,
+            self.base_fee,
+            self.target_fee
+        );
+
+        if !self.rate_limited(now, settings.update_interval)
+            && should_update(self.fee, self.target_fee, settings.min_change)
+        {
+            let fee = self.target_fee;
+            self.push_fee(fee, now, ctx);
+        }
+        Ok(())
+    }
+}
+
+/// Sent when the fee has been set by hand through the dashboard, whatever fee we set last is no
+/// longer what Babel is using
+#[derive(Message)]
+pub struct FeeSetManually;
+
+impl Handler<FeeSetManually> for PriceManager {
+    type Result = ();
+
+    fn handle(&mut self, _msg: FeeSetManually, _ctx: &mut Context<Self>) -> Self::Result {
+        self.fee = None;
+    }
+}
+
+pub struct GetPricing;
+
+impl Message for GetPricing {
+    type Result = Result<PricingStatus, Error>;
+}
+
+impl Handler<GetPricing> for PriceManager {
+    type Result = Result<PricingStatus, Error>;
+
+    fn handle(&mut self, _msg: GetPricing, _ctx: &mut Context<Self>) -> Self::Result {
+        Ok(PricingStatus {
+            enabled: SETTING.get_pricing().enabled,
+            base_fee: self.base_fee,
+            target_fee: self.target_fee,
+            fee: self.fee,
+            utilization: self.utilization.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(name: &str, rx_bytes: u64, tx_bytes: u64) -> DeviceStats {
+        DeviceStats {
+            name: name.to_string(),
+            rx_bytes,
+            rx_packets: 0,
+            rx_errors: 0,
+            rx_dropped: 0,
+            tx_bytes,
+            tx_packets: 0,
+            tx_errors: 0,
+            tx_dropped: 0,
+        }
+    }
+
+    #[test]
+    fn test_congestion_price() {
+        let mut settings = PricingSettings::default();
+        assert_eq!(congestion_price(1000, 0, &settings), 1000);
+        assert_eq!(congestion_price(1000, 50, &settings), 1000);
+        assert_eq!(congestion_price(1000, 75, &settings), 2000);
+        assert_eq!(congestion_price(1000, 100, &settings), 3000);
+        assert_eq!(congestion_price(u32::max_value(), 100, &settings), u32::max_value());
+
+        settings.floor = 1500;
+        settings.ceiling = 2500;
+        assert_eq!(congestion_price(1000, 0, &settings), 1500);
+        assert_eq!(congestion_price(1000, 100, &settings), 2500);
+
+        settings.congestion_threshold = 100;
+        assert_eq!(congestion_price(2000, 100, &settings), 2000);
+    }
+
+    #[test]
+    fn test_schedule() {
+        let schedule = vec![
+            PriceSchedule {
+                start: 18,
+                end: 23,
+                fee: 3000,
+            },
+            PriceSchedule {
+                start: 23,
+                end: 6,
+                fee: 500,
+            },
+        ];
+        assert_eq!(scheduled_fee(&schedule, 12), None);
+        assert_eq!(scheduled_fee(&schedule, 18), Some(3000));
+        assert_eq!(scheduled_fee(&schedule, 23), Some(500));
+        assert_eq!(scheduled_fee(&schedule, 3), Some(500));
+        assert_eq!(scheduled_fee(&schedule, 6), None);
+
+        // 1970-01-01 00:30 UTC
+        assert_eq!(hour_of_day(1800, 0), 0);
+        assert_eq!(hour_of_day(1800, 120), 2);
+        assert_eq!(hour_of_day(1800, -60), 23);
+    }
+
+    #[test]
+    fn test_should_update() {
+        assert!(should_update(None, 1000, 5));
+        assert!(!should_update(Some(1000), 1000, 5));
+        assert!(!should_update(Some(1000), 1040, 5));
+        assert!(should_update(Some(1000), 1050, 5));
+        assert!(should_update(Some(1000), 900, 5));
+        assert!(should_update(Some(0), 1, 5));
+    }
+
+    #[test]
+    fn test_measure_utilization() {
+        let mut manager = PriceManager::default();
+        let settings = PricingSettings {
+            link_capacity: 1000,
+            ..PricingSettings::default()
+        };
+        let interfaces: HashSet<String> = vec!["wlan0".to_string()].into_iter().collect();
+        let start = Instant::now();
+
+        let devices = vec![device("wlan0", 0, 0), device("eth0", 0, 0)];
+        manager.measure(&devices, &interfaces, &settings, start);
+        assert_eq!(manager.busiest(), 0);
+
+        let devices = vec![device("wlan0", 2000, 2000), device("eth0", 99999, 0)];
+        manager.measure(&devices, &interfaces, &settings, start + Duration::from_secs(10));
+        assert_eq!(manager.utilization.get("wlan0"), Some(&40));
+        assert_eq!(manager.utilization.get("eth0"), None);
+
+        // the interface was recreated and its counters started over
+        let devices = vec![device("wlan0", 10, 10)];
+        manager.measure(&devices, &interfaces, &settings, start + Duration::from_secs(20));
+        assert_eq!(manager.busiest(), 0);
+    }
+}
@@ -27,9 +27,16 @@ use rita_common::payment_controller::{PaymentController, PaymentControllerUpdate
 
 use rita_common::peer_listener::GetPeers;
 
+use rita_common::dao_manager::CheckJoins;
 use rita_common::dao_manager::DAOCheck;
 use rita_common::dao_manager::DAOManager;
 
+use rita_common::metrics;
+
+use rita_common::overadvertisement::{AdvertisementMonitor, CheckNeighbors};
+
+use rita_common::pricing::{PriceManager, UpdatePrice};
+
 use rita_common::tunnel_manager::PeersToContact;
 
 use failure::Error;
@@ -87,8 +94,9 @@ impl Handler<Tick> for RitaLoop {
                 Ok(s) => {
                     for ip in s.iter() {
                         trace!("Resolv route {:?}", ip);
-                        KI.manual_peers_route(&ip, &mut SETTING.get_network_mut().default_route)
-                            .unwrap();
+                        if let Err(e) = KI.manual_peers_route(&ip) {
+                            warn!("Failed to add DNS route for {:?} with {:?}", ip, e);
+                        }
                     }
                 }
                 Err(e) => warn!("Failed to add DNS routes with {:?}", e),
@@ -113,6 +121,7 @@ impl Handler<Tick> for RitaLoop {
                         start.elapsed().as_secs(),
                         start.elapsed().subsec_nanos() / 1000000
                     );
+                    metrics::observe_tick("get_neighbors", start);
 
                     TrafficWatcher::from_registry()
                         .send(Watch::new(res))
@@ -123,6 +132,7 @@ impl Handler<Tick> for RitaLoop {
                                 neigh.elapsed().as_secs(),
                                 neigh.elapsed().subsec_nanos() / 1000000
                             );
+                            metrics::observe_tick("traffic_watcher", neigh);
                             DebtKeeper::from_registry().do_send(SendUpdate {});
                             PaymentController::from_registry().do_send(PaymentControllerUpdate {});
                             actix::fut::ok(())
@@ -131,6 +141,7 @@ impl Handler<Tick> for RitaLoop {
         );
 
         trace!("Starting DAOManager loop");
+        DAOManager::from_registry().do_send(CheckJoins);
         Arbiter::spawn(
             TunnelManager::from_registry()
                 .send(GetNeighbors)
@@ -142,6 +153,8 @@ impl Handler<Tick> for RitaLoop {
                                 let their_id = neigh.identity.global.clone();
                                 DAOManager::from_registry().do_send(DAOCheck(their_id));
                             }
+                            AdvertisementMonitor::from_registry()
+                                .do_send(CheckNeighbors(neighbors));
                         }
                         Ok(Err(e)) => {
                             trace!("Failed to get neighbors from tunnel manager {:?}", e);
@@ -154,6 +167,9 @@ impl Handler<Tick> for RitaLoop {
                 }),
         );
 
+        trace!("Updating our forwarding fee");
+        PriceManager::from_registry().do_send(UpdatePrice);
+
         let start = Instant::now();
         Arbiter::spawn(
             TunnelManager::from_registry()
@@ -166,6 +182,7 @@ impl Handler<Tick> for RitaLoop {
                         start.elapsed().subsec_nanos() / 1000000,
                         res
                     );
+                    metrics::observe_tick("tunnel_gc", start);
                     res
                 }).then(|_| Ok(())),
         );
@@ -182,6 +199,7 @@ impl Handler<Tick> for RitaLoop {
                         start.elapsed().subsec_nanos() / 1000000,
                         res
                     );
+                    metrics::observe_tick("peer_listener", start);
                     res
                 }).then(|_| Ok(())),
         );
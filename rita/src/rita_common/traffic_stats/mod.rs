@@ -0,0 +1,366 @@
+//! TrafficStats keeps rolling statistics of the traffic the traffic watcher bills. Every watch round
+//! reports how many bytes went through each neighbor to each destination on each interface along
+//! with what was charged and paid for it. These are kept in time buckets so that totals over the
+//! last minute, hour and day can be served to the dashboard without keeping every round around.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use actix::prelude::*;
+
+use althea_types::Identity;
+
+use num256::Int256;
+
+use failure::Error;
+
+/// One neighbor, destination and interface combination seen by the traffic watcher
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FlowKey {
+    pub neighbor: Identity,
+    pub destination: IpAddr,
+    pub iface: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlowStats {
+    /// Bytes the neighbor sent us for the destination
+    pub bytes_in: u64,
+    /// Bytes we sent the neighbor for the destination
+    pub bytes_out: u64,
+    /// What we billed the neighbor for bytes_in
+    pub charged: Int256,
+    /// What the neighbor billed us for bytes_out
+    pub paid: Int256,
+}
+
+impl Default for FlowStats {
+    fn default() -> FlowStats {
+        FlowStats {
+            bytes_in: 0,
+            bytes_out: 0,
+            charged: Int256::from(0),
+            paid: Int256::from(0),
+        }
+    }
+}
+
+impl FlowStats {
+    fn add(&mut self, other: &FlowStats) {
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+        self.charged += other.charged.clone();
+        self.paid += other.paid.clone();
+    }
+}
+
+/// Flow totals over a sliding span of time, kept as a fixed number of buckets
+struct Window {
+    bucket_secs: u64,
+    bucket_count: u64,
+    buckets: VecDeque<(Instant, HashMap<FlowKey, FlowStats>)>,
+}
+
+impl Window {
+    fn new(bucket_secs: u64, bucket_count: u64) -> Window {
+        Window {
+            bucket_secs,
+            bucket_count,
+            buckets: VecDeque::new(),
+        }
+    }
+
+    fn span(&self) -> Duration {
+        Duration::from_secs(self.bucket_secs * self.bucket_count)
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let span = self.span();
+        while let Some(start) = self.buckets.front().map(|b| b.0) {
+            if now.duration_since(start) < span {
+                break;
+            }
+            self.buckets.pop_front();
+        }
+    }
+
+    fn record(&mut self, now: Instant, flows: &HashMap<FlowKey, FlowStats>) {
+        self.expire(now);
+        let bucket_len = Duration::from_secs(self.bucket_secs);
+        let current = match self.buckets.back() {
+            Some(&(start, _)) => now.duration_since(start) < bucket_len,
+            None => false,
+        };
+        if !current {
+            self.buckets.push_back((now, HashMap::new()));
+        }
+
+        let bucket = &mut self.buckets.back_mut().unwrap().1;
+        for (key, stats) in flows {
+            bucket
+                .entry(key.clone())
+                .or_insert_with(FlowStats::default)
+                .add(stats);
+        }
+    }
+
+    fn totals(&self, now: Instant) -> HashMap<FlowKey, FlowStats> {
+        let span = self.span();
+        let mut totals: HashMap<FlowKey, FlowStats> = HashMap::new();
+        for &(start, ref bucket) in self.buckets.iter() {
+            if now.duration_since(start) >= span {
+                continue;
+            }
+            for (key, stats) in bucket {
+                totals
+                    .entry(key.clone())
+                    .or_insert_with(FlowStats::default)
+                    .add(stats);
+            }
+        }
+        totals
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Usage {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PeriodUsage {
+    /// Keyed by the neighbor's mesh ip
+    pub neighbors: BTreeMap<IpAddr, Usage>,
+    pub destinations: BTreeMap<IpAddr, Usage>,
+    pub interfaces: BTreeMap<String, Usage>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageReport {
+    pub minute: PeriodUsage,
+    pub hour: PeriodUsage,
+    pub day: PeriodUsage,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Earnings {
+    pub charged: Int256,
+    pub paid: Int256,
+    /// What we kept for forwarding, charged minus paid
+    pub earned: Int256,
+}
+
+impl Default for Earnings {
+    fn default() -> Earnings {
+        Earnings {
+            charged: Int256::from(0),
+            paid: Int256::from(0),
+            earned: Int256::from(0),
+        }
+    }
+}
+
+impl Earnings {
+    fn add(&mut self, stats: &FlowStats) {
+        self.charged += stats.charged.clone();
+        self.paid += stats.paid.clone();
+        self.earned = self.charged.clone() - self.paid.clone();
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PeriodEarnings {
+    pub total: Earnings,
+    /// Keyed by the neighbor's mesh ip
+    pub neighbors: BTreeMap<IpAddr, Earnings>,
+    pub destinations: BTreeMap<IpAddr, Earnings>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EarningsReport {
+    pub minute: PeriodEarnings,
+    pub hour: PeriodEarnings,
+    pub day: PeriodEarnings,
+}
+
+fn usage(flows: &HashMap<FlowKey, FlowStats>) -> PeriodUsage {
+    let mut res = PeriodUsage::default();
+    for (key, stats) in flows {
+        for usage in vec![
+            res.neighbors.entry(key.neighbor.mesh_ip).or_insert_with(Default::default),
+            res.destinations.entry(key.destination).or_insert_with(Default::default),
+            res.interfaces.entry(key.iface.clone()).or_insert_with(Default::default),
+        ] {
+            usage.bytes_in += stats.bytes_in;
+            usage.bytes_out += stats.bytes_out;
+        }
+    }
+    res
+}
+
+fn earnings(flows: &HashMap<FlowKey, FlowStats>) -> PeriodEarnings {
+    let mut res = PeriodEarnings::default();
+    for (key, stats) in flows {
+        res.total.add(stats);
+        res.neighbors
+            .entry(key.neighbor.mesh_ip)
+            .or_insert_with(Default::default)
+            .add(stats);
+        res.destinations
+            .entry(key.destination)
+            .or_insert_with(Default::default)
+            .add(stats);
+    }
+    res
+}
+
+pub struct TrafficStats {
+    minute: Window,
+    hour: Window,
+    day: Window,
+}
+
+impl Actor for TrafficStats {
+    type Context = Context<Self>;
+}
+
+impl Supervised for TrafficStats {}
+
+impl SystemService for TrafficStats {
+    fn service_started(&mut self, _ctx: &mut Context<Self>) {
+        info!("Traffic Stats started");
+    }
+}
+
+impl Default for TrafficStats {
+    fn default() -> TrafficStats {
+        TrafficStats {
+            minute: Window::new(10, 6),
+            hour: Window::new(60, 60),
+            day: Window::new(3600, 24),
+        }
+    }
+}
+
+impl TrafficStats {
+    fn record(&mut self, now: Instant, flows: &HashMap<FlowKey, FlowStats>) {
+        self.minute.record(now, flows);
+        self.hour.record(now, flows);
+        self.day.record(now, flows);
+    }
+}
+
+/// The flows billed in a single traffic watcher round
+pub struct RecordTraffic(pub HashMap<FlowKey, FlowStats>);
+
+impl Message for RecordTraffic {
+    type Result = ();
+}
+
+impl Handler<RecordTraffic> for TrafficStats {
+    type Result = ();
+
+    fn handle(&mut self, msg: RecordTraffic, _: &mut Context<Self>) -> Self::Result {
+        self.record(Instant::now(), &msg.0);
+    }
+}
+
+pub struct GetUsage;
+
+impl Message for GetUsage {
+    type Result = Result<UsageReport, Error>;
+}
+
+impl Handler<GetUsage> for TrafficStats {
+    type Result = Result<UsageReport, Error>;
+
+    fn handle(&mut self, _: GetUsage, _: &mut Context<Self>) -> Self::Result {
+        let now = Instant::now();
+        Ok(UsageReport {
+            minute: usage(&self.minute.totals(now)),
+            hour: usage(&self.hour.totals(now)),
+            day: usage(&self.day.totals(now)),
+        })
+    }
+}
+
+pub struct GetEarnings;
+
+impl Message for GetEarnings {
+    type Result = Result<EarningsReport, Error>;
+}
+
+impl Handler<GetEarnings> for TrafficStats {
+    type Result = Result<EarningsReport, Error>;
+
+    fn handle(&mut self, _: GetEarnings, _: &mut Context<Self>) -> Self::Result {
+        let now = Instant::now();
+        Ok(EarningsReport {
+            minute: earnings(&self.minute.totals(now)),
+            hour: earnings(&self.hour.totals(now)),
+            day: earnings(&self.day.totals(now)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flow(destination: &str, bytes_in: u64, charged: i64) -> HashMap<FlowKey, FlowStats> {
+        let neighbor = Identity {
+            eth_address: 1.into(),
+            mesh_ip: "fd00::1".parse().unwrap(),
+            wg_public_key: String::from("AAAAAAAAAAA"),
+        };
+        let mut flows = HashMap::new();
+        flows.insert(
+            FlowKey {
+                neighbor,
+                destination: destination.parse().unwrap(),
+                iface: "wg0".to_string(),
+            },
+            FlowStats {
+                bytes_in,
+                bytes_out: 0,
+                charged: Int256::from(charged),
+                paid: Int256::from(0),
+            },
+        );
+        flows
+    }
+
+    #[test]
+    fn test_windows() {
+        let start = Instant::now();
+        let mut stats = TrafficStats::default();
+        stats.record(start, &flow("fd00::2", 100, 10));
+        stats.record(start + Duration::from_secs(5), &flow("fd00::2", 100, 10));
+        stats.record(start + Duration::from_secs(90), &flow("fd00::3", 50, 5));
+
+        let now = start + Duration::from_secs(95);
+        let minute = usage(&stats.minute.totals(now));
+        assert_eq!(minute.destinations.len(), 1);
+        assert_eq!(minute.interfaces["wg0"].bytes_in, 50);
+
+        let hour = usage(&stats.hour.totals(now));
+        assert_eq!(hour.neighbors[&"fd00::1".parse().unwrap()].bytes_in, 250);
+        assert_eq!(hour.destinations.len(), 2);
+
+        let day = earnings(&stats.day.totals(now));
+        assert_eq!(day.total.earned, Int256::from(25));
+
+        // the first two rounds have aged out of the hour but not the day
+        let later = start + Duration::from_secs(3630);
+        assert_eq!(
+            earnings(&stats.hour.totals(later)).total.charged,
+            Int256::from(5)
+        );
+        assert_eq!(
+            earnings(&stats.day.totals(later)).total.charged,
+            Int256::from(25)
+        );
+    }
+}
@@ -0,0 +1,279 @@
+//! Billing arithmetic shared by the client, exit and common traffic watchers. Byte counts come
+//! straight from the kernel counters and prices from Babel and the settings, so every step is
+//! checked instead of relying on the panicking operators.
+//!
+//! Exit billing plans are applied here too so that a client on a plan works out the same bill
+//! as its exit does.
+
+use althea_types::{ExitPlan, Overage, PlanType};
+
+use num256::Int256;
+use num_traits::ops::checked::{CheckedAdd, CheckedSub};
+
+use std::net::IpAddr;
+
+#[derive(Debug, Fail, PartialEq)]
+pub enum BillingError {
+    #[fail(display = "Charging {} bytes at {} wei per byte overflows", bytes, price)]
+    ChargeOverflow { price: Int256, bytes: u64 },
+    #[fail(display = "Adding {} to a balance of {} overflows", amount, balance)]
+    BalanceOverflow { balance: Int256, amount: Int256 },
+}
+
+/// The cost of `bytes` at `price` wei per byte
+pub fn charge(price: &Int256, bytes: u64) -> Result<Int256, BillingError> {
+    price
+        .checked_mul_u64(bytes)
+        .ok_or_else(|| BillingError::ChargeOverflow {
+            price: price.clone(),
+            bytes,
+        })
+}
+
+pub fn credit(balance: &mut Int256, amount: &Int256) -> Result<(), BillingError> {
+    match balance.checked_add(amount) {
+        Some(sum) => {
+            *balance = sum;
+            Ok(())
+        }
+        None => Err(BillingError::BalanceOverflow {
+            balance: balance.clone(),
+            amount: amount.clone(),
+        }),
+    }
+}
+
+pub fn debit(balance: &mut Int256, amount: &Int256) -> Result<(), BillingError> {
+    match balance.checked_sub(amount) {
+        Some(difference) => {
+            *balance = difference;
+            Ok(())
+        }
+        None => Err(BillingError::BalanceOverflow {
+            balance: balance.clone(),
+            amount: -amount.clone(),
+        }),
+    }
+}
+
+/// A client's billing plan and how much of it they have used this period
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanUsage {
+    pub plan: ExitPlan,
+    /// The client's address inside the exit tunnel, which is what the exit throttles
+    pub internal_ip: IpAddr,
+    pub period_start: u64,
+    pub period_bytes: u64,
+    /// Over quota on a plan that throttles rather than bills overage
+    pub throttled: bool,
+}
+
+impl PlanUsage {
+    /// Usage of a plan that has not been billed yet, the period starts on the first round
+    pub fn new(plan: ExitPlan, internal_ip: IpAddr) -> PlanUsage {
+        PlanUsage {
+            plan,
+            internal_ip,
+            period_start: 0,
+            period_bytes: 0,
+            throttled: false,
+        }
+    }
+}
+
+/// What a client on a plan owes for one round
+#[derive(Debug, PartialEq)]
+pub struct PlanBill {
+    /// Bytes that are still billed per byte in each direction
+    pub billable_in: u64,
+    pub billable_out: u64,
+    /// The plan's price for the time since the last round
+    pub fee: Int256,
+}
+
+/// Moves the client's usage on to the current period if the last one has ended, counts this
+/// round's traffic against the quota and works out what is left to bill. Flat and quota plans
+/// charge their price gradually as the period goes by rather than all at once up front so that
+/// the fee never shows up as one large debt that crosses the close threshold.
+pub fn apply_plan(
+    usage: &mut PlanUsage,
+    bytes_in: u64,
+    bytes_out: u64,
+    now: u64,
+    elapsed_ms: u64,
+) -> Result<PlanBill, BillingError> {
+    let period = usage.plan.period;
+    if usage.period_start == 0 || usage.period_start > now {
+        usage.period_start = now;
+        usage.period_bytes = 0;
+    } else if period > 0 && now - usage.period_start >= period {
+        usage.period_start = now - (now - usage.period_start) % period;
+        usage.period_bytes = 0;
+    }
+
+    let remaining = usage.plan.quota_bytes.saturating_sub(usage.period_bytes);
+    usage.period_bytes = usage
+        .period_bytes
+        .saturating_add(bytes_in)
+        .saturating_add(bytes_out);
+
+    let fee = if period > 0 {
+        charge(&Int256::from(usage.plan.price), elapsed_ms)? / Int256::from(period * 1000)
+    } else {
+        Int256::zero()
+    };
+
+    usage.throttled = false;
+    Ok(match usage.plan.plan_type {
+        PlanType::PerByte => PlanBill {
+            billable_in: bytes_in,
+            billable_out: bytes_out,
+            fee: Int256::zero(),
+        },
+        PlanType::Flat => PlanBill {
+            billable_in: 0,
+            billable_out: 0,
+            fee,
+        },
+        PlanType::Quota => {
+            let free_in = ::std::cmp::min(bytes_in, remaining);
+            let free_out = ::std::cmp::min(bytes_out, remaining - free_in);
+            match usage.plan.overage {
+                Overage::Bill => PlanBill {
+                    billable_in: bytes_in - free_in,
+                    billable_out: bytes_out - free_out,
+                    fee,
+                },
+                Overage::Throttle => {
+                    usage.throttled = usage.period_bytes >= usage.plan.quota_bytes;
+                    PlanBill {
+                        billable_in: 0,
+                        billable_out: 0,
+                        fee,
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_charge_extreme_counters() {
+        let max_bytes = u64::max_value();
+        assert_eq!(
+            charge(&Int256::from(u64::max_value()), max_bytes),
+            Ok(Int256::from(u64::max_value()) * Int256::from(max_bytes))
+        );
+        assert_eq!(
+            charge(&Int256::from(u32::max_value()), max_bytes),
+            Ok(Int256::from(u32::max_value()) * Int256::from(max_bytes))
+        );
+        assert_eq!(charge(&Int256::from(10), 0), Ok(Int256::zero()));
+        assert_eq!(
+            charge(&Int256::max_value(), 2),
+            Err(BillingError::ChargeOverflow {
+                price: Int256::max_value(),
+                bytes: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn test_balance_overflow() {
+        let mut debt = Int256::zero();
+        let bill = charge(&Int256::from(u64::max_value()), u64::max_value()).unwrap();
+        debit(&mut debt, &bill).unwrap();
+        debit(&mut debt, &bill).unwrap();
+        assert_eq!(debt, -(bill.clone() * 2));
+        credit(&mut debt, &bill).unwrap();
+        assert_eq!(debt, -bill.clone());
+
+        let mut debt = Int256::min_value();
+        assert_eq!(
+            debit(&mut debt, &Int256::from(1)),
+            Err(BillingError::BalanceOverflow {
+                balance: Int256::min_value(),
+                amount: Int256::from(-1),
+            })
+        );
+        assert_eq!(debt, Int256::min_value());
+
+        let mut balance = Int256::max_value();
+        assert!(credit(&mut balance, &Int256::from(1)).is_err());
+        assert_eq!(balance, Int256::max_value());
+    }
+
+    fn quota_plan(overage: Overage) -> PlanUsage {
+        PlanUsage {
+            plan: ExitPlan {
+                name: "basic".to_string(),
+                plan_type: PlanType::Quota,
+                quota_bytes: 1000,
+                period: 100,
+                price: 1_000_000,
+                overage,
+                throttle_kbps: 512,
+                description: String::new(),
+            },
+            internal_ip: "172.168.1.5".parse().unwrap(),
+            period_start: 1000,
+            period_bytes: 0,
+            throttled: false,
+        }
+    }
+
+    #[test]
+    fn test_quota_overage_billed() {
+        let mut usage = quota_plan(Overage::Bill);
+
+        let bill = apply_plan(&mut usage, 400, 400, 1010, 5000).unwrap();
+        assert_eq!(bill.billable_in, 0);
+        assert_eq!(bill.billable_out, 0);
+        // 5 seconds of a 100 second period
+        assert_eq!(bill.fee, Int256::from(50_000));
+
+        // 200 bytes of quota left, used up by the input first
+        let bill = apply_plan(&mut usage, 150, 150, 1020, 5000).unwrap();
+        assert_eq!(bill.billable_in, 0);
+        assert_eq!(bill.billable_out, 100);
+        assert_eq!(usage.period_bytes, 1100);
+        assert!(!usage.throttled);
+
+        // a new period starts over the quota, aligned to the old period
+        let bill = apply_plan(&mut usage, 100, 100, 1250, 5000).unwrap();
+        assert_eq!(bill.billable_out, 0);
+        assert_eq!(usage.period_start, 1200);
+        assert_eq!(usage.period_bytes, 200);
+    }
+
+    #[test]
+    fn test_quota_overage_throttled() {
+        let mut usage = quota_plan(Overage::Throttle);
+
+        let bill = apply_plan(&mut usage, 600, 600, 1010, 5000).unwrap();
+        assert_eq!(bill.billable_in + bill.billable_out, 0);
+        assert!(usage.throttled);
+
+        let bill = apply_plan(&mut usage, 0, 0, 1100, 5000).unwrap();
+        assert_eq!(bill.fee, Int256::from(50_000));
+        assert!(!usage.throttled);
+    }
+
+    #[test]
+    fn test_flat_and_per_byte_plans() {
+        let mut usage = quota_plan(Overage::Bill);
+        usage.plan.plan_type = PlanType::Flat;
+        let bill = apply_plan(&mut usage, 5000, 5000, 1010, 10_000).unwrap();
+        assert_eq!((bill.billable_in, bill.billable_out), (0, 0));
+        assert_eq!(bill.fee, Int256::from(100_000));
+
+        usage.plan.plan_type = PlanType::PerByte;
+        let bill = apply_plan(&mut usage, 5000, 5000, 1010, 10_000).unwrap();
+        assert_eq!((bill.billable_in, bill.billable_out), (5000, 5000));
+        assert_eq!(bill.fee, Int256::zero());
+    }
+}
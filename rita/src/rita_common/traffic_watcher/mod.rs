@@ -5,21 +5,27 @@
 use actix::prelude::*;
 use rita_common::tunnel_manager::Neighbor;
 
-use althea_kernel_interface::FilterTarget;
+use althea_kernel_interface::{FilterTarget, RuleGroup};
 use KI;
 
 use althea_types::Identity;
 
-use babel_monitor::Babel;
+use babel_monitor;
+use babel_monitor::Route;
 
 use rita_common::debt_keeper;
 use rita_common::debt_keeper::DebtKeeper;
+use rita_common::firewall::{AddRules, Firewall};
+use rita_common::metrics;
+use rita_common::traffic_stats::{FlowKey, FlowStats, RecordTraffic, TrafficStats};
 
 use num256::Int256;
 
-use std::collections::HashMap;
-use std::io::{Read, Write};
-use std::net::{IpAddr, SocketAddr, TcpStream};
+use futures::Future;
+
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::Instant;
 
 use ipnetwork::IpNetwork;
 
@@ -28,6 +34,10 @@ use SETTING;
 
 use failure::Error;
 
+pub mod billing;
+
+use self::billing::{charge, credit, debit};
+
 pub struct TrafficWatcher;
 
 impl Actor for TrafficWatcher {
@@ -38,14 +48,21 @@ impl Supervised for TrafficWatcher {}
 
 impl SystemService for TrafficWatcher {
     fn service_started(&mut self, _ctx: &mut Context<Self>) {
-        KI.init_counter(&FilterTarget::Input).unwrap();
-        KI.init_counter(&FilterTarget::Output).unwrap();
-        KI.init_counter(&FilterTarget::ForwardInput).unwrap();
-        KI.init_counter(&FilterTarget::ForwardOutput).unwrap();
+        for target in [
+            FilterTarget::Input,
+            FilterTarget::Output,
+            FilterTarget::ForwardInput,
+            FilterTarget::ForwardOutput,
+        ]
+            .iter()
+        {
+            Firewall::from_registry().do_send(AddRules(RuleGroup::Counters(target.clone())));
+        }
 
         match SETTING.get_network().external_nic {
             Some(ref external_nic) => {
-                KI.init_iface_counters(external_nic).unwrap();
+                Firewall::from_registry()
+                    .do_send(AddRules(RuleGroup::IfaceCounters(external_nic.clone())));
             }
             _ => {}
         }
@@ -76,14 +93,14 @@ impl Message for Watch {
 }
 
 impl Handler<Watch> for TrafficWatcher {
-    type Result = Result<(), Error>;
+    type Result = ResponseFuture<(), Error>;
 
     fn handle(&mut self, msg: Watch, _: &mut Context<Self>) -> Self::Result {
-        let stream = TcpStream::connect::<SocketAddr>(
-            format!("[::1]:{}", SETTING.get_network().babel_port).parse()?,
-        )?;
-
-        watch(Babel::new(stream), &msg.neighbors)
+        let start = Instant::now();
+        Box::new(babel_monitor::table().and_then(move |table| {
+            metrics::BABEL_QUERY_SECONDS.observe(metrics::seconds(start.elapsed()));
+            watch(table.routes(), table.local_fee()?, &msg.neighbors)
+        }))
     }
 }
 
@@ -94,11 +111,11 @@ impl Handler<Watch> for TrafficWatcher {
 ///
 /// This first time this is run, it will create the rules and then immediately read and zero them.
 /// (should return 0)
-pub fn watch<T: Read + Write>(mut babel: Babel<T>, neighbors: &Vec<Neighbor>) -> Result<(), Error> {
-    babel.start_connection()?;
-
-    trace!("Getting routes");
-    let routes = babel.parse_routes()?;
+pub fn watch(
+    routes: VecDeque<Route>,
+    local_price: u32,
+    neighbors: &Vec<Neighbor>,
+) -> Result<(), Error> {
     info!("Got routes: {:?}", routes);
 
     let mut identities: HashMap<IpAddr, Identity> = HashMap::new();
@@ -114,14 +131,14 @@ pub fn watch<T: Read + Write>(mut babel: Babel<T>, neighbors: &Vec<Neighbor>) ->
     }
 
     let mut destinations = HashMap::new();
-    let local_price = babel.local_fee().unwrap();
 
     for route in &routes {
         // Only ip6
         if let IpNetwork::V6(ref ip) = route.prefix {
             // Only host addresses and installed routes
             if ip.prefix() == 128 && route.installed {
-                destinations.insert(IpAddr::V6(ip.ip()), Int256::from(route.price + local_price));
+                let price = Int256::from(route.price) + Int256::from(local_price);
+                destinations.insert(IpAddr::V6(ip.ip()), price);
             }
         }
     }
@@ -191,19 +208,23 @@ pub fn watch<T: Read + Write>(mut babel: Babel<T>, neighbors: &Vec<Neighbor>) ->
     let mut total_output_counters = HashMap::new();
 
     for (k, v) in input_counters {
-        *total_input_counters.entry(k).or_insert(0) += v
+        let total = total_input_counters.entry(k).or_insert(0u64);
+        *total = total.saturating_add(v);
     }
 
     for (k, v) in fwd_input_counters {
-        *total_input_counters.entry(k).or_insert(0) += v
+        let total = total_input_counters.entry(k).or_insert(0u64);
+        *total = total.saturating_add(v);
     }
 
     for (k, v) in output_counters {
-        *total_output_counters.entry(k).or_insert(0) += v
+        let total = total_output_counters.entry(k).or_insert(0u64);
+        *total = total.saturating_add(v);
     }
 
     for (k, v) in fwd_output_counters {
-        *total_output_counters.entry(k).or_insert(0) += v
+        let total = total_output_counters.entry(k).or_insert(0u64);
+        *total = total.saturating_add(v);
     }
 
     info!("Got final input counters: {:?}", total_input_counters);
@@ -211,20 +232,27 @@ pub fn watch<T: Read + Write>(mut babel: Babel<T>, neighbors: &Vec<Neighbor>) ->
     let mut total_in: u64 = 0;
     for entry in total_input_counters.iter() {
         let input = entry.1;
-        total_in += input;
+        total_in = total_in.saturating_add(*input);
     }
     info!("Total input of {} bytes this round", total_in);
+    metrics::BYTES_BILLED
+        .with_label_values(&["in"])
+        .inc_by(total_in as i64);
     let mut total_out: u64 = 0;
     for entry in total_output_counters.iter() {
         let output = entry.1;
-        total_out += output;
+        total_out = total_out.saturating_add(*output);
     }
     info!("Total output of {} bytes this round", total_out);
+    metrics::BYTES_BILLED
+        .with_label_values(&["out"])
+        .inc_by(total_out as i64);
 
     // Flow counters should debit your neighbor which you received the packet from
     // Destination counters should credit your neighbor which you sent the packet to
 
     let mut debts = HashMap::new();
+    let mut flows: HashMap<FlowKey, FlowStats> = HashMap::new();
 
     // Setup the debts table
     for (_, ident) in identities.clone() {
@@ -238,13 +266,20 @@ pub fn watch<T: Read + Write>(mut babel: Babel<T>, neighbors: &Vec<Neighbor>) ->
         let state = (destinations.get(&ip), if_to_id.get(&interface));
         match state {
             (Some(dest), Some(id_from_if)) => {
+                let bill = charge(dest, bytes)?;
                 match debts.get_mut(&id_from_if) {
-                    Some(debt) => {
-                        *debt -= (dest.clone()) * bytes;
-                    }
+                    Some(debt) => debit(debt, &bill)?,
                     // debts is generated from identities, this should be impossible
                     None => warn!("No debts entry for input entry id {:?}", id_from_if),
                 }
+                let flow = flows
+                    .entry(FlowKey {
+                        neighbor: id_from_if.clone(),
+                        destination: ip,
+                        iface: interface.clone(),
+                    }).or_insert_with(FlowStats::default);
+                flow.bytes_in = flow.bytes_in.saturating_add(bytes);
+                credit(&mut flow.charged, &bill)?;
             }
             // this can be caused by a peer that has not yet formed a babel route
             // we use _ because ip_to_if is created from identites, if one fails the other must
@@ -264,13 +299,22 @@ pub fn watch<T: Read + Write>(mut babel: Babel<T>, neighbors: &Vec<Neighbor>) ->
     for ((ip, interface), bytes) in total_output_counters {
         let state = (destinations.get(&ip), if_to_id.get(&interface));
         match state {
-            (Some(dest), Some(id_from_if)) => match debts.get_mut(&id_from_if) {
-                Some(debt) => {
-                    *debt += (dest.clone() - local_price) * bytes;
+            (Some(dest), Some(id_from_if)) => {
+                let bill = charge(&(dest.clone() - local_price), bytes)?;
+                match debts.get_mut(&id_from_if) {
+                    Some(debt) => credit(debt, &bill)?,
+                    // debts is generated from identities, this should be impossible
+                    None => warn!("No debts entry for input entry id {:?}", id_from_if),
                 }
-                // debts is generated from identities, this should be impossible
-                None => warn!("No debts entry for input entry id {:?}", id_from_if),
-            },
+                let flow = flows
+                    .entry(FlowKey {
+                        neighbor: id_from_if.clone(),
+                        destination: ip,
+                        iface: interface.clone(),
+                    }).or_insert_with(FlowStats::default);
+                flow.bytes_out = flow.bytes_out.saturating_add(bytes);
+                credit(&mut flow.paid, &bill)?;
+            }
             // this can be caused by a peer that has not yet formed a babel route
             // we use _ because ip_to_if is created from identites, if one fails the other must
             (None, Some(id_from_if)) => warn!("We have an id {:?} but not destination", id_from_if),
@@ -283,8 +327,11 @@ pub fn watch<T: Read + Write>(mut babel: Babel<T>, neighbors: &Vec<Neighbor>) ->
 
     trace!("Collated total debts: {:?}", debts);
 
+    TrafficStats::from_registry().do_send(RecordTraffic(flows));
+
     for (from, amount) in debts {
         trace!("collated debt for {} is {}", from.mesh_ip, amount);
+        metrics::record_debt(&amount);
 
         let update = debt_keeper::TrafficUpdate {
             from: from.clone(),
@@ -314,12 +361,17 @@ mod tests {
     extern crate env_logger;
 
     use super::*;
+    use babel_monitor::Babel;
+    use std::net::{SocketAddr, TcpStream};
 
     #[test]
     #[ignore]
     fn debug_babel_socket_common() {
         env_logger::init();
         let bm_stream = TcpStream::connect::<SocketAddr>("[::1]:9001".parse().unwrap()).unwrap();
-        watch(Babel::new(bm_stream), &Vec::new()).unwrap();
+        let mut babel = Babel::new(bm_stream);
+        babel.start_connection().unwrap();
+        let routes = babel.parse_routes().unwrap();
+        watch(routes, babel.local_fee().unwrap(), &Vec::new()).unwrap();
     }
 }
@@ -4,24 +4,30 @@
 //! up tunnels if they respond, likewise if someone calls us their hello goes through network_endpoints
 //! then into TunnelManager to open a tunnel for them.
 
-use std::collections::HashMap;
-use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, SocketAddr};
 use std::path::Path;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use actix::actors::resolver;
 use actix::prelude::*;
 
+use futures::future;
 use futures::Future;
 
+use althea_kernel_interface::RuleGroup;
 use althea_types::Identity;
 use althea_types::LocalIdentity;
 
 use KI;
 
-use babel_monitor::{Babel, Route};
+use babel_monitor;
+use babel_monitor::Route;
 
 use rita_common;
+use rita_common::event_bus::{self, Event};
+use rita_common::firewall::{AddRules, Firewall, RemoveRules};
+use rita_common::metrics;
 use rita_common::http_client::Hello;
 use rita_common::peer_listener::Peer;
 
@@ -34,7 +40,6 @@ use failure::Error;
 use actix::actors::mocker::Mocker;
 use ipnetwork::IpNetwork;
 use std::fmt;
-use std::io::{Read, Write};
 
 #[cfg(test)]
 type HTTPClient = Mocker<rita_common::http_client::HTTPClient>;
@@ -54,15 +59,34 @@ pub enum TunnelManagerError {
     PortError(String),
     #[fail(display = "Invalid state")]
     InvalidStateError,
+    #[fail(display = "Invalid transition from {} on action {}", _0, _1)]
+    InvalidTransition(TunnelState, TunnelAction),
+    #[fail(display = "No tunnels found for identity {:?}", _0)]
+    UnknownIdentity(Identity),
+    #[fail(display = "A tunnel to {:?}%{} is already being opened", _0, _1)]
+    TunnelPending(Identity, u32),
 }
 
+/// How many state transitions we remember for each tunnel
+const TUNNEL_HISTORY_LEN: usize = 16;
+
 /// Action that progresses the state machine
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum TunnelAction {
+    /// The tunnel was opened and registered with babel
+    HandshakeComplete,
     /// Received confirmed membership of an identity
     MembershipConfirmed,
     /// Membership expired for an identity
     MembershipExpired,
+    /// The neighbor's debt has crossed the close threshold
+    DebtLimitReached,
+    /// The neighbor's debt is back above the close threshold
+    DebtPaid,
+    /// A credit policy blocks the neighbor
+    Blocked,
+    /// The neighbor is no longer blocked
+    Unblocked,
 }
 
 impl fmt::Display for TunnelAction {
@@ -71,17 +95,35 @@ impl fmt::Display for TunnelAction {
     }
 }
 
+/// The reason a tunnel has been suspended
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub enum SuspendReason {
+    /// The neighbor is not on any of our SubnetDAOs
+    MembershipExpired,
+    /// The neighbor owes us more than the close threshold allows
+    Debt,
+    /// The neighbor is blocked by a credit policy
+    Blocked,
+}
+
 /// TunnelState indicates a state where a tunnel is currently in. Made into an enum for adding new
 /// states more easily
 ///
 /// State changes:
-/// NotRegistered -> MembershipConfirmed(not implemented therefore not added) -> Registered
-#[derive(PartialEq, Debug, Clone)]
+/// PendingHandshake -> HandshakeComplete -> Registered
+/// Registered -> MembershipExpired -> Suspended(MembershipExpired) -> MembershipConfirmed -> Registered
+/// Registered -> DebtLimitReached -> Suspended(Debt) -> DebtPaid -> Registered
+/// Registered | Suspended(Debt) -> Blocked -> Suspended(Blocked)
+/// Suspended(Blocked) -> Unblocked -> Registered
+/// Suspended(Blocked) -> DebtLimitReached -> Suspended(Debt)
+#[derive(PartialEq, Debug, Clone, Serialize)]
 pub enum TunnelState {
-    /// Tunnel is not registered
-    NotRegistered,
+    /// Tunnel is allocated but not yet open and registered with babel
+    PendingHandshake,
     /// Tunnel is registered (default)
     Registered,
+    /// Tunnel is unmonitored in babel and carries no traffic
+    Suspended(SuspendReason),
 }
 
 impl fmt::Display for TunnelState {
@@ -90,10 +132,128 @@ impl fmt::Display for TunnelState {
     }
 }
 
+impl TunnelState {
+    /// Returns true if babel should be routing over a tunnel in this state
+    pub fn is_monitored(&self) -> bool {
+        match *self {
+            TunnelState::Registered => true,
+            TunnelState::PendingHandshake | TunnelState::Suspended(_) => false,
+        }
+    }
+
+    /// Validates an action against the current state, returns the new state or None if the
+    /// action does not change anything (for example repeated membership confirmations)
+    pub fn transition(&self, action: &TunnelAction) -> Result<Option<TunnelState>, Error> {
+        use self::TunnelAction::*;
+        use self::TunnelState::*;
+
+        let next = match (self, action) {
+            (&PendingHandshake, &HandshakeComplete) => Some(Registered),
+            (&PendingHandshake, _) | (_, &HandshakeComplete) => {
+                return Err(TunnelManagerError::InvalidTransition(
+                    self.clone(),
+                    action.clone(),
+                ).into())
+            }
+
+            (&Registered, &MembershipConfirmed) => None,
+            (&Registered, &MembershipExpired) => Some(Suspended(SuspendReason::MembershipExpired)),
+            (&Registered, &DebtLimitReached) => Some(Suspended(SuspendReason::Debt)),
+            (&Registered, &DebtPaid) => None,
+            (&Registered, &Blocked) => Some(Suspended(SuspendReason::Blocked)),
+            (&Registered, &Unblocked) => None,
+
+            (&Suspended(SuspendReason::MembershipExpired), &MembershipConfirmed) => {
+                Some(Registered)
+            }
+            (&Suspended(SuspendReason::Debt), &DebtPaid) => Some(Registered),
+            // A block outlasts the debt, once it is lifted the debt keeper decides whether the
+            // tunnel opens or goes back to being suspended for debt
+            (&Suspended(SuspendReason::Debt), &Blocked) => {
+                Some(Suspended(SuspendReason::Blocked))
+            }
+            (&Suspended(SuspendReason::Blocked), &Unblocked) => Some(Registered),
+            (&Suspended(SuspendReason::Blocked), &DebtLimitReached) => {
+                Some(Suspended(SuspendReason::Debt))
+            }
+            // A suspended tunnel stays suspended for its original reason until that reason
+            // is resolved, other actions are ignored
+            (&Suspended(_), _) => None,
+        };
+        Ok(next)
+    }
+}
+
 #[test]
 fn test_tunnel_state() {
-    assert_eq!(TunnelState::NotRegistered.to_string(), "NotRegistered");
+    assert_eq!(TunnelState::PendingHandshake.to_string(), "PendingHandshake");
     assert_eq!(TunnelState::Registered.to_string(), "Registered");
+    assert_eq!(
+        TunnelState::Suspended(SuspendReason::Debt).to_string(),
+        "Suspended(Debt)"
+    );
+}
+
+#[test]
+fn test_tunnel_state_transitions() {
+    use self::SuspendReason::*;
+    use self::TunnelAction::*;
+
+    let state = TunnelState::PendingHandshake;
+    assert!(state.transition(&MembershipConfirmed).is_err());
+    let state = state.transition(&HandshakeComplete).unwrap().unwrap();
+    assert_eq!(state, TunnelState::Registered);
+    assert!(state.transition(&HandshakeComplete).is_err());
+    assert_eq!(state.transition(&MembershipConfirmed).unwrap(), None);
+
+    let expired = state.transition(&TunnelAction::MembershipExpired).unwrap().unwrap();
+    assert_eq!(
+        expired,
+        TunnelState::Suspended(SuspendReason::MembershipExpired)
+    );
+    // paying debts does not lift a membership suspension
+    assert_eq!(expired.transition(&DebtPaid).unwrap(), None);
+    assert_eq!(
+        expired.transition(&MembershipConfirmed).unwrap(),
+        Some(TunnelState::Registered)
+    );
+
+    let in_debt = state.transition(&DebtLimitReached).unwrap().unwrap();
+    assert_eq!(in_debt, TunnelState::Suspended(Debt));
+    assert_eq!(in_debt.transition(&MembershipConfirmed).unwrap(), None);
+    assert_eq!(
+        in_debt.transition(&DebtPaid).unwrap(),
+        Some(TunnelState::Registered)
+    );
+
+    let blocked = state.transition(&TunnelAction::Blocked).unwrap().unwrap();
+    assert_eq!(blocked, TunnelState::Suspended(SuspendReason::Blocked));
+    assert_eq!(blocked.transition(&DebtPaid).unwrap(), None);
+    assert_eq!(
+        blocked.transition(&Unblocked).unwrap(),
+        Some(TunnelState::Registered)
+    );
+    assert_eq!(state.transition(&Unblocked).unwrap(), None);
+
+    // blocking a tunnel suspended for debt, then unblocking it while still in debt
+    assert_eq!(
+        in_debt.transition(&TunnelAction::Blocked).unwrap(),
+        Some(TunnelState::Suspended(SuspendReason::Blocked))
+    );
+    assert_eq!(
+        blocked.transition(&DebtLimitReached).unwrap(),
+        Some(TunnelState::Suspended(Debt))
+    );
+}
+
+/// A record of a single state change, kept in a tunnel's history
+#[derive(Debug, Clone, Serialize)]
+pub struct TunnelTransition {
+    pub from: TunnelState,
+    pub to: TunnelState,
+    pub action: TunnelAction,
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
 }
 
 #[derive(Debug, Clone)]
@@ -105,6 +265,7 @@ pub struct Tunnel {
     pub neigh_id: LocalIdentity, // the identity of the counterparty tunnel
     pub last_contact: Instant,   // When's the last we heard from the other end of this tunnel?
     state: TunnelState,
+    history: VecDeque<TunnelTransition>,
 }
 
 impl Tunnel {
@@ -122,11 +283,35 @@ impl Tunnel {
             listen_port: our_listen_port,
             neigh_id: their_id.clone(),
             last_contact: Instant::now(),
-            // By default new tunnels are in Registered state
-            state: TunnelState::Registered,
+            // New tunnels become Registered once they are open and monitored
+            state: TunnelState::PendingHandshake,
+            history: VecDeque::with_capacity(TUNNEL_HISTORY_LEN),
         }
     }
 
+    /// Records a state change in the tunnel history, the transition must already have been
+    /// validated with TunnelState::transition
+    fn set_state(&mut self, next: TunnelState, action: TunnelAction) {
+        info!(
+            "Tunnel {} changing state {} -> {} on {}",
+            self.iface_name, self.state, next, action
+        );
+
+        if self.history.len() >= TUNNEL_HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(TunnelTransition {
+            from: self.state.clone(),
+            to: next.clone(),
+            action,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        });
+        self.state = next;
+    }
+
     /// Open physical tunnel
     pub fn open(&self) -> Result<(), Error> {
         let network = SETTING.get_network().clone();
@@ -141,31 +326,27 @@ impl Tunnel {
                 None => bail!("No mesh IP configured yet"),
             },
             network.external_nic.clone(),
-            &mut SETTING.get_network_mut().default_route,
         )
     }
 
-    /// Register this tunnel into Babel monitor
-    pub fn monitor<T: Read + Write>(&self, stream: T) -> Result<(), Error> {
+    /// Register this tunnel into Babel monitor, the returned future resolves once Babel has
+    /// accepted the command
+    pub fn monitor(&self) -> Box<Future<Item = (), Error = Error>> {
         info!("Monitoring tunnel {}", self.iface_name);
-        let mut babel = Babel::new(stream);
-        babel.start_connection()?;
-        babel.monitor(&self.iface_name)?;
-        Ok(())
+        babel_monitor::monitor(&self.iface_name)
     }
 
-    pub fn unmonitor<T: Read + Write>(&self, stream: T) -> Result<(), Error> {
+    pub fn unmonitor(&self) -> Box<Future<Item = (), Error = Error>> {
         warn!("Unmonitoring tunnel {}", self.iface_name);
-        let mut babel = Babel::new(stream);
-        babel.start_connection()?;
-        babel.unmonitor(&self.iface_name)?;
-        Ok(())
+        babel_monitor::unmonitor(&self.iface_name)
     }
 }
 
 pub struct TunnelManager {
     free_ports: Vec<u16>,
     tunnels: HashMap<Identity, HashMap<u32, Tunnel>>,
+    /// Tunnels that are being set up, they are only added to `tunnels` once Babel monitors them
+    pending: HashSet<(Identity, u32)>,
 }
 
 impl Actor for TunnelManager {
@@ -205,7 +386,7 @@ impl IdentityCallback {
 }
 
 impl Message for IdentityCallback {
-    type Result = Option<(Tunnel, bool)>;
+    type Result = Result<(Tunnel, bool), Error>;
 }
 
 // An attempt to contact a neighbor has succeeded or a neighbor has contacted us, either way
@@ -215,7 +396,7 @@ impl Message for IdentityCallback {
 // we now must attach to their tunnel entry. If we also return a bool for if the tunnel already
 // exists
 impl Handler<IdentityCallback> for TunnelManager {
-    type Result = Option<(Tunnel, bool)>;
+    type Result = ResponseActFuture<Self, (Tunnel, bool), Error>;
 
     fn handle(&mut self, msg: IdentityCallback, _: &mut Context<Self>) -> Self::Result {
         let our_port = match msg.our_port {
@@ -224,19 +405,20 @@ impl Handler<IdentityCallback> for TunnelManager {
                 Some(p) => p,
                 None => {
                     warn!("Failed to allocate tunnel port! All tunnel opening will fail");
-                    return None;
+                    return Box::new(fut::err(
+                        TunnelManagerError::PortError("No free ports".to_string()).into(),
+                    ));
                 }
             },
         };
 
-        let res = self.open_tunnel(msg.local_identity, msg.peer, our_port);
-        match res {
-            Ok(res) => Some(res),
-            Err(e) => {
-                warn!("Open Tunnel failed with {:?}", e);
-                return None;
-            }
-        }
+        Box::new(
+            self.open_tunnel(msg.local_identity, msg.peer, our_port)
+                .map_err(|e, _act, _ctx| {
+                    warn!("Open Tunnel failed with {:?}", e);
+                    e
+                }),
+        )
     }
 }
 
@@ -262,39 +444,30 @@ impl Message for GetPhyIpFromMeshIp {
     type Result = Result<IpAddr, Error>;
 }
 
-fn make_babel_stream() -> Result<TcpStream, Error> {
-    let stream = TcpStream::connect::<SocketAddr>(
-        format!("[::1]:{}", SETTING.get_network().babel_port).parse()?,
-    )?;
-    Ok(stream)
-}
-
 impl Handler<GetPhyIpFromMeshIp> for TunnelManager {
-    type Result = Result<IpAddr, Error>;
+    type Result = ResponseFuture<IpAddr, Error>;
 
     fn handle(&mut self, mesh_ip: GetPhyIpFromMeshIp, _: &mut Context<Self>) -> Self::Result {
-        let mut babel = Babel::new(make_babel_stream()?);
-        babel.start_connection()?;
-        let routes = babel.parse_routes()?;
-
-        let mut route_to_des: Option<Route> = None;
-
-        for route in routes {
-            // Only ip6
-            if let IpNetwork::V6(ref ip) = route.prefix {
-                // Only host addresses and installed routes
-                if ip.prefix() == 128 && route.installed {
-                    if IpAddr::V6(ip.ip()) == mesh_ip.0 {
-                        route_to_des = Some(route.clone());
+        Box::new(babel_monitor::routes().and_then(move |routes| {
+            let mut route_to_des: Option<Route> = None;
+
+            for route in routes {
+                // Only ip6
+                if let IpNetwork::V6(ref ip) = route.prefix {
+                    // Only host addresses and installed routes
+                    if ip.prefix() == 128 && route.installed {
+                        if IpAddr::V6(ip.ip()) == mesh_ip.0 {
+                            route_to_des = Some(route.clone());
+                        }
                     }
                 }
             }
-        }
 
-        match route_to_des {
-            Some(route) => Ok(KI.get_wg_remote_ip(&route.iface)?),
-            None => bail!("No route found for mesh ip: {:?}", mesh_ip),
-        }
+            match route_to_des {
+                Some(route) => Ok(KI.get_wg_remote_ip(&route.iface)?),
+                None => bail!("No route found for mesh ip: {:?}", mesh_ip),
+            }
+        }))
     }
 }
 
@@ -334,6 +507,8 @@ impl Handler<GetNeighbors> for TunnelManager {
                 ));
             }
         }
+        metrics::TUNNELS_OPEN.set(res.len() as i64);
+        metrics::FREE_PORTS.set(self.free_ports.len() as i64);
         Ok(res)
     }
 }
@@ -348,20 +523,6 @@ impl Message for TriggerGC {
 impl Handler<TriggerGC> for TunnelManager {
     type Result = Result<(), Error>;
     fn handle(&mut self, msg: TriggerGC, _ctx: &mut Context<Self>) -> Self::Result {
-        let stream = match make_babel_stream() {
-            Ok(stream) => stream,
-            Err(e) => {
-                warn!("Tunnel GC failed to open babel stream with {:?}", e);
-                return Err(e);
-            }
-        };
-        let mut babel = Babel::new(stream);
-        let res = babel.start_connection();
-        if res.is_err() {
-            warn!("Failed to start Babel RPC connection! {:?}", res);
-            bail!("Failed to start Babel RPC connection!");
-        }
-
         let mut good: HashMap<Identity, HashMap<u32, Tunnel>> = HashMap::new();
         let mut timed_out: HashMap<Identity, HashMap<u32, Tunnel>> = HashMap::new();
         // Split entries into good and timed out rebuilding the double hashmap strucutre
@@ -409,16 +570,27 @@ impl Handler<TriggerGC> for TunnelManager {
         // would lead to nasty bugs in case del_interface() goes wrong for whatever reason.
         self.tunnels = good;
 
-        for (_ident, tunnels) in timed_out {
+        for (ident, tunnels) in timed_out {
             for (_ifidx, tunnel) in tunnels {
                 // In the same spirit, we return the port to the free port pool only after tunnel
                 // deletion goes well.
-                let res = babel.unmonitor(&tunnel.iface_name);
-                if res.is_err() {
-                    warn!("Failed to unmonitor {} with {:?}", tunnel.iface_name, res);
-                }
+                // the interface is going away regardless, so a failure is only logged
+                let iface_name = tunnel.iface_name.clone();
+                Arbiter::spawn(tunnel.unmonitor().then(move |res| {
+                    if let Err(e) = res {
+                        warn!("Failed to unmonitor {} with {:?}", iface_name, e);
+                    }
+                    Ok(())
+                }));
                 KI.del_interface(&tunnel.iface_name)?;
+                Firewall::from_registry().do_send(RemoveRules(RuleGroup::SuspendTunnel(
+                    tunnel.iface_name.clone(),
+                )));
                 self.free_ports.push(tunnel.listen_port);
+                event_bus::publish(Event::TunnelClosed {
+                    neighbor: ident.clone(),
+                    iface: tunnel.iface_name,
+                });
             }
         }
 
@@ -491,10 +663,7 @@ impl Handler<PeersToContact> for TunnelManager {
 /// Sets out to contact a neighbor, takes a speculative port (only assigned if the neighbor
 /// responds successfully)
 fn contact_neighbor(peer: &Peer, our_port: u16) -> Result<(), Error> {
-    KI.manual_peers_route(
-        &peer.contact_socket.ip(),
-        &mut SETTING.get_network_mut().default_route,
-    )?;
+    KI.manual_peers_route(&peer.contact_socket.ip())?;
 
     let _res = HTTPClient::from_registry().do_send(Hello {
         my_id: LocalIdentity {
@@ -517,6 +686,7 @@ impl TunnelManager {
         TunnelManager {
             free_ports: ports,
             tunnels: HashMap::new(),
+            pending: HashSet::new(),
         }
     }
 
@@ -591,13 +761,14 @@ impl TunnelManager {
     }
 
     /// Given a LocalIdentity, connect to the neighbor over wireguard
-    /// return the tunnel object and if already had a tunnel
+    /// return the tunnel object and if already had a tunnel. A new tunnel is only registered
+    /// once Babel has accepted monitoring it
     pub fn open_tunnel(
         &mut self,
         their_localid: LocalIdentity,
         peer: Peer,
         our_port: u16,
-    ) -> Result<(Tunnel, bool), Error> {
+    ) -> ResponseActFuture<Self, (Tunnel, bool), Error> {
         trace!("getting existing tunnel or opening a new one");
         // ifidx must be a part of the key so that we can open multiple tunnels
         // if we have more than one physical connection to the same peer
@@ -648,7 +819,7 @@ impl TunnelManager {
                     .get(&peer.ifidx)
                     .expect("Unable to find tunnel by ifidx how did this happen?");
 
-                return Ok((tunnel.clone(), true));
+                return Box::new(fut::ok((tunnel.clone(), true)));
             } else {
                 // In the case that we have a tunnel and they don't we drop our existing one
                 // and agree on the new parameters in this message
@@ -681,18 +852,33 @@ impl TunnelManager {
                         tunnel.iface_name, res
                     );
                 }
+                Firewall::from_registry().do_send(RemoveRules(RuleGroup::SuspendTunnel(
+                    tunnel.iface_name.clone(),
+                )));
 
                 self.free_ports.push(tunnel.listen_port);
+                event_bus::publish(Event::TunnelClosed {
+                    neighbor: key.clone(),
+                    iface: tunnel.iface_name,
+                });
                 return_bool = true;
             }
         }
+        // hellos can cross while a tunnel is being set up, only the first one opens it
+        let pending = (key.clone(), peer.ifidx);
+        if self.pending.contains(&pending) {
+            self.free_ports.push(our_port);
+            return Box::new(fut::err(
+                TunnelManagerError::TunnelPending(key, peer.ifidx).into(),
+            ));
+        }
         info!(
             "no tunnel found for {:?}%{:?} creating",
             peer.contact_socket.ip(),
             peer.ifidx,
         );
         // Create new tunnel
-        let tunnel = Tunnel::new(
+        let mut tunnel = Tunnel::new(
             peer.contact_socket.ip(),
             KI.setup_wg_if().unwrap(),
             our_port,
@@ -704,27 +890,43 @@ impl TunnelManager {
             Ok(_) => info!("Tunnel {:?} is open", tunnel),
             Err(e) => {
                 error!("Unable to open tunnel {:?}: {}", tunnel, e);
-                return Err(e);
-            }
-        }
-        match tunnel.monitor(make_babel_stream()?) {
-            Ok(_) => {
-                let new_key = tunnel.neigh_id.global.clone();
-                // Add a tunnel to internal map based on identity, and interface index.
-                self.tunnels
-                    .entry(new_key)
-                    .or_insert(HashMap::new())
-                    .insert(tunnel.listen_ifidx.clone(), tunnel.clone());
-                Ok((tunnel, return_bool))
-            }
-            Err(e) => {
-                error!(
-                    "Unable to execute babel monitor on tunnel {:?}: {}",
-                    tunnel, e
-                );
-                Err(e)
+                return Box::new(fut::err(e));
             }
         }
+        self.pending.insert(pending.clone());
+        Box::new(tunnel.monitor().into_actor(self).then(
+            move |res, act: &mut Self, _ctx| match res {
+                Ok(_) => {
+                    act.pending.remove(&pending);
+                    tunnel.set_state(TunnelState::Registered, TunnelAction::HandshakeComplete);
+                    let new_key = tunnel.neigh_id.global.clone();
+                    // Add a tunnel to internal map based on identity, and interface index.
+                    act.tunnels
+                        .entry(new_key)
+                        .or_insert(HashMap::new())
+                        .insert(tunnel.listen_ifidx.clone(), tunnel.clone());
+                    event_bus::publish(Event::TunnelOpened {
+                        neighbor: tunnel.neigh_id.global.clone(),
+                        iface: tunnel.iface_name.clone(),
+                        listen_port: tunnel.listen_port,
+                    });
+                    fut::ok((tunnel, return_bool))
+                }
+                Err(e) => {
+                    act.pending.remove(&pending);
+                    error!("Unable to execute babel monitor on {}: {}", tunnel.iface_name, e);
+                    // The tunnel was never registered, tear it down and give back its port
+                    if let Err(e) = KI.del_interface(&tunnel.iface_name) {
+                        warn!(
+                            "We failed to delete the interface {:?} with {:?} it's now orphaned",
+                            tunnel.iface_name, e
+                        );
+                    }
+                    act.free_ports.push(tunnel.listen_port);
+                    fut::err(e)
+                }
+            },
+        ))
     }
 }
 
@@ -739,57 +941,165 @@ impl Message for TunnelStateChange {
 
 // Called by DAOManager to notify TunnelManager about the registration state of a given peer
 impl Handler<TunnelStateChange> for TunnelManager {
-    type Result = Result<(), Error>;
+    type Result = ResponseActFuture<Self, (), Error>;
 
     fn handle(&mut self, msg: TunnelStateChange, _: &mut Context<Self>) -> Self::Result {
         info!(
             "Tunnel state change request for {:?} with action {:?}",
             msg.identity, msg.action
         );
-        // Find a tunnel
-        match self.tunnels.get_mut(&msg.identity) {
-            Some(tunnels) => {
-                for (_, tunnel) in tunnels.iter_mut() {
-                    trace!("Handle action {} on tunnel {:?}", msg.action, tunnel);
-                    match msg.action {
-                        TunnelAction::MembershipConfirmed => {
-                            info!(
-                                "Membership confirmed for identity {:?} returned tunnel {:?}",
-                                msg.identity, tunnel
-                            );
-                            match tunnel.state {
-                                TunnelState::NotRegistered => {
-                                    tunnel.monitor(make_babel_stream()?)?;
-                                    tunnel.state = TunnelState::Registered;
-                                }
-                                TunnelState::Registered => {
-                                    trace!("Tunnel {:?} already in registered state", tunnel);
-                                    continue;
-                                }
-                            }
-                        }
-                        TunnelAction::MembershipExpired => {
-                            info!("Membership for identity {:?} is expired", msg.identity);
-                            match tunnel.state {
-                                TunnelState::Registered => {
-                                    tunnel.unmonitor(make_babel_stream()?)?;
-                                    tunnel.state = TunnelState::NotRegistered;
-                                }
-                                TunnelState::NotRegistered => {
-                                    trace!("Tunnel {:?} already in not registered state.", tunnel);
-                                    continue;
-                                }
-                            }
+        let changes = match self.tunnel_state_change(&msg.identity, &msg.action) {
+            Ok(changes) => changes,
+            Err(e) => return Box::new(fut::err(e)),
+        };
+
+        // Babel is updated first, a tunnel only moves to its new state once Babel has accepted
+        // the change so that a failure leaves the recorded state matching reality
+        let mut babel = Vec::new();
+        for (ifidx, next) in changes {
+            let tunnel = &self.tunnels[&msg.identity][&ifidx];
+            let update: Box<Future<Item = (), Error = Error>> =
+                if next.is_monitored() && !tunnel.state.is_monitored() {
+                    tunnel.monitor()
+                } else if !next.is_monitored() && tunnel.state.is_monitored() {
+                    tunnel.unmonitor()
+                } else {
+                    Box::new(future::ok(()))
+                };
+            babel.push(update.then(move |res| Ok::<_, Error>((ifidx, next, res))));
+        }
+
+        let identity = msg.identity;
+        let action = msg.action;
+        Box::new(future::join_all(babel).into_actor(self).and_then(
+            move |results: Vec<(u32, TunnelState, Result<(), Error>)>, act: &mut Self, _ctx| {
+                let mut failed = None;
+                for (ifidx, next, res) in results {
+                    match res {
+                        Ok(_) => act.set_tunnel_state(&identity, ifidx, next, &action),
+                        Err(e) => {
+                            error!("Babel refused {} on {:?}%{}: {}", action, identity, ifidx, e);
+                            failed = Some(e);
                         }
                     }
                 }
+                match failed {
+                    Some(e) => fut::err(e),
+                    None => fut::ok(()),
+                }
+            },
+        ))
+    }
+}
+
+impl TunnelManager {
+    /// Works out the new state of every tunnel we have with the given identity under an action,
+    /// tunnels which are already in the resulting state are left out
+    fn tunnel_state_change(
+        &self,
+        identity: &Identity,
+        action: &TunnelAction,
+    ) -> Result<Vec<(u32, TunnelState)>, Error> {
+        let tunnels = match self.tunnels.get(identity) {
+            Some(tunnels) => tunnels,
+            None => return Err(TunnelManagerError::UnknownIdentity(identity.clone()).into()),
+        };
+
+        let mut changes = Vec::new();
+        for (ifidx, tunnel) in tunnels.iter() {
+            trace!("Handle action {} on tunnel {:?}", action, tunnel);
+            match tunnel.state.transition(action)? {
+                Some(next) => changes.push((*ifidx, next)),
+                None => trace!("Tunnel {:?} already in state {}", tunnel, tunnel.state),
             }
+        }
+        Ok(changes)
+    }
+
+    /// Moves a tunnel into a state Babel has already been updated for
+    fn set_tunnel_state(
+        &mut self,
+        identity: &Identity,
+        ifidx: u32,
+        next: TunnelState,
+        action: &TunnelAction,
+    ) {
+        let tunnel = match self.tunnels.get_mut(identity).and_then(|t| t.get_mut(&ifidx)) {
+            Some(tunnel) => tunnel,
             None => {
-                // TODO: This should probably return error
-                warn!("Couldn't find tunnel for identity {:?}", msg.identity);
+                warn!("Tunnel {:?}%{} went away during {}", identity, ifidx, action);
+                return;
+            }
+        };
+
+        // another action may have moved the tunnel while Babel was being updated, in which case
+        // the state worked out before no longer follows from where the tunnel is now
+        match tunnel.state.transition(action) {
+            Ok(Some(ref current)) if *current == next => {}
+            _ => {
+                warn!(
+                    "Tunnel {:?}%{} changed to {} during {}, dropping stale state {}",
+                    identity, ifidx, tunnel.state, action, next
+                );
+                return;
             }
         }
-        Ok(())
+
+        // babel no longer routes over a suspended tunnel, the firewall makes sure nothing else
+        // gets forwarded over it either
+        let suspend = RuleGroup::SuspendTunnel(tunnel.iface_name.clone());
+        match (&tunnel.state, &next) {
+            (&TunnelState::Suspended(_), &TunnelState::Suspended(_)) => {}
+            (_, &TunnelState::Suspended(_)) => Firewall::from_registry().do_send(AddRules(suspend)),
+            (&TunnelState::Suspended(_), _) => {
+                Firewall::from_registry().do_send(RemoveRules(suspend))
+            }
+            _ => {}
+        }
+        tunnel.set_state(next, action.clone());
+    }
+}
+
+pub struct GetTunnels;
+
+/// The dashboard view of a single tunnel
+#[derive(Debug, Serialize)]
+pub struct TunnelInfo {
+    pub identity: Identity,
+    pub iface_name: String,
+    pub ip: IpAddr,
+    pub listen_ifidx: u32,
+    pub listen_port: u16,
+    /// Seconds since we last heard from the other end of this tunnel
+    pub last_contact: u64,
+    pub state: TunnelState,
+    pub history: Vec<TunnelTransition>,
+}
+
+impl Message for GetTunnels {
+    type Result = Result<Vec<TunnelInfo>, Error>;
+}
+
+impl Handler<GetTunnels> for TunnelManager {
+    type Result = Result<Vec<TunnelInfo>, Error>;
+
+    fn handle(&mut self, _: GetTunnels, _: &mut Context<Self>) -> Self::Result {
+        let mut res = Vec::new();
+        for (identity, tunnels) in self.tunnels.iter() {
+            for (_, tunnel) in tunnels.iter() {
+                res.push(TunnelInfo {
+                    identity: identity.clone(),
+                    iface_name: tunnel.iface_name.clone(),
+                    ip: tunnel.ip,
+                    listen_ifidx: tunnel.listen_ifidx,
+                    listen_port: tunnel.listen_port,
+                    last_contact: tunnel.last_contact.elapsed().as_secs(),
+                    state: tunnel.state.clone(),
+                    history: tunnel.history.iter().cloned().collect(),
+                });
+            }
+        }
+        Ok(res)
     }
 }
 
@@ -840,9 +1150,9 @@ pub fn test_tunnel_manager_lookup() {
             .unwrap()
             .get_mut(&0u32)
             .expect("Unable to find existing tunnel");
-        assert_eq!(existing_tunnel.state, TunnelState::Registered);
+        assert_eq!(existing_tunnel.state, TunnelState::PendingHandshake);
         // Verify mutability - manual modifications shouldn't happen elsewhere
-        existing_tunnel.state = TunnelState::NotRegistered;
+        existing_tunnel.set_state(TunnelState::Registered, TunnelAction::HandshakeComplete);
     }
 
     // Verify if object is modified
@@ -853,6 +1163,89 @@ pub fn test_tunnel_manager_lookup() {
             .unwrap()
             .get_mut(&0u32)
             .expect("Unable to find existing tunnel");
-        assert_eq!(existing_tunnel.state, TunnelState::NotRegistered);
+        assert_eq!(existing_tunnel.state, TunnelState::Registered);
+        assert_eq!(existing_tunnel.history.len(), 1);
+    }
+
+    // Actions for identities we have no tunnels with are errors
+    let unknown = Identity::new(
+        "fd00::1".parse().unwrap(),
+        EthAddress::from_str("ffffffffffffffffffffffffffffffffffffffff").unwrap(),
+        String::from("abc0abc1abc2abc3abc4abc5abc6abc7abc8abc9"),
+    );
+    assert!(
+        tunnel_manager
+            .tunnel_state_change(&unknown, &TunnelAction::MembershipConfirmed)
+            .is_err()
+    );
+    // No-op transitions don't touch babel or the history
+    assert!(
+        tunnel_manager
+            .tunnel_state_change(&id, &TunnelAction::MembershipConfirmed)
+            .unwrap()
+            .is_empty()
+    );
+    assert_eq!(
+        tunnel_manager
+            .tunnel_state_change(&id, &TunnelAction::MembershipExpired)
+            .unwrap(),
+        vec![(0, TunnelState::Suspended(SuspendReason::MembershipExpired))]
+    );
+    // Applying a change records it in the history
+    tunnel_manager.set_tunnel_state(
+        &id,
+        0,
+        TunnelState::Suspended(SuspendReason::MembershipExpired),
+        &TunnelAction::MembershipExpired,
+    );
+    assert_eq!(tunnel_manager.tunnels[&id][&0].history.len(), 2);
+    // A state worked out before the tunnel moved on is dropped
+    tunnel_manager.set_tunnel_state(
+        &id,
+        0,
+        TunnelState::Suspended(SuspendReason::Debt),
+        &TunnelAction::DebtLimitReached,
+    );
+    assert_eq!(
+        tunnel_manager.tunnels[&id][&0].state,
+        TunnelState::Suspended(SuspendReason::MembershipExpired)
+    );
+    assert_eq!(tunnel_manager.tunnels[&id][&0].history.len(), 2);
+}
+
+#[test]
+pub fn test_tunnel_history_bounded() {
+    use althea_types::EthAddress;
+    use std::str::FromStr;
+
+    let id = Identity::new(
+        "0.0.0.0".parse().unwrap(),
+        EthAddress::from_str("ffffffffffffffffffffffffffffffffffffffff").unwrap(),
+        String::from("abc0abc1abc2abc3abc4abc5abc6abc7abc8abc9"),
+    );
+    let mut tunnel = Tunnel::new(
+        "0.0.0.0".parse().unwrap(),
+        "iface".into(),
+        65535,
+        0,
+        LocalIdentity {
+            wg_port: 65535,
+            have_tunnel: Some(true),
+            global: id,
+        },
+    );
+    tunnel.set_state(TunnelState::Registered, TunnelAction::HandshakeComplete);
+    for _ in 0..TUNNEL_HISTORY_LEN {
+        tunnel.set_state(
+            TunnelState::Suspended(SuspendReason::Debt),
+            TunnelAction::DebtLimitReached,
+        );
+        tunnel.set_state(TunnelState::Registered, TunnelAction::DebtPaid);
     }
+    assert_eq!(tunnel.history.len(), TUNNEL_HISTORY_LEN);
+    assert_eq!(tunnel.history.back().unwrap().to, TunnelState::Registered);
+    assert_eq!(
+        tunnel.history.front().unwrap().action,
+        TunnelAction::DebtLimitReached
+    );
 }
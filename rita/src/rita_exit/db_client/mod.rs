@@ -14,6 +14,7 @@ use diesel::select;
 
 use reqwest;
 
+use std::collections::HashMap;
 use std::net::IpAddr;
 use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -42,7 +43,11 @@ use ipnetwork::IpNetwork;
 
 use failure::Error;
 
-use althea_types::{ExitClientDetails, ExitClientIdentity, ExitDetails, ExitState};
+use althea_types::{
+    ExitClientDetails, ExitClientIdentity, ExitDetails, ExitPlan, ExitState, Overage, PlanType,
+};
+
+use rita_common::traffic_watcher::billing::PlanUsage;
 
 #[derive(Default)]
 pub struct DbClient;
@@ -137,6 +142,69 @@ fn verify_ip(request_ip: &IpAddr) -> Result<(), Error> {
     }
 }
 
+fn to_exit_plan(plan: &models::Plan) -> Result<ExitPlan, Error> {
+    let plan_type = match plan.plan_type.as_str() {
+        "per_byte" => PlanType::PerByte,
+        "flat" => PlanType::Flat,
+        "quota" => PlanType::Quota,
+        other => bail!("Unknown plan type {}", other),
+    };
+    let overage = match plan.overage.as_str() {
+        "bill" => Overage::Bill,
+        "throttle" => Overage::Throttle,
+        other => bail!("Unknown overage {}", other),
+    };
+    if plan.quota_bytes < 0 || plan.period < 0 || plan.price < 0 || plan.throttle_kbps < 0 {
+        bail!("Negative values in plan {}", plan.name)
+    }
+    Ok(ExitPlan {
+        name: plan.name.clone(),
+        plan_type,
+        quota_bytes: plan.quota_bytes as u64,
+        period: plan.period as u64,
+        price: plan.price as u64,
+        overage,
+        throttle_kbps: plan.throttle_kbps as u32,
+        description: plan.description.clone(),
+    })
+}
+
+/// The valid plans in the plans table, plans that can't be used are logged and left out
+pub fn list_plans() -> Vec<ExitPlan> {
+    use self::schema::plans::dsl::*;
+    let rows = SqliteConnection::establish(&SETTING.get_db_file())
+        .map_err(Error::from)
+        .and_then(|connection| Ok(plans.load::<models::Plan>(&connection)?));
+    match rows {
+        Ok(rows) => rows
+            .iter()
+            .filter_map(|row| match to_exit_plan(row) {
+                Ok(plan) => Some(plan),
+                Err(e) => {
+                    warn!("Ignoring plan {}: {}", row.name, e);
+                    None
+                }
+            }).collect(),
+        Err(e) => {
+            error!("Failed to load plans {:?}", e);
+            Vec::new()
+        }
+    }
+}
+
+pub struct ListPlans;
+impl Message for ListPlans {
+    type Result = Result<Vec<ExitPlan>, Error>;
+}
+
+impl Handler<ListPlans> for DbClient {
+    type Result = Result<Vec<ExitPlan>, Error>;
+
+    fn handle(&mut self, _: ListPlans, _: &mut Self::Context) -> Self::Result {
+        Ok(list_plans())
+    }
+}
+
 pub fn get_exit_info() -> ExitDetails {
     ExitDetails {
         server_internal_ip: SETTING.get_exit_network().own_internal_ip,
@@ -144,9 +212,14 @@ pub fn get_exit_info() -> ExitDetails {
         exit_price: SETTING.get_exit_network().exit_price,
         netmask: SETTING.get_exit_network().netmask,
         description: SETTING.get_description(),
+        plans: list_plans(),
     }
 }
 
+fn client_plan(client: &models::Client) -> Option<String> {
+    Some(client.plan.clone()).filter(|plan| !plan.is_empty())
+}
+
 fn add_dummy(conn: &SqliteConnection) -> Result<(), Error> {
     use self::schema::clients::dsl::*;
 
@@ -266,6 +339,9 @@ fn client_to_new_db_client(
         email_code: format!("{:06}", rand_code),
         verified: false,
         email_sent_time: 0,
+        plan: String::new(),
+        period_start: 0,
+        period_bytes: 0,
     }
 }
 
@@ -369,6 +445,9 @@ impl Handler<SetupClient> for DbClient {
                             Ok(ExitState::Registered {
                                 our_details: ExitClientDetails {
                                     client_internal_ip: their_record.internal_ip.parse()?,
+                                    plan: client_plan(&their_record),
+                                    period_start: their_record.period_start as u64,
+                                    period_bytes: their_record.period_bytes as u64,
                                 },
                                 general_details: get_exit_info(),
                                 message: "Registration OK".to_string(),
@@ -503,6 +582,9 @@ impl Handler<ClientStatus> for DbClient {
                 Ok(ExitState::Registered {
                     our_details: ExitClientDetails {
                         client_internal_ip: current_ip,
+                        plan: client_plan(&their_record),
+                        period_start: their_record.period_start as u64,
+                        period_bytes: their_record.period_bytes as u64,
                     },
                     general_details: get_exit_info(),
                     message: "Registration OK".to_string(),
@@ -514,6 +596,38 @@ impl Handler<ClientStatus> for DbClient {
     }
 }
 
+/// Stores how far clients are into their plan's period, keyed by mesh ip
+pub struct SetUsage(pub HashMap<IpAddr, PlanUsage>);
+
+impl Message for SetUsage {
+    type Result = Result<(), Error>;
+}
+
+impl Handler<SetUsage> for DbClient {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: SetUsage, _: &mut Self::Context) -> Self::Result {
+        use self::schema::clients::dsl::{clients, period_bytes, period_start};
+        let conn = match SqliteConnection::establish(&SETTING.get_db_file()) {
+            Ok(connection) => connection,
+            Err(e) => {
+                error!("We could not connect to the database file! {:?}", e);
+                bail!("Could not connect to database file!")
+            }
+        };
+        conn.transaction::<_, Error, _>(|| {
+            for (ip, usage) in msg.0.iter() {
+                diesel::update(clients.find(&ip.to_string()))
+                    .set((
+                        period_start.eq(usage.period_start as i64),
+                        period_bytes.eq(usage.period_bytes as i64),
+                    )).execute(&conn)?;
+            }
+            Ok(())
+        })
+    }
+}
+
 pub struct TruncateTables;
 impl Message for TruncateTables {
     type Result = Result<(), Error>;
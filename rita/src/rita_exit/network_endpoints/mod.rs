@@ -10,9 +10,8 @@ use futures::Future;
 use rita_exit::db_client::{get_exit_info, ClientStatus, DbClient, SetupClient};
 
 use std::boxed::Box;
-use std::time::SystemTime;
 
-use althea_types::{ExitClientIdentity, ExitState, RTTimestamps};
+use althea_types::{ExitClientIdentity, ExitState};
 
 use rita_common::tunnel_manager::{GetPhyIpFromMeshIp, TunnelManager};
 
@@ -78,16 +77,6 @@ pub fn list_clients(_req: HttpRequest) -> Box<Future<Item = Json<Vec<Client>>, E
         .responder()
 }
 
-/// An endpoint handler for the inner tunnel RTT. It responds with the request arrival and
-/// transmission time timestamps; presently the two values are very close because no exit-side
-/// processing happens yet.
-pub fn rtt(_req: HttpRequest) -> Result<Json<RTTimestamps>> {
-    Ok(Json(RTTimestamps {
-        exit_rx: SystemTime::now(),
-        exit_tx: SystemTime::now(),
-    }))
-}
-
 #[cfg(not(feature = "development"))]
 pub fn nuke_db(_req: HttpRequest) -> Result<HttpResponse, Error> {
     // This is returned on production builds.
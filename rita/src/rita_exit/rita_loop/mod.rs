@@ -4,25 +4,32 @@
 //! In this loop the exit checks it's database for registered users and deploys the endpoint for
 //! their exit tunnel
 
+use std::collections::HashMap;
+use std::net::IpAddr;
 use std::time::{Duration, Instant};
 
 use actix::prelude::*;
 use actix::registry::SystemService;
 
-use rita_exit::db_client::{DbClient, ListClients};
+use rita_exit::db_client::{DbClient, ListClients, ListPlans};
 
 use rita_exit::traffic_watcher::{TrafficWatcher, Watch};
 
+use rita_common::metrics;
+use rita_common::traffic_watcher::billing::PlanUsage;
+
 use exit_db::models::Client;
 
 use failure::Error;
 
+use futures::Future;
+
 use settings::{RitaCommonSettings, RitaExitSettings};
 use SETTING;
 
 use althea_kernel_interface::{ExitClient, KI};
 
-use althea_types::Identity;
+use althea_types::{ExitPlan, Identity};
 
 pub struct RitaLoop;
 
@@ -52,6 +59,32 @@ fn to_identity(client: Client) -> Identity {
     }
 }
 
+fn to_plan_usage(client: &Client, plans: &[ExitPlan]) -> Option<(IpAddr, PlanUsage)> {
+    if client.plan.is_empty() {
+        return None;
+    }
+    let plan = match plans.iter().find(|plan| plan.name == client.plan) {
+        Some(plan) => plan.clone(),
+        None => {
+            warn!(
+                "Client {} is on unknown plan {}, billing per byte",
+                client.mesh_ip, client.plan
+            );
+            return None;
+        }
+    };
+    Some((
+        client.mesh_ip.parse().ok()?,
+        PlanUsage {
+            plan,
+            internal_ip: client.internal_ip.parse().ok()?,
+            period_start: client.period_start as u64,
+            period_bytes: client.period_bytes as u64,
+            throttled: false,
+        },
+    ))
+}
+
 fn to_exit_client(client: Client) -> Result<ExitClient, Error> {
     Ok(ExitClient {
         mesh_ip: client.mesh_ip.parse()?,
@@ -70,16 +103,25 @@ impl Handler<Tick> for RitaLoop {
         ctx.spawn(
             DbClient::from_registry()
                 .send(ListClients {})
+                .join(DbClient::from_registry().send(ListPlans))
                 .into_actor(self)
                 .then(move |res, _act, _ctx| {
-                    let clients = res.unwrap().unwrap();
+                    let (clients, plans) = res.unwrap();
+                    let clients = clients.unwrap();
+                    let plans = plans.unwrap();
                     let ids = clients
                         .clone()
                         .into_iter()
                         .filter(|c| c.verified)
                         .map(to_identity)
-                        .collect();
-                    TrafficWatcher::from_registry().do_send(Watch(ids));
+                        .collect::<Vec<Identity>>();
+                    let usage = clients
+                        .iter()
+                        .filter(|c| c.verified)
+                        .filter_map(|c| to_plan_usage(c, &plans))
+                        .collect::<HashMap<IpAddr, PlanUsage>>();
+                    metrics::EXIT_CLIENTS_REGISTERED.set(ids.len() as i64);
+                    TrafficWatcher::from_registry().do_send(Watch(ids, usage));
 
                     let mut wg_clients = Vec::new();
 
@@ -110,6 +152,7 @@ impl Handler<Tick> for RitaLoop {
                         start.elapsed().as_secs(),
                         start.elapsed().subsec_nanos() / 1000000
                     );
+                    metrics::observe_tick("exit", start);
                     actix::fut::ok(())
                 }),
         );
@@ -5,24 +5,36 @@
 //! This is the exit specific billing code used to determine how exits should be compensted. Which is
 //! different in that mesh nodes are paid by forwarding traffic, but exits have to return traffic and
 //! must get paid for doing so.
+//!
+//! Clients the operator has put on a billing plan pay a share of the plan's price every round
+//! instead of, or on top of, paying per byte. See `billing::apply_plan` for how each plan type is
+//! billed.
 
 use actix::prelude::*;
 
-use althea_kernel_interface::ExitFilterTarget;
+use althea_kernel_interface::{ExitFilterTarget, RuleGroup};
 use althea_kernel_interface::KI;
 
 use althea_types::Identity;
 
-use babel_monitor::Babel;
+use babel_monitor;
+use babel_monitor::Route;
 
 use rita_common::debt_keeper;
 use rita_common::debt_keeper::DebtKeeper;
+use rita_common::firewall::{AddRules, Firewall, RemoveRules};
+use rita_common::metrics;
+use rita_common::payment_controller::spending::unix_time;
+use rita_common::traffic_watcher::billing::{apply_plan, charge, debit, PlanUsage};
+use rita_exit::db_client::{DbClient, SetUsage};
 
 use num256::Int256;
 
-use std::collections::HashMap;
-use std::io::{Read, Write};
-use std::net::{IpAddr, SocketAddr, TcpStream};
+use futures::Future;
+
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
 
 use ipnetwork::IpNetwork;
 
@@ -31,7 +43,10 @@ use SETTING;
 
 use failure::Error;
 
-pub struct TrafficWatcher;
+pub struct TrafficWatcher {
+    last_watch: Option<Instant>,
+    throttled: Vec<RuleGroup>,
+}
 
 impl Actor for TrafficWatcher {
     type Context = Context<Self>;
@@ -39,49 +54,115 @@ impl Actor for TrafficWatcher {
 impl Supervised for TrafficWatcher {}
 impl SystemService for TrafficWatcher {
     fn service_started(&mut self, _ctx: &mut Context<Self>) {
-        KI.init_exit_counter(&ExitFilterTarget::Input).unwrap();
-        KI.init_exit_counter(&ExitFilterTarget::Output).unwrap();
+        let firewall = Firewall::from_registry();
+        firewall.do_send(AddRules(RuleGroup::ExitCounters(ExitFilterTarget::Input)));
+        firewall.do_send(AddRules(RuleGroup::ExitCounters(ExitFilterTarget::Output)));
 
         match KI.setup_wg_if_named("wg_exit") {
             Err(e) => warn!("exit setup returned {}", e),
             _ => {}
         }
-        KI.setup_nat(&SETTING.get_network().external_nic.clone().unwrap())
-            .unwrap();
+        firewall.do_send(AddRules(RuleGroup::ExitNat(
+            SETTING.get_network().external_nic.clone().unwrap(),
+        )));
 
         info!("Traffic Watcher started");
     }
 }
 impl Default for TrafficWatcher {
     fn default() -> TrafficWatcher {
-        TrafficWatcher {}
+        TrafficWatcher {
+            last_watch: None,
+            throttled: Vec::new(),
+        }
     }
 }
 
-pub struct Watch(pub Vec<Identity>);
+/// The verified clients and the plans of those that have one, keyed by mesh ip
+pub struct Watch(pub Vec<Identity>, pub HashMap<IpAddr, PlanUsage>);
 
 impl Message for Watch {
     type Result = Result<(), Error>;
 }
 
 impl Handler<Watch> for TrafficWatcher {
-    type Result = Result<(), Error>;
+    type Result = ResponseFuture<(), Error>;
 
     fn handle(&mut self, msg: Watch, _: &mut Context<Self>) -> Self::Result {
-        let stream = TcpStream::connect::<SocketAddr>(
-            format!("[::1]:{}", SETTING.get_network().babel_port).parse()?,
-        )?;
+        let start = Instant::now();
+        let elapsed = match self.last_watch {
+            Some(last) => last.elapsed(),
+            None => Duration::from_secs(0),
+        };
+        let elapsed_ms = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_nanos() / 1_000_000);
+        self.last_watch = Some(start);
+
+        Box::new(babel_monitor::table().and_then(move |table| {
+            metrics::BABEL_QUERY_SECONDS.observe(metrics::seconds(start.elapsed()));
+            let stored = msg.1.clone();
+            let mut plans = msg.1;
+            watch(table.routes(), table.local_fee()?, msg.0, &mut plans, elapsed_ms)?;
+
+            let throttled = plans
+                .values()
+                .filter(|usage| usage.throttled)
+                .map(|usage| {
+                    RuleGroup::ThrottleExitClient(usage.internal_ip, usage.plan.throttle_kbps)
+                }).collect();
+            TrafficWatcher::from_registry().do_send(Throttle(throttled));
+            // only the clients that were billed this round need their row rewritten
+            let changed = plans
+                .into_iter()
+                .filter(|&(ref ip, ref usage)| match stored.get(ip) {
+                    Some(old) => {
+                        old.period_start != usage.period_start
+                            || old.period_bytes != usage.period_bytes
+                    }
+                    None => true,
+                }).collect::<HashMap<IpAddr, PlanUsage>>();
+            if !changed.is_empty() {
+                DbClient::from_registry().do_send(SetUsage(changed));
+            }
+            Ok(())
+        }))
+    }
+}
 
-        watch(Babel::new(stream), msg.0)
+/// The full set of clients that should currently be throttled
+#[derive(Message)]
+struct Throttle(Vec<RuleGroup>);
+
+impl Handler<Throttle> for TrafficWatcher {
+    type Result = ();
+
+    fn handle(&mut self, msg: Throttle, _: &mut Context<Self>) -> Self::Result {
+        let firewall = Firewall::from_registry();
+        for group in self.throttled.iter() {
+            if !msg.0.contains(group) {
+                info!("Lifting {:?}", group);
+                firewall.do_send(RemoveRules(group.clone()));
+            }
+        }
+        for group in msg.0.iter() {
+            if !self.throttled.contains(group) {
+                info!("Client is over quota, applying {:?}", group);
+                firewall.do_send(AddRules(group.clone()));
+            }
+        }
+        self.throttled = msg.0;
     }
 }
 
 /// This traffic watcher watches how much traffic each we send and receive from each client.
-pub fn watch<T: Read + Write>(mut babel: Babel<T>, clients: Vec<Identity>) -> Result<(), Error> {
-    babel.start_connection()?;
-
-    trace!("Getting routes");
-    let routes = babel.parse_routes()?;
+/// `plans` is updated with each client's usage, `elapsed_ms` is the time since the last round
+/// which plan fees are charged for.
+pub fn watch(
+    routes: VecDeque<Route>,
+    local_fee: u32,
+    clients: Vec<Identity>,
+    plans: &mut HashMap<IpAddr, PlanUsage>,
+    elapsed_ms: u64,
+) -> Result<(), Error> {
     info!("Got routes: {:?}", routes);
 
     let mut destinations = HashMap::new();
@@ -90,7 +171,7 @@ pub fn watch<T: Read + Write>(mut babel: Babel<T>, clients: Vec<Identity>) -> Re
             Some(ip) => ip,
             None => bail!("No mesh IP configured yet"),
         },
-        Int256::from(babel.local_fee().unwrap()),
+        Int256::from(local_fee),
     );
 
     let mut identities: HashMap<IpAddr, Identity> = HashMap::new();
@@ -133,16 +214,22 @@ pub fn watch<T: Read + Write>(mut babel: Babel<T>, clients: Vec<Identity>) -> Re
     let mut total_in: u64 = 0;
     for entry in input_counters.iter() {
         let input = entry.1;
-        total_in += input;
+        total_in = total_in.saturating_add(*input);
     }
     info!("Total Exit input of {} bytes this round", total_in);
+    metrics::BYTES_BILLED
+        .with_label_values(&["in"])
+        .inc_by(total_in as i64);
     trace!("output exit counters: {:?}", output_counters);
     let mut total_out: u64 = 0;
     for entry in output_counters.iter() {
         let output = entry.1;
-        total_out += output;
+        total_out = total_out.saturating_add(*output);
     }
     info!("Total Exit output of {} bytes this round", total_out);
+    metrics::BYTES_BILLED
+        .with_label_values(&["out"])
+        .inc_by(total_out as i64);
 
     let mut debts = HashMap::new();
 
@@ -151,15 +238,23 @@ pub fn watch<T: Read + Write>(mut babel: Babel<T>, clients: Vec<Identity>) -> Re
         debts.insert(ident, Int256::from(0));
     }
 
-    let price = SETTING.get_exit_network().exit_price;
+    let price = Int256::from(SETTING.get_exit_network().exit_price);
+
+    let now = unix_time();
+    let mut bills = HashMap::new();
+    for (ip, usage) in plans.iter_mut() {
+        let bytes_in = input_counters.get(ip).cloned().unwrap_or(0);
+        let bytes_out = output_counters.get(ip).cloned().unwrap_or(0);
+        bills.insert(*ip, apply_plan(usage, bytes_in, bytes_out, now, elapsed_ms)?);
+    }
+    trace!("Plan bills: {:?}", bills);
 
     for (ip, bytes) in input_counters {
+        let bytes = bills.get(&ip).map(|bill| bill.billable_in).unwrap_or(bytes);
         let state = (identities.get(&ip), destinations.get(&ip));
         match state {
             (Some(id), Some(_dest)) => match debts.get_mut(&id) {
-                Some(debt) => {
-                    *debt -= price * bytes;
-                }
+                Some(debt) => debit(debt, &charge(&price, bytes)?)?,
                 // debts is generated from identities, this should be impossible
                 None => warn!("No debts entry for input entry id {:?}", id),
             },
@@ -175,12 +270,11 @@ pub fn watch<T: Read + Write>(mut babel: Babel<T>, clients: Vec<Identity>) -> Re
     trace!("Collated input exit debts: {:?}", debts);
 
     for (ip, bytes) in output_counters {
+        let bytes = bills.get(&ip).map(|bill| bill.billable_out).unwrap_or(bytes);
         let state = (identities.get(&ip), destinations.get(&ip));
         match state {
             (Some(id), Some(dest)) => match debts.get_mut(&id) {
-                Some(debt) => {
-                    *debt -= (dest.clone() + price) * bytes;
-                }
+                Some(debt) => debit(debt, &charge(&(dest.clone() + price.clone()), bytes)?)?,
                 // debts is generated from identities, this should be impossible
                 None => warn!("No debts entry for input entry id {:?}", id),
             },
@@ -193,22 +287,34 @@ pub fn watch<T: Read + Write>(mut babel: Babel<T>, clients: Vec<Identity>) -> Re
         }
     }
 
+    for (ip, bill) in bills.iter() {
+        if let Some(id) = identities.get(ip) {
+            if let Some(debt) = debts.get_mut(id) {
+                debit(debt, &bill.fee)?;
+            }
+        }
+    }
+
     trace!("Collated total exit debts: {:?}", debts);
 
     info!("Computed exit debts for {:?} clients", debts.len());
     let mut total_income = Int256::zero();
     for entry in debts.iter() {
         let income = entry.1;
-        total_income += income;
+        total_income = total_income.saturating_add(income);
     }
     info!("Total income of {:?} Wei this round", total_income);
 
     match KI.get_wg_exit_clients_online() {
-        Ok(users) => info!("Total of {} users online", users),
+        Ok(users) => {
+            info!("Total of {} users online", users);
+            metrics::EXIT_CLIENTS_ONLINE.set(users as i64);
+        }
         Err(e) => warn!("Getting clients failed with {:?}", e),
     }
 
     for (from, amount) in debts {
+        metrics::record_debt(&amount);
         let update = debt_keeper::TrafficUpdate {
             from: from.clone(),
             amount,
@@ -225,12 +331,23 @@ mod tests {
     extern crate env_logger;
 
     use super::*;
+    use babel_monitor::Babel;
+    use std::net::{SocketAddr, TcpStream};
 
     #[test]
     #[ignore]
     fn debug_babel_socket_client() {
         env_logger::init();
         let bm_stream = TcpStream::connect::<SocketAddr>("[::1]:9001".parse().unwrap()).unwrap();
-        watch(Babel::new(bm_stream), Vec::new()).unwrap();
+        let mut babel = Babel::new(bm_stream);
+        babel.start_connection().unwrap();
+        let routes = babel.parse_routes().unwrap();
+        watch(
+            routes,
+            babel.local_fee().unwrap(),
+            Vec::new(),
+            &mut HashMap::new(),
+            0,
+        ).unwrap();
     }
 }
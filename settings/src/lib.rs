@@ -47,15 +47,15 @@ use std::time::Duration;
 use althea_kernel_interface::KernelInterface;
 
 #[cfg(not(test))]
-use althea_kernel_interface::LinuxCommandRunner;
+use althea_kernel_interface::linux_kernel_interface;
 #[cfg(test)]
 use althea_kernel_interface::TestCommandRunner;
 
 use config::Config;
 
-use althea_types::{EthAddress, ExitRegistrationDetails, ExitState, Identity};
+use althea_types::{EthAddress, EthPrivateKey, ExitRegistrationDetails, ExitState, Identity};
 
-use num256::Int256;
+use num256::{Int256, Uint256};
 
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -75,7 +75,7 @@ lazy_static! {
 
 #[cfg(not(test))]
 lazy_static! {
-    static ref KI: Box<KernelInterface> = Box::new(LinuxCommandRunner {});
+    static ref KI: Box<KernelInterface> = linux_kernel_interface();
 }
 
 fn default_discovery_ip() -> Ipv6Addr {
@@ -87,6 +87,10 @@ fn default_tunnel_timeout() -> u64 {
     900 // 15 minutes
 }
 
+fn default_metrics_port() -> u16 {
+    4878
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
 pub struct NetworkSettings {
     /// The static IP used on mesh interfaces
@@ -110,6 +114,9 @@ pub struct NetworkSettings {
     pub rita_contact_port: u16,
     /// Port over which the dashboard will be accessible upon
     pub rita_dashboard_port: u16,
+    /// Port on localhost over which Prometheus metrics are served
+    #[serde(default = "default_metrics_port")]
+    pub metrics_port: u16,
     /// Port over which the bounty hunter will be contacted
     pub bounty_port: u16,
     /// The tick interval in seconds between rita hellos, traffic watcher measurements and payments
@@ -131,9 +138,6 @@ pub struct NetworkSettings {
     /// such as for connecting to external peers from gateways or to peer 2 althea nodes with a
     /// complex network in between
     pub manual_peers: Vec<String>,
-    /// This is a route in the format of `ip route` which is set by default (assuming it will reach
-    /// the internet), used to tunnel manual peers over a specific route
-    pub default_route: Vec<String>,
     /// This is the NIC which connects to the internet, used by gateways/exits to find its
     /// globally routable ip
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -144,6 +148,10 @@ pub struct NetworkSettings {
     /// How long do we wait without contact from a peer before we delete the associated tunnel?
     #[serde(default = "default_tunnel_timeout")]
     pub tunnel_timeout_seconds: u64,
+    /// Raise the Babel rxcost on the tunnels of neighbors that consistently advertise routes as
+    /// faster or cheaper than we measure them
+    #[serde(default)]
+    pub penalize_overadvertisers: bool,
 }
 
 impl Default for NetworkSettings {
@@ -156,6 +164,7 @@ impl Default for NetworkSettings {
             babel_port: 6872,
             rita_hello_port: 4876,
             rita_dashboard_port: 4877,
+            metrics_port: default_metrics_port(),
             rita_contact_port: 4875,
             bounty_port: 8888,
             rita_tick_interval: 5,
@@ -166,9 +175,9 @@ impl Default for NetworkSettings {
             peer_interfaces: HashSet::new(),
             manual_peers: Vec::new(),
             external_nic: None,
-            default_route: Vec::new(),
             is_gateway: false,
             tunnel_timeout_seconds: default_tunnel_timeout(),
+            penalize_overadvertisers: false,
         }
     }
 }
@@ -217,13 +226,148 @@ impl Default for LoggingSettings {
     }
 }
 
+fn default_stats_dest_port() -> u16 {
+    4880
+}
+
+fn default_stats_interval() -> u64 {
+    60
+}
+
+fn default_stats_batch_size() -> usize {
+    10
+}
+
+fn default_stats_max_buffered() -> usize {
+    1440
+}
+
+/// Stats reporting settings. Like remote logging the samples are sent over the exit tunnel, by
+/// default to a collector listening on the exit's internal ip.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct StatsSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Address of the collector, the exit's internal ip when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dest_ip: Option<IpAddr>,
+    /// Port of the collector
+    #[serde(default = "default_stats_dest_port")]
+    pub dest_port: u16,
+    /// Seconds between samples
+    #[serde(default = "default_stats_interval")]
+    pub interval: u64,
+    /// How many samples are sent together
+    #[serde(default = "default_stats_batch_size")]
+    pub batch_size: usize,
+    /// Samples kept while the collector is unreachable, the oldest are dropped past this
+    #[serde(default = "default_stats_max_buffered")]
+    pub max_buffered: usize,
+}
+
+impl Default for StatsSettings {
+    fn default() -> Self {
+        StatsSettings {
+            enabled: false,
+            dest_ip: None,
+            dest_port: default_stats_dest_port(),
+            interval: default_stats_interval(),
+            batch_size: default_stats_batch_size(),
+            max_buffered: default_stats_max_buffered(),
+        }
+    }
+}
+
+fn default_session_timeout() -> u64 {
+    3600
+}
+
+fn default_tls_cert_path() -> String {
+    String::from("/etc/rita/dashboard.crt")
+}
+
+fn default_tls_key_path() -> String {
+    String::from("/etc/rita/dashboard.key")
+}
+
+/// Dashboard access settings, until a password is set the dashboard can only be used to set one
+/// and only from localhost
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct DashboardSettings {
+    /// Bcrypt hash of the dashboard password, None until first run setup is done
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_hash: Option<String>,
+    /// Seconds a login stays valid without being used
+    #[serde(default = "default_session_timeout")]
+    pub session_timeout: u64,
+    /// Serve the dashboard over https, a self signed certificate is generated if none exists
+    #[serde(default)]
+    pub tls: bool,
+    #[serde(default = "default_tls_cert_path")]
+    pub tls_cert_path: String,
+    #[serde(default = "default_tls_key_path")]
+    pub tls_key_path: String,
+}
+
+impl Default for DashboardSettings {
+    fn default() -> Self {
+        DashboardSettings {
+            password_hash: None,
+            session_timeout: default_session_timeout(),
+            tls: false,
+            tls_cert_path: default_tls_cert_path(),
+            tls_key_path: default_tls_key_path(),
+        }
+    }
+}
+
+/// A neighbor named by any one of its identifiers
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NeighborKey {
+    MeshIp(IpAddr),
+    EthAddress(EthAddress),
+    WgKey(String),
+}
+
+impl NeighborKey {
+    pub fn matches(&self, id: &Identity) -> bool {
+        match *self {
+            NeighborKey::MeshIp(ip) => ip == id.mesh_ip,
+            NeighborKey::EthAddress(ref address) => *address == id.eth_address,
+            NeighborKey::WgKey(ref key) => *key == id.wg_public_key,
+        }
+    }
+}
+
+/// Billing overrides for a single neighbor, thresholds that are left out fall back to the ones
+/// in `PaymentSettings`
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct CreditPolicy {
+    pub neighbor: NeighborKey,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pay_threshold: Option<Int256>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub close_threshold: Option<Int256>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub close_fraction: Option<Int256>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buffer_period: Option<u32>,
+    /// Free peering, traffic with this neighbor is never billed in either direction
+    #[serde(default)]
+    pub free: bool,
+    /// Keep this neighbor's tunnels suspended
+    #[serde(default)]
+    pub blocked: bool,
+}
+
 /// This struct is used by both rita and rita_exit to configure the dummy payment controller and
 /// debt keeper
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
 pub struct PaymentSettings {
     /// The threshold above which we will kick off a payment
     pub pay_threshold: Int256,
-    /// The threshold below which we will kick another node off (not implemented yet)
+    /// The threshold below which we will suspend a node's tunnels
     pub close_threshold: Int256,
     /// This is used to control the amount of grace, as `total_payment/close_fraction` which we will
     /// give to a node
@@ -232,6 +376,18 @@ pub struct PaymentSettings {
     pub buffer_period: u32,
     /// Our own eth address
     pub eth_address: EthAddress,
+    /// The key for `eth_address`, generated on first start, used to sign our transactions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub eth_private_key: Option<EthPrivateKey>,
+    /// The fee we charge for forwarding traffic, set in Babel at startup if present
+    #[serde(default)]
+    pub local_fee: Option<u32>,
+    /// Overrides for specific neighbors, the first policy matching a neighbor is used
+    #[serde(default)]
+    pub credit_policies: Vec<CreditPolicy>,
+    /// Neighbors without a credit policy are blocked
+    #[serde(default)]
+    pub allowlist_only: bool,
 }
 
 impl Default for PaymentSettings {
@@ -242,10 +398,145 @@ impl Default for PaymentSettings {
             close_fraction: 100.into(),
             buffer_period: 3,
             eth_address: 1.into(),
+            eth_private_key: None,
+            local_fee: None,
+            credit_policies: Vec::new(),
+            allowlist_only: false,
+        }
+    }
+}
+
+impl PaymentSettings {
+    pub fn credit_policy(&self, id: &Identity) -> Option<&CreditPolicy> {
+        self.credit_policies.iter().find(|p| p.neighbor.matches(id))
+    }
+}
+
+fn default_price_ceiling() -> u32 {
+    u32::max_value()
+}
+
+fn default_congestion_threshold() -> u8 {
+    50
+}
+
+fn default_congestion_multiplier() -> u32 {
+    300
+}
+
+fn default_link_capacity() -> u64 {
+    12_500_000 // 100Mbit/s
+}
+
+fn default_price_update_interval() -> u64 {
+    60
+}
+
+fn default_min_price_change() -> u8 {
+    5
+}
+
+/// A base forwarding fee for part of the day. `start` and `end` are hours from 0 to 24, a
+/// schedule with `end` before `start` runs past midnight
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct PriceSchedule {
+    pub start: u8,
+    pub end: u8,
+    pub fee: u32,
+}
+
+/// Settings for adjusting our forwarding fee in Babel with link utilization and time of day.
+/// The fee from `payment.local_fee` is the base price outside of any schedule
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct PricingSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub floor: u32,
+    #[serde(default = "default_price_ceiling")]
+    pub ceiling: u32,
+    /// Utilization percentage past which the fee starts to rise
+    #[serde(default = "default_congestion_threshold")]
+    pub congestion_threshold: u8,
+    /// The fee at full utilization as a percentage of the base fee
+    #[serde(default = "default_congestion_multiplier")]
+    pub congestion_multiplier: u32,
+    /// Interfaces to measure, `network.peer_interfaces` if empty
+    #[serde(default)]
+    pub interfaces: HashSet<String>,
+    /// Capacity in bytes per second of any interface missing from `capacities`
+    #[serde(default = "default_link_capacity")]
+    pub link_capacity: u64,
+    #[serde(default)]
+    pub capacities: HashMap<String, u64>,
+    /// Base fees by time of day, the first matching schedule is used
+    #[serde(default)]
+    pub schedule: Vec<PriceSchedule>,
+    /// Offset of the schedule's hours from UTC
+    #[serde(default)]
+    pub utc_offset_minutes: i32,
+    /// The least number of seconds between fee updates sent to Babel
+    #[serde(default = "default_price_update_interval")]
+    pub update_interval: u64,
+    /// Fee changes smaller than this percentage are not sent to Babel
+    #[serde(default = "default_min_price_change")]
+    pub min_change: u8,
+}
+
+impl Default for PricingSettings {
+    fn default() -> Self {
+        PricingSettings {
+            enabled: false,
+            floor: 0,
+            ceiling: default_price_ceiling(),
+            congestion_threshold: default_congestion_threshold(),
+            congestion_multiplier: default_congestion_multiplier(),
+            interfaces: HashSet::new(),
+            link_capacity: default_link_capacity(),
+            capacities: HashMap::new(),
+            schedule: Vec::new(),
+            utc_offset_minutes: 0,
+            update_interval: default_price_update_interval(),
+            min_change: default_min_price_change(),
         }
     }
 }
 
+/// Limits on what we pay out and where to send alerts about them. Caps and the low balance
+/// threshold are in wei, days and months start at midnight UTC
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Default)]
+pub struct SpendingSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_cap: Option<Int256>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monthly_cap: Option<Int256>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub low_balance: Option<Int256>,
+    /// Stop forwarding our clients' traffic to the exit while a cap is reached
+    #[serde(default)]
+    pub hard_stop: bool,
+    /// Alerts are POSTed here as JSON in the same format as the dashboard events
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook_url: Option<String>,
+    /// Alerts are emailed here using the from address, test and smtp settings of `mailer`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alert_email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mailer: Option<ExitMailerSettings>,
+    /// Kept up to date by rita so the totals carry over a restart
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totals: Option<SpendingTotals>,
+}
+
+/// What we have paid out in the current day and month, in wei
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct SpendingTotals {
+    /// The day the totals were last updated on, counted from the unix epoch
+    pub day: u64,
+    pub spent_today: Int256,
+    pub spent_this_month: Int256,
+}
+
 /// This struct is used by rita to store exit specific information
 /// There is one instance per exit
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
@@ -330,6 +621,9 @@ pub struct SubnetDAOSettings {
     /// List of subnet DAO's to which we are a member
     #[serde(default = "default_dao_address")]
     pub dao_addresses: Vec<EthAddress>,
+    /// The most wei we will pay to join a DAO, joins of DAOs charging more are refused
+    #[serde(default)]
+    pub max_join_fee: Uint256,
 }
 
 /// This is the main struct for rita
@@ -340,6 +634,14 @@ pub struct RitaSettingsStruct {
     dao: SubnetDAOSettings,
     #[serde(default)]
     log: LoggingSettings,
+    #[serde(default)]
+    stats: StatsSettings,
+    #[serde(default)]
+    dashboard: DashboardSettings,
+    #[serde(default)]
+    pricing: PricingSettings,
+    #[serde(default)]
+    spending: SpendingSettings,
     network: NetworkSettings,
     exit_client: ExitClientSettings,
     #[serde(skip)]
@@ -435,6 +737,12 @@ pub struct RitaExitSettingsStruct {
     allowed_countries: HashSet<String>,
     #[serde(default)]
     mailer: Option<ExitMailerSettings>,
+    #[serde(default)]
+    dashboard: DashboardSettings,
+    #[serde(default)]
+    pricing: PricingSettings,
+    #[serde(default)]
+    spending: SpendingSettings,
     #[serde(skip)]
     future: bool,
 }
@@ -455,6 +763,25 @@ pub trait RitaCommonSettings<T: Serialize + Deserialize<'static>> {
         &'me self,
     ) -> RwLockWriteGuardRefMut<'ret, T, NetworkSettings>;
 
+    fn get_dashboard<'ret, 'me: 'ret>(
+        &'me self,
+    ) -> RwLockReadGuardRef<'ret, T, DashboardSettings>;
+    fn get_dashboard_mut<'ret, 'me: 'ret>(
+        &'me self,
+    ) -> RwLockWriteGuardRefMut<'ret, T, DashboardSettings>;
+
+    fn get_pricing<'ret, 'me: 'ret>(&'me self) -> RwLockReadGuardRef<'ret, T, PricingSettings>;
+    fn get_pricing_mut<'ret, 'me: 'ret>(
+        &'me self,
+    ) -> RwLockWriteGuardRefMut<'ret, T, PricingSettings>;
+
+    fn get_spending<'ret, 'me: 'ret>(
+        &'me self,
+    ) -> RwLockReadGuardRef<'ret, T, SpendingSettings>;
+    fn get_spending_mut<'ret, 'me: 'ret>(
+        &'me self,
+    ) -> RwLockWriteGuardRefMut<'ret, T, SpendingSettings>;
+
     fn merge(&self, changed_settings: Value) -> Result<(), Error>;
     fn get_all(&self) -> Result<serde_json::Value, Error>;
 
@@ -516,6 +843,42 @@ impl RitaCommonSettings<RitaSettingsStruct> for Arc<RwLock<RitaSettingsStruct>>
         RwLockWriteGuardRefMut::new(self.write().unwrap()).map_mut(|g| &mut g.network)
     }
 
+    fn get_dashboard<'ret, 'me: 'ret>(
+        &'me self,
+    ) -> RwLockReadGuardRef<'ret, RitaSettingsStruct, DashboardSettings> {
+        RwLockReadGuardRef::new(self.read().unwrap()).map(|g| &g.dashboard)
+    }
+
+    fn get_dashboard_mut<'ret, 'me: 'ret>(
+        &'me self,
+    ) -> RwLockWriteGuardRefMut<'ret, RitaSettingsStruct, DashboardSettings> {
+        RwLockWriteGuardRefMut::new(self.write().unwrap()).map_mut(|g| &mut g.dashboard)
+    }
+
+    fn get_pricing<'ret, 'me: 'ret>(
+        &'me self,
+    ) -> RwLockReadGuardRef<'ret, RitaSettingsStruct, PricingSettings> {
+        RwLockReadGuardRef::new(self.read().unwrap()).map(|g| &g.pricing)
+    }
+
+    fn get_pricing_mut<'ret, 'me: 'ret>(
+        &'me self,
+    ) -> RwLockWriteGuardRefMut<'ret, RitaSettingsStruct, PricingSettings> {
+        RwLockWriteGuardRefMut::new(self.write().unwrap()).map_mut(|g| &mut g.pricing)
+    }
+
+    fn get_spending<'ret, 'me: 'ret>(
+        &'me self,
+    ) -> RwLockReadGuardRef<'ret, RitaSettingsStruct, SpendingSettings> {
+        RwLockReadGuardRef::new(self.read().unwrap()).map(|g| &g.spending)
+    }
+
+    fn get_spending_mut<'ret, 'me: 'ret>(
+        &'me self,
+    ) -> RwLockWriteGuardRefMut<'ret, RitaSettingsStruct, SpendingSettings> {
+        RwLockWriteGuardRefMut::new(self.write().unwrap()).map_mut(|g| &mut g.spending)
+    }
+
     fn merge(&self, changed_settings: serde_json::Value) -> Result<(), Error> {
         let mut settings_value = serde_json::to_value(self.read().unwrap().clone())?;
 
@@ -588,6 +951,42 @@ impl RitaCommonSettings<RitaExitSettingsStruct> for Arc<RwLock<RitaExitSettingsS
         RwLockWriteGuardRefMut::new(self.write().unwrap()).map_mut(|g| &mut g.network)
     }
 
+    fn get_dashboard<'ret, 'me: 'ret>(
+        &'me self,
+    ) -> RwLockReadGuardRef<'ret, RitaExitSettingsStruct, DashboardSettings> {
+        RwLockReadGuardRef::new(self.read().unwrap()).map(|g| &g.dashboard)
+    }
+
+    fn get_dashboard_mut<'ret, 'me: 'ret>(
+        &'me self,
+    ) -> RwLockWriteGuardRefMut<'ret, RitaExitSettingsStruct, DashboardSettings> {
+        RwLockWriteGuardRefMut::new(self.write().unwrap()).map_mut(|g| &mut g.dashboard)
+    }
+
+    fn get_pricing<'ret, 'me: 'ret>(
+        &'me self,
+    ) -> RwLockReadGuardRef<'ret, RitaExitSettingsStruct, PricingSettings> {
+        RwLockReadGuardRef::new(self.read().unwrap()).map(|g| &g.pricing)
+    }
+
+    fn get_pricing_mut<'ret, 'me: 'ret>(
+        &'me self,
+    ) -> RwLockWriteGuardRefMut<'ret, RitaExitSettingsStruct, PricingSettings> {
+        RwLockWriteGuardRefMut::new(self.write().unwrap()).map_mut(|g| &mut g.pricing)
+    }
+
+    fn get_spending<'ret, 'me: 'ret>(
+        &'me self,
+    ) -> RwLockReadGuardRef<'ret, RitaExitSettingsStruct, SpendingSettings> {
+        RwLockReadGuardRef::new(self.read().unwrap()).map(|g| &g.spending)
+    }
+
+    fn get_spending_mut<'ret, 'me: 'ret>(
+        &'me self,
+    ) -> RwLockWriteGuardRefMut<'ret, RitaExitSettingsStruct, SpendingSettings> {
+        RwLockWriteGuardRefMut::new(self.write().unwrap()).map_mut(|g| &mut g.spending)
+    }
+
     fn merge(&self, changed_settings: serde_json::Value) -> Result<(), Error> {
         let mut settings_value = serde_json::to_value(self.read().unwrap().clone())?;
 
@@ -642,6 +1041,12 @@ pub trait RitaClientSettings {
     fn get_log_mut<'ret, 'me: 'ret>(
         &'me self,
     ) -> RwLockWriteGuardRefMut<'ret, RitaSettingsStruct, LoggingSettings>;
+    fn get_stats<'ret, 'me: 'ret>(
+        &'me self,
+    ) -> RwLockReadGuardRef<'ret, RitaSettingsStruct, StatsSettings>;
+    fn get_stats_mut<'ret, 'me: 'ret>(
+        &'me self,
+    ) -> RwLockWriteGuardRefMut<'ret, RitaSettingsStruct, StatsSettings>;
 }
 
 impl RitaClientSettings for Arc<RwLock<RitaSettingsStruct>> {
@@ -679,6 +1084,18 @@ impl RitaClientSettings for Arc<RwLock<RitaSettingsStruct>> {
     ) -> RwLockWriteGuardRefMut<'ret, RitaSettingsStruct, LoggingSettings> {
         RwLockWriteGuardRefMut::new(self.write().unwrap()).map_mut(|g| &mut g.log)
     }
+
+    fn get_stats<'ret, 'me: 'ret>(
+        &'me self,
+    ) -> RwLockReadGuardRef<'ret, RitaSettingsStruct, StatsSettings> {
+        RwLockReadGuardRef::new(self.read().unwrap()).map(|g| &g.stats)
+    }
+
+    fn get_stats_mut<'ret, 'me: 'ret>(
+        &'me self,
+    ) -> RwLockWriteGuardRefMut<'ret, RitaSettingsStruct, StatsSettings> {
+        RwLockWriteGuardRefMut::new(self.write().unwrap()).map_mut(|g| &mut g.stats)
+    }
 }
 
 pub trait RitaExitSettings {
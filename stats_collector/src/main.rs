@@ -0,0 +1,85 @@
+//! A reference collector for the stats rita sends when stats reporting is enabled. Every batch
+//! received on `/stats` is printed as a line of JSON and the most recent ones can be listed with
+//! `/list`, which is enough for testing a router's reporting end to end.
+//!
+//! Usage: stats_collector [listen address, defaults to [::0]:4880]
+
+#[macro_use]
+extern crate log;
+
+#[macro_use]
+extern crate rouille;
+use rouille::{Request, Response};
+
+extern crate env_logger;
+extern crate serde_json;
+
+extern crate althea_types;
+use althea_types::StatsBatch;
+
+use std::collections::VecDeque;
+use std::env;
+use std::io::Read;
+use std::sync::Mutex;
+
+/// How many batches are kept for `/list`
+const MAX_BATCHES: usize = 1000;
+
+fn main() {
+    env_logger::init();
+    let addr = env::args().nth(1).unwrap_or_else(|| "[::0]:4880".to_string());
+    info!("Collecting stats on {}", addr);
+
+    let batches = Mutex::new(VecDeque::new());
+
+    rouille::start_server(addr, move |request| {
+        router!(request,
+            (POST) (/stats) => {
+                receive_stats(request, &batches)
+            },
+            (GET) (/list) => {
+                list_stats(request, &batches)
+            },
+            _ => rouille::Response::empty_404()
+        )
+    });
+}
+
+fn receive_stats(request: &Request, batches: &Mutex<VecDeque<StatsBatch>>) -> Response {
+    let mut body = String::new();
+    match request.data() {
+        Some(mut data) => {
+            if let Err(e) = data.read_to_string(&mut body) {
+                warn!("Failed to read stats body {:?}", e);
+                return Response::text("Unreadable body").with_status_code(400);
+            }
+        }
+        None => return Response::text("Empty body").with_status_code(400),
+    }
+
+    let batch: StatsBatch = match serde_json::from_str(&body) {
+        Ok(batch) => batch,
+        Err(e) => {
+            warn!("Received malformed stats {:?}", e);
+            return Response::text(format!("Malformed stats: {}", e)).with_status_code(400);
+        }
+    };
+    trace!(
+        "Received {} samples from {}",
+        batch.samples.len(),
+        batch.from.mesh_ip
+    );
+    println!("{}", body);
+
+    let mut batches = batches.lock().unwrap();
+    batches.push_back(batch);
+    while batches.len() > MAX_BATCHES {
+        batches.pop_front();
+    }
+    Response::text("Received Successfully")
+}
+
+fn list_stats(_request: &Request, batches: &Mutex<VecDeque<StatsBatch>>) -> Response {
+    let batches = batches.lock().unwrap();
+    Response::text(serde_json::to_string(&*batches).unwrap())
+}
@@ -0,0 +1,133 @@
+//! Just enough of the contract ABI to call functions that take and return static types, which
+//! covers the contracts rita talks to. Dynamic types like `bytes` or arrays are not supported.
+
+use failure::Error;
+
+use tiny_keccak::keccak256;
+
+use althea_types::EthAddress;
+use num256::Uint256;
+
+use Web3Error;
+
+/// A single static argument
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Uint(Uint256),
+    Address(EthAddress),
+    Bool(bool),
+    /// `bytes1` to `bytes32`, left aligned in its word
+    FixedBytes(Vec<u8>),
+}
+
+impl Token {
+    fn encode(&self) -> Result<[u8; 32], Error> {
+        let mut word = [0u8; 32];
+        match *self {
+            Token::Uint(ref value) => value.to_big_endian(&mut word),
+            Token::Address(ref address) => word[12..].copy_from_slice(&address.0),
+            Token::Bool(value) => word[31] = value as u8,
+            Token::FixedBytes(ref bytes) => {
+                if bytes.len() > 32 {
+                    return Err(Web3Error::ArgumentTooLong(bytes.len()).into());
+                }
+                word[..bytes.len()].copy_from_slice(bytes);
+            }
+        }
+        Ok(word)
+    }
+}
+
+/// The first four bytes of the hash of a signature like `transfer(address,uint256)`
+pub fn selector(signature: &str) -> [u8; 4] {
+    let hash = keccak256(signature.as_bytes());
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// Call data for a function with a known selector
+pub fn encode_call_with_selector(selector: [u8; 4], args: &[Token]) -> Result<Vec<u8>, Error> {
+    let mut data = selector.to_vec();
+    for arg in args {
+        data.extend_from_slice(&arg.encode()?);
+    }
+    Ok(data)
+}
+
+pub fn encode_call(signature: &str, args: &[Token]) -> Result<Vec<u8>, Error> {
+    encode_call_with_selector(selector(signature), args)
+}
+
+/// The first word of a call result
+fn first_word(data: &[u8]) -> Result<&[u8], Error> {
+    if data.len() < 32 {
+        return Err(Web3Error::ShortResult(data.len()).into());
+    }
+    Ok(&data[..32])
+}
+
+pub fn decode_uint(data: &[u8]) -> Result<Uint256, Error> {
+    Ok(Uint256::from_big_endian(first_word(data)?))
+}
+
+pub fn decode_bool(data: &[u8]) -> Result<bool, Error> {
+    let value = decode_uint(data)?;
+    if value == Uint256::zero() {
+        Ok(false)
+    } else if value == Uint256::from(1u64) {
+        Ok(true)
+    } else {
+        Err(Web3Error::NotABool.into())
+    }
+}
+
+pub fn decode_address(data: &[u8]) -> Result<EthAddress, Error> {
+    Ok(EthAddress::from_slice(&first_word(data)?[12..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex;
+
+    #[test]
+    fn test_selector() {
+        assert_eq!(selector("transfer(address,uint256)"), [0xa9, 0x05, 0x9c, 0xbb]);
+        assert_eq!(selector("balanceOf(address)"), [0x70, 0xa0, 0x82, 0x31]);
+    }
+
+    #[test]
+    fn test_encode_call() {
+        let data = encode_call(
+            "transfer(address,uint256)",
+            &[Token::Address(1.into()), Token::Uint(Uint256::from(256u64))],
+        ).unwrap();
+        assert_eq!(
+            hex::encode(data),
+            "a9059cbb\
+             0000000000000000000000000000000000000000000000000000000000000001\
+             0000000000000000000000000000000000000000000000000000000000000100"
+        );
+
+        let data =
+            encode_call_with_selector([1, 2, 3, 4], &[Token::FixedBytes(vec![0xff; 16])]).unwrap();
+        assert_eq!(
+            hex::encode(data),
+            "01020304ffffffffffffffffffffffffffffffff00000000000000000000000000000000"
+        );
+        assert!(encode_call_with_selector([0; 4], &[Token::FixedBytes(vec![0; 33])]).is_err());
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut word = [0u8; 32];
+        assert_eq!(decode_bool(&word).unwrap(), false);
+        word[31] = 1;
+        assert_eq!(decode_bool(&word).unwrap(), true);
+        assert_eq!(decode_uint(&word).unwrap(), Uint256::from(1u64));
+        assert_eq!(decode_address(&word).unwrap(), 1.into());
+        word[31] = 2;
+        assert!(decode_bool(&word).is_err());
+        assert!(decode_uint(&word[..31]).is_err());
+        assert!(decode_uint(&[]).is_err());
+    }
+}
@@ -0,0 +1,324 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_web::client;
+use actix_web::HttpMessage;
+
+use failure::Error;
+
+use futures::future::{err, loop_fn, Loop};
+use futures::Future;
+
+use serde::de::DeserializeOwned;
+use serde_json;
+use serde_json::Value;
+
+use althea_types::EthAddress;
+use num256::Uint256;
+
+use nodes::NodeList;
+use types::{
+    format_address, format_data, format_hash, parse_data, parse_quantity, RawReceipt, Request,
+    Response, TransactionReceipt, TransactionRequest,
+};
+use Web3Error;
+
+/// A handle to the full nodes, clones share the node health. Requests need a running actix system.
+#[derive(Clone)]
+pub struct Web3Client {
+    nodes: Arc<Mutex<NodeList>>,
+    timeout: Duration,
+}
+
+/// Posts a request to a single node, anything but a well formed JSON-RPC response is an error
+fn send(url: &str, body: String, timeout: Duration) -> Box<Future<Item = Response, Error = Error>> {
+    let request = match client::post(url)
+        .timeout(timeout)
+        .content_type("application/json")
+        .body(body)
+    {
+        Ok(request) => request,
+        Err(e) => return Box::new(err(format_err!("Bad request to {}: {}", url, e))),
+    };
+    Box::new(
+        request
+            .send()
+            .from_err()
+            .and_then(|response| response.json().from_err()),
+    )
+}
+
+impl Web3Client {
+    pub fn new(urls: &[String], timeout: Duration) -> Web3Client {
+        Web3Client {
+            nodes: Arc::new(Mutex::new(NodeList::new(urls))),
+            timeout,
+        }
+    }
+
+    pub fn set_nodes(&self, urls: &[String]) {
+        self.nodes.lock().unwrap().set_urls(urls);
+    }
+
+    pub fn nodes(&self) -> NodeList {
+        self.nodes.lock().unwrap().clone()
+    }
+
+    /// Sends a request to the best node, moving on to the next one whenever a node can't be
+    /// reached or doesn't answer properly. An error reported by a node is returned as is.
+    pub fn request<T: DeserializeOwned + 'static>(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> Box<Future<Item = T, Error = Error>> {
+        let body = match serde_json::to_string(&Request {
+            jsonrpc: "2.0",
+            method,
+            params,
+            id: 1,
+        }) {
+            Ok(body) => body,
+            Err(e) => return Box::new(err(e.into())),
+        };
+        let urls = self.nodes.lock().unwrap().order();
+        if urls.is_empty() {
+            return Box::new(err(Web3Error::NoNodes.into()));
+        }
+        trace!("Sending {} to full nodes {:?}", body, urls);
+
+        let nodes = self.nodes.clone();
+        let timeout = self.timeout;
+        let response = loop_fn((urls, 0), move |(urls, i): (Vec<String>, usize)| {
+            let url = urls[i].clone();
+            let nodes = nodes.clone();
+            send(&url, body.clone(), timeout).then(move |response| match response {
+                Ok(response) => {
+                    nodes.lock().unwrap().success(&url);
+                    Ok(Loop::Break(response))
+                }
+                Err(e) => {
+                    warn!("Full node {} failed with {:?}", url, e);
+                    nodes.lock().unwrap().failure(&url);
+                    if i + 1 < urls.len() {
+                        Ok(Loop::Continue((urls, i + 1)))
+                    } else {
+                        Err(Web3Error::AllNodesFailed(e.to_string()).into())
+                    }
+                }
+            })
+        });
+
+        Box::new(response.and_then(|response: Response| match response.error {
+            Some(e) => Err(Web3Error::Rpc {
+                code: e.code,
+                message: e.message,
+            }.into()),
+            None => Ok(serde_json::from_value(response.result)?),
+        }))
+    }
+
+    /// Runs a call against the latest block and returns its raw result
+    pub fn eth_call(
+        &self,
+        call: &TransactionRequest,
+    ) -> Box<Future<Item = Vec<u8>, Error = Error>> {
+        Box::new(
+            self.request("eth_call", json!([call.to_json(), "latest"]))
+                .and_then(|result: String| parse_data(&result)),
+        )
+    }
+
+    pub fn eth_get_balance(
+        &self,
+        address: &EthAddress,
+    ) -> Box<Future<Item = Uint256, Error = Error>> {
+        Box::new(
+            self.request("eth_getBalance", json!([format_address(address), "latest"]))
+                .and_then(|result: String| parse_quantity(&result)),
+        )
+    }
+
+    pub fn eth_block_number(&self) -> Box<Future<Item = Uint256, Error = Error>> {
+        Box::new(
+            self.request("eth_blockNumber", json!([]))
+                .and_then(|result: String| parse_quantity(&result)),
+        )
+    }
+
+    /// The nonce for the next transaction from this address, counting pending ones
+    pub fn eth_get_transaction_count(
+        &self,
+        address: &EthAddress,
+    ) -> Box<Future<Item = Uint256, Error = Error>> {
+        Box::new(
+            self.request(
+                "eth_getTransactionCount",
+                json!([format_address(address), "pending"]),
+            ).and_then(|result: String| parse_quantity(&result)),
+        )
+    }
+
+    pub fn eth_gas_price(&self) -> Box<Future<Item = Uint256, Error = Error>> {
+        Box::new(
+            self.request("eth_gasPrice", json!([]))
+                .and_then(|result: String| parse_quantity(&result)),
+        )
+    }
+
+    /// The chain id transactions are signed for
+    pub fn net_version(&self) -> Box<Future<Item = u64, Error = Error>> {
+        Box::new(
+            self.request("net_version", json!([]))
+                .and_then(|result: String| Ok(result.parse::<u64>()?)),
+        )
+    }
+
+    /// Submits a signed transaction and returns its hash
+    pub fn eth_send_raw_transaction(
+        &self,
+        transaction: &[u8],
+    ) -> Box<Future<Item = Uint256, Error = Error>> {
+        Box::new(
+            self.request("eth_sendRawTransaction", json!([format_data(transaction)]))
+                .and_then(|result: String| parse_quantity(&result)),
+        )
+    }
+
+    /// None until the transaction has been mined
+    pub fn eth_get_transaction_receipt(
+        &self,
+        hash: &Uint256,
+    ) -> Box<Future<Item = Option<TransactionReceipt>, Error = Error>> {
+        Box::new(
+            self.request("eth_getTransactionReceipt", json!([format_hash(hash)]))
+                .and_then(|result: Option<RawReceipt>| match result {
+                    Some(receipt) => Ok(Some(receipt.parse()?)),
+                    None => Ok(None),
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::System;
+    use mock::{unreachable_url, MockNode};
+
+    fn client(urls: Vec<String>) -> Web3Client {
+        Web3Client::new(&urls, Duration::from_secs(5))
+    }
+
+    #[test]
+    fn test_typed_requests() {
+        let node = MockNode::new()
+            .result("eth_blockNumber", json!("0x10"))
+            .result("eth_getBalance", json!("0xde0b6b3a7640000"))
+            .result("eth_call", json!(format!("0x{}01", "00".repeat(31))))
+            .result("eth_sendRawTransaction", json!(format_hash(&Uint256::from(7u64))))
+            .result("eth_getTransactionReceipt", Value::Null)
+            .result("eth_getTransactionCount", json!("0x2"))
+            .result("eth_gasPrice", json!("0x3b9aca00"))
+            .result("net_version", json!("4"))
+            .start();
+        let web3 = client(vec![node.url.clone()]);
+        let mut sys = System::new("test");
+
+        assert_eq!(
+            sys.block_on(web3.eth_block_number()).unwrap(),
+            Uint256::from(16u64)
+        );
+        assert_eq!(
+            sys.block_on(web3.eth_get_balance(&1.into())).unwrap(),
+            Uint256::from(1_000_000_000_000_000_000u64)
+        );
+        let call = TransactionRequest {
+            from: None,
+            to: 2.into(),
+            data: vec![1, 2, 3, 4],
+        };
+        let result = sys.block_on(web3.eth_call(&call)).unwrap();
+        assert_eq!(result.len(), 32);
+        assert_eq!(result[31], 1);
+        assert_eq!(
+            sys.block_on(web3.eth_send_raw_transaction(&[0xf8])).unwrap(),
+            Uint256::from(7u64)
+        );
+        assert_eq!(
+            sys.block_on(web3.eth_get_transaction_receipt(&Uint256::from(7u64)))
+                .unwrap(),
+            None
+        );
+
+        assert_eq!(
+            sys.block_on(web3.eth_get_transaction_count(&1.into()))
+                .unwrap(),
+            Uint256::from(2u64)
+        );
+        assert_eq!(
+            sys.block_on(web3.eth_gas_price()).unwrap(),
+            Uint256::from(1_000_000_000u64)
+        );
+        assert_eq!(sys.block_on(web3.net_version()).unwrap(), 4);
+
+        let requests = node.requests();
+        assert_eq!(requests.len(), 8);
+        assert_eq!(
+            requests[1]["params"],
+            json!(["0x0000000000000000000000000000000000000001", "latest"])
+        );
+        assert_eq!(
+            requests[2]["params"][0],
+            json!({"to": "0x0000000000000000000000000000000000000002", "data": "0x01020304"})
+        );
+        assert_eq!(requests[3]["params"], json!(["0xf8"]));
+    }
+
+    #[test]
+    fn test_failover() {
+        let node = MockNode::new()
+            .result("eth_blockNumber", json!("0x1"))
+            .start();
+        let down = unreachable_url();
+        let web3 = client(vec![down.clone(), node.url.clone()]);
+        let mut sys = System::new("test");
+        // nodes with equal scores are shuffled, start the live one lower so the dead one goes first
+        web3.nodes.lock().unwrap().failure(&node.url);
+
+        for _ in 0..3 {
+            assert_eq!(
+                sys.block_on(web3.eth_block_number()).unwrap(),
+                Uint256::from(1u64)
+            );
+        }
+        assert_eq!(node.requests().len(), 3);
+        let nodes = web3.nodes();
+        assert!(nodes.score(&down).unwrap() < 0);
+        assert!(nodes.score(&node.url).unwrap() > nodes.score(&down).unwrap());
+
+        let web3 = client(vec![down]);
+        assert!(sys.block_on(web3.eth_block_number()).is_err());
+        assert!(sys.block_on(client(vec![]).eth_block_number()).is_err());
+    }
+
+    #[test]
+    fn test_node_errors() {
+        let node = MockNode::new()
+            .error("eth_call", -32015, "VM execution error.")
+            .start();
+        let web3 = client(vec![node.url.clone()]);
+        let mut sys = System::new("test");
+
+        let call = TransactionRequest {
+            from: None,
+            to: 2.into(),
+            data: Vec::new(),
+        };
+        let e = sys.block_on(web3.eth_call(&call)).unwrap_err();
+        assert_eq!(e.to_string(), "Full node returned error -32015: VM execution error.");
+        // the node answered so it isn't marked as failing
+        assert_eq!(web3.nodes().score(&node.url), Some(1));
+        // methods the node doesn't know about come back as errors too
+        assert!(sys.block_on(web3.eth_block_number()).is_err());
+    }
+}
@@ -0,0 +1,54 @@
+//! A small asynchronous client for the Ethereum JSON-RPC api, used to read contract state and
+//! submit transactions through a list of full nodes. Requests go to the healthiest node first
+//! and fail over to the others if it can't be reached, see `NodeList`. Transactions are signed
+//! locally with the node's own key before being sent.
+
+extern crate actix;
+extern crate actix_web;
+extern crate althea_types;
+#[macro_use]
+extern crate failure;
+extern crate futures;
+extern crate hex;
+#[macro_use]
+extern crate log;
+extern crate num256;
+extern crate rand;
+extern crate secp256k1;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+extern crate tiny_keccak;
+
+pub mod abi;
+mod client;
+pub mod mock;
+mod nodes;
+mod rlp;
+mod transaction;
+mod types;
+
+pub use client::Web3Client;
+pub use nodes::NodeList;
+pub use transaction::{generate_key, key_address, Transaction};
+pub use types::{format_hash, TransactionReceipt, TransactionRequest};
+
+#[derive(Debug, Fail)]
+pub enum Web3Error {
+    #[fail(display = "No full nodes configured")]
+    NoNodes,
+    #[fail(display = "Every full node failed, the last with: {}", _0)]
+    AllNodesFailed(String),
+    #[fail(display = "Full node returned error {}: {}", code, message)]
+    Rpc { code: i64, message: String },
+    #[fail(display = "Invalid hex value '{}'", _0)]
+    BadHex(String),
+    #[fail(display = "Can't encode {} bytes as a fixed size argument", _0)]
+    ArgumentTooLong(usize),
+    #[fail(display = "Expected at least 32 bytes of call result, got {}", _0)]
+    ShortResult(usize),
+    #[fail(display = "Call result is not a bool")]
+    NotABool,
+}
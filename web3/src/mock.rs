@@ -0,0 +1,122 @@
+//! A stand in full node for tests. It answers JSON-RPC requests over plain http with canned
+//! replies per method and keeps every request it got so tests can check what was sent.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use failure::Error;
+
+use serde_json;
+use serde_json::Value;
+
+/// The replies to give, set up before starting the node
+#[derive(Debug, Clone, Default)]
+pub struct MockNode {
+    replies: HashMap<String, Value>,
+}
+
+impl MockNode {
+    pub fn new() -> MockNode {
+        MockNode::default()
+    }
+
+    pub fn result(mut self, method: &str, result: Value) -> MockNode {
+        self.replies
+            .insert(method.to_string(), json!({ "result": result }));
+        self
+    }
+
+    pub fn error(mut self, method: &str, code: i64, message: &str) -> MockNode {
+        self.replies.insert(
+            method.to_string(),
+            json!({"error": {"code": code, "message": message}}),
+        );
+        self
+    }
+
+    /// Listens on a random local port until the test process exits
+    pub fn start(self) -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind mock node");
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let received = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if let Err(e) = self.answer(stream, &received) {
+                            warn!("Mock node failed to answer {:?}", e);
+                        }
+                    }
+                    Err(e) => warn!("Mock node failed to accept {:?}", e),
+                }
+            }
+        });
+
+        MockServer { url, requests }
+    }
+
+    fn answer(&self, stream: TcpStream, received: &Mutex<Vec<Value>>) -> Result<(), Error> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            let lower = line.to_lowercase();
+            if lower.starts_with("content-length:") {
+                content_length = lower["content-length:".len()..].trim().parse()?;
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+
+        let request: Value = serde_json::from_slice(&body)?;
+        received.lock().unwrap().push(request.clone());
+
+        let method = request["method"].as_str().unwrap_or_default();
+        let mut reply = match self.replies.get(method) {
+            Some(reply) => reply.clone(),
+            None => json!({"error": {"code": -32601, "message": "Method not found"}}),
+        };
+        reply["jsonrpc"] = json!("2.0");
+        reply["id"] = request["id"].clone();
+        let reply = reply.to_string();
+
+        let mut stream = stream;
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+             Connection: close\r\n\r\n{}",
+            reply.len(),
+            reply
+        )?;
+        stream.flush()?;
+        Ok(())
+    }
+}
+
+pub struct MockServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<Value>>>,
+}
+
+impl MockServer {
+    /// Every request received so far
+    pub fn requests(&self) -> Vec<Value> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// A url nothing is listening on, for testing failover
+pub fn unreachable_url() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind a free port");
+    format!("http://{}/", listener.local_addr().unwrap())
+}
@@ -0,0 +1,111 @@
+//! Health tracking for the configured full nodes. Every node starts with the same score, answers
+//! raise it and failures lower it faster, requests go to the best scoring nodes first and nodes
+//! with equal scores are shuffled to spread the load between them.
+
+use rand::{thread_rng, Rng};
+
+const MAX_SCORE: i32 = 10;
+const MIN_SCORE: i32 = -10;
+const FAILURE_PENALTY: i32 = 5;
+
+#[derive(Debug, Clone)]
+struct Node {
+    url: String,
+    score: i32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NodeList {
+    nodes: Vec<Node>,
+}
+
+impl NodeList {
+    pub fn new(urls: &[String]) -> NodeList {
+        let mut list = NodeList::default();
+        list.set_urls(urls);
+        list
+    }
+
+    /// Replaces the nodes, keeping the scores of those that are still listed
+    pub fn set_urls(&mut self, urls: &[String]) {
+        let nodes = urls
+            .iter()
+            .map(|url| Node {
+                url: url.clone(),
+                score: self.score(url).unwrap_or(0),
+            }).collect();
+        self.nodes = nodes;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn score(&self, url: &str) -> Option<i32> {
+        self.nodes
+            .iter()
+            .find(|node| node.url == url)
+            .map(|node| node.score)
+    }
+
+    /// Every node in the order they should be tried
+    pub fn order(&self) -> Vec<String> {
+        let mut nodes = self.nodes.clone();
+        thread_rng().shuffle(&mut nodes);
+        nodes.sort_by(|a, b| b.score.cmp(&a.score));
+        nodes.into_iter().map(|node| node.url).collect()
+    }
+
+    pub fn success(&mut self, url: &str) {
+        if let Some(node) = self.nodes.iter_mut().find(|node| node.url == url) {
+            node.score = (node.score + 1).min(MAX_SCORE);
+        }
+    }
+
+    pub fn failure(&mut self, url: &str) {
+        if let Some(node) = self.nodes.iter_mut().find(|node| node.url == url) {
+            node.score = (node.score - FAILURE_PENALTY).max(MIN_SCORE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls() -> Vec<String> {
+        vec!["http://a".to_string(), "http://b".to_string()]
+    }
+
+    #[test]
+    fn test_failover_order() {
+        let mut list = NodeList::new(&urls());
+        list.failure("http://a");
+        assert_eq!(list.order(), vec!["http://b", "http://a"]);
+
+        // a couple of answers don't make up for a failure
+        list.success("http://a");
+        list.success("http://a");
+        assert_eq!(list.order(), vec!["http://b", "http://a"]);
+        assert_eq!(list.score("http://a"), Some(-3));
+
+        for _ in 0..20 {
+            list.failure("http://b");
+        }
+        assert_eq!(list.score("http://b"), Some(MIN_SCORE));
+        assert_eq!(list.order(), vec!["http://a", "http://b"]);
+    }
+
+    #[test]
+    fn test_set_urls() {
+        let mut list = NodeList::new(&urls());
+        list.failure("http://b");
+        list.set_urls(&["http://b".to_string(), "http://c".to_string()]);
+        assert_eq!(list.score("http://a"), None);
+        assert_eq!(list.score("http://b"), Some(-FAILURE_PENALTY));
+        assert_eq!(list.score("http://c"), Some(0));
+        assert!(!list.is_empty());
+        list.set_urls(&[]);
+        assert!(list.is_empty());
+    }
+}
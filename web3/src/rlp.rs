@@ -0,0 +1,68 @@
+//! The subset of RLP needed to serialize transactions
+
+use num256::Uint256;
+
+fn length_prefix(len: usize, offset: u8) -> Vec<u8> {
+    if len < 56 {
+        vec![offset + len as u8]
+    } else {
+        let mut len_bytes = Vec::new();
+        let mut remaining = len;
+        while remaining > 0 {
+            len_bytes.insert(0, remaining as u8);
+            remaining >>= 8;
+        }
+        let mut prefix = vec![offset + 55 + len_bytes.len() as u8];
+        prefix.extend(len_bytes);
+        prefix
+    }
+}
+
+pub fn encode_bytes(bytes: &[u8]) -> Vec<u8> {
+    if bytes.len() == 1 && bytes[0] < 0x80 {
+        return bytes.to_vec();
+    }
+    let mut encoded = length_prefix(bytes.len(), 0x80);
+    encoded.extend_from_slice(bytes);
+    encoded
+}
+
+/// Numbers are big endian without leading zeroes, so zero is the empty string
+pub fn encode_uint(value: &Uint256) -> Vec<u8> {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    let start = bytes.iter().position(|byte| *byte != 0).unwrap_or(32);
+    encode_bytes(&bytes[start..])
+}
+
+/// Takes items that have already been encoded
+pub fn encode_list(items: &[Vec<u8>]) -> Vec<u8> {
+    let payload: Vec<u8> = items.concat();
+    let mut encoded = length_prefix(payload.len(), 0xc0);
+    encoded.extend(payload);
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode_bytes(b"dog"), vec![0x83, b'd', b'o', b'g']);
+        assert_eq!(encode_bytes(&[]), vec![0x80]);
+        assert_eq!(encode_bytes(&[0x0f]), vec![0x0f]);
+        assert_eq!(encode_bytes(&[0x80]), vec![0x81, 0x80]);
+        assert_eq!(encode_uint(&Uint256::zero()), vec![0x80]);
+        assert_eq!(encode_uint(&Uint256::from(1024u64)), vec![0x82, 0x04, 0x00]);
+        assert_eq!(
+            encode_list(&[encode_bytes(b"cat"), encode_bytes(b"dog")]),
+            vec![0xc8, 0x83, b'c', b'a', b't', 0x83, b'd', b'o', b'g']
+        );
+
+        let long = vec![b'a'; 56];
+        let encoded = encode_bytes(&long);
+        assert_eq!(&encoded[..2], &[0xb8, 56]);
+        assert_eq!(encoded.len(), 58);
+    }
+}
@@ -0,0 +1,128 @@
+//! Signing of plain value and contract call transactions, with EIP-155 replay protection
+
+use failure::Error;
+
+use rand::{thread_rng, Rng};
+
+use secp256k1::recovery::RecoverableSignature;
+use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+
+use tiny_keccak::keccak256;
+
+use althea_types::{EthAddress, EthPrivateKey};
+use num256::Uint256;
+
+use rlp::{encode_bytes, encode_list, encode_uint};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transaction {
+    pub nonce: Uint256,
+    pub gas_price: Uint256,
+    pub gas_limit: Uint256,
+    pub to: EthAddress,
+    pub value: Uint256,
+    pub data: Vec<u8>,
+}
+
+impl Transaction {
+    fn fields(&self) -> Vec<Vec<u8>> {
+        vec![
+            encode_uint(&self.nonce),
+            encode_uint(&self.gas_price),
+            encode_uint(&self.gas_limit),
+            encode_bytes(&self.to.0),
+            encode_uint(&self.value),
+            encode_bytes(&self.data),
+        ]
+    }
+
+    /// The hash that gets signed, which commits to the chain id
+    pub fn signing_hash(&self, chain_id: u64) -> [u8; 32] {
+        let mut fields = self.fields();
+        fields.push(encode_uint(&Uint256::from(chain_id)));
+        fields.push(encode_uint(&Uint256::zero()));
+        fields.push(encode_uint(&Uint256::zero()));
+        keccak256(&encode_list(&fields))
+    }
+
+    /// Signs the transaction, the result can be sent with `eth_sendRawTransaction`
+    pub fn sign(&self, key: &EthPrivateKey, chain_id: u64) -> Result<Vec<u8>, Error> {
+        let secp = Secp256k1::new();
+        let secret = SecretKey::from_slice(&key.0)?;
+        let message = Message::from_slice(&self.signing_hash(chain_id))?;
+        let signature: RecoverableSignature = secp.sign_recoverable(&message, &secret);
+        let (recovery_id, compact) = signature.serialize_compact();
+
+        let v = chain_id * 2 + 35 + recovery_id.to_i32() as u64;
+        let mut fields = self.fields();
+        fields.push(encode_uint(&Uint256::from(v)));
+        fields.push(encode_uint(&Uint256::from_big_endian(&compact[..32])));
+        fields.push(encode_uint(&Uint256::from_big_endian(&compact[32..])));
+        Ok(encode_list(&fields))
+    }
+}
+
+/// The address belonging to a private key
+pub fn key_address(key: &EthPrivateKey) -> Result<EthAddress, Error> {
+    let secp = Secp256k1::new();
+    let secret = SecretKey::from_slice(&key.0)?;
+    let public = PublicKey::from_secret_key(&secp, &secret).serialize_uncompressed();
+    // the first byte only marks the key as uncompressed
+    let hash = keccak256(&public[1..]);
+    Ok(EthAddress::from_slice(&hash[12..]))
+}
+
+pub fn generate_key() -> EthPrivateKey {
+    loop {
+        let mut bytes = [0u8; 32];
+        thread_rng().fill(&mut bytes);
+        // almost every 32 bytes are a valid key, those past the curve order are not
+        if SecretKey::from_slice(&bytes).is_ok() {
+            return EthPrivateKey::from(bytes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex;
+
+    fn eip155_example() -> (Transaction, EthPrivateKey) {
+        let transaction = Transaction {
+            nonce: Uint256::from(9u64),
+            gas_price: Uint256::from(20_000_000_000u64),
+            gas_limit: Uint256::from(21000u64),
+            to: EthAddress::from_slice(&[0x35; 20]),
+            value: Uint256::from(1_000_000_000_000_000_000u64),
+            data: Vec::new(),
+        };
+        (transaction, EthPrivateKey::from([0x46u8; 32]))
+    }
+
+    #[test]
+    fn test_sign() {
+        let (transaction, key) = eip155_example();
+        assert_eq!(
+            hex::encode(transaction.signing_hash(1)),
+            "daf5a779ae972f972197303d7b574746c7ef83eadac0f2791ad23db92e4c8e53"
+        );
+        assert_eq!(
+            hex::encode(transaction.sign(&key, 1).unwrap()),
+            "f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a7640000\
+             8025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f\
+             761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83"
+        );
+    }
+
+    #[test]
+    fn test_key_address() {
+        let (_, key) = eip155_example();
+        assert_eq!(
+            hex::encode(key_address(&key).unwrap().0),
+            "9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f"
+        );
+        let key = generate_key();
+        assert_ne!(key_address(&key).unwrap(), key_address(&generate_key()).unwrap());
+    }
+}
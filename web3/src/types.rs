@@ -0,0 +1,189 @@
+//! JSON-RPC envelopes and the conversions between Ethereum's hex encoding and our types
+
+use std::str::FromStr;
+
+use failure::Error;
+
+use hex;
+
+use serde_json::Value;
+
+use althea_types::EthAddress;
+use num256::Uint256;
+
+use Web3Error;
+
+#[derive(Serialize, Debug)]
+pub struct Request<'a> {
+    pub jsonrpc: &'static str,
+    pub method: &'a str,
+    pub params: Value,
+    pub id: u64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Response {
+    /// Left as json since some methods legitimately return null
+    #[serde(default)]
+    pub result: Value,
+    pub error: Option<RpcError>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+/// A contract call or transaction to estimate, `eth_call` only needs the target and data
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionRequest {
+    pub from: Option<EthAddress>,
+    pub to: EthAddress,
+    pub data: Vec<u8>,
+}
+
+impl TransactionRequest {
+    pub fn to_json(&self) -> Value {
+        let mut request = json!({
+            "to": format_address(&self.to),
+            "data": format_data(&self.data),
+        });
+        if let Some(ref from) = self.from {
+            request["from"] = json!(format_address(from));
+        }
+        request
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionReceipt {
+    pub transaction_hash: Uint256,
+    /// None while the transaction is still pending
+    pub block_number: Option<Uint256>,
+    pub gas_used: Uint256,
+    /// Whether the transaction succeeded, only reported by nodes after Byzantium
+    pub status: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RawReceipt {
+    transaction_hash: String,
+    block_number: Option<String>,
+    gas_used: String,
+    status: Option<String>,
+}
+
+impl RawReceipt {
+    pub fn parse(&self) -> Result<TransactionReceipt, Error> {
+        Ok(TransactionReceipt {
+            transaction_hash: parse_quantity(&self.transaction_hash)?,
+            block_number: match self.block_number {
+                Some(ref number) => Some(parse_quantity(number)?),
+                None => None,
+            },
+            gas_used: parse_quantity(&self.gas_used)?,
+            status: match self.status {
+                Some(ref status) => Some(parse_quantity(status)? != Uint256::zero()),
+                None => None,
+            },
+        })
+    }
+}
+
+fn strip_prefix(value: &str) -> Result<&str, Error> {
+    if value.starts_with("0x") {
+        Ok(&value[2..])
+    } else {
+        Err(Web3Error::BadHex(value.to_string()).into())
+    }
+}
+
+/// Parses a `0x` prefixed number or hash of up to 32 bytes
+pub fn parse_quantity(value: &str) -> Result<Uint256, Error> {
+    let digits = strip_prefix(value)?;
+    if digits.is_empty() || digits.len() > 64 {
+        return Err(Web3Error::BadHex(value.to_string()).into());
+    }
+    match Uint256::from_str(digits) {
+        Ok(quantity) => Ok(quantity),
+        Err(_) => Err(Web3Error::BadHex(value.to_string()).into()),
+    }
+}
+
+pub fn parse_data(value: &str) -> Result<Vec<u8>, Error> {
+    match hex::decode(strip_prefix(value)?) {
+        Ok(data) => Ok(data),
+        Err(_) => Err(Web3Error::BadHex(value.to_string()).into()),
+    }
+}
+
+pub fn format_data(data: &[u8]) -> String {
+    format!("0x{}", hex::encode(data))
+}
+
+pub fn format_address(address: &EthAddress) -> String {
+    format_data(&address.0)
+}
+
+/// Hashes are sent with all 32 bytes
+pub fn format_hash(hash: &Uint256) -> String {
+    let mut bytes = [0u8; 32];
+    hash.to_big_endian(&mut bytes);
+    format_data(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn test_quantities() {
+        assert_eq!(parse_quantity("0x0").unwrap(), Uint256::zero());
+        assert_eq!(parse_quantity("0x1b4").unwrap(), Uint256::from(436u64));
+        assert!(parse_quantity("1b4").is_err());
+        assert!(parse_quantity("0x").is_err());
+        assert!(parse_quantity("0xzz").is_err());
+        assert!(parse_quantity(&format!("0x1{}", "0".repeat(64))).is_err());
+
+        assert_eq!(parse_data("0x").unwrap(), Vec::<u8>::new());
+        assert_eq!(parse_data("0x00ff").unwrap(), vec![0, 255]);
+        assert!(parse_data("0x0").is_err());
+
+        let hash = format_hash(&Uint256::from(1u64));
+        assert_eq!(hash.len(), 66);
+        assert_eq!(parse_quantity(&hash).unwrap(), Uint256::from(1u64));
+    }
+
+    #[test]
+    fn test_transaction_request() {
+        let request = TransactionRequest {
+            from: None,
+            to: 1.into(),
+            data: vec![0xab],
+        };
+        assert_eq!(
+            request.to_json().to_string(),
+            r#"{"data":"0xab","to":"0x0000000000000000000000000000000000000001"}"#
+        );
+    }
+
+    #[test]
+    fn test_receipt() {
+        let raw: RawReceipt = serde_json::from_str(
+            r#"{"transactionHash":"0x01","blockNumber":null,"gasUsed":"0x5208",
+                "status":"0x1","logs":[]}"#,
+        ).unwrap();
+        assert_eq!(
+            raw.parse().unwrap(),
+            TransactionReceipt {
+                transaction_hash: Uint256::from(1u64),
+                block_number: None,
+                gas_used: Uint256::from(21000u64),
+                status: Some(true),
+            }
+        );
+    }
+}